@@ -1,5 +1,7 @@
 fn main() {
-    // Propagate ESP-IDF build environment (linker scripts, etc.)
+    // Propagate ESP-IDF build environment (linker scripts, etc.). The
+    // simulator links for the host and has no ESP-IDF.
+    #[cfg(feature = "esp")]
     embuild::espidf::sysenv::output();
 
     // Explicit bitmap font sizes matching consolidated UI font sizes.
@@ -0,0 +1,151 @@
+//! Animated avatar playback.
+//!
+//! An animated avatar is an [`Animation`] container saved in the profile's
+//! avatar slot. While it plays, the container stays in memory and one-shot
+//! Slint timers on the UI thread step through its frames:
+//!
+//! - Frame delays come from the container, never shorter than
+//!   [`MIN_FRAME_MS`] (the frame rate cap).
+//! - If all frames fit in [`DECODED_BUDGET`] they are decoded once up front;
+//!   otherwise each frame is decoded just before it is shown, so memory
+//!   stays at the compressed container plus one frame.
+//! - Playback pauses while the badge page is hidden, the display is
+//!   dimmed or off (`display-dimmed`) or the battery is low
+//!   (`low-battery`), checked again every [`PAUSED_POLL_MS`].
+//!
+//! There is one player (the badge shows one avatar); [`play`] replaces it
+//! and [`stop`] ends it. Both must be called from the UI thread.
+
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use slint::{ComponentHandle, Image, Weak};
+
+use crate::image_codec::{Animation, MIN_FRAME_MS};
+use crate::ui_helpers::avatar_image;
+use crate::BadgeUI;
+
+/// Decoded frames kept in RAM when the whole animation fits (RGB888).
+const DECODED_BUDGET: usize = 2 * 1024 * 1024;
+
+/// How often a paused animation checks whether it may resume.
+const PAUSED_POLL_MS: u64 = 250;
+
+struct Player {
+    animation: Animation,
+    /// Every frame, pre-decoded, when they fit in [`DECODED_BUDGET`].
+    frames: Option<Vec<Image>>,
+    current: usize,
+    /// Ties scheduled timers to this player; stale ones do nothing.
+    generation: u32,
+}
+
+thread_local! {
+    static PLAYER: RefCell<Option<Player>> = const { RefCell::new(None) };
+    static GENERATION: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Show the first frame of `animation` as the avatar and start playing it.
+pub fn play(ui: &BadgeUI, animation: Animation) {
+    let generation = GENERATION.with(|g| {
+        g.set(g.get().wrapping_add(1));
+        g.get()
+    });
+    let count = animation.frame_count();
+    let frame_bytes = usize::from(animation.width) * usize::from(animation.height) * 3;
+    let frames = if count * frame_bytes <= DECODED_BUDGET {
+        (0..count)
+            .map(|i| animation.decode_frame(i).map(avatar_image))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| log::warn!("Animated avatar: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
+    let player = Player {
+        animation,
+        frames,
+        current: 0,
+        generation,
+    };
+    let Some(image) = player.frame_image() else {
+        PLAYER.with(|p| *p.borrow_mut() = None);
+        ui.set_avatar_image(Image::default());
+        return;
+    };
+    ui.set_avatar_image(image);
+    log::info!(
+        "Playing animated avatar: {count} frames, {} KB ({})",
+        player.animation.encoded_len() / 1024,
+        if player.frames.is_some() {
+            "pre-decoded"
+        } else {
+            "decoded per frame"
+        }
+    );
+    let delay = player.delay();
+    PLAYER.with(|p| *p.borrow_mut() = Some(player));
+    schedule(ui.as_weak(), generation, delay);
+}
+
+/// Stop any animation. The avatar image itself is left for the caller to
+/// replace.
+pub fn stop() {
+    GENERATION.with(|g| g.set(g.get().wrapping_add(1)));
+    PLAYER.with(|p| *p.borrow_mut() = None);
+}
+
+impl Player {
+    /// Image for the current frame, decoding it if not cached.
+    fn frame_image(&self) -> Option<Image> {
+        if let Some(frames) = &self.frames {
+            return frames.get(self.current).cloned();
+        }
+        match self.animation.decode_frame(self.current) {
+            Ok(rgb) => Some(avatar_image(rgb)),
+            Err(e) => {
+                log::warn!("Animated avatar frame {}: {e}", self.current);
+                None
+            }
+        }
+    }
+
+    /// How long the current frame stays up, with the frame rate cap applied.
+    fn delay(&self) -> Duration {
+        let ms = self.animation.delay_ms(self.current).max(MIN_FRAME_MS);
+        Duration::from_millis(ms.into())
+    }
+}
+
+fn schedule(weak: Weak<BadgeUI>, generation: u32, delay: Duration) {
+    slint::Timer::single_shot(delay, move || tick(weak, generation));
+}
+
+/// Advance to the next frame, or wait while paused.
+fn tick(weak: Weak<BadgeUI>, generation: u32) {
+    let Some(ui) = weak.upgrade() else { return };
+    let next = PLAYER.with(|p| {
+        let mut slot = p.borrow_mut();
+        let player = slot.as_mut().filter(|p| p.generation == generation)?;
+
+        if ui.get_current_page() != 0 || ui.get_display_dimmed() || ui.get_low_battery() {
+            return Some(Duration::from_millis(PAUSED_POLL_MS));
+        }
+        player.current = (player.current + 1) % player.animation.frame_count();
+        match player.frame_image() {
+            Some(image) => {
+                ui.set_avatar_image(image);
+                Some(player.delay())
+            }
+            None => {
+                // A corrupt frame stops playback on the last good one.
+                *slot = None;
+                None
+            }
+        }
+    });
+    if let Some(delay) = next {
+        schedule(weak, generation, delay);
+    }
+}
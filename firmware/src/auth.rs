@@ -0,0 +1,710 @@
+//! Web API authentication: an admin PIN shown on the badge, traded for a
+//! session token.
+//!
+//! The PIN (6 digits) is made up on the first boot, saved in NVS and shown
+//! under Settings → Web PIN, where it can also be replaced. `POST
+//! /api/login` with the PIN opens a session: a random 128-bit token, set as
+//! a cookie for the web page and returned in the body for scripts (send it
+//! as `Authorization: Bearer <token>`). Every handler that changes
+//! something, and the backup download, needs a session; read-only status
+//! stays public.
+//!
+//! Sessions live in RAM only, so a restart or a new PIN ends them all. At
+//! most [`MAX_SESSIONS`] are open; a new one pushes out the oldest.
+//!
+//! Wrong PINs are counted per client address: after [`FREE_ATTEMPTS`]
+//! misses in a row, that client's logins are refused for [`LOCKOUT_BASE`],
+//! doubling with every further miss up to [`LOCKOUT_MAX`], while everyone
+//! else can still log in. That puts a million PINs far out of reach, and
+//! the owner can always read the PIN off the badge. A new address starts
+//! with a clean count, but getting one means joining the AP again; only the
+//! last [`MAX_TRACKED_CLIENTS`] clients with misses are remembered. The PIN
+//! is never logged: `/api/logs` is public.
+//!
+//! A PIN can be overheard, so a session also has to be approved on the
+//! badge before its first change. The badge shows a 4-digit code, which the
+//! web page shows too, with Allow / Deny; Allow marks the session approved
+//! for as long as it lasts, Deny ends it. Approval is not saved: like the
+//! session it is lost on a restart, after which the browser logs in and is
+//! allowed again. Each session waiting for approval gets its own prompt;
+//! the badge shows them one at a time in the order they were asked for,
+//! and each lapses [`APPROVAL_TIMEOUT`] after it comes up, so a browser
+//! that walked away holds up the others for at most that long. The backup
+//! download, which holds every profile and can hold the WiFi password,
+//! needs approval too.
+
+use std::collections::VecDeque;
+#[cfg(any(not(feature = "simulator"), test))]
+use std::fmt;
+#[cfg(any(not(feature = "simulator"), test))]
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::hal::KeyValueStore;
+use crate::storage;
+
+/// Digits in the admin PIN.
+pub const PIN_LEN: usize = 6;
+
+/// Sessions open at once; logging in again pushes out the oldest.
+#[cfg(any(not(feature = "simulator"), test))]
+pub const MAX_SESSIONS: usize = 8;
+
+/// Wrong PINs in a row before logins are locked out.
+#[cfg(any(not(feature = "simulator"), test))]
+pub const FREE_ATTEMPTS: u32 = 5;
+
+/// First lockout, doubled for each further wrong PIN.
+#[cfg(any(not(feature = "simulator"), test))]
+pub const LOCKOUT_BASE: Duration = Duration::from_secs(30);
+
+/// Longest lockout.
+#[cfg(any(not(feature = "simulator"), test))]
+pub const LOCKOUT_MAX: Duration = Duration::from_secs(15 * 60);
+
+/// Clients whose wrong PINs are remembered; past this the one that missed
+/// longest ago is forgotten.
+#[cfg(any(not(feature = "simulator"), test))]
+pub const MAX_TRACKED_CLIENTS: usize = 16;
+
+/// Cookie carrying the session token for the web page.
+#[cfg(any(not(feature = "simulator"), test))]
+pub const SESSION_COOKIE: &str = "vrcb_session";
+
+/// How long the badge shows an approval prompt before it lapses.
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[cfg(any(not(feature = "simulator"), test))]
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoginError {
+    /// Wrong PIN; this many more misses before the lockout.
+    WrongPin { attempts_left: u32 },
+    /// Too many wrong PINs; try again after this long.
+    Locked { retry_after: Duration },
+}
+
+#[cfg(any(not(feature = "simulator"), test))]
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongPin { attempts_left } => {
+                write!(f, "Wrong PIN ({attempts_left} tries before a lockout)")
+            }
+            Self::Locked { retry_after } => write!(
+                f,
+                "Too many wrong PINs, try again in {} s",
+                retry_after.as_secs().max(1)
+            ),
+        }
+    }
+}
+
+/// Whether a session may make a change.
+#[cfg(any(not(feature = "simulator"), test))]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Logged in and approved on the badge.
+    Allowed,
+    /// No open session with this token.
+    NoSession,
+    /// Waiting for Allow on the badge, which shows `code` once the
+    /// `ahead` prompts before it are answered or lapse.
+    Pending { code: String, ahead: usize },
+}
+
+struct Session {
+    token: String,
+    /// Allowed on the badge.
+    approved: bool,
+}
+
+/// An approval prompt, shown on the badge once those before it are gone.
+struct Prompt {
+    token: String,
+    code: String,
+    /// Set when it comes up on the badge.
+    expires: Option<Instant>,
+}
+
+/// Wrong PINs from one client since its last right one.
+#[cfg(any(not(feature = "simulator"), test))]
+struct Failures {
+    client: Ipv4Addr,
+    count: u32,
+    locked_until: Option<Instant>,
+    last: Instant,
+}
+
+pub struct Auth {
+    pin: String,
+    /// Open sessions, oldest first.
+    sessions: VecDeque<Session>,
+    /// Clients with wrong PINs, at most [`MAX_TRACKED_CLIENTS`].
+    #[cfg(any(not(feature = "simulator"), test))]
+    failures: Vec<Failures>,
+    /// Prompts waiting for an answer, oldest (the one on the badge) first.
+    prompts: VecDeque<Prompt>,
+}
+
+impl Auth {
+    pub fn new(pin: String) -> Self {
+        Self {
+            pin,
+            sessions: VecDeque::new(),
+            #[cfg(any(not(feature = "simulator"), test))]
+            failures: Vec::new(),
+            prompts: VecDeque::new(),
+        }
+    }
+
+    pub fn pin(&self) -> &str {
+        &self.pin
+    }
+
+    /// Replace the PIN. Ends every session and lifts every lockout.
+    pub fn set_pin(&mut self, pin: String) {
+        self.pin = pin;
+        self.sessions.clear();
+        self.prompts.clear();
+        #[cfg(any(not(feature = "simulator"), test))]
+        self.failures.clear();
+    }
+
+    /// Open session `token` if `pin` is right and `client` isn't locked out.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn login(
+        &mut self,
+        client: Ipv4Addr,
+        pin: &str,
+        token: String,
+        now: Instant,
+    ) -> Result<(), LoginError> {
+        let entry = self.failures.iter().position(|f| f.client == client);
+        if let Some(until) = entry
+            .and_then(|i| self.failures[i].locked_until)
+            .filter(|&until| now < until)
+        {
+            return Err(LoginError::Locked {
+                retry_after: until - now,
+            });
+        }
+        if !constant_time_eq(pin.as_bytes(), self.pin.as_bytes()) {
+            let failures = self.miss(entry, client, now);
+            if failures.count < FREE_ATTEMPTS {
+                return Err(LoginError::WrongPin {
+                    attempts_left: FREE_ATTEMPTS - failures.count,
+                });
+            }
+            let lockout = lockout(failures.count);
+            failures.locked_until = Some(now + lockout);
+            log::warn!(
+                "{} wrong admin PINs from {client}, locked for {lockout:?}",
+                failures.count
+            );
+            return Err(LoginError::Locked {
+                retry_after: lockout,
+            });
+        }
+        if let Some(i) = entry {
+            self.failures.swap_remove(i);
+        }
+        if self.sessions.len() >= MAX_SESSIONS {
+            if let Some(oldest) = self.sessions.pop_front() {
+                self.drop_prompt_of(&oldest.token);
+            }
+        }
+        self.sessions.push_back(Session {
+            token,
+            approved: false,
+        });
+        Ok(())
+    }
+
+    /// Count a wrong PIN from `client`, whose entry is at `entry` if it has
+    /// one. A new entry pushes out the one that missed longest ago.
+    #[cfg(any(not(feature = "simulator"), test))]
+    fn miss(&mut self, entry: Option<usize>, client: Ipv4Addr, now: Instant) -> &mut Failures {
+        let i = entry.unwrap_or_else(|| {
+            if self.failures.len() >= MAX_TRACKED_CLIENTS {
+                if let Some(stalest) =
+                    (0..self.failures.len()).min_by_key(|&i| self.failures[i].last)
+                {
+                    self.failures.swap_remove(stalest);
+                }
+            }
+            self.failures.push(Failures {
+                client,
+                count: 0,
+                locked_until: None,
+                last: now,
+            });
+            self.failures.len() - 1
+        });
+        let failures = &mut self.failures[i];
+        failures.count += 1;
+        failures.last = now;
+        failures
+    }
+
+    #[cfg(any(not(feature = "simulator"), test))]
+    fn session(&self, token: &str) -> Option<&Session> {
+        self.sessions
+            .iter()
+            .find(|s| constant_time_eq(s.token.as_bytes(), token.as_bytes()))
+    }
+
+    /// Whether `token` belongs to an open session.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn is_valid(&self, token: &str) -> bool {
+        self.session(token).is_some()
+    }
+
+    /// Whether session `token` has been allowed on the badge.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn is_approved(&self, token: &str) -> bool {
+        self.session(token).is_some_and(|s| s.approved)
+    }
+
+    /// End session `token`, if open.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn logout(&mut self, token: &str) {
+        self.sessions.retain(|s| s.token != token);
+        self.drop_prompt_of(token);
+    }
+
+    /// Whether session `token` may make a change. An unapproved session
+    /// gets a prompt, queued behind any already waiting.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn check_change(&mut self, token: &str, now: Instant) -> Access {
+        if !self.is_valid(token) {
+            return Access::NoSession;
+        }
+        if self.is_approved(token) {
+            return Access::Allowed;
+        }
+        if let Some((code, ahead)) = self.pending_code(token, now) {
+            return Access::Pending {
+                code: code.to_string(),
+                ahead,
+            };
+        }
+        // Codes tell the prompts apart, so keep them distinct
+        let code = loop {
+            let code = new_approval_code();
+            if self.prompts.iter().all(|p| p.code != code) {
+                break code;
+            }
+        };
+        log::info!("Web client waiting for approval on the badge");
+        self.prompts.push_back(Prompt {
+            token: token.to_string(),
+            code: code.clone(),
+            expires: None,
+        });
+        self.expire_prompts(now);
+        Access::Pending {
+            code,
+            ahead: self.prompts.len() - 1,
+        }
+    }
+
+    /// Code of session `token`'s prompt and how many prompts are ahead of
+    /// it, if it has one.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn pending_code(&mut self, token: &str, now: Instant) -> Option<(&str, usize)> {
+        self.expire_prompts(now);
+        let ahead = self.prompts.iter().position(|p| p.token == token)?;
+        Some((self.prompts[ahead].code.as_str(), ahead))
+    }
+
+    /// Code of the prompt the badge should show, if any.
+    pub fn prompt(&mut self, now: Instant) -> Option<&str> {
+        self.expire_prompts(now);
+        self.prompts.front().map(|p| p.code.as_str())
+    }
+
+    /// Allow or deny the session behind the prompt showing `code`. Deny
+    /// ends it. Returns false if that prompt is no longer up (e.g. it
+    /// lapsed).
+    pub fn answer_prompt(&mut self, code: &str, allow: bool, now: Instant) -> bool {
+        self.expire_prompts(now);
+        if self.prompts.front().map(|p| p.code.as_str()) != Some(code) {
+            return false;
+        }
+        let Some(prompt) = self.prompts.pop_front() else {
+            return false;
+        };
+        self.expire_prompts(now);
+        if allow {
+            if let Some(session) = self.sessions.iter_mut().find(|s| s.token == prompt.token) {
+                session.approved = true;
+            }
+            log::info!("Web client allowed on the badge");
+        } else {
+            self.sessions.retain(|s| s.token != prompt.token);
+            log::warn!("Web client denied on the badge, session ended");
+        }
+        true
+    }
+
+    /// Drop the prompt on the badge once it has lapsed, and start the
+    /// clock on the next one.
+    fn expire_prompts(&mut self, now: Instant) {
+        while let Some(front) = self.prompts.front_mut() {
+            match front.expires {
+                None => {
+                    front.expires = Some(now + APPROVAL_TIMEOUT);
+                    break;
+                }
+                Some(expires) if now < expires => break,
+                Some(_) => {
+                    self.prompts.pop_front();
+                }
+            }
+        }
+    }
+
+    #[cfg(any(not(feature = "simulator"), test))]
+    fn drop_prompt_of(&mut self, token: &str) {
+        self.prompts.retain(|p| p.token != token);
+    }
+}
+
+/// Lockout after `failures` wrong PINs in a row (at least
+/// [`FREE_ATTEMPTS`]).
+#[cfg(any(not(feature = "simulator"), test))]
+fn lockout(failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(FREE_ATTEMPTS).min(16);
+    (LOCKOUT_BASE * 2u32.pow(doublings)).min(LOCKOUT_MAX)
+}
+
+/// Compare without stopping at the first difference, so response times
+/// don't give away how much of a guess was right.
+#[cfg(any(not(feature = "simulator"), test))]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The session token sent with a request: `Authorization: Bearer <token>`,
+/// or else the [`SESSION_COOKIE`] in `Cookie`.
+#[cfg(any(not(feature = "simulator"), test))]
+pub fn request_token<'a>(
+    cookie: Option<&'a str>,
+    authorization: Option<&'a str>,
+) -> Option<&'a str> {
+    if let Some(token) = authorization.and_then(|h| h.strip_prefix("Bearer ")) {
+        return Some(token.trim());
+    }
+    cookie?
+        .split(';')
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token)
+}
+
+/// Whether `pin` has the shape of an admin PIN.
+pub fn is_valid_pin(pin: &str) -> bool {
+    pin.len() == PIN_LEN && pin.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// A fresh random PIN.
+pub fn new_pin() -> String {
+    format!("{:06}", random_u32() % 1_000_000)
+}
+
+/// A fresh 4-digit approval code.
+#[cfg(any(not(feature = "simulator"), test))]
+fn new_approval_code() -> String {
+    format!("{:04}", random_u32() % 10_000)
+}
+
+/// A fresh session token: 128 random bits in hex.
+#[cfg(any(not(feature = "simulator"), test))]
+pub fn new_token() -> String {
+    (0..4).map(|_| format!("{:08x}", random_u32())).collect()
+}
+
+/// The saved PIN, or a new one (saved) on the first boot.
+pub fn load_or_create_pin(nvs: &mut dyn KeyValueStore) -> String {
+    if let Some(pin) = storage::load_admin_pin(nvs) {
+        return pin;
+    }
+    let pin = new_pin();
+    storage::save_admin_pin(nvs, &pin);
+    log::info!("Created a new web admin PIN");
+    pin
+}
+
+/// Auth state shared between the HTTP handlers and the Settings page.
+pub type SharedAuth = Arc<Mutex<Auth>>;
+
+/// Make up a new PIN, save it and end every session. Main thread only
+/// (NVS is `!Send`). Returns the new PIN.
+pub fn replace_pin(shared: &SharedAuth, nvs: &mut dyn KeyValueStore) -> Option<String> {
+    let pin = new_pin();
+    let mut auth = shared.lock().ok()?;
+    storage::save_admin_pin(nvs, &pin);
+    auth.set_pin(pin.clone());
+    log::info!("Web admin PIN replaced, all sessions ended");
+    Some(pin)
+}
+
+/// Hardware RNG; random once the radio is on, which it is before the HTTP
+/// server starts.
+#[cfg(not(feature = "simulator"))]
+fn random_u32() -> u32 {
+    unsafe { esp_idf_sys::esp_random() }
+}
+
+/// Good enough for the simulator: std's randomly keyed hasher.
+#[cfg(feature = "simulator")]
+fn random_u32() -> u32 {
+    use std::hash::BuildHasher;
+    std::collections::hash_map::RandomState::new().hash_one(std::time::SystemTime::now()) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: &str = "123456";
+    const PHONE: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 2);
+    const LAPTOP: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 3);
+
+    fn miss(auth: &mut Auth, client: Ipv4Addr, now: Instant) -> Result<(), LoginError> {
+        auth.login(client, "000000", new_token(), now)
+    }
+
+    #[test]
+    fn right_pin_opens_a_session() {
+        let mut auth = Auth::new(PIN.into());
+        let now = Instant::now();
+        auth.login(PHONE, PIN, "t1".into(), now).unwrap();
+        assert!(auth.is_valid("t1"));
+        assert!(!auth.is_approved("t1"));
+        assert!(!auth.is_valid("t2"));
+        auth.logout("t1");
+        assert!(!auth.is_valid("t1"));
+    }
+
+    #[test]
+    fn wrong_pins_lock_out_only_that_client() {
+        let mut auth = Auth::new(PIN.into());
+        let now = Instant::now();
+        for left in (1..FREE_ATTEMPTS).rev() {
+            assert_eq!(
+                miss(&mut auth, PHONE, now),
+                Err(LoginError::WrongPin {
+                    attempts_left: left
+                })
+            );
+        }
+        assert_eq!(
+            miss(&mut auth, PHONE, now),
+            Err(LoginError::Locked {
+                retry_after: LOCKOUT_BASE
+            })
+        );
+        // Even the right PIN is refused while locked
+        assert!(matches!(
+            auth.login(PHONE, PIN, "t1".into(), now),
+            Err(LoginError::Locked { .. })
+        ));
+        auth.login(LAPTOP, PIN, "t2".into(), now).unwrap();
+        assert!(!auth.is_valid("t1"));
+        assert!(auth.is_valid("t2"));
+    }
+
+    #[test]
+    fn lockouts_expire_and_double_up_to_the_max() {
+        let mut auth = Auth::new(PIN.into());
+        let mut now = Instant::now();
+        for _ in 0..FREE_ATTEMPTS {
+            let _ = miss(&mut auth, PHONE, now);
+        }
+        let mut expected = LOCKOUT_BASE;
+        for _ in 0..10 {
+            now += expected;
+            expected = (expected * 2).min(LOCKOUT_MAX);
+            assert_eq!(
+                miss(&mut auth, PHONE, now),
+                Err(LoginError::Locked {
+                    retry_after: expected
+                })
+            );
+        }
+        assert_eq!(expected, LOCKOUT_MAX);
+
+        // A right PIN after the lockout starts the count over
+        now += LOCKOUT_MAX;
+        auth.login(PHONE, PIN, "t1".into(), now).unwrap();
+        assert_eq!(
+            miss(&mut auth, PHONE, now),
+            Err(LoginError::WrongPin {
+                attempts_left: FREE_ATTEMPTS - 1
+            })
+        );
+    }
+
+    #[test]
+    fn only_recent_clients_are_remembered() {
+        let mut auth = Auth::new(PIN.into());
+        let mut now = Instant::now();
+        for _ in 0..FREE_ATTEMPTS {
+            let _ = miss(&mut auth, PHONE, now);
+        }
+        for host in 0..MAX_TRACKED_CLIENTS as u8 {
+            now += Duration::from_secs(1);
+            let _ = miss(&mut auth, Ipv4Addr::new(10, 0, 0, host), now);
+        }
+        assert!(auth.failures.len() <= MAX_TRACKED_CLIENTS);
+        auth.login(PHONE, PIN, "t1".into(), now).unwrap();
+    }
+
+    #[test]
+    fn the_oldest_session_is_pushed_out() {
+        let mut auth = Auth::new(PIN.into());
+        let now = Instant::now();
+        for i in 0..=MAX_SESSIONS {
+            auth.login(PHONE, PIN, format!("t{i}"), now).unwrap();
+        }
+        assert!(!auth.is_valid("t0"));
+        assert!((1..=MAX_SESSIONS).all(|i| auth.is_valid(&format!("t{i}"))));
+    }
+
+    #[test]
+    fn a_new_pin_ends_sessions_and_lockouts() {
+        let mut auth = Auth::new(PIN.into());
+        let now = Instant::now();
+        auth.login(LAPTOP, PIN, "t1".into(), now).unwrap();
+        for _ in 0..FREE_ATTEMPTS {
+            let _ = miss(&mut auth, PHONE, now);
+        }
+        auth.set_pin("654321".into());
+        assert!(!auth.is_valid("t1"));
+        assert!(auth.login(PHONE, PIN, "t2".into(), now).is_err());
+        auth.login(PHONE, "654321", "t2".into(), now).unwrap();
+    }
+
+    /// Auth with sessions `tokens`, none approved yet.
+    fn logged_in(tokens: &[&str], now: Instant) -> Auth {
+        let mut auth = Auth::new(PIN.into());
+        for token in tokens {
+            auth.login(PHONE, PIN, token.to_string(), now).unwrap();
+        }
+        auth
+    }
+
+    fn code(access: Access) -> String {
+        match access {
+            Access::Pending { code, .. } => code,
+            other => panic!("expected a prompt, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_change_waits_for_allow_on_the_badge() {
+        let now = Instant::now();
+        let mut auth = logged_in(&["t1"], now);
+        assert_eq!(auth.check_change("nope", now), Access::NoSession);
+
+        let shown = code(auth.check_change("t1", now));
+        assert_eq!(auth.prompt(now), Some(shown.as_str()));
+        // Asking again keeps the same prompt
+        assert_eq!(
+            auth.check_change("t1", now),
+            Access::Pending {
+                code: shown.clone(),
+                ahead: 0
+            }
+        );
+        assert!(auth.answer_prompt(&shown, true, now));
+        assert_eq!(auth.prompt(now), None);
+        assert_eq!(auth.check_change("t1", now), Access::Allowed);
+    }
+
+    #[test]
+    fn deny_ends_the_session() {
+        let now = Instant::now();
+        let mut auth = logged_in(&["t1"], now);
+        let shown = code(auth.check_change("t1", now));
+        assert!(auth.answer_prompt(&shown, false, now));
+        assert!(!auth.is_valid("t1"));
+        assert_eq!(auth.check_change("t1", now), Access::NoSession);
+    }
+
+    #[test]
+    fn prompts_queue_up_one_per_session() {
+        let now = Instant::now();
+        let mut auth = logged_in(&["t1", "t2", "t3"], now);
+        let first = code(auth.check_change("t1", now));
+        let second = auth.check_change("t2", now);
+        let third = code(auth.check_change("t3", now));
+        let Access::Pending {
+            code: second,
+            ahead: 1,
+        } = second
+        else {
+            panic!("expected t2 behind t1, got {second:?}");
+        };
+        assert_ne!(first, second);
+        assert_eq!(auth.pending_code("t3", now), Some((third.as_str(), 2)));
+
+        // Only the prompt on the badge can be answered
+        assert_eq!(auth.prompt(now), Some(first.as_str()));
+        assert!(!auth.answer_prompt(&second, true, now));
+        assert!(auth.answer_prompt(&first, true, now));
+        assert_eq!(auth.prompt(now), Some(second.as_str()));
+        assert_eq!(auth.pending_code("t3", now), Some((third.as_str(), 1)));
+
+        // Logging out takes a session's prompt out of the queue
+        auth.logout("t2");
+        assert_eq!(auth.prompt(now), Some(third.as_str()));
+        assert!(auth.answer_prompt(&third, true, now));
+        assert!(auth.is_approved("t1") && auth.is_approved("t3"));
+    }
+
+    #[test]
+    fn each_prompt_lapses_on_its_own_clock() {
+        let start = Instant::now();
+        let mut auth = logged_in(&["t1", "t2"], start);
+        let first = code(auth.check_change("t1", start));
+        let second = code(auth.check_change("t2", start));
+
+        // The second one's clock starts when it comes up
+        let lapsed = start + APPROVAL_TIMEOUT;
+        assert_eq!(auth.prompt(lapsed), Some(second.as_str()));
+        assert_eq!(auth.pending_code("t1", lapsed), None);
+        assert!(!auth.answer_prompt(&first, true, lapsed));
+        let later = lapsed + APPROVAL_TIMEOUT - Duration::from_secs(1);
+        assert!(auth.answer_prompt(&second, true, later));
+        assert!(!auth.is_approved("t1"));
+        assert!(auth.is_approved("t2"));
+
+        // A lapsed session asks again at the back of the queue
+        assert!(matches!(
+            auth.check_change("t1", later),
+            Access::Pending { ahead: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn bearer_token_wins_over_the_cookie() {
+        let cookie = Some("theme=dark; vrcb_session=fromcookie");
+        assert_eq!(request_token(cookie, None), Some("fromcookie"));
+        assert_eq!(
+            request_token(cookie, Some("Bearer fromheader")),
+            Some("fromheader")
+        );
+        assert_eq!(request_token(Some("theme=dark"), Some("Basic abc")), None);
+    }
+
+    #[test]
+    fn pins_are_six_digits() {
+        assert!(is_valid_pin(&new_pin()));
+        assert!(!is_valid_pin("12345"));
+        assert!(!is_valid_pin("12345a"));
+    }
+}
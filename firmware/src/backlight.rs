@@ -0,0 +1,45 @@
+//! Display backlight: LEDC PWM on GPIO 2.
+//!
+//! The JC8048W550 backlight is driven through a boost LED driver IC (U5).
+//! GPIO 2 connects to the IC's EN pin, which accepts PWM for dimming.
+//! 600Hz matches the Arduino demos for this board.
+
+use esp_idf_hal::gpio::Gpio2;
+use esp_idf_hal::ledc::config::TimerConfig;
+use esp_idf_hal::ledc::{LedcDriver, LedcTimerDriver, Resolution, CHANNEL0, TIMER0};
+use esp_idf_hal::units::FromValueType;
+
+use crate::hal::Backlight;
+
+/// 8-bit LEDC PWM driving the backlight boost converter.
+pub struct LedcBacklight {
+    driver: LedcDriver<'static>,
+    max_duty: u32,
+}
+
+impl LedcBacklight {
+    /// Configure the PWM channel (600Hz, 8-bit) and start at `percent`.
+    pub fn new(timer: TIMER0, channel: CHANNEL0, pin: Gpio2, percent: f32) -> anyhow::Result<Self> {
+        let timer = LedcTimerDriver::new(
+            timer,
+            &TimerConfig::new()
+                .frequency(600.Hz().into())
+                .resolution(Resolution::Bits8),
+        )?;
+        let driver = LedcDriver::new(channel, timer, pin)?;
+        let max_duty = driver.get_max_duty();
+
+        let mut backlight = Self { driver, max_duty };
+        backlight.set_percent(percent)?;
+        log::info!("Backlight PWM initialized (GPIO 2, 600Hz, {percent:.0}%)");
+        Ok(backlight)
+    }
+}
+
+impl Backlight for LedcBacklight {
+    fn set_percent(&mut self, percent: f32) -> anyhow::Result<()> {
+        let duty = (percent.clamp(0.0, 100.0) / 100.0 * self.max_duty as f32) as u32;
+        self.driver.set_duty(duty)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,646 @@
+//! Backup archives: the whole badge configuration in one file.
+//!
+//! `GET /api/backup` streams an archive; `POST /api/restore` takes one back.
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic "VBAK"
+//!      4     1  archive format version (1)
+//!      5     3  reserved (0)
+//!      8        entries, each:
+//!                 1  name length (1-64)
+//!                 n  name (ASCII)
+//!                 4  data length (u32)
+//!                 m  data
+//!               end marker: a name length of 0
+//!      +     4  CRC-32 of everything before it
+//! ```
+//!
+//! The first entry is `manifest.json`: the profiles (fields in the current
+//! [`profile_schema`] version), which one is active, the device settings
+//! and, only if asked for, the WiFi credentials and AP password. Each
+//! profile's avatar and background follow as `avatar_<id>.img` /
+//! `background_<id>.img`, copied byte for byte from SPIFFS (they carry
+//! their own header and CRC).
+//!
+//! Restoring streams the archive one entry at a time, so its size is bound
+//! by free flash rather than RAM. It gives the restored profiles fresh ids,
+//! so their images never overwrite the profiles they replace, and nothing
+//! else changes until the whole archive has been read and its checksum
+//! matches; a bad archive leaves no images behind.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::hal::ImageStore;
+use crate::image_codec::{self, Animation, Crc32};
+use crate::log_filter::LogFilter;
+use crate::profile::{ProfileError, ProfileIndex, ProfileSet, MAX_PROFILES};
+use crate::settings::DeviceSettings;
+use crate::{profile_schema, storage, sysinfo};
+
+/// Largest manifest accepted: eight full profiles come to a few KB.
+const MAX_MANIFEST_BYTES: usize = 64 * 1024;
+
+/// Largest image entry accepted: an animated avatar. A still image takes
+/// at most 3 bytes a pixel, which for a background is less.
+const MAX_IMAGE_ENTRY: usize = image_codec::MAX_ANIMATION_BYTES;
+
+const MAGIC: &[u8; 4] = b"VBAK";
+const FORMAT: u8 = 1;
+const HEADER_LEN: usize = 8;
+const MAX_NAME_LEN: usize = 64;
+const MANIFEST: &str = "manifest.json";
+
+/// Settings kept in NVS that go into a backup besides the profiles.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackupSettings {
+    /// Log filter spec, e.g. `"info,wifi=debug"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_levels: Option<String>,
+    /// Saved station network. Only included when the backup asks for
+    /// secrets; restoring without it keeps the badge's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wifi: Option<WifiCredentials>,
+    /// [`DeviceSettings`] as JSON, from [`device_settings`]. Restored as a
+    /// patch over the badge's own, so a key the backup lacks (the AP
+    /// password, or a setting newer than the backup) keeps its value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<Value>,
+}
+
+/// `settings` for [`BackupSettings::device`]. The AP password is a secret
+/// like the WiFi network's, so it is only kept with `secrets`.
+pub fn device_settings(settings: &DeviceSettings, secrets: bool) -> Option<Value> {
+    let mut json = serde_json::to_value(settings).ok()?;
+    if !secrets {
+        if let Some(ap) = json.get_mut("ap").and_then(Value::as_object_mut) {
+            ap.remove("password");
+        }
+    }
+    Some(json)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WifiCredentials {
+    pub ssid: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: u8,
+    /// Firmware that wrote the backup (informational).
+    firmware: String,
+    active: u32,
+    profiles: Vec<ManifestProfile>,
+    #[serde(flatten)]
+    settings: BackupSettings,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestProfile {
+    id: u32,
+    name: String,
+    profile: serde_json::Value,
+}
+
+/// Why an archive was refused.
+#[derive(Debug)]
+pub struct BackupError(pub String);
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+fn invalid(msg: impl Into<String>) -> BackupError {
+    BackupError(msg.into())
+}
+
+// ---------------------------------------------------------------------------
+// Writing
+// ---------------------------------------------------------------------------
+
+/// Stream a backup of `set`, its images and `settings` into `out`. Images
+/// are read one at a time, so memory use stays at one file.
+pub fn write_backup(
+    out: &mut impl Write,
+    set: &ProfileSet,
+    images: &dyn ImageStore,
+    settings: &BackupSettings,
+) -> io::Result<()> {
+    let manifest = Manifest {
+        format: FORMAT,
+        firmware: sysinfo::firmware_version().into(),
+        active: set.active_id(),
+        profiles: set
+            .index
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                Some(ManifestProfile {
+                    id: entry.id,
+                    name: entry.name.clone(),
+                    profile: profile_schema::to_json(set.get(entry.id)?),
+                })
+            })
+            .collect(),
+        settings: settings.clone(),
+    };
+    let manifest = serde_json::to_vec(&manifest).map_err(io::Error::other)?;
+
+    let mut out = CrcWriter {
+        inner: out,
+        crc: Crc32::new(),
+    };
+    let mut header = [0u8; HEADER_LEN];
+    header[..4].copy_from_slice(MAGIC);
+    header[4] = FORMAT;
+    out.write_all(&header)?;
+    write_entry(&mut out, MANIFEST, &manifest)?;
+    for entry in &set.index.entries {
+        for name in [
+            storage::avatar_name(entry.id),
+            storage::background_name(entry.id),
+        ] {
+            match images.read(&name) {
+                Ok(data) => write_entry(&mut out, &format!("{name}.img"), &data)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    out.write_all(&[0])?;
+    let crc = out.crc.finish();
+    out.inner.write_all(&crc.to_le_bytes())
+}
+
+fn write_entry(out: &mut impl Write, name: &str, data: &[u8]) -> io::Result<()> {
+    out.write_all(&[name.len() as u8])?;
+    out.write_all(name.as_bytes())?;
+    out.write_all(&(data.len() as u32).to_le_bytes())?;
+    out.write_all(data)
+}
+
+/// Passes writes through, keeping a CRC of everything written.
+struct CrcWriter<'a, W: Write> {
+    inner: &'a mut W,
+    crc: Crc32,
+}
+
+impl<W: Write> Write for CrcWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Reading
+// ---------------------------------------------------------------------------
+
+/// A restored archive: its profiles under new ids, ready to apply, and its
+/// images already written under those ids.
+pub struct Restore {
+    /// The restored profiles under their new ids, first one `first_id`.
+    pub set: ProfileSet,
+    /// Image files written, already renamed to the new ids.
+    pub images: Vec<String>,
+    pub settings: BackupSettings,
+}
+
+/// Why a restore stopped. Either way the images written so far have been
+/// removed again.
+#[derive(Debug)]
+pub enum RestoreError {
+    /// Not a valid backup.
+    Invalid(BackupError),
+    /// Reading the upload or writing an image failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(e) => e.fmt(f),
+            Self::Io(e) => write!(f, "Restore failed: {e}"),
+        }
+    }
+}
+
+impl From<BackupError> for RestoreError {
+    fn from(e: BackupError) -> Self {
+        Self::Invalid(e)
+    }
+}
+
+/// Read an archive from `input` one entry at a time, checking each as it
+/// arrives, and write its images to `images` under profile ids from
+/// `first_id` (ids the caller has reserved, so no current file is touched).
+/// Memory use stays at one image, whatever the archive's size.
+///
+/// Only the checksum at the end vouches for the whole archive, so anything
+/// written is removed again if the archive turns out bad or the upload
+/// stops short.
+pub fn restore_backup(
+    input: &mut impl Read,
+    first_id: u32,
+    images: &dyn ImageStore,
+) -> Result<Restore, RestoreError> {
+    let mut written = Vec::new();
+    let result = read_backup(input, first_id, images, &mut written);
+    if result.is_err() {
+        for name in &written {
+            let _ = images.remove(name);
+        }
+    }
+    result
+}
+
+fn read_backup(
+    input: &mut impl Read,
+    first_id: u32,
+    images: &dyn ImageStore,
+    written: &mut Vec<String>,
+) -> Result<Restore, RestoreError> {
+    let mut input = CrcReader {
+        inner: input,
+        crc: Crc32::new(),
+    };
+    let mut header = [0u8; HEADER_LEN];
+    read_exact(&mut input, &mut header)?;
+    if !header.starts_with(MAGIC) {
+        return Err(invalid("Not a VRCBadge backup").into());
+    }
+    if header[4] != FORMAT {
+        return Err(invalid(format!(
+            "Unsupported backup format {} (this firmware reads {FORMAT})",
+            header[4]
+        ))
+        .into());
+    }
+
+    // The writer puts the manifest first, so every image can be checked
+    // against it and written as soon as it arrives.
+    let manifest = match read_entry(&mut input, MAX_MANIFEST_BYTES)? {
+        Some((name, data)) if name == MANIFEST => data,
+        _ => return Err(invalid("Backup has no manifest").into()),
+    };
+    let (set, new_ids, settings) = read_manifest(&manifest, first_id)?;
+
+    let mut seen = BTreeSet::new();
+    while let Some((name, data)) = read_entry(&mut input, MAX_IMAGE_ENTRY)? {
+        if !seen.insert(name.clone()) {
+            return Err(invalid(format!("Backup entry {name} appears twice")).into());
+        }
+        let (new_name, animated, width, height) = image_target(&name, &new_ids)?;
+        check_image(&name, &data, animated, width, height)?;
+        images.write(&new_name, &data).map_err(RestoreError::Io)?;
+        written.push(new_name);
+    }
+
+    let crc = input.crc.finish();
+    let mut stored = [0u8; 4];
+    read_exact(input.inner, &mut stored)?;
+    if crc != u32::from_le_bytes(stored) {
+        return Err(invalid("Backup checksum mismatch (truncated or corrupted)").into());
+    }
+    if input.inner.read(&mut [0u8; 1]).map_err(RestoreError::Io)? != 0 {
+        return Err(invalid("Unexpected data after the end of the backup").into());
+    }
+    Ok(Restore {
+        set,
+        images: std::mem::take(written),
+        settings,
+    })
+}
+
+/// The manifest's profiles renumbered from `first_id`, the map from
+/// archived to new ids, and the checked settings.
+fn read_manifest(
+    data: &[u8],
+    first_id: u32,
+) -> Result<(ProfileSet, BTreeMap<u32, u32>, BackupSettings), BackupError> {
+    let manifest: Manifest = serde_json::from_slice(data)
+        .map_err(|e| invalid(format!("Invalid backup manifest: {e}")))?;
+    if manifest.profiles.is_empty() || manifest.profiles.len() > MAX_PROFILES {
+        return Err(invalid(format!(
+            "Backup must hold 1-{MAX_PROFILES} profiles"
+        )));
+    }
+
+    // Rebuild the set under new ids; `create` checks names and fields.
+    let mut set = ProfileSet {
+        index: ProfileIndex {
+            active: first_id,
+            next_id: first_id,
+            entries: Vec::new(),
+        },
+        profiles: BTreeMap::new(),
+    };
+    let mut new_ids = BTreeMap::new();
+    for entry in manifest.profiles {
+        let context = |e: ProfileError| invalid(format!("Profile '{}': {e}", entry.name));
+        let profile = profile_schema::from_json(entry.profile).map_err(context)?;
+        let id = set.create(&entry.name, profile).map_err(context)?;
+        if new_ids.insert(entry.id, id).is_some() {
+            return Err(invalid(format!("Profile id {} appears twice", entry.id)));
+        }
+    }
+    let active = new_ids
+        .get(&manifest.active)
+        .ok_or_else(|| invalid("Backup's active profile is missing"))?;
+    set.index.active = *active;
+
+    let settings = manifest.settings;
+    if let Some(spec) = &settings.log_levels {
+        spec.parse::<LogFilter>()
+            .map_err(|e| invalid(format!("Invalid log levels: {e}")))?;
+    }
+    if let Some(wifi) = &settings.wifi {
+        let password_ok = wifi.password.is_empty() || (8..=64).contains(&wifi.password.len());
+        if wifi.ssid.is_empty() || wifi.ssid.len() > 32 || !password_ok {
+            return Err(invalid("Invalid WiFi credentials in backup"));
+        }
+    }
+    if let Some(device) = &settings.device {
+        // Checked against the defaults here; the badge's own settings are
+        // only at hand when it is applied, and every field is checked on
+        // its own.
+        if let Err(errors) = DeviceSettings::default().patched(device) {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            return Err(invalid(format!(
+                "Invalid device settings in backup: {}",
+                errors.join(", ")
+            )));
+        }
+    }
+    Ok((set, new_ids, settings))
+}
+
+/// The next entry's name and data, or `None` at the end marker. Entries
+/// over `max` bytes are refused before anything is allocated.
+fn read_entry(
+    input: &mut impl Read,
+    max: usize,
+) -> Result<Option<(String, Vec<u8>)>, RestoreError> {
+    let mut name_len = [0u8; 1];
+    read_exact(input, &mut name_len)?;
+    let name_len = usize::from(name_len[0]);
+    if name_len == 0 {
+        return Ok(None);
+    }
+    if name_len > MAX_NAME_LEN {
+        return Err(invalid("Backup entry name is too long").into());
+    }
+    let mut name = vec![0u8; name_len];
+    read_exact(input, &mut name)?;
+    let name = String::from_utf8(name).map_err(|_| invalid("Backup entry name is not text"))?;
+    let mut len = [0u8; 4];
+    read_exact(input, &mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > max {
+        return Err(invalid(format!("Backup entry {name} is too large ({len} bytes)")).into());
+    }
+    let mut data = vec![0u8; len];
+    read_exact(input, &mut data)?;
+    Ok(Some((name, data)))
+}
+
+/// `read_exact`, with an archive that ends early reported as truncated.
+fn read_exact(input: &mut impl Read, buf: &mut [u8]) -> Result<(), RestoreError> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid("Backup is truncated").into(),
+        _ => RestoreError::Io(e),
+    })
+}
+
+/// Passes reads through, keeping a CRC of everything read.
+struct CrcReader<'a, R: Read> {
+    inner: &'a mut R,
+    crc: Crc32,
+}
+
+impl<R: Read> Read for CrcReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// New file name for an archived image, whether it may be animated, and
+/// its expected size.
+fn image_target(
+    name: &str,
+    new_ids: &BTreeMap<u32, u32>,
+) -> Result<(String, bool, u32, u32), BackupError> {
+    let unknown = || invalid(format!("Unexpected backup entry {name}"));
+    let stem = name.strip_suffix(".img").ok_or_else(unknown)?;
+    let (kind, id) = stem.rsplit_once('_').ok_or_else(unknown)?;
+    let id: u32 = id.parse().map_err(|_| unknown())?;
+    let new_id = *new_ids
+        .get(&id)
+        .ok_or_else(|| invalid(format!("{name} belongs to no profile in the backup")))?;
+    match kind {
+        "avatar" => Ok((
+            storage::avatar_name(new_id),
+            true,
+            storage::AVATAR_WIDTH,
+            storage::AVATAR_HEIGHT,
+        )),
+        "background" => Ok((
+            storage::background_name(new_id),
+            false,
+            storage::BACKGROUND_WIDTH,
+            storage::BACKGROUND_HEIGHT,
+        )),
+        _ => Err(unknown()),
+    }
+}
+
+/// Decode an archived image to be sure it will load after the restore.
+fn check_image(
+    name: &str,
+    data: &[u8],
+    animated: bool,
+    width: u32,
+    height: u32,
+) -> Result<(), BackupError> {
+    let corrupt = |e: image_codec::CorruptImage| invalid(format!("{name}: {e}"));
+    let size = if animated && image_codec::is_animation(data) {
+        let animation = Animation::parse(data.to_vec()).map_err(corrupt)?;
+        for i in 0..animation.frame_count() {
+            animation.decode_frame(i).map_err(corrupt)?;
+        }
+        (animation.width, animation.height)
+    } else {
+        let (header, _) = image_codec::decode_rgb888(data).map_err(corrupt)?;
+        (header.width, header.height)
+    };
+    if (u32::from(size.0), u32::from(size.1)) != (width, height) {
+        return Err(invalid(format!(
+            "{name} is {}x{}, expected {width}x{height}",
+            size.0, size.1
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::fake::MemoryImageStore;
+    use crate::profile::Profile;
+
+    /// A store holding profile 1's avatar.
+    fn badge_images() -> MemoryImageStore {
+        let images = MemoryImageStore::default();
+        let rgb = vec![0x40; storage::AVATAR_IMAGE_SIZE];
+        let avatar = image_codec::encode_rgb888(
+            &rgb,
+            storage::AVATAR_WIDTH as u16,
+            storage::AVATAR_HEIGHT as u16,
+        );
+        images.write(&storage::avatar_name(1), &avatar).unwrap();
+        images
+    }
+
+    fn backup(settings: &BackupSettings) -> Vec<u8> {
+        let set = ProfileSet::single("Main", Profile::default());
+        let mut out = Vec::new();
+        write_backup(&mut out, &set, &badge_images(), settings).unwrap();
+        out
+    }
+
+    fn restore(data: &[u8], images: &MemoryImageStore) -> Result<Restore, RestoreError> {
+        restore_backup(&mut &data[..], 10, images)
+    }
+
+    fn names(images: &MemoryImageStore) -> Vec<String> {
+        let mut names: Vec<String> = images.files.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn device() -> DeviceSettings {
+        let mut device = DeviceSettings {
+            brightness: 80,
+            dim_after_secs: 30,
+            ..Default::default()
+        };
+        device.ap.password = "hunter22".into();
+        device
+    }
+
+    #[test]
+    fn restore_writes_images_under_new_ids() {
+        let data = backup(&BackupSettings::default());
+        let images = MemoryImageStore::default();
+        let restore = restore(&data, &images).unwrap();
+        assert_eq!(restore.set.active_id(), 10);
+        assert_eq!(restore.images, [storage::avatar_name(10)]);
+        assert_eq!(
+            images.read(&storage::avatar_name(10)).unwrap(),
+            badge_images().read(&storage::avatar_name(1)).unwrap()
+        );
+    }
+
+    #[test]
+    fn bad_archives_leave_no_images_behind() {
+        let data = backup(&BackupSettings::default());
+
+        let mut corrupt = data.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xFF;
+        let truncated = &data[..data.len() - 10];
+        let mut trailing = data.clone();
+        trailing.push(0);
+
+        for bad in [&corrupt[..], truncated, &trailing[..]] {
+            let images = MemoryImageStore::default();
+            assert!(matches!(
+                restore(bad, &images),
+                Err(RestoreError::Invalid(_))
+            ));
+            assert!(names(&images).is_empty());
+        }
+    }
+
+    #[test]
+    fn oversized_entries_are_refused_unread() {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&[FORMAT, 0, 0, 0]);
+        data.push(MANIFEST.len() as u8);
+        data.extend_from_slice(MANIFEST.as_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        let err = restore(&data, &MemoryImageStore::default()).err().unwrap();
+        assert!(err.to_string().contains("too large"), "{err}");
+    }
+
+    #[test]
+    fn device_settings_round_trip() {
+        let settings = BackupSettings {
+            device: device_settings(&device(), true),
+            ..Default::default()
+        };
+        let restore = restore(&backup(&settings), &MemoryImageStore::default()).unwrap();
+        let patch = restore.settings.device.unwrap();
+        assert_eq!(DeviceSettings::default().patched(&patch).unwrap(), device());
+    }
+
+    #[test]
+    fn ap_password_only_with_secrets() {
+        let patch = device_settings(&device(), false).unwrap();
+        assert!(patch["ap"].get("password").is_none());
+        // Restoring keeps the badge's own password.
+        let mut current = DeviceSettings::default();
+        current.ap.password = "badgepass".into();
+        let restored = current.patched(&patch).unwrap();
+        assert_eq!(restored.ap.password, "badgepass");
+        assert_eq!(restored.brightness, 80);
+    }
+
+    #[test]
+    fn invalid_device_settings_are_rejected() {
+        for device in [
+            serde_json::json!({ "brightness": 5 }),
+            serde_json::json!({ "no_such_setting": 1 }),
+        ] {
+            let settings = BackupSettings {
+                device: Some(device),
+                ..Default::default()
+            };
+            let images = MemoryImageStore::default();
+            let err = restore(&backup(&settings), &images).err().unwrap();
+            assert!(err.to_string().contains("device settings"), "{err}");
+            assert!(names(&images).is_empty());
+        }
+    }
+
+    #[test]
+    fn backups_without_device_settings_still_restore() {
+        let settings = BackupSettings {
+            log_levels: Some("info".into()),
+            ..Default::default()
+        };
+        let restore = restore(&backup(&settings), &MemoryImageStore::default()).unwrap();
+        assert_eq!(restore.settings.device, None);
+        assert_eq!(restore.settings.log_levels.as_deref(), Some("info"));
+    }
+}
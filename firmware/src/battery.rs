@@ -0,0 +1,156 @@
+//! MAX17048 fuel gauge.
+//!
+//! Sits at I2C address 0x36 on the same bus as the GT911 (SDA 19 / SCL 20)
+//! and is read from the main loop every ~2 s. The chip's ModelGauge
+//! algorithm needs no configuration: after power-up it reports state of
+//! charge, cell voltage and charge rate in three 16-bit big-endian registers.
+//!
+//! Register decoding is split into pure functions so it can be checked on the
+//! host against [`hal::fake::FakeI2c`](crate::hal) register maps.
+
+use serde::Serialize;
+
+use crate::hal::I2cBus;
+
+/// MAX17048 7-bit I2C address.
+pub const MAX17048_ADDR: u8 = 0x36;
+
+/// Cell voltage, 78.125 µV/LSB.
+pub const REG_VCELL: u8 = 0x02;
+/// State of charge, 1/256 %/LSB.
+pub const REG_SOC: u8 = 0x04;
+/// Production version (0x001x).
+pub const REG_VERSION: u8 = 0x08;
+/// Charge/discharge rate, signed, 0.208 %/hr/LSB.
+pub const REG_CRATE: u8 = 0x16;
+
+/// One reading of the fuel gauge.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BatteryReading {
+    /// State of charge in percent. ModelGauge may briefly report >100.
+    pub percent: f32,
+    /// Cell voltage in volts.
+    pub voltage: f32,
+    /// Percent per hour; positive while charging, negative while discharging.
+    pub charge_rate: f32,
+}
+
+impl BatteryReading {
+    /// Whole percent for the status bar, clamped to 0-100.
+    pub fn display_percent(&self) -> i32 {
+        self.percent.round().clamp(0.0, 100.0) as i32
+    }
+}
+
+/// Decode `REG_SOC`: high byte is whole percent, low byte is 1/256 %.
+pub fn soc_percent(raw: u16) -> f32 {
+    raw as f32 / 256.0
+}
+
+/// Decode `REG_VCELL` to volts.
+pub fn cell_voltage(raw: u16) -> f32 {
+    raw as f32 * 78.125e-6
+}
+
+/// Decode `REG_CRATE` (two's complement) to percent per hour.
+pub fn charge_rate(raw: u16) -> f32 {
+    raw as i16 as f32 * 0.208
+}
+
+/// MAX17048 on a (possibly shared) I2C bus.
+pub struct Max17048<B: I2cBus> {
+    bus: B,
+}
+
+impl<B: I2cBus> Max17048<B> {
+    /// Probe the gauge by reading its version register.
+    ///
+    /// Fails if nothing answers at 0x36 (e.g. running from USB power with
+    /// the battery board unplugged); the badge then keeps its placeholder.
+    pub fn new(bus: B) -> anyhow::Result<Self> {
+        let mut gauge = Self { bus };
+        let version = gauge
+            .read_register(REG_VERSION)
+            .map_err(|e| anyhow::anyhow!("MAX17048 not found at 0x{MAX17048_ADDR:02X}: {e}"))?;
+        log::info!("MAX17048 fuel gauge found (version 0x{version:04X})");
+        Ok(gauge)
+    }
+
+    /// Read state of charge, voltage and charge rate.
+    pub fn read(&mut self) -> anyhow::Result<BatteryReading> {
+        Ok(BatteryReading {
+            percent: soc_percent(self.read_register(REG_SOC)?),
+            voltage: cell_voltage(self.read_register(REG_VCELL)?),
+            charge_rate: charge_rate(self.read_register(REG_CRATE)?),
+        })
+    }
+
+    /// Read one 16-bit big-endian register.
+    fn read_register(&mut self, reg: u8) -> anyhow::Result<u16> {
+        let mut buf = [0u8; 2];
+        self.bus.write_read(MAX17048_ADDR, &[reg], &mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::fake::FakeI2c;
+
+    fn gauge(soc: u16, vcell: u16, rate: u16) -> FakeI2c {
+        let mut bus = FakeI2c::default();
+        bus.set_u16(MAX17048_ADDR, REG_VERSION, 0x0012);
+        bus.set_u16(MAX17048_ADDR, REG_SOC, soc);
+        bus.set_u16(MAX17048_ADDR, REG_VCELL, vcell);
+        bus.set_u16(MAX17048_ADDR, REG_CRATE, rate);
+        bus
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn registers_decode_to_units() {
+        assert_close(soc_percent(0x3280), 50.5);
+        assert_close(soc_percent(0x6400), 100.0);
+        assert_close(cell_voltage(0xCC00), 4.08);
+        assert_close(cell_voltage(0), 0.0);
+        assert_close(charge_rate(0x000A), 2.08);
+        // Two's complement: discharging.
+        assert_close(charge_rate(0xFFF6), -2.08);
+        assert_close(charge_rate(0x8000), -32768.0 * 0.208);
+    }
+
+    #[test]
+    fn read_takes_each_register_big_endian() {
+        let mut battery = Max17048::new(gauge(0x4C80, 0xBB80, 0xFFF6)).unwrap();
+        let reading = battery.read().unwrap();
+        assert_close(reading.percent, 76.5);
+        assert_close(reading.voltage, 3.75);
+        assert_close(reading.charge_rate, -2.08);
+    }
+
+    #[test]
+    fn missing_gauge_fails_the_probe() {
+        assert!(Max17048::new(FakeI2c::default()).is_err());
+
+        let mut bus = FakeI2c::default();
+        bus.set_u16(MAX17048_ADDR, REG_VERSION, 0x0012);
+        let mut battery = Max17048::new(bus).unwrap();
+        assert!(battery.read().is_err());
+    }
+
+    #[test]
+    fn display_percent_rounds_and_clamps() {
+        let reading = |percent| BatteryReading {
+            percent,
+            voltage: 3.9,
+            charge_rate: 0.0,
+        };
+        assert_eq!(reading(76.5).display_percent(), 77);
+        assert_eq!(reading(101.3).display_percent(), 100);
+        assert_eq!(reading(-0.4).display_percent(), 0);
+    }
+}
@@ -1,42 +1,217 @@
 //! UI callback wiring.
 //!
 //! All Slint callbacks live here. Each closure captures the shared state it
-//! needs (cheap `Arc`/`Rc` clones); ownership of `backlight` is consumed by
-//! the brightness slider closure.
+//! needs (cheap `Arc`/`Rc` clones). Settings changed here are saved to NVS
+//! at once, except brightness, which is saved when the slider is released.
 
 use std::cell::RefCell;
-use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
-use esp_idf_hal::ledc::LedcDriver;
-use esp_idf_svc::nvs::{EspNvs, NvsDefault};
-use esp_idf_svc::wifi::{BlockingWifi, EspWifi};
 use slint::ComponentHandle;
 
+use crate::auth::{self, SharedAuth};
+use crate::factory_reset::{self, PendingFactoryReset, ResetStep};
+use crate::hal::{ImageStore, SharedBacklight, SharedImageStore, SharedKvStore, SharedWifi};
+use crate::ota::OtaUpdater;
+use crate::profile::SharedProfiles;
+use crate::settings::{self, ApSettings, DeviceSettings, SharedSettings};
+use crate::ui_helpers::{
+    show_active_profile, show_ap_form, show_log_levels, show_qr, show_settings,
+};
 use crate::wifi_state::WiFiState;
-use crate::{storage, wifi, BadgeUI, VirtualKeyboardHandler};
+use crate::{log_filter, logger, storage, BadgeUI, VirtualKeyboardHandler};
 
-/// Wire all UI callbacks: brightness slider, virtual keyboard, WiFi controls.
-pub fn wire(
-    ui: &BadgeUI,
-    wifi_handle: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
-    wifi_state: WiFiState,
-    nvs: Rc<RefCell<EspNvs<NvsDefault>>>,
-    backlight: LedcDriver<'static>,
-    max_duty: u32,
-) {
-    // Brightness slider → LEDC PWM (debounced to avoid flicker).
-    // Converts 10-100% from the UI slider to 0-255 LEDC duty cycle.
+/// Shared state the UI callbacks capture.
+pub struct UiDeps {
+    pub wifi_handle: SharedWifi,
+    pub wifi_state: WiFiState,
+    pub nvs: SharedKvStore,
+    pub images: SharedImageStore,
+    pub profiles: SharedProfiles,
+    pub settings: SharedSettings,
+    pub reset: PendingFactoryReset,
+    pub restart: Arc<AtomicBool>,
+    pub auth: SharedAuth,
+    pub backlight: SharedBacklight,
+    pub ota: OtaUpdater,
+}
+
+/// Wire all UI callbacks: brightness slider, device settings, virtual
+/// keyboard, profile switching, QR page, WiFi controls, access point form,
+/// web PIN and approval prompt, log level chips, firmware update buttons, factory reset.
+pub fn wire(ui: &BadgeUI, deps: UiDeps) {
+    let UiDeps {
+        wifi_handle,
+        wifi_state,
+        nvs,
+        images,
+        profiles,
+        settings,
+        reset,
+        restart,
+        auth,
+        backlight,
+        ota,
+    } = deps;
+
+    // Brightness slider → backlight PWM (debounced to avoid flicker).
     {
-        let backlight = RefCell::new(backlight);
-        let last_brightness = RefCell::new(50.0_f32);
+        let start = settings.lock().map_or(50, |s| s.brightness);
+        let last_brightness = RefCell::new(start as f32);
         ui.on_brightness_changed(move |percent| {
-            let clamped = percent.clamp(10.0, 100.0);
+            let clamped = percent.clamp(settings::MIN_BRIGHTNESS as f32, 100.0);
             let mut last = last_brightness.borrow_mut();
             if (clamped - *last).abs() >= 2.0 {
                 *last = clamped;
-                let duty = (clamped / 100.0 * max_duty as f32) as u32;
-                let _ = backlight.borrow_mut().set_duty(duty);
+                let _ = backlight.borrow_mut().set_percent(clamped);
+            }
+        });
+    }
+
+    // Slider released: save the brightness it ended on
+    {
+        let nvs = nvs.clone();
+        let settings = settings.clone();
+        ui.on_brightness_released(move |percent| {
+            let percent = percent
+                .round()
+                .clamp(settings::MIN_BRIGHTNESS as f32, 100.0) as u8;
+            settings::update(&settings, &mut *nvs.borrow_mut(), |s| {
+                s.brightness = percent
+            });
+        });
+    }
+
+    // Dim / screen timeout rows: step through the presets. The event loop
+    // picks up the new timeouts on its next periodic tick.
+    {
+        let weak = ui.as_weak();
+        let nvs = nvs.clone();
+        let settings = settings.clone();
+        ui.on_cycle_dim_after(move || {
+            let Some(ui) = weak.upgrade() else { return };
+            settings::update(&settings, &mut *nvs.borrow_mut(), |s| {
+                s.dim_after_secs = settings::next_timeout(s.dim_after_secs);
+                show_settings(&ui, s);
+            });
+        });
+    }
+    {
+        let weak = ui.as_weak();
+        let nvs = nvs.clone();
+        let settings = settings.clone();
+        ui.on_cycle_screen_timeout(move || {
+            let Some(ui) = weak.upgrade() else { return };
+            settings::update(&settings, &mut *nvs.borrow_mut(), |s| {
+                s.screen_timeout_secs = settings::next_timeout(s.screen_timeout_secs);
+                show_settings(&ui, s);
+            });
+        });
+    }
+
+    // UI preference switches
+    {
+        let nvs = nvs.clone();
+        let settings = settings.clone();
+        ui.on_show_battery_percent_toggled(move |on| {
+            settings::update(&settings, &mut *nvs.borrow_mut(), |s| {
+                s.ui.show_battery_percent = on
+            });
+        });
+    }
+    {
+        let nvs = nvs.clone();
+        let settings = settings.clone();
+        ui.on_swipe_profiles_toggled(move |on| {
+            settings::update(&settings, &mut *nvs.borrow_mut(), |s| {
+                s.ui.swipe_profiles = on
+            });
+        });
+    }
+
+    // Access Point page: load the saved settings into the form; save
+    // checks them and restarts into the new AP on the next tick.
+    {
+        let weak = ui.as_weak();
+        let settings = settings.clone();
+        ui.on_edit_ap_settings(move || {
+            let Some(ui) = weak.upgrade() else { return };
+            if let Ok(s) = settings.lock() {
+                show_ap_form(&ui, &s.ap);
+            }
+        });
+    }
+    {
+        let weak = ui.as_weak();
+        let nvs = nvs.clone();
+        let settings = settings.clone();
+        ui.on_save_ap_settings(move || {
+            let Some(ui) = weak.upgrade() else { return };
+            let ap = ApSettings {
+                ssid: ui.get_ap_ssid().trim().into(),
+                password: ui.get_ap_password().into(),
+                channel: ui.get_ap_channel().clamp(0, u8::MAX as i32) as u8,
+                max_clients: ui.get_ap_max_clients().clamp(0, u8::MAX as i32) as u8,
+                hidden: ui.get_ap_hidden(),
+            };
+            let checked = settings.lock().map(|s| {
+                let updated = DeviceSettings { ap, ..s.clone() };
+                updated.validate().map(|()| updated.ap)
+            });
+            match checked {
+                Ok(Ok(ap)) => {
+                    settings::update(&settings, &mut *nvs.borrow_mut(), |s| s.ap = ap);
+                    ui.set_toast_message("Access point saved, restarting".into());
+                    ui.set_toast_visible(true);
+                    restart.store(true, Ordering::Relaxed);
+                }
+                Ok(Err(errors)) => {
+                    let message = errors
+                        .iter()
+                        .filter_map(|e| e.field.strip_prefix("ap.").map(|f| (f, &e.message)))
+                        .map(|(field, message)| format!("{}: {message}", ap_field_label(field)))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    ui.set_ap_error(message.into());
+                }
+                Err(_) => {}
+            }
+        });
+    }
+
+    // Approval prompt: allow or deny the web client showing the same code
+    {
+        let weak = ui.as_weak();
+        let auth = auth.clone();
+        ui.on_answer_approval(move |allow| {
+            let Some(ui) = weak.upgrade() else { return };
+            ui.set_approval_visible(false);
+            let code = ui.get_approval_code();
+            let answered = auth
+                .lock()
+                .is_ok_and(|mut auth| auth.answer_prompt(&code, allow, Instant::now()));
+            let message = match (answered, allow) {
+                (false, _) => "Request expired",
+                (true, true) => "Web client allowed until restart",
+                (true, false) => "Web client denied",
+            };
+            ui.set_toast_message(message.into());
+            ui.set_toast_visible(true);
+        });
+    }
+
+    // Settings → Web PIN: make up a new one, which logs out every browser
+    {
+        let weak = ui.as_weak();
+        let nvs = nvs.clone();
+        ui.on_new_admin_pin(move || {
+            let Some(ui) = weak.upgrade() else { return };
+            if let Some(pin) = auth::replace_pin(&auth, &mut *nvs.borrow_mut()) {
+                ui.set_admin_pin(pin.into());
+                ui.set_toast_message("New web PIN, browsers logged out".into());
+                ui.set_toast_visible(true);
             }
         });
     }
@@ -58,6 +233,44 @@ pub fn wire(
             });
     }
 
+    // Profiles: swipe on the badge page steps through the list
+    {
+        let weak = ui.as_weak();
+        let nvs = nvs.clone();
+        let images = images.clone();
+        let profiles = profiles.clone();
+        ui.on_switch_profile(move |steps| {
+            let Some(ui) = weak.upgrade() else { return };
+            let id = match profiles.lock() {
+                Ok(set) => set.neighbour(steps),
+                Err(_) => return,
+            };
+            switch_profile(&ui, &nvs, &*images, &profiles, id);
+        });
+    }
+
+    // QR page: render the chosen code of the active profile
+    {
+        let weak = ui.as_weak();
+        let profiles = profiles.clone();
+        ui.on_show_qr(move |index| {
+            let Some(ui) = weak.upgrade() else { return };
+            if let Ok(set) = profiles.lock() {
+                show_qr(&ui, set.active(), index.max(0) as usize);
+            }
+        });
+    }
+
+    // Profiles: tap an entry on the Profiles page
+    {
+        let weak = ui.as_weak();
+        let nvs = nvs.clone();
+        ui.on_activate_profile(move |id| {
+            let Some(ui) = weak.upgrade() else { return };
+            switch_profile(&ui, &nvs, &*images, &profiles, id as u32);
+        });
+    }
+
     // WiFi: scan for nearby networks (background thread)
     {
         let weak = ui.as_weak();
@@ -88,7 +301,7 @@ pub fn wire(
         ui.on_wifi_disconnect(move || {
             let Some(ui) = weak.upgrade() else { return };
             if let Ok(mut wifi) = wifi.lock() {
-                let _ = wifi::disconnect_sta(&mut wifi);
+                let _ = wifi.disconnect_sta();
             }
             ui.set_sta_connected(false);
             ui.set_sta_ssid("".into());
@@ -97,6 +310,30 @@ pub fn wire(
         });
     }
 
+    // Log levels: tapping a chip cycles that target's level (applied at once)
+    {
+        let weak = ui.as_weak();
+        let nvs = nvs.clone();
+        ui.on_cycle_log_level(move |target| {
+            let Some(ui) = weak.upgrade() else { return };
+            let mut filter = logger::filter();
+            let level = if target.is_empty() {
+                filter.default_level()
+            } else {
+                filter.level_for(&target)
+            };
+            if let Err(e) = filter.set(&target, log_filter::next_level(level)) {
+                log::warn!("Log level change rejected: {e}");
+                return;
+            }
+            logger::set_filter(filter.clone());
+            show_log_levels(&ui, &filter);
+            settings::update(&settings, &mut *nvs.borrow_mut(), |s| {
+                s.log_levels = filter.to_string()
+            });
+        });
+    }
+
     // WiFi: forget saved credentials and disconnect
     {
         let weak = ui.as_weak();
@@ -104,9 +341,9 @@ pub fn wire(
         ui.on_wifi_forget(move || {
             let Some(ui) = weak.upgrade() else { return };
             if let Ok(mut wifi) = wifi.lock() {
-                let _ = wifi::disconnect_sta(&mut wifi);
+                let _ = wifi.disconnect_sta();
             }
-            storage::delete_wifi_credentials(&mut nvs.borrow_mut());
+            storage::delete_wifi_credentials(&mut *nvs.borrow_mut());
             ui.set_sta_connected(false);
             ui.set_sta_ssid("".into());
             ui.set_sta_ip("".into());
@@ -114,4 +351,59 @@ pub fn wire(
             ui.set_wifi_connect_status("Credentials forgotten".into());
         });
     }
+
+    // Factory reset: confirmed in the Settings dialog; the main loop erases
+    {
+        let weak = ui.as_weak();
+        ui.on_factory_reset(move || {
+            let Some(ui) = weak.upgrade() else { return };
+            if factory_reset::request(&reset) {
+                ui.set_factory_reset_status(ResetStep::Requested.status().into());
+            }
+        });
+    }
+
+    // Firmware update: check GitHub / download + flash (background threads)
+    {
+        let ota = ota.clone();
+        ui.on_check_update(move || ota.spawn_check());
+    }
+    ui.on_install_update(move || ota.spawn_install());
+}
+
+/// Name of an `ap.*` settings field as labelled on the Access Point page.
+fn ap_field_label(field: &str) -> &str {
+    match field {
+        "ssid" => "Network name",
+        "password" => "Password",
+        "channel" => "Channel",
+        "max_clients" => "Max clients",
+        other => other,
+    }
+}
+
+/// Make profile `id` active, show it and persist the choice.
+fn switch_profile(
+    ui: &BadgeUI,
+    nvs: &SharedKvStore,
+    images: &dyn ImageStore,
+    profiles: &SharedProfiles,
+    id: u32,
+) {
+    let Ok(mut set) = profiles.lock() else { return };
+    if set.active_id() == id {
+        return;
+    }
+    if let Err(e) = set.activate(id) {
+        log::warn!("Profile switch rejected: {e}");
+        return;
+    }
+    show_active_profile(ui, images, &set);
+    if let Err(e) = storage::save_profile_index(&mut *nvs.borrow_mut(), &set.index) {
+        log::error!("{e}");
+    }
+    let name = set.name(id).unwrap_or_default();
+    log::info!("Switched to profile {id} ({name})");
+    ui.set_toast_message(format!("Profile: {name}").into());
+    ui.set_toast_visible(true);
 }
@@ -0,0 +1,220 @@
+//! Crash history that survives reboots.
+//!
+//! Two sources feed it:
+//!
+//! - A panic hook writes `panic.json` (message, location, uptime, the tail
+//!   of the log ring) just before the panic aborts and the chip resets.
+//! - At boot, [`init`] reads `esp_reset_reason()`. If the last reset was
+//!   abnormal (panic, watchdog, brownout) or a `panic.json` is waiting, it
+//!   appends a [`CrashReport`] to `crashes.json` and removes `panic.json`.
+//!
+//! Resets that bypass the hook (watchdogs, brownouts, C-side aborts) carry
+//! only the reset cause: the log ring lives in RAM and is gone by then.
+//! History is capped at [`MAX_REPORTS`], oldest dropped first.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{self, StdFs};
+use crate::{logger, sysinfo};
+
+/// Reports kept in `crashes.json`.
+pub const MAX_REPORTS: usize = 5;
+
+/// Log lines captured by the panic hook.
+const PANIC_LOG_LINES: usize = 40;
+
+const HISTORY_FILE: &str = "crashes.json";
+const PANIC_FILE: &str = "panic.json";
+
+/// Directory holding the crash files (SPIFFS mount, or `sim-storage`).
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Written by the panic hook, consumed on the next boot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PanicRecord {
+    message: String,
+    uptime_secs: u64,
+    log: String,
+}
+
+/// One entry in the crash history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// Increases by one per recorded crash, across reboots.
+    pub id: u32,
+    /// Firmware version running when the crash was recorded.
+    pub firmware_version: String,
+    /// `esp_reset_reason()` of the boot after the crash, e.g. `"panic"`.
+    pub reset_reason: String,
+    /// Panic message with source location; `None` for watchdog/brownout.
+    pub panic: Option<String>,
+    /// Seconds since boot when the panic happened.
+    pub uptime_secs: Option<u64>,
+    /// Log ring tail at panic time (empty when no panic hook ran).
+    pub log: String,
+}
+
+impl CrashReport {
+    /// One-line summary for the About page.
+    pub fn summary(&self) -> String {
+        match &self.panic {
+            Some(message) => format!("#{} {}: {message}", self.id, self.reset_reason),
+            None => format!("#{} {}", self.id, self.reset_reason),
+        }
+    }
+}
+
+/// Append `report` (numbered after the newest entry) and drop the oldest
+/// entries beyond [`MAX_REPORTS`]. Returns the stored copy.
+pub fn push_report(history: &mut Vec<CrashReport>, mut report: CrashReport) -> CrashReport {
+    report.id = history.last().map_or(1, |last| last.id + 1);
+    history.push(report.clone());
+    if history.len() > MAX_REPORTS {
+        let excess = history.len() - MAX_REPORTS;
+        history.drain(..excess);
+    }
+    report
+}
+
+/// Install the panic hook and fold the previous boot's crash (if any) into
+/// the history. Call once, as soon as `dir` is writable.
+///
+/// Returns the report recorded for the previous boot, if it crashed.
+pub fn init(dir: impl Into<PathBuf>) -> Option<CrashReport> {
+    let dir = DIR.get_or_init(|| dir.into());
+    install_panic_hook();
+    for file in [HISTORY_FILE, PANIC_FILE] {
+        storage::recover_file(&StdFs, &dir.join(file), |data| {
+            serde_json::from_slice::<serde_json::Value>(data).is_ok()
+        });
+    }
+
+    let (reset_reason, abnormal) = reset_reason();
+    let panic = take_panic_record(dir);
+    if !abnormal && panic.is_none() {
+        return None;
+    }
+
+    let report = CrashReport {
+        id: 0,
+        firmware_version: sysinfo::firmware_version().into(),
+        reset_reason: reset_reason.into(),
+        panic: panic.as_ref().map(|p| p.message.clone()),
+        uptime_secs: panic.as_ref().map(|p| p.uptime_secs),
+        log: panic.map(|p| p.log).unwrap_or_default(),
+    };
+    let mut history = load_history();
+    let report = push_report(&mut history, report);
+    if let Err(e) = write_json(&dir.join(HISTORY_FILE), &history) {
+        log::error!("Failed to save crash history: {e}");
+    }
+    log::warn!("Previous boot crashed: {}", report.summary());
+    Some(report)
+}
+
+/// All stored reports, oldest first.
+pub fn load_history() -> Vec<CrashReport> {
+    let Some(dir) = DIR.get() else {
+        return Vec::new();
+    };
+    match std::fs::read(dir.join(HISTORY_FILE)) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+            log::warn!("{HISTORY_FILE} is corrupt, starting a new history: {e}");
+            Vec::new()
+        }),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to read {HISTORY_FILE}: {e}");
+            }
+            Vec::new()
+        }
+    }
+}
+
+/// Delete the stored history.
+#[cfg(not(feature = "simulator"))]
+pub fn clear_history() -> std::io::Result<()> {
+    let Some(dir) = DIR.get() else {
+        return Ok(());
+    };
+    match std::fs::remove_file(dir.join(HISTORY_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => {
+            log::info!("Crash history cleared");
+            Ok(())
+        }
+    }
+}
+
+/// Chain a hook in front of the default one that saves a [`PanicRecord`].
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "(non-string panic payload)".into());
+        let message = match info.location() {
+            Some(loc) => format!("panicked at {}:{}: {payload}", loc.file(), loc.line()),
+            None => format!("panicked: {payload}"),
+        };
+        let record = PanicRecord {
+            message,
+            uptime_secs: logger::uptime_ms() / 1000,
+            // try_lock: the panic may have happened while the ring was held.
+            log: logger::try_recent_text(PANIC_LOG_LINES).unwrap_or_default(),
+        };
+        if let Some(dir) = DIR.get() {
+            let _ = write_json(&dir.join(PANIC_FILE), &record);
+        }
+        default_hook(info);
+    }));
+}
+
+/// Read and delete `panic.json`.
+fn take_panic_record(dir: &Path) -> Option<PanicRecord> {
+    let path = dir.join(PANIC_FILE);
+    let data = std::fs::read(&path).ok()?;
+    let _ = std::fs::remove_file(&path);
+    match serde_json::from_slice(&data) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            log::warn!("Ignoring unreadable {PANIC_FILE}: {e}");
+            None
+        }
+    }
+}
+
+/// Write `value` crash-safely (see [`storage::replace_file`]).
+fn write_json<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    storage::replace_file(&StdFs, path, &serde_json::to_vec(value)?)?;
+    Ok(())
+}
+
+/// Name of the last reset cause and whether it counts as a crash.
+#[cfg(not(feature = "simulator"))]
+fn reset_reason() -> (&'static str, bool) {
+    match unsafe { esp_idf_sys::esp_reset_reason() } {
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_POWERON => ("power-on", false),
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_EXT => ("external pin", false),
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_SW => ("software restart", false),
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => ("deep sleep wake", false),
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_PANIC => ("panic", true),
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_INT_WDT => ("interrupt watchdog", true),
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_TASK_WDT => ("task watchdog", true),
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_WDT => ("watchdog", true),
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_BROWNOUT => ("brownout", true),
+        _ => ("other", false),
+    }
+}
+
+/// Simulator: every start is a clean one; only `panic.json` marks a crash.
+#[cfg(feature = "simulator")]
+fn reset_reason() -> (&'static str, bool) {
+    ("power-on", false)
+}
@@ -5,44 +5,69 @@
 //! ```text
 //! ┌──────────────────────────────────────────────┐
 //! │ Slint timers/animations                      │
-//! │ Touch poll → Slint events                    │
+//! │ Touch poll → Slint events (or wake screen)   │
+//! │ Idle timer → dim / backlight off             │
 //! │ Deferred keyboard dismiss                    │
 //! │ WiFiState.poll  (drain async wifi results)   │
-//! │ Every 125 ticks (~2s):                       │
+//! │ Every ~2s:                                   │
+//! │   • Battery gauge → status bar               │
 //! │   • AP client count                          │
 //! │   • STA status (connect/disconnect)          │
+//! │   • OTA auto-check on STA connect + progress │
+//! │   • Publish /api/status snapshot             │
 //! │   • Toast auto-hide (5s)                     │
 //! │   • About page sysinfo + log snapshot        │
-//! │   • PendingState.poll_into_ui (web uploads)  │
-//! │ Render into DMA framebuffer                  │
-//! │ Sleep ~8ms                                   │
+//! │   • PendingState.poll_into_ui (web changes)  │
+//! │   • Approval prompt keeps the screen on      │
+//! │   • Idle timeouts from the device settings   │
+//! │   • Power policy → low battery mode, radios, │
+//! │     DFS / light sleep                        │
+//! │ Render into DMA framebuffer (not while off)  │
+//! │ Sleep 8-100ms, by idle state                 │
 //! └──────────────────────────────────────────────┘
 //! ```
+//!
+//! The loop polls touch and renders every ~8 ms while the screen is in
+//! use, and slows down once it is left alone (see `idle`).
 
-use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use esp_idf_svc::nvs::{EspNvs, NvsDefault};
-use esp_idf_svc::wifi::{BlockingWifi, EspWifi};
 use slint::platform::software_renderer::{MinimalSoftwareWindow, Rgb565Pixel};
+use slint::ComponentHandle;
 
+use crate::battery::Max17048;
+use crate::hal::{SharedBacklight, SharedImageStore, SharedKvStore, SharedWifi, StaStatus};
+use crate::i2c::SharedI2c;
+use crate::idle::{IdleState, IdleTimer};
+use crate::ota::OtaUpdater;
 use crate::pending::PendingState;
+use crate::power::{self, PowerActions, PowerInput, PowerPolicy, StaAction};
+use crate::settings::{DeviceSettings, PowerSettings};
+use crate::status::{SharedStatus, StaInfo, StatusSnapshot, StorageUsage};
 use crate::touch::TouchController;
 use crate::wifi_state::WiFiState;
-use crate::{logger, platform, sysinfo, wifi, BadgeUI};
+use crate::{logger, platform, storage, sysinfo, BadgeUI};
+
+/// How often the periodic block (battery, WiFi, web changes...) runs.
+const PERIODIC: Duration = Duration::from_secs(2);
 
 /// Everything the event loop needs to own.
 pub struct LoopDeps {
     pub window: Rc<MinimalSoftwareWindow>,
     pub framebuffer: &'static mut [Rgb565Pixel],
     pub ui: BadgeUI,
-    pub touch: Option<TouchController<'static>>,
-    pub nvs: Rc<RefCell<EspNvs<NvsDefault>>>,
-    pub wifi_handle: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    pub touch: Option<TouchController>,
+    pub battery: Option<Max17048<SharedI2c>>,
+    pub status: SharedStatus,
+    pub nvs: SharedKvStore,
+    pub images: SharedImageStore,
+    pub backlight: SharedBacklight,
+    pub wifi_handle: SharedWifi,
     pub wifi_state: WiFiState,
+    pub ota: OtaUpdater,
     pub pending: PendingState,
     pub dismiss_keyboard: Arc<AtomicBool>,
     pub sta_connected: bool,
@@ -51,20 +76,56 @@ pub struct LoopDeps {
 
 /// Run the badge event loop forever.
 pub fn run(mut deps: LoopDeps) -> ! {
-    let mut loop_count: u32 = 0;
+    let mut last_periodic = Instant::now();
     let mut toast_shown_at: Option<Instant> = if deps.ui.get_toast_visible() {
         Some(Instant::now())
     } else {
         None
     };
+    // Whether the current STA session has already been checked for updates.
+    let mut ota_checked = false;
+    // Set after a failed gauge read so a missing battery logs once, not every tick.
+    let mut battery_failing = false;
+    // Built up across the periodic block, then copied into `deps.status`.
+    // Fields keep their last value when a source is busy (e.g. WiFi scan).
+    let mut status = deps
+        .status
+        .lock()
+        .map(|s| s.clone())
+        .unwrap_or_else(|_| StatusSnapshot::new());
+    // Dim / off after the configured timeouts, and the battery power policy.
+    let mut config = deps
+        .pending
+        .settings
+        .lock()
+        .map(|s| PowerConfig::from_settings(&s))
+        .unwrap_or_else(|_| PowerConfig::from_settings(&DeviceSettings::default()));
+    let mut idle = IdleTimer::new(
+        config.dim_after_secs,
+        config.screen_timeout_secs,
+        Instant::now(),
+    );
+    let mut policy = PowerPolicy::new(Instant::now());
+    let mut radio_changes = RadioChanges::default();
 
     loop {
+        let now = Instant::now();
+
         // 1. Process Slint timers and animations
         slint::platform::update_timers_and_animations();
 
-        // 2. Poll touch input → dispatch events to Slint
+        // 2. Poll touch input → dispatch events to Slint. A touch on a
+        // dimmed or dark screen only wakes it.
         if let Some(ref mut touch) = deps.touch {
-            touch.poll(&deps.window);
+            let wake_only = idle.state() != IdleState::Active;
+            if touch.poll(&deps.window, wake_only) {
+                if let Some(state) = idle.touch(now) {
+                    apply_idle_state(&deps, state, policy.mode().brightness(config.brightness));
+                }
+            }
+        }
+        if let Some(state) = idle.tick(now) {
+            apply_idle_state(&deps, state, policy.mode().brightness(config.brightness));
         }
 
         // 2b. Dismiss virtual keyboard if requested (deferred from callback)
@@ -82,40 +143,75 @@ pub fn run(mut deps: LoopDeps) -> ! {
         // 2c. Drain background WiFi op results
         deps.wifi_state.poll(
             &deps.ui,
-            &mut deps.nvs.borrow_mut(),
+            &mut *deps.nvs.borrow_mut(),
             &deps.dismiss_keyboard,
             &mut deps.sta_connected,
         );
 
-        // 3. Periodic poll (~every 2 seconds at 16ms sleep = 125 iterations)
-        loop_count = loop_count.wrapping_add(1);
-        if loop_count % 125 == 0 {
-            // WiFi AP client count
-            deps.ui.set_wifi_clients(wifi::connected_clients() as i32);
+        // 3. Periodic poll (~every 2 seconds)
+        if last_periodic.elapsed() >= PERIODIC {
+            last_periodic = now;
+            // Battery state of charge → status bar + HTTP snapshot
+            if let Some(ref mut gauge) = deps.battery {
+                match gauge.read() {
+                    Ok(reading) => {
+                        deps.ui.set_battery_percent(reading.display_percent());
+                        status.battery = Some(reading);
+                        battery_failing = false;
+                    }
+                    Err(e) => {
+                        if !battery_failing {
+                            log::warn!("Battery read failed: {e}");
+                        }
+                        battery_failing = true;
+                    }
+                }
+            }
 
-            // WiFi STA connection status
+            // WiFi AP client count + STA connection status
             if let Ok(wifi) = deps.wifi_handle.try_lock() {
-                match wifi::sta_status(&wifi) {
-                    wifi::StaStatus::Connected { ssid, ip } => {
+                let ap_clients = wifi.ap_clients();
+                deps.ui.set_wifi_clients(ap_clients as i32);
+                status.ap_clients = ap_clients;
+                match wifi.sta_status() {
+                    StaStatus::Connected { ssid, ip, rssi } => {
                         if !deps.sta_connected {
-                            log::info!("WiFi STA connected: {ssid} ({ip})");
+                            log::info!("WiFi STA connected: {ssid} ({ip}, {rssi} dBm)");
                             deps.sta_connected = true;
                         }
                         deps.ui.set_sta_connected(true);
-                        deps.ui.set_sta_ssid(ssid.into());
+                        deps.ui.set_sta_ssid(ssid.as_str().into());
                         deps.ui.set_sta_ip(ip.to_string().into());
+                        status.sta = Some(StaInfo {
+                            ssid,
+                            ip: ip.to_string(),
+                            rssi,
+                        });
                     }
-                    wifi::StaStatus::Disconnected => {
+                    StaStatus::Disconnected => {
                         if deps.sta_connected {
                             log::warn!("WiFi STA disconnected");
                             deps.sta_connected = false;
                         }
                         deps.ui.set_sta_connected(false);
+                        status.sta = None;
                     }
                 }
             }
 
+            // Firmware update: check once per STA connect, then mirror progress
+            if deps.sta_connected && !ota_checked {
+                deps.ota.spawn_check();
+                ota_checked = true;
+            } else if !deps.sta_connected {
+                ota_checked = false;
+            }
+            deps.ota.poll(&deps.ui);
+
             // Toast auto-hide after 5 seconds
+            if deps.ui.get_toast_visible() && toast_shown_at.is_none() {
+                toast_shown_at = Some(Instant::now());
+            }
             if let Some(shown_at) = toast_shown_at {
                 if shown_at.elapsed() >= Duration::from_secs(5) {
                     deps.ui.set_toast_visible(false);
@@ -125,33 +221,209 @@ pub fn run(mut deps: LoopDeps) -> ! {
             }
 
             // About page: system info + logs
+            status.uptime_secs = deps.boot_time.elapsed().as_secs();
+            status.uptime = sysinfo::uptime_string(&deps.boot_time);
+            status.free_heap_kb = sysinfo::free_heap_kb();
+            status.free_psram_kb = sysinfo::free_psram_kb();
+            deps.ui.set_about_uptime(status.uptime.as_str().into());
             deps.ui
-                .set_about_uptime(sysinfo::uptime_string(&deps.boot_time).into());
+                .set_about_heap(format!("{} KB", status.free_heap_kb).into());
             deps.ui
-                .set_about_heap(format!("{} KB", sysinfo::free_heap_kb()).into());
-            deps.ui
-                .set_about_psram(format!("{} KB", sysinfo::free_psram_kb()).into());
+                .set_about_psram(format!("{} KB", status.free_psram_kb).into());
             deps.ui.set_log_text(logger::snapshot().into());
 
+            // Publish the /api/status snapshot
+            status.storage = storage::spiffs_usage()
+                .ok()
+                .map(|(used_bytes, total_bytes)| StorageUsage {
+                    used_bytes,
+                    total_bytes,
+                });
+            if let Ok(mut shared) = deps.status.try_lock() {
+                *shared = status.clone();
+            }
+
             // Drain any pending profile/avatar/background updates from web
-            deps.pending.poll_into_ui(&deps.ui, &deps.nvs);
+            deps.pending
+                .poll_into_ui(&deps.ui, &deps.nvs, &*deps.images, &deps.backlight);
+
+            // Keep the screen on while a web client waits for approval
+            if deps.ui.get_approval_visible() {
+                if let Some(state) = idle.touch(now) {
+                    apply_idle_state(&deps, state, policy.mode().brightness(config.brightness));
+                }
+            }
+
+            // Changed timeouts or brightness (Settings page or web) count
+            // as activity, so the new settings are seen at full brightness
+            // (or the low battery cap).
+            let current = deps
+                .pending
+                .settings
+                .try_lock()
+                .map(|s| PowerConfig::from_settings(&s));
+            if let Ok(current) = current {
+                if current != config {
+                    config = current;
+                    idle.set_timeouts(config.dim_after_secs, config.screen_timeout_secs, now);
+                    apply_idle_state(
+                        &deps,
+                        idle.state(),
+                        policy.mode().brightness(config.brightness),
+                    );
+                }
+            }
+
+            // Power policy: battery mode, radios, CPU frequency
+            let actions = policy.step(
+                &PowerInput {
+                    battery: status.battery.as_ref(),
+                    screen: idle.state(),
+                    idle_for: idle.idle_for(now),
+                    ap_clients: status.ap_clients,
+                    sta_connected: deps.sta_connected,
+                    settings: &config.power,
+                },
+                now,
+            );
+            status.power_mode = policy.mode();
+            if let Some(mode) = actions.mode {
+                log::info!("Power mode: {mode:?}");
+                deps.ui.set_low_battery(mode.low_battery());
+                apply_idle_state(&deps, idle.state(), mode.brightness(config.brightness));
+            }
+            apply_power_actions(&mut deps, &policy, &actions, &mut radio_changes);
+            radio_changes.apply(&deps.wifi_handle);
         }
 
         // 4. Render directly into the DMA framebuffer.
         // The RGB panel hardware continuously DMA-refreshes from this buffer.
-        let fb = &mut *deps.framebuffer;
-        deps.window.draw_if_needed(|renderer| {
-            renderer.render(fb, platform::DISPLAY_WIDTH as usize);
-        });
-
-        // 5. Sleep until next event needed
-        if !deps.window.has_active_animations() {
-            if let Some(duration) = slint::platform::duration_until_next_timer_update() {
-                let sleep_ms = duration.as_millis().min(8) as u32;
-                esp_idf_hal::delay::FreeRtos::delay_ms(sleep_ms);
-            } else {
-                esp_idf_hal::delay::FreeRtos::delay_ms(8);
+        // Nothing to show with the backlight off; the dirty regions are
+        // drawn on wake.
+        if idle.state() != IdleState::Off {
+            let fb = &mut *deps.framebuffer;
+            deps.window.draw_if_needed(|renderer| {
+                renderer.render(fb, platform::DISPLAY_WIDTH as usize);
+            });
+        }
+
+        // 5. Sleep until the next timer or touch poll, whichever is first.
+        // Animations only keep the loop spinning while the screen is on.
+        if idle.state() != IdleState::Active || !deps.window.has_active_animations() {
+            let period = idle.poll_interval(now);
+            let sleep = slint::platform::duration_until_next_timer_update()
+                .map_or(period, |d| d.min(period));
+            esp_idf_hal::delay::FreeRtos::delay_ms(sleep.as_millis() as u32);
+        }
+    }
+}
+
+/// Carry out the power policy's CPU changes and queue its radio changes in
+/// `radio`.
+fn apply_power_actions(
+    deps: &mut LoopDeps,
+    policy: &PowerPolicy,
+    actions: &PowerActions,
+    radio: &mut RadioChanges,
+) {
+    if let Some(cpu) = actions.cpu {
+        if let Err(e) = power::apply_cpu(cpu) {
+            log::warn!("Power management not applied ({cpu:?}): {e}");
+        }
+    }
+    match actions.sta {
+        Some(StaAction::Suspend) => {
+            log::info!("Low battery, disconnecting WiFi STA");
+            radio.sta_disconnect = true;
+            deps.sta_connected = false;
+            deps.ui.set_sta_connected(false);
+        }
+        Some(StaAction::Resume) => {
+            radio.sta_disconnect = false;
+            if let Some((ssid, password)) = storage::load_wifi_credentials(&*deps.nvs.borrow()) {
+                log::info!("Battery recovered, reconnecting to {ssid}");
+                deps.wifi_state.spawn_connect(
+                    deps.wifi_handle.clone(),
+                    deps.ui.as_weak(),
+                    ssid,
+                    password,
+                );
             }
         }
+        None => {}
+    }
+    // Dropping the STA brings the AP back up; keep it down if it should be.
+    let ap = actions
+        .ap
+        .or((actions.sta.is_some() && !policy.ap_enabled()).then_some(false));
+    if ap.is_some() {
+        radio.ap = ap;
+    }
+}
+
+/// Radio changes from the power policy not made yet. A scan or connect can
+/// hold the WiFi lock for seconds, so instead of blocking the loop they
+/// wait here and are retried every periodic tick.
+#[derive(Default)]
+struct RadioChanges {
+    sta_disconnect: bool,
+    ap: Option<bool>,
+}
+
+impl RadioChanges {
+    /// Make the pending changes if the WiFi is free, else keep them.
+    fn apply(&mut self, wifi: &SharedWifi) {
+        if !self.sta_disconnect && self.ap.is_none() {
+            return;
+        }
+        let Ok(mut wifi) = wifi.try_lock() else {
+            log::debug!("WiFi busy, radio changes wait for the next tick");
+            return;
+        };
+        if std::mem::take(&mut self.sta_disconnect) {
+            if let Err(e) = wifi.disconnect_sta() {
+                log::warn!("WiFi STA disconnect failed: {e}");
+            }
+        }
+        if let Some(ap) = self.ap.take() {
+            if let Err(e) = wifi.set_ap_enabled(ap) {
+                log::warn!("WiFi AP switch failed: {e}");
+            }
+        }
+    }
+}
+
+/// The device settings the idle timer, backlight and power policy were
+/// last set from.
+#[derive(PartialEq)]
+struct PowerConfig {
+    dim_after_secs: u32,
+    screen_timeout_secs: u32,
+    brightness: u8,
+    power: PowerSettings,
+}
+
+impl PowerConfig {
+    fn from_settings(s: &DeviceSettings) -> Self {
+        Self {
+            dim_after_secs: s.dim_after_secs,
+            screen_timeout_secs: s.screen_timeout_secs,
+            brightness: s.brightness,
+            power: s.power.clone(),
+        }
+    }
+}
+
+/// Set the backlight for an idle state change. `display-dimmed` pauses the
+/// animated avatar while the screen is dim or dark.
+fn apply_idle_state(deps: &LoopDeps, state: IdleState, brightness: u8) {
+    log::debug!("Screen {state:?}");
+    if let Err(e) = deps
+        .backlight
+        .borrow_mut()
+        .set_percent(state.backlight_percent(brightness))
+    {
+        log::warn!("Failed to set backlight: {e}");
     }
+    deps.ui.set_display_dimmed(state != IdleState::Active);
 }
@@ -0,0 +1,193 @@
+//! Factory reset: erase the NVS `badge` namespace and every file on SPIFFS,
+//! then restart. The next boot finds nothing saved and starts from
+//! `Profile::default()`, with no WiFi network and default device settings.
+//!
+//! Two triggers queue a reset in a [`PendingFactoryReset`] slot:
+//!
+//! - Settings → Factory Reset, after a confirm dialog;
+//! - holding BOOT (IO0) for [`BOOT_HOLD`] right after power-up
+//!   ([`boot_button_held`]).
+//!
+//! `POST /api/factory-reset` only opens the confirm dialog ([`prompt`]): the
+//! API has no authentication, so erasing takes someone holding the badge.
+//!
+//! `PendingState::poll_into_ui` then runs one [`ResetStep`] per ~2 s tick.
+//! The progress overlay shows each step before it starts, and nothing else
+//! is saved while the reset runs.
+
+use std::sync::{Arc, Mutex};
+#[cfg(not(feature = "simulator"))]
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "simulator"))]
+use esp_idf_hal::gpio::{Gpio0, PinDriver, Pull};
+
+use crate::hal::{ImageStore, SharedKvStore};
+use crate::{ota, BadgeUI};
+
+/// How long BOOT must stay pressed at power-up to trigger a reset.
+#[cfg(not(feature = "simulator"))]
+pub const BOOT_HOLD: Duration = Duration::from_secs(3);
+
+/// Steps of a running reset, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetStep {
+    /// Asked for over HTTP; the main loop opens the confirm dialog and
+    /// clears the slot. Nothing is queued until Erase is tapped.
+    Confirm,
+    /// Queued by a trigger; nothing erased yet.
+    Requested,
+    EraseSettings,
+    EraseFiles,
+    Restart,
+}
+
+impl ResetStep {
+    /// Progress text shown while this step is next.
+    pub fn status(self) -> &'static str {
+        match self {
+            Self::Confirm => "Waiting for confirmation on the badge…",
+            Self::Requested => "Starting…",
+            Self::EraseSettings => "Erasing settings…",
+            Self::EraseFiles => "Erasing images…",
+            Self::Restart => "Restarting…",
+        }
+    }
+}
+
+/// The running reset, if any. Shared with the HTTP server.
+pub type PendingFactoryReset = Arc<Mutex<Option<ResetStep>>>;
+
+/// Queue a reset. Returns `false` if one is already running.
+pub fn request(pending: &PendingFactoryReset) -> bool {
+    let Ok(mut step) = pending.lock() else {
+        return false;
+    };
+    if step.is_some_and(|s| s != ResetStep::Confirm) {
+        return false;
+    }
+    *step = Some(ResetStep::Requested);
+    log::warn!("Factory reset requested");
+    true
+}
+
+/// Ask for the confirm dialog on the badge. Returns `false` if a reset is
+/// already running.
+#[cfg(any(not(feature = "simulator"), test))]
+pub fn prompt(pending: &PendingFactoryReset) -> bool {
+    let Ok(mut step) = pending.lock() else {
+        return false;
+    };
+    match *step {
+        Some(ResetStep::Confirm) | None => {
+            *step = Some(ResetStep::Confirm);
+            log::warn!("Factory reset asked for over HTTP, waiting for confirmation");
+            true
+        }
+        Some(_) => false,
+    }
+}
+
+/// Run `step` and show the progress of the one after it. Returns the next
+/// step, or `None` if erasing failed: the overlay then shows the error and
+/// the badge keeps running on what is still in memory.
+pub fn advance(
+    step: ResetStep,
+    ui: &BadgeUI,
+    nvs: &SharedKvStore,
+    images: &dyn ImageStore,
+) -> Option<ResetStep> {
+    let result = match step {
+        // Cleared by the main loop before it gets here; never erase
+        // without the tap.
+        ResetStep::Confirm => return None,
+        ResetStep::Requested => Ok(ResetStep::EraseSettings),
+        ResetStep::EraseSettings => nvs
+            .borrow_mut()
+            .erase_all()
+            .map(|()| ResetStep::EraseFiles)
+            .map_err(|e| format!("settings: {e}")),
+        ResetStep::EraseFiles => images
+            .erase_all()
+            .map(|()| ResetStep::Restart)
+            .map_err(|e| format!("storage: {e}")),
+        ResetStep::Restart => {
+            log::warn!("Factory reset complete, restarting");
+            ota::restart();
+        }
+    };
+    match result {
+        Ok(next) => {
+            log::info!("Factory reset: {}", next.status());
+            ui.set_factory_reset_status(next.status().into());
+            Some(next)
+        }
+        Err(e) => {
+            log::error!("Factory reset failed erasing {e}");
+            ui.set_factory_reset_status(format!("Reset failed erasing {e}").into());
+            ui.set_factory_reset_failed(true);
+            None
+        }
+    }
+}
+
+/// Whether BOOT (IO0) is pressed now and stays pressed for [`BOOT_HOLD`].
+/// Call once, early in `main`, before the display is up.
+///
+/// IO0 is a strapping pin: held through power-on or reset, it starts the
+/// ROM serial bootloader instead of this firmware. So "during power-up"
+/// means pressing it just after switching on, in the second or so before
+/// this check runs.
+#[cfg(not(feature = "simulator"))]
+pub fn boot_button_held(pin: Gpio0) -> anyhow::Result<bool> {
+    let mut button = PinDriver::input(pin)?;
+    button.set_pull(Pull::Up)?;
+    if button.is_high() {
+        return Ok(false);
+    }
+    log::warn!(
+        "BOOT pressed, hold for {} s to factory reset",
+        BOOT_HOLD.as_secs()
+    );
+    let pressed_at = Instant::now();
+    while pressed_at.elapsed() < BOOT_HOLD {
+        if button.is_high() {
+            log::info!("BOOT released, no factory reset");
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot() -> PendingFactoryReset {
+        Arc::new(Mutex::new(None))
+    }
+
+    #[test]
+    fn prompt_only_asks_for_confirmation() {
+        let pending = slot();
+        assert!(prompt(&pending));
+        assert_eq!(*pending.lock().unwrap(), Some(ResetStep::Confirm));
+        // Asking again while the dialog is up is harmless.
+        assert!(prompt(&pending));
+        // The tap on Erase queues the reset.
+        assert!(request(&pending));
+        assert_eq!(*pending.lock().unwrap(), Some(ResetStep::Requested));
+    }
+
+    #[test]
+    fn a_running_reset_is_left_alone() {
+        let pending = slot();
+        assert!(request(&pending));
+        assert!(!request(&pending));
+        assert!(!prompt(&pending));
+        *pending.lock().unwrap() = Some(ResetStep::EraseFiles);
+        assert!(!prompt(&pending));
+        assert_eq!(*pending.lock().unwrap(), Some(ResetStep::EraseFiles));
+    }
+}
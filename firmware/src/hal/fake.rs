@@ -0,0 +1,393 @@
+//! In-memory fakes for the [`hal`](super) traits.
+//!
+//! Used by the desktop simulator and by host-side tests. None of them touch
+//! the filesystem or sleep unless asked to.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+#[cfg(test)]
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+#[cfg(test)]
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+#[cfg(test)]
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::{Backlight, I2cBus, ScannedAp, StaStatus, WifiController};
+#[cfg(test)]
+use super::{FileSystem, ImageStore, KeyValueStore};
+
+/// [`KeyValueStore`] backed by a `BTreeMap`.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryKvStore {
+    pub entries: BTreeMap<String, String>,
+    /// Writes fail, as on a full NVS partition.
+    pub full: bool,
+}
+
+#[cfg(test)]
+impl KeyValueStore for MemoryKvStore {
+    fn get_str(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn set_str(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        if self.full {
+            anyhow::bail!("ESP_ERR_NVS_NOT_ENOUGH_SPACE");
+        }
+        self.entries.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.entries.remove(key).is_some())
+    }
+
+    fn erase_all(&mut self) -> anyhow::Result<()> {
+        self.entries.clear();
+        Ok(())
+    }
+}
+
+/// [`ImageStore`] backed by a `HashMap` of named blobs.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryImageStore {
+    pub files: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+#[cfg(test)]
+impl ImageStore for MemoryImageStore {
+    fn read(&self, name: &str) -> std::io::Result<Vec<u8>> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> std::io::Result<()> {
+        match self.files.lock().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(std::io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn erase_all(&self) -> std::io::Result<()> {
+        self.files.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// A network visible to [`FakeWifi`].
+#[derive(Debug, Clone)]
+pub struct FakeNetwork {
+    pub ssid: String,
+    pub rssi: i8,
+    /// Empty for open networks.
+    pub password: String,
+}
+
+/// [`WifiController`] over a fixed list of networks.
+///
+/// `connect_sta` succeeds when the SSID exists and the password matches.
+pub struct FakeWifi {
+    pub networks: Vec<FakeNetwork>,
+    pub connected: Option<String>,
+    pub ap_enabled: bool,
+    pub clients: u8,
+    /// Address handed out on a successful connect.
+    pub sta_ip: Ipv4Addr,
+    /// Artificial latency for `scan` and `connect_sta` (zero by default).
+    pub delay: Duration,
+}
+
+impl FakeWifi {
+    pub fn new(networks: Vec<FakeNetwork>) -> Self {
+        Self {
+            networks,
+            connected: None,
+            ap_enabled: true,
+            clients: 0,
+            sta_ip: Ipv4Addr::new(192, 168, 1, 42),
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+impl WifiController for FakeWifi {
+    fn scan(&mut self) -> anyhow::Result<Vec<ScannedAp>> {
+        std::thread::sleep(self.delay);
+        let mut aps: Vec<ScannedAp> = self
+            .networks
+            .iter()
+            .map(|n| ScannedAp {
+                ssid: n.ssid.clone(),
+                rssi: n.rssi,
+                auth_required: !n.password.is_empty(),
+            })
+            .collect();
+        aps.sort_by_key(|ap| std::cmp::Reverse(ap.rssi));
+        Ok(aps)
+    }
+
+    fn connect_sta(&mut self, ssid: &str, password: &str) -> anyhow::Result<Ipv4Addr> {
+        std::thread::sleep(self.delay);
+        self.connected = None;
+        let Some(network) = self.networks.iter().find(|n| n.ssid == ssid) else {
+            anyhow::bail!("WiFi connect failed: no AP named {ssid}");
+        };
+        if network.password != password {
+            anyhow::bail!("WiFi connect failed: authentication failed");
+        }
+        self.connected = Some(ssid.to_string());
+        Ok(self.sta_ip)
+    }
+
+    fn disconnect_sta(&mut self) -> anyhow::Result<()> {
+        self.connected = None;
+        Ok(())
+    }
+
+    fn sta_status(&self) -> StaStatus {
+        match &self.connected {
+            Some(ssid) => StaStatus::Connected {
+                ssid: ssid.clone(),
+                ip: self.sta_ip,
+                rssi: self
+                    .networks
+                    .iter()
+                    .find(|n| &n.ssid == ssid)
+                    .map_or(0, |n| n.rssi),
+            },
+            None => StaStatus::Disconnected,
+        }
+    }
+
+    fn ap_clients(&self) -> u8 {
+        if self.ap_enabled {
+            self.clients
+        } else {
+            0
+        }
+    }
+
+    #[cfg(not(feature = "simulator"))]
+    fn set_ap_enabled(&mut self, enabled: bool) -> anyhow::Result<()> {
+        self.ap_enabled = enabled;
+        Ok(())
+    }
+}
+
+/// [`Backlight`] that records the last brightness it was set to.
+#[derive(Default, Clone)]
+pub struct FakeBacklight {
+    pub percent: Rc<Cell<f32>>,
+}
+
+impl Backlight for FakeBacklight {
+    fn set_percent(&mut self, percent: f32) -> anyhow::Result<()> {
+        log::info!("Backlight set to {percent:.0}%");
+        self.percent.set(percent);
+        Ok(())
+    }
+}
+
+/// [`I2cBus`] backed by a register map per device address.
+///
+/// `write_read(addr, [reg], buf)` copies the bytes stored at `(addr, reg)`;
+/// unknown addresses or registers fail like a NACK.
+#[derive(Default)]
+pub struct FakeI2c {
+    pub registers: BTreeMap<(u8, u8), Vec<u8>>,
+}
+
+impl FakeI2c {
+    /// Store a big-endian 16-bit register value.
+    pub fn set_u16(&mut self, addr: u8, reg: u8, value: u16) {
+        self.registers
+            .insert((addr, reg), value.to_be_bytes().to_vec());
+    }
+}
+
+impl I2cBus for FakeI2c {
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> anyhow::Result<()> {
+        let Some(&reg) = bytes.first() else {
+            anyhow::bail!("I2C 0x{addr:02X}: empty register address");
+        };
+        let Some(value) = self.registers.get(&(addr, reg)) else {
+            anyhow::bail!("I2C 0x{addr:02X}: NACK on register 0x{reg:02X}");
+        };
+        let n = buf.len().min(value.len());
+        buf[..n].copy_from_slice(&value[..n]);
+        Ok(())
+    }
+}
+
+/// [`FileSystem`] in memory that can lose power part-way through a write
+/// sequence.
+///
+/// After [`FaultyFs::fail_at`]`(n)`, the `n`-th following write, rename or
+/// remove is cut off: a write leaves only the first half of its data, a
+/// rename or remove doesn't happen. From then on every call fails until
+/// [`FaultyFs::power_on`], as after a brown-out.
+#[cfg(test)]
+#[derive(Default)]
+pub struct FaultyFs {
+    pub files: Mutex<BTreeMap<PathBuf, Vec<u8>>>,
+    fault: Mutex<Fault>,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Fault {
+    /// Changes left before the power goes.
+    changes_left: Option<usize>,
+    powered_off: bool,
+}
+
+#[cfg(test)]
+impl FaultyFs {
+    /// Lose power on the `n`-th change from now (0 = the next one).
+    pub fn fail_at(&self, n: usize) {
+        self.fault.lock().unwrap().changes_left = Some(n);
+    }
+
+    /// Power back on with no fault pending.
+    pub fn power_on(&self) {
+        *self.fault.lock().unwrap() = Fault::default();
+    }
+
+    /// Fails if the power is off; `Ok(true)` if it goes during this change.
+    fn change(&self) -> std::io::Result<bool> {
+        let mut fault = self.fault.lock().unwrap();
+        if fault.powered_off {
+            return Err(power_off());
+        }
+        match &mut fault.changes_left {
+            Some(0) => {
+                fault.powered_off = true;
+                Ok(true)
+            }
+            Some(n) => {
+                *n -= 1;
+                Ok(false)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn powered(&self) -> std::io::Result<()> {
+        if self.fault.lock().unwrap().powered_off {
+            return Err(power_off());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn power_off() -> std::io::Error {
+    std::io::Error::other("power lost")
+}
+
+#[cfg(test)]
+impl FileSystem for FaultyFs {
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        self.powered()?;
+        self.files
+            .lock()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        let torn = self.change()?;
+        let kept = if torn { &data[..data.len() / 2] } else { data };
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), kept.to_vec());
+        if torn {
+            return Err(power_off());
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        if self.change()? {
+            return Err(power_off());
+        }
+        let mut files = self.files.lock().unwrap();
+        if files.contains_key(to) {
+            return Err(std::io::ErrorKind::AlreadyExists.into());
+        }
+        let data = files
+            .remove(from)
+            .ok_or(std::io::Error::from(std::io::ErrorKind::NotFound))?;
+        files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> std::io::Result<()> {
+        if self.change()? {
+            return Err(power_off());
+        }
+        match self.files.lock().unwrap().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(std::io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn list(&self, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        self.powered()?;
+        Ok(self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Slint platform without a screen: windows render nowhere.
+#[cfg(test)]
+struct HeadlessPlatform;
+
+#[cfg(test)]
+impl slint::platform::Platform for HeadlessPlatform {
+    fn create_window_adapter(
+        &self,
+    ) -> Result<Rc<dyn slint::platform::WindowAdapter>, slint::PlatformError> {
+        use slint::platform::software_renderer::{MinimalSoftwareWindow, RepaintBufferType};
+        Ok(MinimalSoftwareWindow::new(RepaintBufferType::NewBuffer))
+    }
+}
+
+/// Run `f` with a fresh [`BadgeUI`](crate::BadgeUI) on a headless window.
+///
+/// Slint is built single-threaded, so UI tests take turns behind a lock.
+#[cfg(test)]
+pub fn with_ui(f: impl FnOnce(&crate::BadgeUI)) {
+    static UI_LOCK: Mutex<()> = Mutex::new(());
+    let _guard = UI_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // Only the first call installs it; later ones get an error back.
+    let _ = slint::platform::set_platform(Box::new(HeadlessPlatform));
+    let ui = crate::BadgeUI::new().unwrap();
+    f(&ui);
+}
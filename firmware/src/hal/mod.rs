@@ -0,0 +1,155 @@
+//! Hardware abstraction traits.
+//!
+//! Core logic (`storage`, `WiFiState`, `PendingState`, `callbacks`) talks to
+//! hardware only through these traits, so it can run off-target:
+//!
+//! | Trait              | On the badge                      | Off-target            |
+//! |--------------------|-----------------------------------|-----------------------|
+//! | [`KeyValueStore`]  | `EspNvs` (`storage.rs`)           | [`fake::MemoryKvStore`] |
+//! | [`ImageStore`]     | `storage::FsImageStore` on SPIFFS | [`fake::MemoryImageStore`] |
+//! | [`FileSystem`]     | `storage::StdFs` (SPIFFS via VFS) | [`fake::FaultyFs`]      |
+//! | [`WifiController`] | `BlockingWifi` (`wifi.rs`)        | [`fake::FakeWifi`]      |
+//! | [`Backlight`]      | `backlight::LedcBacklight`        | [`fake::FakeBacklight`] |
+//! | [`I2cBus`]         | `I2cDriver` (`i2c.rs`)            | [`fake::FakeI2c`]       |
+
+#[cfg(any(test, feature = "simulator"))]
+pub mod fake;
+
+use std::cell::RefCell;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/// String key-value store (the NVS `badge` namespace).
+///
+/// Keys are at most 15 characters (NVS limit).
+pub trait KeyValueStore {
+    /// Read a string value. `Ok(None)` if the key does not exist.
+    fn get_str(&self, key: &str) -> anyhow::Result<Option<String>>;
+
+    /// Write a string value, replacing any existing one.
+    fn set_str(&mut self, key: &str, value: &str) -> anyhow::Result<()>;
+
+    /// Remove a key. Returns `true` if it existed.
+    fn remove(&mut self, key: &str) -> anyhow::Result<bool>;
+
+    /// Remove every key in the namespace (factory reset).
+    fn erase_all(&mut self) -> anyhow::Result<()>;
+}
+
+/// Named binary blob store (image files on SPIFFS).
+///
+/// Errors are `std::io::Error` so callers can tell `NotFound` (expected on
+/// first boot) from real failures.
+pub trait ImageStore: Send + Sync {
+    fn read(&self, name: &str) -> std::io::Result<Vec<u8>>;
+    fn write(&self, name: &str, data: &[u8]) -> std::io::Result<()>;
+    fn remove(&self, name: &str) -> std::io::Result<()>;
+
+    /// Delete every file in the store, not only images: a factory reset
+    /// empties the whole partition.
+    fn erase_all(&self) -> std::io::Result<()>;
+}
+
+/// Raw file operations under `storage::FsImageStore` and the crash
+/// history, split out so their power-loss-safe write sequence can run
+/// against a fake that fails part-way through.
+pub trait FileSystem: Send + Sync {
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>>;
+
+    /// Create or truncate `path`, write `data` and flush it to flash.
+    fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()>;
+
+    /// Rename `from` to `to`, which must not exist (SPIFFS can't replace a
+    /// file by renaming onto it).
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()>;
+
+    fn remove(&self, path: &Path) -> std::io::Result<()>;
+
+    /// Paths of the files in `dir`.
+    fn list(&self, dir: &Path) -> std::io::Result<Vec<PathBuf>>;
+}
+
+/// Result of scanning a nearby access point.
+#[derive(Debug, Clone)]
+pub struct ScannedAp {
+    pub ssid: String,
+    /// RSSI in dBm (e.g. -40 = strong, -80 = weak).
+    pub rssi: i8,
+    /// Whether the network requires authentication.
+    pub auth_required: bool,
+}
+
+/// Station connection status.
+#[derive(Debug, Clone)]
+pub enum StaStatus {
+    Disconnected,
+    Connected {
+        ssid: String,
+        ip: Ipv4Addr,
+        /// Signal strength of the joined AP in dBm.
+        rssi: i8,
+    },
+}
+
+/// WiFi radio: always-on AP plus optional station connection.
+///
+/// All methods may block; `WiFiState` calls the slow ones from worker
+/// threads, hence the `Send` bound.
+pub trait WifiController: Send {
+    /// Scan for nearby access points, strongest first.
+    fn scan(&mut self) -> anyhow::Result<Vec<ScannedAp>>;
+
+    /// Connect to an external network while keeping the AP running.
+    fn connect_sta(&mut self, ssid: &str, password: &str) -> anyhow::Result<Ipv4Addr>;
+
+    /// Disconnect the station and return to AP-only mode.
+    fn disconnect_sta(&mut self) -> anyhow::Result<()>;
+
+    /// Current station status.
+    fn sta_status(&self) -> StaStatus;
+
+    /// Number of clients joined to the AP.
+    fn ap_clients(&self) -> u8;
+
+    /// Turn the access point off or back on, keeping the station as it is.
+    /// Off with no station stops the radio. Only the ESP power policy
+    /// turns the AP off.
+    #[cfg(not(feature = "simulator"))]
+    fn set_ap_enabled(&mut self, enabled: bool) -> anyhow::Result<()>;
+}
+
+/// Display backlight.
+pub trait Backlight {
+    /// Set brightness in percent (0-100).
+    fn set_percent(&mut self, percent: f32) -> anyhow::Result<()>;
+}
+
+/// I2C master. One bus carries the GT911 touch panel and the MAX17048 fuel
+/// gauge, both driven from the main loop.
+pub trait I2cBus {
+    /// Write `bytes` then read into `buf` in one transaction (repeated start),
+    /// e.g. a register address followed by its value.
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> anyhow::Result<()>;
+}
+
+/// Drivers that share a bus hold an `Rc<RefCell<_>>` to it.
+impl<B: I2cBus + ?Sized> I2cBus for Rc<RefCell<B>> {
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> anyhow::Result<()> {
+        self.borrow_mut().write_read(addr, bytes, buf)
+    }
+}
+
+/// Main-thread handle to the key-value store (NVS is `!Send`).
+pub type SharedKvStore = Rc<RefCell<dyn KeyValueStore>>;
+
+/// Image store shared between the main loop and the HTTP thread.
+pub type SharedImageStore = Arc<dyn ImageStore>;
+
+/// WiFi controller shared between the main loop and background WiFi threads.
+pub type SharedWifi = Arc<Mutex<dyn WifiController>>;
+
+/// Backlight driven from the main thread: the brightness slider and
+/// settings changed over HTTP.
+pub type SharedBacklight = Rc<RefCell<dyn Backlight>>;
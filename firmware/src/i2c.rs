@@ -0,0 +1,39 @@
+//! Shared I2C bus (I2C0 on SDA 19 / SCL 20).
+//!
+//! The GT911 touch controller (0x5D) and the MAX17048 fuel gauge (0x36) hang
+//! off the same two pins. Both are polled from the main loop, so the driver
+//! is shared as an `Rc<RefCell<_>>` rather than behind a mutex.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use esp_idf_hal::delay::TickType;
+use esp_idf_hal::gpio::AnyIOPin;
+use esp_idf_hal::i2c::config::Config as I2cConfig;
+use esp_idf_hal::i2c::{I2cDriver, I2C0};
+
+use crate::hal::I2cBus;
+
+/// I2C bus speed — use 100kHz (standard mode) for reliability with internal pull-ups.
+const I2C_FREQ: u32 = 100_000;
+
+/// Per-transaction timeout. A stuck slave must not stall the render loop.
+const I2C_TIMEOUT_MS: u64 = 50;
+
+/// Main-thread handle to the bus.
+pub type SharedI2c = Rc<RefCell<I2cDriver<'static>>>;
+
+/// Configure I2C0 as master on the given pins.
+pub fn init(i2c: I2C0, sda: AnyIOPin, scl: AnyIOPin) -> anyhow::Result<SharedI2c> {
+    let config = I2cConfig::new().baudrate(I2C_FREQ.into());
+    let driver = I2cDriver::new(i2c, sda, scl, &config)?;
+    Ok(Rc::new(RefCell::new(driver)))
+}
+
+impl I2cBus for I2cDriver<'_> {
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> anyhow::Result<()> {
+        let timeout = TickType::new_millis(I2C_TIMEOUT_MS).ticks();
+        I2cDriver::write_read(self, addr, bytes, buf, timeout)?;
+        Ok(())
+    }
+}
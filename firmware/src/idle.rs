@@ -0,0 +1,239 @@
+//! Idle timer: dims the backlight, then turns it off, after a stretch
+//! without touches, and sets how often the main loop polls and renders.
+//!
+//! ```text
+//!            dim_after           screen_timeout
+//! Active ───────────────▶ Dimmed ───────────────▶ Off
+//!    ▲                      │                      │
+//!    └──────── touch ───────┴──────────────────────┘
+//! ```
+//!
+//! Both timeouts count from the last touch, and 0 disables one. A timeout
+//! of 0 for dimming with a screen timeout set goes straight from `Active`
+//! to `Off`. The touch that wakes the screen is not passed on to the UI,
+//! so tapping a dark badge can't press a button nobody saw.
+//!
+//! Pure logic on [`Instant`]s; the event loop owns the timer and drives
+//! the backlight on each transition.
+
+use std::time::{Duration, Instant};
+
+use crate::settings::MIN_BRIGHTNESS;
+
+/// Loop period while someone is using the screen (~120 Hz touch polling).
+pub const INTERACTIVE_POLL: Duration = Duration::from_millis(8);
+
+/// Loop period once the screen has been left alone for [`QUIET_AFTER`]
+/// but isn't dimmed yet. Still smooth enough for the first touch.
+pub const QUIET_POLL: Duration = Duration::from_millis(33);
+
+/// Loop period while dimmed or off: only the touch that wakes the screen
+/// needs catching.
+pub const IDLE_POLL: Duration = Duration::from_millis(100);
+
+/// How long after the last touch the loop drops to [`QUIET_POLL`].
+pub const QUIET_AFTER: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleState {
+    Active,
+    Dimmed,
+    Off,
+}
+
+impl IdleState {
+    /// Backlight level for this state, in percent.
+    pub fn backlight_percent(self, brightness: u8) -> f32 {
+        match self {
+            Self::Active => brightness as f32,
+            Self::Dimmed => brightness.min(MIN_BRIGHTNESS) as f32,
+            Self::Off => 0.0,
+        }
+    }
+}
+
+pub struct IdleTimer {
+    dim_after: Option<Duration>,
+    off_after: Option<Duration>,
+    last_touch: Instant,
+    state: IdleState,
+}
+
+impl IdleTimer {
+    /// A timer that is `Active` as of `now`, with timeouts in seconds
+    /// (0 = never).
+    pub fn new(dim_after_secs: u32, off_after_secs: u32, now: Instant) -> Self {
+        Self {
+            dim_after: timeout(dim_after_secs),
+            off_after: timeout(off_after_secs),
+            last_touch: now,
+            state: IdleState::Active,
+        }
+    }
+
+    pub fn state(&self) -> IdleState {
+        self.state
+    }
+
+    /// Time since the last touch.
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_touch)
+    }
+
+    /// Replace the timeouts. Counts as a touch, so new settings are seen at
+    /// full brightness. Returns the new state if that woke the screen.
+    pub fn set_timeouts(
+        &mut self,
+        dim_after_secs: u32,
+        off_after_secs: u32,
+        now: Instant,
+    ) -> Option<IdleState> {
+        self.dim_after = timeout(dim_after_secs);
+        self.off_after = timeout(off_after_secs);
+        self.touch(now)
+    }
+
+    /// A finger is on the screen. Returns `Some(Active)` if this woke it.
+    pub fn touch(&mut self, now: Instant) -> Option<IdleState> {
+        self.last_touch = now;
+        self.transition(IdleState::Active)
+    }
+
+    /// Move to the state due at `now`. Returns the new state on a change.
+    /// Only ever goes darker; waking is up to [`touch`](Self::touch).
+    pub fn tick(&mut self, now: Instant) -> Option<IdleState> {
+        let idle = self.idle_for(now);
+        let due = if self.off_after.is_some_and(|t| idle >= t) {
+            IdleState::Off
+        } else if self.dim_after.is_some_and(|t| idle >= t) {
+            IdleState::Dimmed
+        } else {
+            IdleState::Active
+        };
+        match (self.state, due) {
+            (IdleState::Off, _) | (IdleState::Dimmed, IdleState::Active) => None,
+            _ => self.transition(due),
+        }
+    }
+
+    /// How long the main loop may sleep before polling touch again.
+    pub fn poll_interval(&self, now: Instant) -> Duration {
+        match self.state {
+            IdleState::Active if self.idle_for(now) < QUIET_AFTER => INTERACTIVE_POLL,
+            IdleState::Active => QUIET_POLL,
+            IdleState::Dimmed | IdleState::Off => IDLE_POLL,
+        }
+    }
+
+    fn transition(&mut self, to: IdleState) -> Option<IdleState> {
+        if self.state == to {
+            return None;
+        }
+        self.state = to;
+        Some(to)
+    }
+}
+
+fn timeout(secs: u32) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn dims_then_turns_off() {
+        let start = Instant::now();
+        let mut timer = IdleTimer::new(30, 120, start);
+        assert_eq!(timer.tick(start + secs(29)), None);
+        assert_eq!(timer.tick(start + secs(30)), Some(IdleState::Dimmed));
+        assert_eq!(timer.tick(start + secs(60)), None);
+        assert_eq!(timer.tick(start + secs(120)), Some(IdleState::Off));
+        assert_eq!(timer.tick(start + secs(600)), None);
+        assert_eq!(timer.state(), IdleState::Off);
+    }
+
+    #[test]
+    fn late_tick_goes_straight_to_off() {
+        let start = Instant::now();
+        let mut timer = IdleTimer::new(30, 120, start);
+        assert_eq!(timer.tick(start + secs(500)), Some(IdleState::Off));
+    }
+
+    #[test]
+    fn zero_disables_a_timeout() {
+        let start = Instant::now();
+        let mut timer = IdleTimer::new(0, 60, start);
+        assert_eq!(timer.tick(start + secs(59)), None);
+        assert_eq!(timer.tick(start + secs(60)), Some(IdleState::Off));
+
+        let mut timer = IdleTimer::new(30, 0, start);
+        assert_eq!(timer.tick(start + secs(30)), Some(IdleState::Dimmed));
+        assert_eq!(timer.tick(start + secs(86_400)), None);
+    }
+
+    #[test]
+    fn touch_wakes_and_restarts_the_count() {
+        let start = Instant::now();
+        let mut timer = IdleTimer::new(30, 120, start);
+        assert_eq!(timer.tick(start + secs(30)), Some(IdleState::Dimmed));
+        assert_eq!(timer.touch(start + secs(40)), Some(IdleState::Active));
+        assert_eq!(timer.tick(start + secs(69)), None);
+        assert_eq!(timer.tick(start + secs(70)), Some(IdleState::Dimmed));
+
+        assert_eq!(timer.tick(start + secs(160)), Some(IdleState::Off));
+        assert_eq!(timer.touch(start + secs(200)), Some(IdleState::Active));
+        // Touches while awake change nothing.
+        assert_eq!(timer.touch(start + secs(201)), None);
+        assert_eq!(timer.state(), IdleState::Active);
+    }
+
+    #[test]
+    fn new_timeouts_mid_cycle_wake_and_apply() {
+        let start = Instant::now();
+        let mut timer = IdleTimer::new(30, 120, start);
+        assert_eq!(timer.tick(start + secs(30)), Some(IdleState::Dimmed));
+        assert_eq!(
+            timer.set_timeouts(10, 20, start + secs(50)),
+            Some(IdleState::Active)
+        );
+        assert_eq!(timer.tick(start + secs(59)), None);
+        assert_eq!(timer.tick(start + secs(60)), Some(IdleState::Dimmed));
+        assert_eq!(timer.tick(start + secs(70)), Some(IdleState::Off));
+
+        // Disabling both while off wakes the screen for good.
+        assert_eq!(
+            timer.set_timeouts(0, 0, start + secs(80)),
+            Some(IdleState::Active)
+        );
+        assert_eq!(timer.tick(start + secs(86_400)), None);
+    }
+
+    #[test]
+    fn poll_slows_down_when_idle() {
+        let start = Instant::now();
+        let mut timer = IdleTimer::new(30, 120, start);
+        assert_eq!(timer.poll_interval(start), INTERACTIVE_POLL);
+        assert_eq!(timer.poll_interval(start + QUIET_AFTER), QUIET_POLL);
+        timer.tick(start + secs(30));
+        assert_eq!(timer.poll_interval(start + secs(30)), IDLE_POLL);
+        timer.touch(start + secs(31));
+        assert_eq!(timer.poll_interval(start + secs(31)), INTERACTIVE_POLL);
+    }
+
+    #[test]
+    fn dimmed_level_never_exceeds_the_setting() {
+        assert_eq!(IdleState::Active.backlight_percent(80), 80.0);
+        assert_eq!(
+            IdleState::Dimmed.backlight_percent(80),
+            MIN_BRIGHTNESS as f32
+        );
+        assert_eq!(IdleState::Dimmed.backlight_percent(5), 5.0);
+        assert_eq!(IdleState::Off.backlight_percent(80), 0.0);
+    }
+}
@@ -0,0 +1,688 @@
+//! Compact on-flash image format for avatars and backgrounds.
+//!
+//! The panel is RGB565, so images are stored as RGB565 and compressed with
+//! a QOI-style byte stream: runs, a 64-entry cache of recent colours, and
+//! small per-channel deltas. Photos shrink to roughly a third of raw RGB888,
+//! flat colour areas to almost nothing.
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic "VBIM"
+//!      4     1  version (1)
+//!      5     1  pixel format (1 = RGB565, QOI-style ops)
+//!      6     2  width   (u16 LE)
+//!      8     2  height  (u16 LE)
+//!     10     2  reserved (0)
+//!     12     4  payload length (u32 LE)
+//!     16     4  CRC-32 of the payload (u32 LE)
+//!     20     …  payload
+//! ```
+//!
+//! Payload ops, one pixel state `prev` (starts black) and an index table:
+//!
+//! ```text
+//! 00iiiiii             INDEX  pixel = index[i]
+//! 01rrggbb             DIFF   r,g,b += (-2..1) each
+//! 10gggggg rrrrbbbb    LUMA   g += -32..31; r,b += dg/2 + (-8..7)
+//! 11nnnnnn (n < 62)    RUN    repeat prev n+1 times
+//! 11111110 lo hi       PIXEL  raw RGB565
+//! ```
+//!
+//! Animated avatars ("VBAN") bundle several such images, each with its own
+//! header and CRC, so one frame can be decoded at a time:
+//!
+//! ```text
+//! offset  size  field
+//!      0     4  magic "VBAN"
+//!      4     1  version (1)
+//!      5     1  reserved (0)
+//!      6     2  frame count (u16 LE)
+//!      8     2  width   (u16 LE)
+//!     10     2  height  (u16 LE)
+//!     12     …  frames: delay ms (u16 LE), length (u32 LE), VBIM image
+//! ```
+//!
+//! Encoding and decoding are pure so they can be checked on the host.
+
+use std::fmt;
+use std::ops::Range;
+
+const MAGIC: &[u8; 4] = b"VBIM";
+const VERSION: u8 = 1;
+
+const ANIM_MAGIC: &[u8; 4] = b"VBAN";
+const ANIM_VERSION: u8 = 1;
+const ANIM_HEADER_LEN: usize = 12;
+/// Per-frame prefix: delay (u16) + image length (u32).
+const ANIM_FRAME_PREFIX: usize = 6;
+
+/// Pixel format byte: RGB565 with the ops above.
+const FORMAT_RGB565_QOI: u8 = 1;
+
+/// Header bytes before the payload.
+pub const HEADER_LEN: usize = 20;
+
+/// Largest encoding of a `width` x `height` image: every pixel a 3-byte
+/// `PIXEL` op (noise; photos come out far smaller).
+#[cfg(any(not(feature = "simulator"), test))]
+pub fn max_encoded_len(width: u16, height: u16) -> usize {
+    HEADER_LEN + 3 * width as usize * height as usize
+}
+
+const OP_INDEX: u8 = 0b0000_0000;
+const OP_DIFF: u8 = 0b0100_0000;
+const OP_LUMA: u8 = 0b1000_0000;
+const OP_RUN: u8 = 0b1100_0000;
+const OP_PIXEL: u8 = 0xFE;
+const OP_MASK: u8 = 0b1100_0000;
+
+/// Longest run one op can carry (`RUN` values 62 and 63 are taken by
+/// `PIXEL` and a reserved tag).
+const MAX_RUN: u8 = 62;
+
+/// Size and format read from an image header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageHeader {
+    pub width: u16,
+    pub height: u16,
+    pub payload_len: u32,
+    pub crc: u32,
+}
+
+impl ImageHeader {
+    pub fn pixels(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+/// A stored image that can't be decoded (bad magic, unknown version,
+/// truncated or failed checksum).
+#[derive(Debug)]
+pub struct CorruptImage(pub String);
+
+impl fmt::Display for CorruptImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CorruptImage {}
+
+/// Encode `width` x `height` RGB888 pixels (3 bytes each, row-major).
+///
+/// Panics if `rgb` is not exactly `width * height * 3` bytes.
+pub fn encode_rgb888(rgb: &[u8], width: u16, height: u16) -> Vec<u8> {
+    let pixels = width as usize * height as usize;
+    assert_eq!(rgb.len(), pixels * 3, "RGB888 buffer does not match size");
+
+    let mut out = Vec::with_capacity(HEADER_LEN + pixels / 2);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(FORMAT_RGB565_QOI);
+    out.extend_from_slice(&width.to_le_bytes());
+    out.extend_from_slice(&height.to_le_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&[0; 8]); // payload length + CRC, filled in below
+
+    let mut index = [0u16; 64];
+    let mut prev = 0u16;
+    let mut run = 0u8;
+    for px in rgb.chunks_exact(3).map(|c| to_rgb565(c[0], c[1], c[2])) {
+        if px == prev {
+            run += 1;
+            if run == MAX_RUN {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let slot = hash(px);
+        if index[slot] == px {
+            out.push(OP_INDEX | slot as u8);
+        } else {
+            index[slot] = px;
+            encode_delta(&mut out, prev, px);
+        }
+        prev = px;
+    }
+    if run > 0 {
+        out.push(OP_RUN | (run - 1));
+    }
+
+    let payload_len = (out.len() - HEADER_LEN) as u32;
+    let crc = crc32(&out[HEADER_LEN..]);
+    out[12..16].copy_from_slice(&payload_len.to_le_bytes());
+    out[16..20].copy_from_slice(&crc.to_le_bytes());
+    out
+}
+
+/// Parse and check the header of an encoded image (not the payload).
+pub fn read_header(data: &[u8]) -> Result<ImageHeader, CorruptImage> {
+    if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+        return Err(CorruptImage("Not a VBIM image".into()));
+    }
+    if data[4] != VERSION {
+        return Err(CorruptImage(format!(
+            "Unsupported image version {}",
+            data[4]
+        )));
+    }
+    if data[5] != FORMAT_RGB565_QOI {
+        return Err(CorruptImage(format!(
+            "Unsupported pixel format {}",
+            data[5]
+        )));
+    }
+    let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    Ok(ImageHeader {
+        width: u16::from_le_bytes([data[6], data[7]]),
+        height: u16::from_le_bytes([data[8], data[9]]),
+        payload_len: u32_at(12),
+        crc: u32_at(16),
+    })
+}
+
+/// Check an encoded image's header, payload length and CRC. Returns the
+/// header and payload.
+fn checked_payload(data: &[u8]) -> Result<(ImageHeader, &[u8]), CorruptImage> {
+    let header = read_header(data)?;
+    let payload = &data[HEADER_LEN..];
+    if payload.len() != header.payload_len as usize {
+        return Err(CorruptImage(format!(
+            "Image payload is {} bytes, header says {}",
+            payload.len(),
+            header.payload_len
+        )));
+    }
+    if crc32(payload) != header.crc {
+        return Err(CorruptImage("Image checksum mismatch".into()));
+    }
+    Ok((header, payload))
+}
+
+/// Check a stored file, image or animation, without decoding any pixels:
+/// its structure and the CRC of every image in it. Catches files cut short
+/// or garbled by a power loss mid-write.
+pub fn verify(data: &[u8]) -> Result<(), CorruptImage> {
+    if !is_animation(data) {
+        return checked_payload(data).map(|_| ());
+    }
+    let (_, frames) = index_frames(data)?;
+    for (i, (range, _)) in frames.into_iter().enumerate() {
+        checked_payload(&data[range])
+            .map_err(|e| CorruptImage(format!("Animation frame {i}: {e}")))?;
+    }
+    Ok(())
+}
+
+/// Decode an encoded image to RGB888, checking its length and CRC.
+pub fn decode_rgb888(data: &[u8]) -> Result<(ImageHeader, Vec<u8>), CorruptImage> {
+    let (header, payload) = checked_payload(data)?;
+
+    let pixels = header.pixels();
+    let mut out = Vec::with_capacity(pixels * 3);
+    let mut index = [0u16; 64];
+    let mut prev = 0u16;
+    let mut pos = 0;
+    let truncated = || CorruptImage("Image payload ends early".into());
+
+    while out.len() < pixels * 3 {
+        let op = *payload.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        let (r, g, b) = split(prev);
+
+        let (px, count) = if op == OP_PIXEL {
+            let bytes = payload.get(pos..pos + 2).ok_or_else(truncated)?;
+            pos += 2;
+            (u16::from_le_bytes([bytes[0], bytes[1]]), 1)
+        } else {
+            match op & OP_MASK {
+                OP_INDEX => (index[op as usize], 1),
+                OP_DIFF => {
+                    let dr = ((op >> 4) & 0x03) as i8 - 2;
+                    let dg = ((op >> 2) & 0x03) as i8 - 2;
+                    let db = (op & 0x03) as i8 - 2;
+                    (join(r, dr, g, dg, b, db), 1)
+                }
+                OP_LUMA => {
+                    let next = *payload.get(pos).ok_or_else(truncated)?;
+                    pos += 1;
+                    let dg = (op & 0x3F) as i8 - 32;
+                    let dr = (next >> 4) as i8 - 8 + (dg >> 1);
+                    let db = (next & 0x0F) as i8 - 8 + (dg >> 1);
+                    (join(r, dr, g, dg, b, db), 1)
+                }
+                _ if op & !OP_MASK < MAX_RUN => (prev, (op & !OP_MASK) as usize + 1),
+                _ => return Err(CorruptImage(format!("Unknown image op 0x{op:02X}"))),
+            }
+        };
+
+        if out.len() + count * 3 > pixels * 3 {
+            return Err(CorruptImage("Image payload has too many pixels".into()));
+        }
+        index[hash(px)] = px;
+        let rgb = to_rgb888(px);
+        for _ in 0..count {
+            out.extend_from_slice(&rgb);
+        }
+        prev = px;
+    }
+
+    if pos != payload.len() {
+        return Err(CorruptImage("Image payload has trailing bytes".into()));
+    }
+    Ok((header, out))
+}
+
+/// Emit `DIFF`, `LUMA` or `PIXEL` for the step from `prev` to `px`.
+fn encode_delta(out: &mut Vec<u8>, prev: u16, px: u16) {
+    let (pr, pg, pb) = split(prev);
+    let (r, g, b) = split(px);
+    // Wrap into the signed range of each channel's bit width.
+    let dr = (r.wrapping_sub(pr).wrapping_add(16) & 0x1F) as i8 - 16;
+    let dg = (g.wrapping_sub(pg).wrapping_add(32) & 0x3F) as i8 - 32;
+    let db = (b.wrapping_sub(pb).wrapping_add(16) & 0x1F) as i8 - 16;
+
+    if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+        out.push(OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+        return;
+    }
+    let dr_dg = dr - (dg >> 1);
+    let db_dg = db - (dg >> 1);
+    if (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+        out.push(OP_LUMA | (dg + 32) as u8);
+        out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+        return;
+    }
+    out.push(OP_PIXEL);
+    out.extend_from_slice(&px.to_le_bytes());
+}
+
+fn to_rgb565(r: u8, g: u8, b: u8) -> u16 {
+    (u16::from(r) >> 3) << 11 | (u16::from(g) >> 2) << 5 | u16::from(b) >> 3
+}
+
+/// Expand RGB565 to RGB888, replicating high bits so white stays 0xFF.
+fn to_rgb888(px: u16) -> [u8; 3] {
+    let (r, g, b) = split(px);
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// Channels of an RGB565 pixel: red 0..31, green 0..63, blue 0..31.
+fn split(px: u16) -> (u8, u8, u8) {
+    (
+        (px >> 11) as u8,
+        ((px >> 5) & 0x3F) as u8,
+        (px & 0x1F) as u8,
+    )
+}
+
+/// Apply wrapping per-channel deltas and pack back into RGB565.
+fn join(r: u8, dr: i8, g: u8, dg: i8, b: u8, db: i8) -> u16 {
+    let r = r.wrapping_add_signed(dr) & 0x1F;
+    let g = g.wrapping_add_signed(dg) & 0x3F;
+    let b = b.wrapping_add_signed(db) & 0x1F;
+    u16::from(r) << 11 | u16::from(g) << 5 | u16::from(b)
+}
+
+fn hash(px: u16) -> usize {
+    let (r, g, b) = split(px);
+    (r as usize * 3 + g as usize * 5 + b as usize * 7) % 64
+}
+
+/// CRC-32 (IEEE 802.3, as used by zlib and PNG).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// [`crc32`] over data that arrives in pieces.
+#[derive(Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= u32::from(byte);
+            for _ in 0..8 {
+                self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & (self.0 & 1).wrapping_neg());
+            }
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Animations
+// ---------------------------------------------------------------------------
+
+/// Most frames kept from an uploaded animation.
+pub const MAX_ANIMATION_FRAMES: usize = 60;
+
+/// Largest animation container, in bytes (on flash and in RAM while it
+/// plays).
+pub const MAX_ANIMATION_BYTES: usize = 1536 * 1024;
+
+/// Frame rate cap: shorter frame delays are stretched to this.
+pub const MIN_FRAME_MS: u16 = 1000 / 15;
+
+/// Whether `data` is an animation container rather than a single image.
+pub fn is_animation(data: &[u8]) -> bool {
+    data.starts_with(ANIM_MAGIC)
+}
+
+/// Builds an animation container one RGB888 frame at a time.
+pub struct AnimationWriter {
+    out: Vec<u8>,
+    width: u16,
+    height: u16,
+    frames: u16,
+}
+
+impl AnimationWriter {
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut out = Vec::with_capacity(64 * 1024);
+        out.extend_from_slice(ANIM_MAGIC);
+        out.push(ANIM_VERSION);
+        out.push(0);
+        out.extend_from_slice(&[0, 0]); // frame count, filled in by `finish`
+        out.extend_from_slice(&width.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        Self {
+            out,
+            width,
+            height,
+            frames: 0,
+        }
+    }
+
+    /// Encode and append a frame shown for `delay_ms`.
+    ///
+    /// Panics if `rgb` is not `width * height * 3` bytes.
+    pub fn push_frame(&mut self, rgb: &[u8], delay_ms: u16) {
+        let image = encode_rgb888(rgb, self.width, self.height);
+        self.out.extend_from_slice(&delay_ms.to_le_bytes());
+        self.out
+            .extend_from_slice(&(image.len() as u32).to_le_bytes());
+        self.out.extend_from_slice(&image);
+        self.frames += 1;
+    }
+
+    pub fn frame_count(&self) -> u16 {
+        self.frames
+    }
+
+    /// Container size so far, in bytes.
+    pub fn encoded_len(&self) -> usize {
+        self.out.len()
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.out[6..8].copy_from_slice(&self.frames.to_le_bytes());
+        self.out
+    }
+}
+
+/// A parsed animation container. Frames are only indexed here; each one is
+/// decoded (and its CRC checked) on demand.
+pub struct Animation {
+    data: Vec<u8>,
+    pub width: u16,
+    pub height: u16,
+    frames: FrameIndex,
+}
+
+/// Byte range of each frame's image in an animation container, and its
+/// delay in ms.
+type FrameIndex = Vec<(Range<usize>, u16)>;
+
+impl Animation {
+    /// Index the frames of `data`, checking the container structure and
+    /// every frame header against the container size.
+    pub fn parse(data: Vec<u8>) -> Result<Self, CorruptImage> {
+        let ((width, height), frames) = index_frames(&data)?;
+        Ok(Self {
+            data,
+            width,
+            height,
+            frames,
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// How long frame `i` stays on screen, as stored.
+    pub fn delay_ms(&self, i: usize) -> u16 {
+        self.frames[i].1
+    }
+
+    /// Container size in bytes.
+    pub fn encoded_len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Decode frame `i` to RGB888.
+    pub fn decode_frame(&self, i: usize) -> Result<Vec<u8>, CorruptImage> {
+        let (range, _) = &self.frames[i];
+        decode_rgb888(&self.data[range.clone()]).map(|(_, rgb)| rgb)
+    }
+
+    /// The container bytes, e.g. to save to flash.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Size and frame index of an animation container: each frame's image
+/// range and delay. Checks the structure and every frame header against
+/// the container size, not the frame CRCs.
+fn index_frames(data: &[u8]) -> Result<((u16, u16), FrameIndex), CorruptImage> {
+    if data.len() < ANIM_HEADER_LEN || !is_animation(data) {
+        return Err(CorruptImage("Not a VBAN animation".into()));
+    }
+    if data[4] != ANIM_VERSION {
+        return Err(CorruptImage(format!(
+            "Unsupported animation version {}",
+            data[4]
+        )));
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let count = u16_at(6) as usize;
+    let (width, height) = (u16_at(8), u16_at(10));
+    if count == 0 {
+        return Err(CorruptImage("Animation has no frames".into()));
+    }
+
+    let mut frames = Vec::with_capacity(count);
+    let mut pos = ANIM_HEADER_LEN;
+    for i in 0..count {
+        let prefix = data
+            .get(pos..pos + ANIM_FRAME_PREFIX)
+            .ok_or_else(|| CorruptImage(format!("Animation ends before frame {i}")))?;
+        let delay = u16::from_le_bytes([prefix[0], prefix[1]]);
+        let len = u32::from_le_bytes([prefix[2], prefix[3], prefix[4], prefix[5]]) as usize;
+        let start = pos + ANIM_FRAME_PREFIX;
+        let image = data
+            .get(start..start + len)
+            .ok_or_else(|| CorruptImage(format!("Animation frame {i} is truncated")))?;
+        let header = read_header(image)?;
+        if (header.width, header.height) != (width, height) {
+            return Err(CorruptImage(format!(
+                "Animation frame {i} is {}x{}, expected {width}x{height}",
+                header.width, header.height
+            )));
+        }
+        frames.push((start..start + len, delay));
+        pos = start + len;
+    }
+    if pos != data.len() {
+        return Err(CorruptImage("Animation has trailing bytes".into()));
+    }
+    Ok(((width, height), frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic noise: each pixel unrelated to the last.
+    fn noise(pixels: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491u32;
+        (0..pixels * 3)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Flat areas, long runs, gentle gradients and hard edges, so every op
+    /// shows up.
+    fn scene(width: u16, height: u16) -> Vec<u8> {
+        let mut rgb = Vec::new();
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let px = match (x / 16 + y / 16) % 4 {
+                    0 => [0x20, 0x40, 0x80],
+                    1 => [(x * 4) as u8, (y * 2) as u8, 0x10],
+                    2 => [0xFF, 0xFF, 0xFF],
+                    _ => [(x * 37) as u8, (y * 91) as u8, ((x + y) * 53) as u8],
+                };
+                rgb.extend_from_slice(&px);
+            }
+        }
+        rgb
+    }
+
+    /// What the panel can show of `rgb`: RGB565 expanded back to RGB888.
+    fn quantized(rgb: &[u8]) -> Vec<u8> {
+        rgb.chunks_exact(3)
+            .flat_map(|c| to_rgb888(to_rgb565(c[0], c[1], c[2])))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_keeps_every_rgb565_pixel() {
+        for (width, height, rgb) in [
+            (100, 70, scene(100, 70)),
+            (64, 48, noise(64 * 48)),
+            (200, 1, vec![0x55; 600]),
+            (1, 1, vec![0xFF, 0, 0x80]),
+        ] {
+            let encoded = encode_rgb888(&rgb, width, height);
+            let (header, decoded) = decode_rgb888(&encoded).unwrap();
+            assert_eq!((header.width, header.height), (width, height));
+            assert_eq!(decoded, quantized(&rgb), "{width}x{height}");
+            // Decoded pixels are exact RGB565, so re-encoding is lossless.
+            assert_eq!(encode_rgb888(&decoded, width, height), encoded);
+        }
+    }
+
+    #[test]
+    fn worst_case_bound_holds() {
+        let (width, height) = (240, 300);
+        let pixels = width as usize * height as usize;
+        let noisy = encode_rgb888(&noise(pixels), width, height);
+        assert!(noisy.len() <= max_encoded_len(width, height));
+
+        // Every pixel a big jump away from the last and from the cache.
+        let jumps: Vec<u8> = (0..pixels)
+            .flat_map(|i| {
+                let v = (i * 157 % 251) as u8;
+                [v, v.wrapping_add(128), !v]
+            })
+            .collect();
+        let jumpy = encode_rgb888(&jumps, width, height);
+        assert!(jumpy.len() <= max_encoded_len(width, height));
+
+        let flat = encode_rgb888(&vec![0x80; pixels * 3], width, height);
+        assert!(flat.len() < HEADER_LEN + pixels / 60);
+    }
+
+    #[test]
+    fn corrupt_images_are_rejected() {
+        let encoded = encode_rgb888(&scene(32, 32), 32, 32);
+        assert!(verify(&encoded).is_ok());
+
+        let mut flipped = encoded.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0x01;
+        assert!(decode_rgb888(&flipped).is_err());
+        assert!(verify(&flipped).is_err());
+
+        assert!(decode_rgb888(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_rgb888(&encoded[..HEADER_LEN - 1]).is_err());
+
+        let mut magic = encoded.clone();
+        magic[0] = b'X';
+        assert!(decode_rgb888(&magic).is_err());
+    }
+
+    #[test]
+    fn animation_round_trip() {
+        let (width, height) = (24, 30);
+        let frames = [scene(width, height), noise(24 * 30), vec![0; 24 * 30 * 3]];
+        let mut writer = AnimationWriter::new(width, height);
+        for (i, rgb) in frames.iter().enumerate() {
+            writer.push_frame(rgb, 100 + i as u16);
+        }
+        let data = writer.finish();
+        assert!(is_animation(&data));
+        assert!(verify(&data).is_ok());
+
+        let anim = Animation::parse(data).unwrap();
+        assert_eq!((anim.width, anim.height), (width, height));
+        assert_eq!(anim.frame_count(), frames.len());
+        for (i, rgb) in frames.iter().enumerate() {
+            assert_eq!(anim.delay_ms(i), 100 + i as u16);
+            assert_eq!(anim.decode_frame(i).unwrap(), quantized(rgb));
+        }
+    }
+
+    #[test]
+    fn damaged_animations_are_caught() {
+        let mut writer = AnimationWriter::new(8, 8);
+        writer.push_frame(&scene(8, 8), 100);
+        writer.push_frame(&noise(64), 100);
+        let data = writer.finish();
+
+        // A garbled pixel in the last frame: the structure still parses, the
+        // CRC does not match.
+        let mut garbled = data.clone();
+        let last = garbled.len() - 1;
+        garbled[last] ^= 0xFF;
+        assert!(Animation::parse(garbled.clone()).is_ok());
+        assert!(verify(&garbled).is_err());
+
+        assert!(Animation::parse(data[..data.len() - 1].to_vec()).is_err());
+        let mut trailing = data;
+        trailing.push(0);
+        assert!(Animation::parse(trailing).is_err());
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,707 @@
+//! PNG, JPEG and GIF decoding for avatar/background uploads.
+//!
+//! Uploads are decoded on the badge, scaled to cover the target size and
+//! centre-cropped, so clients can send a photo as-is instead of converting
+//! it to raw RGB888 first. The body is read straight from the request as the
+//! decoder asks for it; only the decoder state and the output live on the
+//! heap (PSRAM, via `CONFIG_SPIRAM_USE_MALLOC`), never the handler's stack.
+//!
+//! - PNG: rows are streamed through [`CoverResampler`] one at a time, so
+//!   memory stays at a few rows however large the source is. Interlaced
+//!   PNGs need the whole frame and are limited to [`MAX_DECODE_BYTES`].
+//! - JPEG: baseline only. The decoder's DCT scaling (1/8 … 1) brings the
+//!   image close to the target size before the full frame is allocated, and
+//!   the resampler does the rest.
+//! - GIF: frames are composited on a canvas of the GIF's own size (honouring
+//!   disposal), then each is resampled and encoded into an
+//!   [`Animation`] straight away, so only the canvas and the
+//!   compressed frames are held. A single-frame GIF is a still image.
+
+use std::fmt;
+use std::io::{BufReader, Read};
+use std::num::NonZeroU64;
+
+use crate::image_codec::{
+    Animation, AnimationWriter, MAX_ANIMATION_BYTES, MAX_ANIMATION_FRAMES, MIN_FRAME_MS,
+};
+
+/// Largest encoded body accepted, in bytes.
+#[cfg(not(feature = "simulator"))]
+pub const MAX_ENCODED_UPLOAD: usize = 4 * 1024 * 1024;
+
+/// Heap the decoders may use for frame buffers.
+const MAX_DECODE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Body format, chosen from the request's `Content-Type`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadFormat {
+    /// Raw RGB888 at exactly the target size (the original format).
+    Raw,
+    Png,
+    Jpeg,
+    Gif,
+}
+
+/// A decoded upload at the target size.
+pub enum DecodedImage {
+    /// RGB888 pixels.
+    Still(Vec<u8>),
+    /// Several frames, encoded for storage.
+    Animated(Animation),
+}
+
+impl UploadFormat {
+    /// `image/png`, `image/jpeg` and `image/gif` select a decoder; a missing header or
+    /// `application/octet-stream` keeps the raw path.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn from_content_type(content_type: Option<&str>) -> Result<Self, UploadError> {
+        let mime = content_type
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        match mime.as_str() {
+            "" | "application/octet-stream" => Ok(Self::Raw),
+            "image/png" => Ok(Self::Png),
+            "image/jpeg" | "image/jpg" => Ok(Self::Jpeg),
+            "image/gif" => Ok(Self::Gif),
+            other => Err(UploadError::Unsupported(format!(
+                "Unsupported Content-Type '{other}' (expected image/png, image/jpeg, image/gif or application/octet-stream)"
+            ))),
+        }
+    }
+}
+
+/// Why an upload was refused.
+#[derive(Debug)]
+pub enum UploadError {
+    /// Content type or image flavour the badge can't decode.
+    Unsupported(String),
+    /// Body or decoded image over the size limits.
+    TooLarge(String),
+    /// Truncated or malformed data.
+    Invalid(String),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(msg) | Self::TooLarge(msg) | Self::Invalid(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+/// Decode a PNG, JPEG or GIF from `reader` at `width` x `height`, scaled to
+/// cover and centre-cropped. With `animated` false only a GIF's first frame
+/// is used.
+pub fn decode_upload(
+    format: UploadFormat,
+    reader: impl Read,
+    width: u32,
+    height: u32,
+    animated: bool,
+) -> Result<DecodedImage, UploadError> {
+    match format {
+        UploadFormat::Png => decode_png(reader, width, height).map(DecodedImage::Still),
+        UploadFormat::Jpeg => decode_jpeg(reader, width, height).map(DecodedImage::Still),
+        UploadFormat::Gif => decode_gif(reader, width, height, animated),
+        UploadFormat::Raw => Err(UploadError::Unsupported(
+            "Raw RGB888 bodies are not decoded".into(),
+        )),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// PNG
+// ---------------------------------------------------------------------------
+
+fn decode_png(reader: impl Read, width: u32, height: u32) -> Result<Vec<u8>, UploadError> {
+    let mut decoder = png::Decoder::new_with_limits(
+        reader,
+        png::Limits {
+            bytes: MAX_DECODE_BYTES,
+        },
+    );
+    // Expand palettes and low bit depths, strip 16-bit to 8.
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut png = decoder.read_info().map_err(png_error)?;
+
+    let info = png.info();
+    let (src_w, src_h) = (info.width, info.height);
+    let interlaced = info.interlaced;
+    let (color, _) = png.output_color_type();
+    let channels = match color {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => {
+            return Err(UploadError::Unsupported(
+                "Unexpected indexed PNG output".into(),
+            ))
+        }
+    };
+    let mut resampler = CoverResampler::new(src_w, src_h, width, height)?;
+    let mut rgb_row = vec![0u8; src_w as usize * 3];
+
+    if interlaced {
+        // Adam7 passes arrive out of row order; decode the whole frame.
+        let size = png.output_buffer_size();
+        if size > MAX_DECODE_BYTES {
+            return Err(UploadError::TooLarge(format!(
+                "Interlaced PNG of {src_w}x{src_h} is too large; save it non-interlaced"
+            )));
+        }
+        let mut frame = vec![0u8; size];
+        let out = png.next_frame(&mut frame).map_err(png_error)?;
+        for row in frame.chunks_exact(out.line_size).take(src_h as usize) {
+            to_rgb(row, channels, &mut rgb_row);
+            resampler.push_row(&rgb_row);
+        }
+    } else {
+        while let Some(row) = png.next_row().map_err(png_error)? {
+            to_rgb(row.data(), channels, &mut rgb_row);
+            resampler.push_row(&rgb_row);
+        }
+    }
+    resampler.finish()
+}
+
+fn png_error(e: png::DecodingError) -> UploadError {
+    match e {
+        png::DecodingError::LimitsExceeded => {
+            UploadError::TooLarge("PNG needs more memory than the badge allows".into())
+        }
+        png::DecodingError::Parameter(_) => UploadError::Unsupported(format!("PNG: {e}")),
+        e => UploadError::Invalid(format!("PNG: {e}")),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// JPEG
+// ---------------------------------------------------------------------------
+
+fn decode_jpeg(reader: impl Read, width: u32, height: u32) -> Result<Vec<u8>, UploadError> {
+    // The decoder reads a byte at a time; buffer the body reads.
+    let mut decoder = Box::new(jpeg_decoder::Decoder::new(BufReader::new(reader)));
+    decoder.read_info().map_err(jpeg_error)?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| UploadError::Invalid("JPEG: missing frame header".into()))?;
+    if info.coding_process != jpeg_decoder::CodingProcess::DctSequential {
+        // Progressive frames keep every coefficient of the full-size image.
+        return Err(UploadError::Unsupported(
+            "Only baseline JPEGs are supported; re-save without progressive encoding".into(),
+        ));
+    }
+    let channels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => 1,
+        jpeg_decoder::PixelFormat::RGB24 => 3,
+        other => {
+            return Err(UploadError::Unsupported(format!(
+                "Unsupported JPEG pixel format {other:?}"
+            )))
+        }
+    };
+
+    // Ask for the source scaled so its crop just covers the target; the
+    // decoder rounds up to the next of 1/8, 1/4, 1/2 or 1.
+    let (crop_w, crop_h) = cover_crop(u32::from(info.width), u32::from(info.height), width, height);
+    let want_w = u32::from(info.width) * width / crop_w;
+    let want_h = u32::from(info.height) * height / crop_h;
+    let (src_w, src_h) = decoder
+        .scale(
+            want_w.clamp(1, u16::MAX.into()) as u16,
+            want_h.clamp(1, u16::MAX.into()) as u16,
+        )
+        .map_err(jpeg_error)?;
+    // Checked up front: the decoder only enforces its own limit after the
+    // component planes are allocated.
+    if usize::from(src_w) * usize::from(src_h) * channels > MAX_DECODE_BYTES {
+        return Err(UploadError::TooLarge(format!(
+            "JPEG of {}x{} is too large to decode",
+            info.width, info.height
+        )));
+    }
+    decoder.set_max_decoding_buffer_size(MAX_DECODE_BYTES);
+    let pixels = decoder.decode().map_err(jpeg_error)?;
+
+    let mut resampler = CoverResampler::new(src_w.into(), src_h.into(), width, height)?;
+    let mut rgb_row = vec![0u8; src_w as usize * 3];
+    for row in pixels.chunks_exact(src_w as usize * channels) {
+        to_rgb(row, channels, &mut rgb_row);
+        resampler.push_row(&rgb_row);
+    }
+    resampler.finish()
+}
+
+fn jpeg_error(e: jpeg_decoder::Error) -> UploadError {
+    match e {
+        jpeg_decoder::Error::Unsupported(_) => UploadError::Unsupported(format!("JPEG: {e}")),
+        e => UploadError::Invalid(format!("JPEG: {e}")),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// GIF
+// ---------------------------------------------------------------------------
+
+fn decode_gif(
+    reader: impl Read,
+    width: u32,
+    height: u32,
+    animated: bool,
+) -> Result<DecodedImage, UploadError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    if let Some(limit) = NonZeroU64::new(MAX_DECODE_BYTES as u64) {
+        options.set_memory_limit(gif::MemoryLimit::Bytes(limit));
+    }
+    let mut decoder = options
+        .read_info(BufReader::new(reader))
+        .map_err(gif_error)?;
+    let (src_w, src_h) = (usize::from(decoder.width()), usize::from(decoder.height()));
+    // The canvas, plus a copy for frames disposed to "previous".
+    if src_w * src_h * 4 * 2 > MAX_DECODE_BYTES {
+        return Err(UploadError::TooLarge(format!(
+            "GIF of {src_w}x{src_h} is too large to decode"
+        )));
+    }
+
+    let mut canvas = vec![0u8; src_w * src_h * 4];
+    let mut writer = AnimationWriter::new(width as u16, height as u16);
+    let mut last_rgb = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(gif_error)? {
+        if writer.frame_count() as usize == MAX_ANIMATION_FRAMES {
+            return Err(UploadError::TooLarge(format!(
+                "Animation has more than {MAX_ANIMATION_FRAMES} frames"
+            )));
+        }
+        let saved = (frame.dispose == gif::DisposalMethod::Previous).then(|| canvas.clone());
+        // Frames may hang off the canvas; clip them to it.
+        let (left, top) = (usize::from(frame.left).min(src_w), usize::from(frame.top));
+        let (frame_w, frame_h) = (usize::from(frame.width), usize::from(frame.height));
+        let rows = frame.buffer.chunks_exact(frame_w * 4).take(frame_h);
+        for (y, row) in (top..src_h).zip(rows) {
+            let start = (y * src_w + left) * 4;
+            let end = (y * src_w + (left + frame_w).min(src_w)) * 4;
+            for (dst, px) in canvas[start..end]
+                .chunks_exact_mut(4)
+                .zip(row.chunks_exact(4))
+            {
+                // Transparent pixels let the canvas show through.
+                if px[3] != 0 {
+                    dst.copy_from_slice(px);
+                }
+            }
+        }
+
+        let mut resampler = CoverResampler::new(src_w as u32, src_h as u32, width, height)?;
+        let mut rgb_row = vec![0u8; src_w * 3];
+        for row in canvas.chunks_exact(src_w * 4) {
+            to_rgb(row, 4, &mut rgb_row);
+            resampler.push_row(&rgb_row);
+        }
+        last_rgb = resampler.finish()?;
+        if !animated {
+            return Ok(DecodedImage::Still(last_rgb));
+        }
+        writer.push_frame(&last_rgb, gif_delay_ms(frame.delay));
+        if writer.encoded_len() > MAX_ANIMATION_BYTES {
+            return Err(UploadError::TooLarge(format!(
+                "Animation is over {} KB once encoded; use fewer frames",
+                MAX_ANIMATION_BYTES / 1024
+            )));
+        }
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..(top + frame_h).min(src_h) {
+                    let start = (y * src_w + left) * 4;
+                    let end = (y * src_w + (left + frame_w).min(src_w)) * 4;
+                    canvas[start..end].fill(0);
+                }
+            }
+            gif::DisposalMethod::Previous => {
+                if let Some(saved) = saved {
+                    canvas = saved;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match writer.frame_count() {
+        0 => Err(UploadError::Invalid("GIF has no frames".into())),
+        1 => Ok(DecodedImage::Still(last_rgb)),
+        _ => Animation::parse(writer.finish())
+            .map(DecodedImage::Animated)
+            .map_err(|e| UploadError::Invalid(e.to_string())),
+    }
+}
+
+/// Frame delay in ms from GIF centiseconds. Like browsers, near-zero delays
+/// mean 100 ms; anything faster than the frame rate cap is slowed to it.
+fn gif_delay_ms(centis: u16) -> u16 {
+    let ms = match centis {
+        0 | 1 => 100,
+        c => c.saturating_mul(10),
+    };
+    ms.max(MIN_FRAME_MS)
+}
+
+fn gif_error(e: gif::DecodingError) -> UploadError {
+    match e {
+        gif::DecodingError::Io(ref io) if io.kind() == std::io::ErrorKind::OutOfMemory => {
+            UploadError::TooLarge("GIF frame needs more memory than the badge allows".into())
+        }
+        e => UploadError::Invalid(format!("GIF: {e}")),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Resampling
+// ---------------------------------------------------------------------------
+
+/// Convert one row of gray, gray+alpha, RGB or RGBA pixels to RGB888.
+/// Alpha is composited onto black, the badge's background colour.
+fn to_rgb(row: &[u8], channels: usize, out: &mut [u8]) {
+    for (px, rgb) in row.chunks_exact(channels).zip(out.chunks_exact_mut(3)) {
+        let (r, g, b, a) = match *px {
+            [l] => (l, l, l, 255),
+            [l, a] => (l, l, l, a),
+            [r, g, b] => (r, g, b, 255),
+            [r, g, b, a, ..] => (r, g, b, a),
+            _ => (0, 0, 0, 0),
+        };
+        let blend = |c: u8| (u16::from(c) * u16::from(a) / 255) as u8;
+        rgb.copy_from_slice(&[blend(r), blend(g), blend(b)]);
+    }
+}
+
+/// Size of the centred source region with the target's aspect ratio.
+fn cover_crop(src_w: u32, src_h: u32, dst_w: u32, dst_h: u32) -> (u32, u32) {
+    let (dst_w, dst_h) = (u64::from(dst_w), u64::from(dst_h));
+    if u64::from(src_w) * dst_h > u64::from(src_h) * dst_w {
+        // Wider than the target: full height, crop the sides.
+        let w = (u64::from(src_h) * dst_w + dst_h / 2) / dst_h;
+        ((w as u32).clamp(1, src_w), src_h)
+    } else {
+        let h = (u64::from(src_w) * dst_h + dst_w / 2) / dst_w;
+        (src_w, (h as u32).clamp(1, src_h))
+    }
+}
+
+/// Source span `[start, end)` feeding output index `i` of `dst` along an
+/// axis whose crop starts at `offset` and is `crop` long.
+fn span(i: u32, dst: u32, offset: u32, crop: u32) -> (u32, u32) {
+    let start = offset + (u64::from(i) * u64::from(crop) / u64::from(dst)) as u32;
+    let end = offset + (u64::from(i + 1) * u64::from(crop) / u64::from(dst)) as u32;
+    (start, end.max(start + 1))
+}
+
+/// Box-filter resampler fed one RGB888 source row at a time, top to bottom.
+///
+/// Scales the source to cover `dst_w` x `dst_h` and crops the centre. Each
+/// output pixel averages the source pixels it covers (downscaling) or
+/// repeats the nearest one (upscaling). Only one row of sums is kept.
+struct CoverResampler {
+    src_w: u32,
+    dst_h: u32,
+    crop_y: u32,
+    crop_h: u32,
+    /// Source column span per output column.
+    columns: Vec<(u32, u32)>,
+    /// Next source row expected by `push_row`.
+    src_row: u32,
+    /// Output row being accumulated.
+    dst_row: u32,
+    /// Per-channel sums of the current output row, and rows summed.
+    sums: Vec<u32>,
+    rows_summed: u32,
+    out: Vec<u8>,
+}
+
+impl CoverResampler {
+    fn new(src_w: u32, src_h: u32, dst_w: u32, dst_h: u32) -> Result<Self, UploadError> {
+        if src_w == 0 || src_h == 0 {
+            return Err(UploadError::Invalid("Image has no pixels".into()));
+        }
+        let (crop_w, crop_h) = cover_crop(src_w, src_h, dst_w, dst_h);
+        let crop_x = (src_w - crop_w) / 2;
+        Ok(Self {
+            src_w,
+            dst_h,
+            crop_y: (src_h - crop_h) / 2,
+            crop_h,
+            columns: (0..dst_w).map(|x| span(x, dst_w, crop_x, crop_w)).collect(),
+            src_row: 0,
+            dst_row: 0,
+            sums: vec![0; dst_w as usize * 3],
+            rows_summed: 0,
+            out: Vec::with_capacity(dst_w as usize * dst_h as usize * 3),
+        })
+    }
+
+    /// Add the next source row (`src_w * 3` bytes).
+    fn push_row(&mut self, row: &[u8]) {
+        debug_assert_eq!(row.len(), self.src_w as usize * 3);
+        let y = self.src_row;
+        self.src_row += 1;
+
+        while self.dst_row < self.dst_h {
+            let (start, end) = span(self.dst_row, self.dst_h, self.crop_y, self.crop_h);
+            if y < start {
+                return;
+            }
+            // Spans only overlap when upscaling, where each is one row long;
+            // a row past the current span can't happen as rows come in order.
+            for (sum, &(x0, x1)) in self.sums.chunks_exact_mut(3).zip(&self.columns) {
+                let pixels = &row[x0 as usize * 3..x1 as usize * 3];
+                let n = x1 - x0;
+                for (c, s) in sum.iter_mut().enumerate() {
+                    let total: u32 = pixels
+                        .iter()
+                        .skip(c)
+                        .step_by(3)
+                        .map(|&v| u32::from(v))
+                        .sum();
+                    *s += (total + n / 2) / n;
+                }
+            }
+            self.rows_summed += 1;
+            if y + 1 < end {
+                return;
+            }
+            let n = self.rows_summed;
+            self.out
+                .extend(self.sums.iter().map(|&s| ((s + n / 2) / n) as u8));
+            self.sums.fill(0);
+            self.rows_summed = 0;
+            self.dst_row += 1;
+        }
+    }
+
+    /// The finished image, or an error if the source ended early.
+    fn finish(self) -> Result<Vec<u8>, UploadError> {
+        if self.dst_row < self.dst_h {
+            return Err(UploadError::Invalid(format!(
+                "Image ended after {} rows",
+                self.src_row
+            )));
+        }
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    const W: u32 = 240;
+    const H: u32 = 300;
+
+    fn decode(
+        format: UploadFormat,
+        data: &[u8],
+        animated: bool,
+    ) -> Result<DecodedImage, UploadError> {
+        decode_upload(format, data, W, H, animated)
+    }
+
+    fn still(image: Result<DecodedImage, UploadError>) -> Vec<u8> {
+        match image {
+            Ok(DecodedImage::Still(rgb)) => rgb,
+            Ok(DecodedImage::Animated(_)) => panic!("expected a still image"),
+            Err(e) => panic!("decode failed: {e}"),
+        }
+    }
+
+    /// RGB PNG, `left` colour on the left half and `right` on the right.
+    fn two_tone_png(width: u32, height: u32, left: [u8; 3], right: [u8; 3]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        let row: Vec<u8> = (0..width)
+            .flat_map(|x| if x < width / 2 { left } else { right })
+            .collect();
+        writer
+            .write_image_data(&row.repeat(height as usize))
+            .unwrap();
+        writer.finish().unwrap();
+        out
+    }
+
+    /// Baseline grayscale JPEG of one flat `level`: every 8x8 block holds
+    /// only its DC coefficient, so a few bits per block cover any size.
+    fn flat_jpeg(width: u16, height: u16, level: u8) -> Vec<u8> {
+        let dc = 8 * (i32::from(level) - 128);
+        let category = 32 - dc.unsigned_abs().leading_zeros();
+        let mut out = vec![0xFF, 0xD8];
+        // Quantization table of ones
+        out.extend([0xFF, 0xDB, 0, 67, 0]);
+        out.extend([1; 64]);
+        // Frame: 8-bit, one component, no subsampling
+        out.extend([0xFF, 0xC0, 0, 11, 8]);
+        out.extend(height.to_be_bytes());
+        out.extend(width.to_be_bytes());
+        out.extend([1, 1, 0x11, 0]);
+        // DC: "00" is category 0, "01" is `category`; AC: "0" ends the block
+        out.extend([0xFF, 0xC4, 0, 21, 0x00, 0, 2]);
+        out.extend([0; 14]);
+        out.extend([0, category as u8]);
+        out.extend([0xFF, 0xC4, 0, 20, 0x10, 1]);
+        out.extend([0; 15]);
+        out.push(0);
+        out.extend([0xFF, 0xDA, 0, 8, 1, 1, 0x00, 0, 63, 0]);
+
+        let mut bits = Vec::new();
+        let blocks = usize::from(width.div_ceil(8)) * usize::from(height.div_ceil(8));
+        for block in 0..blocks {
+            if block == 0 && category > 0 {
+                bits.extend([false, true]);
+                let value = if dc < 0 { dc - 1 } else { dc };
+                bits.extend((0..category).rev().map(|i| value >> i & 1 == 1));
+            } else {
+                bits.extend([false, false]);
+            }
+            bits.push(false);
+        }
+        bits.resize(bits.len().div_ceil(8) * 8, true);
+        for byte in bits.chunks_exact(8) {
+            let byte = byte.iter().fold(0u8, |b, &bit| b << 1 | u8::from(bit));
+            out.push(byte);
+            if byte == 0xFF {
+                out.push(0);
+            }
+        }
+        out.extend([0xFF, 0xD9]);
+        out
+    }
+
+    /// GIF of `frames` flat frames, alternating black and white.
+    fn gif(width: u16, height: u16, frames: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        {
+            let palette = [0, 0, 0, 255, 255, 255];
+            let mut encoder = gif::Encoder::new(&mut out, width, height, &palette).unwrap();
+            for i in 0..frames {
+                let pixels = vec![(i % 2) as u8; usize::from(width) * usize::from(height)];
+                let frame = gif::Frame {
+                    width,
+                    height,
+                    delay: 10,
+                    buffer: Cow::Owned(pixels),
+                    ..gif::Frame::default()
+                };
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn content_types_select_the_decoder() {
+        let format = |ct| UploadFormat::from_content_type(ct).unwrap();
+        assert_eq!(format(None), UploadFormat::Raw);
+        assert_eq!(format(Some("image/PNG")), UploadFormat::Png);
+        assert_eq!(
+            format(Some("image/jpeg; charset=binary")),
+            UploadFormat::Jpeg
+        );
+        assert_eq!(format(Some("image/gif")), UploadFormat::Gif);
+        assert!(matches!(
+            UploadFormat::from_content_type(Some("image/webp")),
+            Err(UploadError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn wide_png_is_scaled_and_centre_cropped() {
+        // 2:1 source into a 4:5 target: the middle 40% of the width is kept
+        let png = two_tone_png(600, 300, [255, 0, 0], [0, 0, 255]);
+        let rgb = still(decode(UploadFormat::Png, &png, false));
+        assert_eq!(rgb.len(), (W * H * 3) as usize);
+        let pixel = |x: u32, y: u32| {
+            let i = ((y * W + x) * 3) as usize;
+            [rgb[i], rgb[i + 1], rgb[i + 2]]
+        };
+        assert_eq!(pixel(0, 0), [255, 0, 0]);
+        assert_eq!(pixel(W / 2 - 2, H - 1), [255, 0, 0]);
+        assert_eq!(pixel(W / 2 + 1, 0), [0, 0, 255]);
+        assert_eq!(pixel(W - 1, H - 1), [0, 0, 255]);
+    }
+
+    #[test]
+    fn truncated_png_is_invalid() {
+        let png = two_tone_png(300, 400, [255, 0, 0], [0, 0, 255]);
+        for len in [4, 40, png.len() / 2, png.len() - 12] {
+            assert!(
+                matches!(
+                    decode(UploadFormat::Png, &png[..len], false),
+                    Err(UploadError::Invalid(_))
+                ),
+                "{len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn large_jpeg_is_scaled_down_while_decoding() {
+        // 4.3 MB of pixels at full size, over the decoder's memory limit
+        let jpeg = flat_jpeg(2400, 1800, 200);
+        let rgb = still(decode(UploadFormat::Jpeg, &jpeg, false));
+        assert_eq!(rgb.len(), (W * H * 3) as usize);
+        assert!(rgb.iter().all(|&c| c.abs_diff(200) <= 1), "{:?}", &rgb[..6]);
+    }
+
+    #[test]
+    fn truncated_jpeg_is_invalid() {
+        let jpeg = flat_jpeg(320, 240, 60);
+        assert!(matches!(
+            decode(UploadFormat::Jpeg, &jpeg[..100], false),
+            Err(UploadError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn gif_frames_become_an_animation() {
+        let data = gif(60, 75, 3);
+        let Ok(DecodedImage::Animated(anim)) = decode(UploadFormat::Gif, &data, true) else {
+            panic!("expected an animation");
+        };
+        assert_eq!(anim.frame_count(), 3);
+
+        // A background only takes the first frame
+        let rgb = still(decode(UploadFormat::Gif, &data, false));
+        assert_eq!(rgb.len(), (W * H * 3) as usize);
+        assert!(rgb.iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn oversize_gifs_are_refused() {
+        let too_many_frames = gif(8, 10, MAX_ANIMATION_FRAMES + 1);
+        assert!(matches!(
+            decode(UploadFormat::Gif, &too_many_frames, true),
+            Err(UploadError::TooLarge(_))
+        ));
+        let too_wide = gif(2000, 600, 1);
+        assert!(matches!(
+            decode(UploadFormat::Gif, &too_wide, false),
+            Err(UploadError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn raw_bodies_are_not_decoded() {
+        assert!(matches!(
+            decode(UploadFormat::Raw, &[0; 16], false),
+            Err(UploadError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,298 @@
+//! Runtime log level filter with per-target overrides.
+//!
+//! A filter is written as a comma-separated spec, the same shape as
+//! `RUST_LOG`: a bare level sets the default, `target=level` overrides it
+//! for one module and everything below it.
+//!
+//! ```text
+//! info,touch=debug,web::api=trace
+//! ```
+//!
+//! Targets are module paths with the `vrcbadge_firmware::` prefix stripped
+//! (the same names shown on the Logs page); other crates use their full
+//! path, e.g. `esp_idf_svc::http`. The spec is what NVS stores and what
+//! `/api/log-levels` exchanges.
+
+use std::fmt;
+use std::str::FromStr;
+
+use log::LevelFilter;
+
+/// Targets offered on the Settings → Logs page. Any other module can still
+/// be set through the HTTP API.
+pub const UI_TARGETS: &[&str] = &["touch", "wifi", "web::api", "dns"];
+
+/// Limits that keep a spec well inside one NVS string entry.
+const MAX_OVERRIDES: usize = 16;
+const MAX_TARGET_LEN: usize = 48;
+
+/// Default level plus per-target overrides.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    /// Sorted by target, no duplicates.
+    overrides: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    /// `Info` everywhere, matching the logger's behaviour before overrides.
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl LogFilter {
+    /// Level applied to targets without an override.
+    pub fn default_level(&self) -> LevelFilter {
+        self.default
+    }
+
+    /// Per-target overrides, sorted by target.
+    #[cfg(not(feature = "simulator"))]
+    pub fn overrides(&self) -> &[(String, LevelFilter)] {
+        &self.overrides
+    }
+
+    /// Effective level for `target`: the longest override that equals it or
+    /// is a parent module of it, otherwise the default.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.overrides
+            .iter()
+            .filter(|(t, _)| target_matches(t, target))
+            .max_by_key(|(t, _)| t.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Whether a record at `level` from `target` passes.
+    pub fn enabled(&self, target: &str, level: log::Level) -> bool {
+        level <= self.level_for(target)
+    }
+
+    /// Most verbose level any target can reach, for `log::set_max_level`.
+    pub fn max_level(&self) -> LevelFilter {
+        self.overrides
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+
+    /// Set the level for `target` (`""` sets the default). An override equal
+    /// to what the target would inherit anyway is dropped, so the spec stays
+    /// minimal.
+    pub fn set(&mut self, target: &str, level: LevelFilter) -> Result<(), String> {
+        if target.is_empty() {
+            self.default = level;
+            return Ok(());
+        }
+        validate_target(target)?;
+        let inherited = self.inherited_level(target);
+        match self
+            .overrides
+            .binary_search_by(|(t, _)| t.as_str().cmp(target))
+        {
+            Ok(i) if level == inherited => {
+                self.overrides.remove(i);
+            }
+            Ok(i) => self.overrides[i].1 = level,
+            Err(_) if level == inherited => {}
+            Err(i) => {
+                if self.overrides.len() >= MAX_OVERRIDES {
+                    return Err(format!("at most {MAX_OVERRIDES} target overrides"));
+                }
+                self.overrides.insert(i, (target.to_string(), level));
+            }
+        }
+        Ok(())
+    }
+
+    /// Level `target` gets from its parent modules or the default, ignoring
+    /// an override for `target` itself.
+    fn inherited_level(&self, target: &str) -> LevelFilter {
+        self.overrides
+            .iter()
+            .filter(|(t, _)| t != target && target_matches(t, target))
+            .max_by_key(|(t, _)| t.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    /// Parse a spec. Later directives win; whitespace and empty entries are
+    /// ignored, so an empty spec gives the default (`info`).
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut filter = Self::default();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let Some((target, level)) = directive.split_once('=') else {
+                filter.default = parse_level(directive)?;
+                continue;
+            };
+            let target = target.trim();
+            validate_target(target)?;
+            let level = parse_level(level.trim())?;
+            // Kept verbatim (not via `set`): an override may restate the
+            // default on purpose to shadow a parent module's override.
+            match filter
+                .overrides
+                .binary_search_by(|(t, _)| t.as_str().cmp(target))
+            {
+                Ok(i) => filter.overrides[i].1 = level,
+                Err(_) if filter.overrides.len() >= MAX_OVERRIDES => {
+                    return Err(format!("at most {MAX_OVERRIDES} target overrides"));
+                }
+                Err(i) => filter.overrides.insert(i, (target.to_string(), level)),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for LogFilter {
+    /// Canonical spec: lowercase default first, then overrides by target.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", level_name(self.default))?;
+        for (target, level) in &self.overrides {
+            write!(f, ",{target}={}", level_name(*level))?;
+        }
+        Ok(())
+    }
+}
+
+/// Next level when tapping a level chip: off → error → … → trace → off.
+pub fn next_level(level: LevelFilter) -> LevelFilter {
+    match level {
+        LevelFilter::Off => LevelFilter::Error,
+        LevelFilter::Error => LevelFilter::Warn,
+        LevelFilter::Warn => LevelFilter::Info,
+        LevelFilter::Info => LevelFilter::Debug,
+        LevelFilter::Debug => LevelFilter::Trace,
+        LevelFilter::Trace => LevelFilter::Off,
+    }
+}
+
+/// Lowercase level name as used in specs (`"debug"`).
+pub fn level_name(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off => "off",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warn",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug => "debug",
+        LevelFilter::Trace => "trace",
+    }
+}
+
+/// `pattern` matches `target` itself and its submodules (`web` matches
+/// `web::api`, not `webhook`).
+fn target_matches(pattern: &str, target: &str) -> bool {
+    target
+        .strip_prefix(pattern)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+fn parse_level(s: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(s).map_err(|_| {
+        format!("unknown level '{s}' (expected off, error, warn, info, debug or trace)")
+    })
+}
+
+fn validate_target(target: &str) -> Result<(), String> {
+    let valid_chars = target
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+    if target.is_empty() || target.len() > MAX_TARGET_LEN || !valid_chars {
+        return Err(format!("invalid log target '{target}'"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(spec: &str) -> LogFilter {
+        spec.parse().unwrap()
+    }
+
+    #[test]
+    fn empty_spec_is_info_everywhere() {
+        for spec in ["", " ", ",,"] {
+            let f = filter(spec);
+            assert_eq!(f, LogFilter::default());
+            assert_eq!(f.to_string(), "info");
+            assert!(f.enabled("wifi", log::Level::Info));
+            assert!(!f.enabled("wifi", log::Level::Debug));
+        }
+    }
+
+    #[test]
+    fn levels_gate_records() {
+        let f = filter("warn");
+        assert!(f.enabled("touch", log::Level::Error));
+        assert!(f.enabled("touch", log::Level::Warn));
+        assert!(!f.enabled("touch", log::Level::Info));
+        assert!(!filter("off").enabled("touch", log::Level::Error));
+        assert_eq!(filter("info,touch=trace").max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn overrides_match_module_prefixes() {
+        let f = filter("info,web=debug,web::api=warn");
+        assert_eq!(f.level_for("web"), LevelFilter::Debug);
+        assert_eq!(f.level_for("web::captive"), LevelFilter::Debug);
+        assert_eq!(f.level_for("web::api"), LevelFilter::Warn);
+        assert_eq!(f.level_for("web::api::profiles"), LevelFilter::Warn);
+        assert_eq!(f.level_for("webhook"), LevelFilter::Info);
+        assert_eq!(f.level_for("wifi"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn spec_text_round_trips_in_canonical_form() {
+        let f = filter(" wifi=DEBUG , warn,dns=off,wifi=trace ");
+        assert_eq!(f.to_string(), "warn,dns=off,wifi=trace");
+        assert_eq!(filter(&f.to_string()), f);
+        assert!("loud".parse::<LogFilter>().is_err());
+        assert!("wifi=loud".parse::<LogFilter>().is_err());
+        assert!("wi fi=info".parse::<LogFilter>().is_err());
+        assert!("=info".parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn set_keeps_the_spec_minimal() {
+        let mut f = filter("info,web=debug");
+        f.set("web::api", LevelFilter::Debug).unwrap();
+        assert_eq!(f.to_string(), "info,web=debug");
+        f.set("web::api", LevelFilter::Warn).unwrap();
+        assert_eq!(f.to_string(), "info,web=debug,web::api=warn");
+        f.set("web::api", LevelFilter::Debug).unwrap();
+        assert_eq!(f.to_string(), "info,web=debug");
+        f.set("", LevelFilter::Error).unwrap();
+        assert_eq!(f.default_level(), LevelFilter::Error);
+        assert!(f.set("bad target", LevelFilter::Info).is_err());
+    }
+
+    #[test]
+    fn override_count_is_capped() {
+        let mut f = LogFilter::default();
+        for i in 0..MAX_OVERRIDES {
+            f.set(&format!("m{i}"), LevelFilter::Debug).unwrap();
+        }
+        assert!(f.set("one_more", LevelFilter::Debug).is_err());
+        let spec: Vec<String> = (0..=MAX_OVERRIDES).map(|i| format!("m{i}=debug")).collect();
+        assert!(spec.join(",").parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn next_level_cycles() {
+        let mut level = LevelFilter::Off;
+        for _ in 0..6 {
+            level = next_level(level);
+        }
+        assert_eq!(level, LevelFilter::Off);
+        assert_eq!(next_level(LevelFilter::Info), LevelFilter::Debug);
+    }
+}
@@ -1,19 +1,289 @@
+//! Dual logger: serial console plus an in-memory ring for the About page
+//! and `GET /api/logs`.
+//!
+//! Records are packed into one byte arena ([`LogRing`]) allocated at boot
+//! rather than kept as a `String` per line. With `CONFIG_SPIRAM_USE_MALLOC`,
+//! allocations above 16 KB are served from PSRAM, so the ring costs no
+//! internal RAM; small per-line strings would all land in the internal heap.
+//! The arena size is set at build time with `VRCBADGE_LOG_BUFFER_KB`
+//! (see `.cargo/config.toml`).
+//!
+//! Which records get through is decided by a [`LogFilter`] (default level
+//! plus per-target overrides), replaced at runtime with [`set_filter`].
+//!
+//! Every new record wakes the threads waiting in [`wait_entries_since`],
+//! which feed the live log stream (`web::log_stream`).
+
 use std::collections::VecDeque;
+#[cfg(not(feature = "simulator"))]
 use std::ffi::CString;
-use std::sync::Mutex;
+use std::sync::{Condvar, Mutex, OnceLock, RwLock};
+#[cfg(not(feature = "simulator"))]
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::log_filter::LogFilter;
+
+/// Ring size in KB when `VRCBADGE_LOG_BUFFER_KB` is unset or invalid.
+const DEFAULT_BUFFER_KB: usize = 64;
+
+/// Ring size in bytes. Roughly 80 bytes per record, so 64 KB holds ~800 lines.
+const BUFFER_BYTES: usize = match option_env!("VRCBADGE_LOG_BUFFER_KB") {
+    Some(kb) => parse_kb(kb) * 1024,
+    None => DEFAULT_BUFFER_KB * 1024,
+};
+
+/// Lines shown on the on-device Logs page (the Slint text view is small).
+const DISPLAY_LINES: usize = 50;
+
+/// Longest message kept per record; the rest is cut at a char boundary.
+const MAX_MESSAGE_BYTES: usize = 1024;
+
+/// Fixed header per record: seq (8) + uptime ms (8) + level (1) +
+/// target length (1) + message length (2).
+const HEADER_LEN: usize = 20;
+
+static RING: Mutex<Option<LogRing>> = Mutex::new(None);
+/// Notified (with `RING` unlocked) after each record is added.
+static NEW_RECORD: Condvar = Condvar::new();
+/// `None` until [`set_filter`] is first called; treated as `LogFilter::default()`.
+static FILTER: RwLock<Option<LogFilter>> = RwLock::new(None);
+static BOOT: OnceLock<Instant> = OnceLock::new();
+
+/// `const` parse of the build-time KB setting, clamped to 4..=1024.
+const fn parse_kb(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if !b.is_ascii_digit() {
+            return DEFAULT_BUFFER_KB;
+        }
+        value = value * 10 + (b - b'0') as usize;
+        if value > 1024 {
+            return 1024;
+        }
+        i += 1;
+    }
+    if value < 4 {
+        4
+    } else {
+        value
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Log ring
+// ---------------------------------------------------------------------------
+
+/// One decoded log record.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    /// Increases by one per record since boot; used by `?since=` to tail.
+    pub seq: u64,
+    /// Milliseconds since the logger was initialised.
+    pub uptime_ms: u64,
+    #[serde(serialize_with = "serialize_level")]
+    pub level: log::Level,
+    /// Module path with the crate prefix stripped (empty for the crate root).
+    pub target: String,
+    pub message: String,
+}
+
+fn serialize_level<S: serde::Serializer>(level: &log::Level, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(level.as_str())
+}
+
+impl LogEntry {
+    /// `I [wifi] message`, as shown on the badge.
+    pub fn short_line(&self) -> String {
+        if self.target.is_empty() {
+            format!("{} {}", level_char(self.level), self.message)
+        } else {
+            format!(
+                "{} [{}] {}",
+                level_char(self.level),
+                self.target,
+                self.message
+            )
+        }
+    }
+
+    /// One Server-Sent Event: the record as JSON, with its `seq` as the
+    /// event id so a reconnecting browser resumes after it.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn sse_event(&self) -> String {
+        // serde_json escapes newlines, so the data stays on one line.
+        let json = serde_json::to_string(self).unwrap_or_default();
+        format!("id: {}\ndata: {json}\n\n", self.seq)
+    }
+
+    /// `[   12.345] I [wifi] message`, for downloads and crash reports.
+    pub fn text_line(&self) -> String {
+        format!(
+            "[{:5}.{:03}] {}",
+            self.uptime_ms / 1000,
+            self.uptime_ms % 1000,
+            self.short_line()
+        )
+    }
+}
+
+fn level_char(level: log::Level) -> char {
+    match level {
+        log::Level::Error => 'E',
+        log::Level::Warn => 'W',
+        log::Level::Info => 'I',
+        log::Level::Debug => 'D',
+        log::Level::Trace => 'T',
+    }
+}
+
+fn level_from_u8(v: u8) -> log::Level {
+    match v {
+        1 => log::Level::Error,
+        2 => log::Level::Warn,
+        3 => log::Level::Info,
+        4 => log::Level::Debug,
+        _ => log::Level::Trace,
+    }
+}
+
+/// Fixed-size byte ring of encoded records; the oldest records are evicted
+/// to make room. The backing buffer is allocated once, up front.
+pub struct LogRing {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+impl LogRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buf: VecDeque::with_capacity(capacity),
+            capacity,
+            next_seq: 1,
+        }
+    }
+
+    /// Sequence number the next record will get.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Append a record, evicting old ones as needed. Returns its sequence number.
+    pub fn push(&mut self, uptime_ms: u64, level: log::Level, target: &str, message: &str) -> u64 {
+        let target = truncate(target, u8::MAX as usize);
+        let message = truncate(message, MAX_MESSAGE_BYTES);
+        let len = HEADER_LEN + target.len() + message.len();
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if len > self.capacity {
+            return seq;
+        }
+        while self.buf.len() + len > self.capacity {
+            let front = self.record_len(0);
+            self.buf.drain(..front);
+        }
+
+        self.buf.extend(seq.to_le_bytes());
+        self.buf.extend(uptime_ms.to_le_bytes());
+        self.buf.push_back(level as u8);
+        self.buf.push_back(target.len() as u8);
+        self.buf.extend((message.len() as u16).to_le_bytes());
+        self.buf.extend(target.as_bytes());
+        self.buf.extend(message.as_bytes());
+        seq
+    }
+
+    /// All records with `seq > since`, oldest first (`since = 0` for all).
+    pub fn entries_since(&self, since: u64) -> Vec<LogEntry> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos < self.buf.len() {
+            let len = self.record_len(pos);
+            if self.u64_at(pos) > since {
+                entries.push(self.decode(pos));
+            }
+            pos += len;
+        }
+        entries
+    }
+
+    /// The newest `n` records, oldest first.
+    pub fn last(&self, n: usize) -> Vec<LogEntry> {
+        let since = self.next_seq.saturating_sub(n as u64 + 1);
+        self.entries_since(since)
+    }
+
+    /// Total encoded length of the record starting at `pos`.
+    fn record_len(&self, pos: usize) -> usize {
+        let target_len = self.buf[pos + 17] as usize;
+        let message_len = u16::from_le_bytes([self.buf[pos + 18], self.buf[pos + 19]]) as usize;
+        HEADER_LEN + target_len + message_len
+    }
+
+    fn u64_at(&self, pos: usize) -> u64 {
+        let mut bytes = [0u8; 8];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = self.buf[pos + i];
+        }
+        u64::from_le_bytes(bytes)
+    }
+
+    fn string_at(&self, start: usize, len: usize) -> String {
+        let bytes: Vec<u8> = self.buf.range(start..start + len).copied().collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    fn decode(&self, pos: usize) -> LogEntry {
+        let target_len = self.buf[pos + 17] as usize;
+        let message_len = self.record_len(pos) - HEADER_LEN - target_len;
+        let target_start = pos + HEADER_LEN;
+        LogEntry {
+            seq: self.u64_at(pos),
+            uptime_ms: self.u64_at(pos + 8),
+            level: level_from_u8(self.buf[pos + 16]),
+            target: self.string_at(target_start, target_len),
+            message: self.string_at(target_start + target_len, message_len),
+        }
+    }
+}
 
-/// Maximum number of log lines kept in the ring buffer.
-const MAX_LINES: usize = 50;
+/// Cut `s` to at most `max` bytes without splitting a UTF-8 character.
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
 
-static RING_BUFFER: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+// ---------------------------------------------------------------------------
+// Logger
+// ---------------------------------------------------------------------------
 
-/// Dual logger: writes to ESP-IDF serial console AND stores in a ring buffer
-/// for display on the About page.
+/// Dual logger: writes to ESP-IDF serial console (stderr in the simulator)
+/// AND stores in the ring for the About page and the web API.
 struct DualLogger;
 
 impl log::Log for DualLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= log::Level::Info
+        let target = short_target(metadata.target());
+        match FILTER.read() {
+            Ok(filter) => match filter.as_ref() {
+                Some(filter) => filter.enabled(target, metadata.level()),
+                None => metadata.level() <= log::Level::Info,
+            },
+            Err(_) => metadata.level() <= log::Level::Info,
+        }
     }
 
     fn log(&self, record: &log::Record) {
@@ -21,78 +291,302 @@ impl log::Log for DualLogger {
             return;
         }
 
-        // Forward to ESP-IDF serial console via esp_log_write
-        let level = match record.level() {
-            log::Level::Error => esp_idf_sys::esp_log_level_t_ESP_LOG_ERROR,
-            log::Level::Warn => esp_idf_sys::esp_log_level_t_ESP_LOG_WARN,
-            log::Level::Info => esp_idf_sys::esp_log_level_t_ESP_LOG_INFO,
-            log::Level::Debug => esp_idf_sys::esp_log_level_t_ESP_LOG_DEBUG,
-            log::Level::Trace => esp_idf_sys::esp_log_level_t_ESP_LOG_VERBOSE,
-        };
-
-        let target = record.target();
-        let message = format!("{}\n", record.args());
-        if let (Ok(tag), Ok(msg)) = (CString::new(target), CString::new(message)) {
-            unsafe {
-                esp_idf_sys::esp_log_write(level, tag.as_ptr(), msg.as_ptr());
-            }
-        }
+        // Forward to the serial console
+        write_serial(record);
 
-        // Store in ring buffer for About page display
-        let level_char = match record.level() {
-            log::Level::Error => 'E',
-            log::Level::Warn => 'W',
-            log::Level::Info => 'I',
-            log::Level::Debug => 'D',
-            log::Level::Trace => 'T',
-        };
-
-        // Strip the crate prefix for cleaner display
-        let short_target = target
-            .strip_prefix("vrcbadge_firmware::")
-            .or_else(|| target.strip_prefix("vrcbadge_firmware"))
-            .unwrap_or(target);
-
-        let line = if short_target.is_empty() {
-            format!("{} {}", level_char, record.args())
-        } else {
-            format!("{} [{}] {}", level_char, short_target, record.args())
-        };
+        let uptime_ms = uptime_ms();
+        let message = record.args().to_string();
 
-        if let Ok(mut buf) = RING_BUFFER.lock() {
-            if buf.len() >= MAX_LINES {
-                buf.pop_front();
+        if let Ok(mut ring) = RING.lock() {
+            if let Some(ring) = ring.as_mut() {
+                ring.push(
+                    uptime_ms,
+                    record.level(),
+                    short_target(record.target()),
+                    &message,
+                );
             }
-            buf.push_back(line);
         }
+        NEW_RECORD.notify_all();
     }
 
     fn flush(&self) {}
 }
 
+/// Strip the crate prefix for cleaner display and shorter filter specs.
+fn short_target(target: &str) -> &str {
+    target
+        .strip_prefix("vrcbadge_firmware::")
+        .or_else(|| target.strip_prefix("vrcbadge_firmware"))
+        .unwrap_or(target)
+}
+
+/// Forward a record to the ESP-IDF serial console via `esp_log_write`.
+#[cfg(not(feature = "simulator"))]
+fn write_serial(record: &log::Record) {
+    let level = match record.level() {
+        log::Level::Error => esp_idf_sys::esp_log_level_t_ESP_LOG_ERROR,
+        log::Level::Warn => esp_idf_sys::esp_log_level_t_ESP_LOG_WARN,
+        log::Level::Info => esp_idf_sys::esp_log_level_t_ESP_LOG_INFO,
+        log::Level::Debug => esp_idf_sys::esp_log_level_t_ESP_LOG_DEBUG,
+        log::Level::Trace => esp_idf_sys::esp_log_level_t_ESP_LOG_VERBOSE,
+    };
+
+    let message = format!("{}\n", record.args());
+    if let (Ok(tag), Ok(msg)) = (CString::new(record.target()), CString::new(message)) {
+        unsafe {
+            esp_idf_sys::esp_log_write(level, tag.as_ptr(), msg.as_ptr());
+        }
+    }
+}
+
+/// Simulator: print to stderr in the same `LEVEL (target) message` shape.
+#[cfg(feature = "simulator")]
+fn write_serial(record: &log::Record) {
+    eprintln!("{} ({}) {}", record.level(), record.target(), record.args());
+}
+
 static DUAL_LOGGER: DualLogger = DualLogger;
 
-/// Initialize the dual logger (serial + ring buffer).
-/// Must be called once at startup, before any log macros.
+/// Initialize the dual logger (serial + ring buffer) with the default
+/// `Info` filter. Must be called once at startup, before any log macros;
+/// the saved filter is applied with [`set_filter`] once NVS is open.
 pub fn init() {
+    BOOT.get_or_init(Instant::now);
+    if let Ok(mut ring) = RING.lock() {
+        ring.get_or_insert_with(|| LogRing::new(BUFFER_BYTES));
+    }
+
+    // `esp_log_write` drops anything above the tag's level (Info by
+    // default), which would swallow Debug/Trace that `LogFilter` let
+    // through. C components are compiled at Info, so this adds nothing
+    // from them.
+    #[cfg(not(feature = "simulator"))]
+    unsafe {
+        esp_idf_sys::esp_log_level_set(c"*".as_ptr(), esp_idf_sys::esp_log_level_t_ESP_LOG_VERBOSE);
+    }
+
     // Set our dual logger as the global logger
     log::set_logger(&DUAL_LOGGER).ok();
     log::set_max_level(log::LevelFilter::Info);
+    log::info!("Log ring: {} KB", BUFFER_BYTES / 1024);
 }
 
-/// Return all buffered log lines as a single newline-joined string (snapshot).
+/// Replace the active level filter.
+pub fn set_filter(filter: LogFilter) {
+    log::set_max_level(filter.max_level());
+    if let Ok(mut active) = FILTER.write() {
+        *active = Some(filter);
+    }
+}
+
+/// The active level filter.
+pub fn filter() -> LogFilter {
+    match FILTER.read() {
+        Ok(filter) => filter.clone().unwrap_or_default(),
+        Err(_) => LogFilter::default(),
+    }
+}
+
+/// Return the newest lines as a single newline-joined string for the
+/// on-device Logs page.
 pub fn snapshot() -> String {
-    match RING_BUFFER.lock() {
-        Ok(buf) => {
-            let mut result = String::new();
-            for (i, line) in buf.iter().enumerate() {
-                if i > 0 {
-                    result.push('\n');
-                }
-                result.push_str(line);
+    match RING.lock() {
+        Ok(ring) => ring
+            .as_ref()
+            .map(|ring| ring.last(DISPLAY_LINES))
+            .unwrap_or_default()
+            .iter()
+            .map(LogEntry::short_line)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Err(_) => String::from("(log buffer locked)"),
+    }
+}
+
+/// Milliseconds since [`init`].
+pub fn uptime_ms() -> u64 {
+    BOOT.get()
+        .map_or(0, |boot| boot.elapsed().as_millis() as u64)
+}
+
+/// Newest `n` records as download-style text, or `None` if the ring is
+/// locked. Safe to call from the panic hook, which may run while this
+/// thread holds the lock.
+pub fn try_recent_text(n: usize) -> Option<String> {
+    let ring = RING.try_lock().ok()?;
+    let lines: Vec<String> = ring
+        .as_ref()?
+        .last(n)
+        .iter()
+        .map(LogEntry::text_line)
+        .collect();
+    Some(lines.join("\n"))
+}
+
+/// Records with `seq > since` plus the sequence number to pass as `since`
+/// on the next call.
+#[cfg(not(feature = "simulator"))]
+pub fn entries_since(since: u64) -> (Vec<LogEntry>, u64) {
+    match RING.lock() {
+        Ok(ring) => match ring.as_ref() {
+            Some(ring) => (ring.entries_since(since), ring.next_seq() - 1),
+            None => (Vec::new(), since),
+        },
+        Err(_) => (Vec::new(), since),
+    }
+}
+
+/// Like [`entries_since`], but waits up to `timeout` for a record newer
+/// than `since` if there is none yet. A `since` from before the last
+/// restart (newer than any record) counts as 0.
+#[cfg(not(feature = "simulator"))]
+pub fn wait_entries_since(since: u64, timeout: Duration) -> (Vec<LogEntry>, u64) {
+    let Ok(ring) = RING.lock() else {
+        return (Vec::new(), since);
+    };
+    let since = match ring.as_ref() {
+        Some(ring) if since >= ring.next_seq() => 0,
+        _ => since,
+    };
+    let ring = match NEW_RECORD.wait_timeout_while(ring, timeout, |ring| {
+        ring.as_ref()
+            .is_some_and(|ring| ring.next_seq() - 1 <= since)
+    }) {
+        Ok((ring, _)) => ring,
+        Err(_) => return (Vec::new(), since),
+    };
+    match ring.as_ref() {
+        Some(ring) => (ring.entries_since(since), ring.next_seq() - 1),
+        None => (Vec::new(), since),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes one record takes in the arena.
+    fn encoded_len(target: &str, message: &str) -> usize {
+        HEADER_LEN + target.len() + message.len()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let mut ring = LogRing::new(1024);
+        assert_eq!(ring.push(10, log::Level::Info, "wifi", "connected"), 1);
+        assert_eq!(ring.push(20, log::Level::Warn, "", "low battery"), 2);
+        assert_eq!(ring.next_seq(), 3);
+
+        let entries = ring.entries_since(0);
+        assert_eq!(
+            entries[0],
+            LogEntry {
+                seq: 1,
+                uptime_ms: 10,
+                level: log::Level::Info,
+                target: "wifi".into(),
+                message: "connected".into(),
             }
-            result
+        );
+        assert_eq!(entries[1].short_line(), "W low battery");
+        assert_eq!(entries[1].text_line(), "[    0.020] W low battery");
+        assert_eq!(ring.entries_since(1), entries[1..]);
+        assert!(ring.entries_since(2).is_empty());
+    }
+
+    #[test]
+    fn records_are_packed_back_to_back() {
+        let mut ring = LogRing::new(1024);
+        ring.push(0, log::Level::Info, "wifi", "connected");
+        ring.push(0, log::Level::Debug, "web::api", "");
+        ring.push(0, log::Level::Error, "", "käse");
+        let used = encoded_len("wifi", "connected")
+            + encoded_len("web::api", "")
+            + encoded_len("", "käse");
+        assert_eq!(ring.buf.len(), used);
+        assert_eq!(ring.entries_since(0)[2].message, "käse");
+    }
+
+    #[test]
+    fn oldest_records_are_evicted_to_make_room() {
+        let record = encoded_len("t", "message 00");
+        let mut ring = LogRing::new(record * 5 + record / 2);
+        for i in 0..23 {
+            ring.push(i, log::Level::Info, "t", &format!("message {i:02}"));
+            assert!(ring.buf.len() <= ring.capacity);
         }
-        Err(_) => String::from("(log buffer locked)"),
+        // Always whole records: the five newest, in order
+        let entries = ring.entries_since(0);
+        let seqs: Vec<u64> = entries.iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, [19, 20, 21, 22, 23]);
+        assert_eq!(entries[4].message, "message 22");
+        assert_eq!(ring.buf.len(), record * 5);
+        // A reader that fell behind sees the gap in `seq`
+        assert_eq!(ring.entries_since(3).len(), 5);
+        assert_eq!(
+            ring.last(2).iter().map(|e| e.seq).collect::<Vec<_>>(),
+            [22, 23]
+        );
+    }
+
+    #[test]
+    fn a_large_record_evicts_several_small_ones() {
+        let mut ring = LogRing::new(200);
+        for _ in 0..6 {
+            ring.push(0, log::Level::Info, "", "short");
+        }
+        let big = "x".repeat(150);
+        ring.push(0, log::Level::Info, "", &big);
+        let entries = ring.entries_since(0);
+        assert_eq!(entries.last().unwrap().message, big);
+        // 6 x 25 bytes + 170 only fits 200 with one short record left
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].seq, 6);
+        assert_eq!(ring.buf.len(), 195);
+    }
+
+    #[test]
+    fn oversize_records_are_dropped_but_numbered() {
+        let mut ring = LogRing::new(64);
+        ring.push(0, log::Level::Info, "", "kept");
+        assert_eq!(ring.push(0, log::Level::Info, "", &"x".repeat(100)), 2);
+        assert_eq!(ring.push(0, log::Level::Info, "", "next"), 3);
+        let seqs: Vec<u64> = ring.entries_since(0).iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, [1, 3]);
+    }
+
+    #[test]
+    fn long_messages_are_cut_at_a_char_boundary() {
+        let mut ring = LogRing::new(4096);
+        let message = "é".repeat(MAX_MESSAGE_BYTES); // two bytes each
+        ring.push(0, log::Level::Info, "", &message);
+        let kept = &ring.entries_since(0)[0].message;
+        assert_eq!(kept.len(), MAX_MESSAGE_BYTES);
+        assert!(kept.chars().all(|c| c == 'é'));
+        assert_eq!(truncate("aé", 2), "a");
+    }
+
+    #[test]
+    fn sse_events_are_one_line_of_json() {
+        let mut ring = LogRing::new(1024);
+        ring.push(1500, log::Level::Error, "ota", "failed:\nbad image");
+        let event = ring.entries_since(0)[0].sse_event();
+        let (head, data) = event.split_once("\ndata: ").unwrap();
+        assert_eq!(head, "id: 1");
+        let data = data.strip_suffix("\n\n").unwrap();
+        assert!(!data.contains('\n'));
+        let json: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(json["level"], "ERROR");
+        assert_eq!(json["message"], "failed:\nbad image");
+        assert_eq!(json["uptime_ms"], 1500);
+    }
+
+    #[test]
+    fn buffer_size_setting_is_clamped() {
+        assert_eq!(parse_kb("64"), 64);
+        assert_eq!(parse_kb("2"), 4);
+        assert_eq!(parse_kb("4096"), 1024);
+        assert_eq!(parse_kb("64k"), DEFAULT_BUFFER_KB);
     }
 }
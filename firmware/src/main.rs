@@ -1,42 +1,116 @@
+#[cfg(all(feature = "esp", feature = "simulator"))]
+compile_error!("the `simulator` feature runs on the host: build with `--no-default-features`");
+
+mod animation;
+mod auth;
+#[cfg(not(feature = "simulator"))]
+mod backlight;
+// Backups only come and go over HTTP; the simulator has no web server.
+#[cfg(any(not(feature = "simulator"), test))]
+mod backup;
+mod battery;
 mod callbacks;
+mod crash;
+#[cfg(not(feature = "simulator"))]
 mod display;
+#[cfg(not(feature = "simulator"))]
 mod dns;
+#[cfg(not(feature = "simulator"))]
 mod event_loop;
+mod factory_reset;
+mod hal;
+#[cfg(not(feature = "simulator"))]
+mod i2c;
+// Only the ESP event loop drives the idle timer and power policy; the
+// simulator never dims and has no battery.
+#[cfg(any(not(feature = "simulator"), test))]
+mod idle;
+mod image_codec;
+mod image_decode;
+mod log_filter;
 mod logger;
+mod ota;
 mod pending;
 mod platform;
+#[cfg(any(not(feature = "simulator"), test))]
+mod power;
 mod profile;
+mod profile_schema;
+mod qr;
+mod settings;
+#[cfg(feature = "simulator")]
+mod simulator;
+#[cfg(not(feature = "simulator"))]
+mod status;
 mod storage;
 mod sysinfo;
+#[cfg(not(feature = "simulator"))]
 mod touch;
 mod ui_helpers;
+#[cfg(not(feature = "simulator"))]
 mod web;
+#[cfg(not(feature = "simulator"))]
 mod wifi;
 mod wifi_state;
 
+#[cfg(not(feature = "simulator"))]
 use std::cell::RefCell;
+#[cfg(not(feature = "simulator"))]
+use std::rc::Rc;
+#[cfg(not(feature = "simulator"))]
 use std::sync::{Arc, Mutex};
+#[cfg(not(feature = "simulator"))]
 use std::time::Instant;
 
-use esp_idf_hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver};
+#[cfg(not(feature = "simulator"))]
 use esp_idf_hal::peripherals::Peripherals;
-use esp_idf_hal::units::FromValueType;
+#[cfg(not(feature = "simulator"))]
 use esp_idf_svc::eventloop::EspSystemEventLoop;
+#[cfg(not(feature = "simulator"))]
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
-use esp_idf_svc::wifi::{BlockingWifi, EspWifi};
+#[cfg(not(feature = "simulator"))]
 use esp_idf_sys as _;
-use slint::{Image, Rgb8Pixel, SharedPixelBuffer};
 
-use crate::platform::Esp32Platform;
-use crate::touch::TouchController;
+#[cfg(not(feature = "simulator"))]
+use crate::auth::Auth;
+#[cfg(not(feature = "simulator"))]
+use crate::backlight::LedcBacklight;
+#[cfg(not(feature = "simulator"))]
+use crate::battery::Max17048;
+#[cfg(not(feature = "simulator"))]
+use crate::callbacks::UiDeps;
+#[cfg(not(feature = "simulator"))]
 use crate::event_loop::LoopDeps;
+#[cfg(not(feature = "simulator"))]
+use crate::hal::{SharedBacklight, SharedImageStore, SharedKvStore, SharedWifi};
+#[cfg(not(feature = "simulator"))]
+use crate::ota::OtaUpdater;
+#[cfg(not(feature = "simulator"))]
 use crate::pending::PendingState;
-use crate::ui_helpers::{apply_profile_colors, apply_rounded_corners};
+#[cfg(not(feature = "simulator"))]
+use crate::platform::Esp32Platform;
+#[cfg(not(feature = "simulator"))]
+use crate::status::{SharedStatus, StatusSnapshot};
+#[cfg(not(feature = "simulator"))]
+use crate::touch::TouchController;
+#[cfg(not(feature = "simulator"))]
+use crate::ui_helpers::{
+    show_active_profile, show_last_crash, show_log_levels, show_running_ap, show_settings,
+};
+#[cfg(not(feature = "simulator"))]
+use crate::web::WebContext;
+#[cfg(not(feature = "simulator"))]
 use crate::wifi_state::WiFiState;
 
 // Generated by slint-build from ui/badge.slint
 slint::include_modules!();
 
+#[cfg(feature = "simulator")]
+fn main() -> anyhow::Result<()> {
+    simulator::run()
+}
+
+#[cfg(not(feature = "simulator"))]
 fn main() -> anyhow::Result<()> {
     // Initialize dual logger (serial + ring buffer for About page)
     logger::init();
@@ -46,25 +120,61 @@ fn main() -> anyhow::Result<()> {
     // Take hardware peripherals
     let peripherals = Peripherals::take()?;
 
+    // BOOT held at power-up: factory reset, run once the UI is up
+    let boot_reset = factory_reset::boot_button_held(peripherals.pins.gpio0).unwrap_or_else(|e| {
+        log::warn!("BOOT button read failed: {e}");
+        false
+    });
+
     // --- Persistent storage (NVS + SPIFFS) ---
     let nvs_partition = EspDefaultNvsPartition::take()?;
     let nvs_for_storage = nvs_partition.clone(); // clone before WiFi consumes it
-    let nvs = std::rc::Rc::new(RefCell::new(storage::init_nvs(nvs_for_storage)?));
-    storage::init_spiffs()?;
+    let nvs: SharedKvStore = Rc::new(RefCell::new(storage::init_nvs(nvs_for_storage)?));
+    let settings = storage::load_settings(&mut *nvs.borrow_mut());
+    let log_filter = settings.log_filter();
+    logger::set_filter(log_filter.clone());
+    let spiffs = storage::init_spiffs()?;
+    spiffs.recover();
+    spiffs.migrate_raw_images();
+    let images: SharedImageStore = Arc::new(spiffs);
+    // Panic hook + reset reason; records the previous boot's crash if any.
+    crash::init(storage::SPIFFS_MOUNT);
 
     // --- WiFi AP + HTTP server ---
     let sys_loop = EspSystemEventLoop::take()?;
-    let (wifi_driver, ap_ip) = wifi::init(peripherals.modem, sys_loop, nvs_partition)?;
-    let wifi_handle: Arc<Mutex<BlockingWifi<EspWifi<'static>>>> = Arc::new(Mutex::new(wifi_driver));
+    let (wifi_driver, ap_ip, running_ap, ap_fallback) =
+        wifi::init(peripherals.modem, sys_loop, nvs_partition, &settings.ap)?;
+    wifi_state::set_running_ap(running_ap);
+    let wifi_handle: SharedWifi = Arc::new(Mutex::new(wifi_driver));
     dns::start(ap_ip)?;
-    let saved_profile = storage::load_profile(&nvs.borrow()).unwrap_or_default();
-    let pending = PendingState::new(saved_profile.clone());
+    // Web API admin PIN; made up after the radio is on, so the RNG is random
+    let pin = auth::load_or_create_pin(&mut *nvs.borrow_mut());
+    let profiles = storage::load_profiles(&mut *nvs.borrow_mut(), &*images);
+    let brightness = settings.brightness as f32;
+    let pending = PendingState::new(profiles, settings, Auth::new(pin));
+    let ota = OtaUpdater::new();
+    let status: SharedStatus = Arc::new(Mutex::new(StatusSnapshot::new()));
     let _server = web::init(
         ap_ip,
-        pending.background.clone(),
-        pending.avatar.clone(),
-        pending.current_profile.clone(),
-        pending.profile.clone(),
+        WebContext {
+            pending_background: pending.background.clone(),
+            pending_avatar: pending.avatar.clone(),
+            pending_avatar_animation: pending.avatar_animation.clone(),
+            profiles: pending.profiles.clone(),
+            profile_ops: pending.profile_ops.clone(),
+            pending_log_filter: pending.log_filter.clone(),
+            pending_restore: pending.restore.clone(),
+            wifi_export: pending.wifi_export.clone(),
+            factory_reset: pending.factory_reset.clone(),
+            settings: pending.settings.clone(),
+            settings_changed: pending.settings_changed.clone(),
+            restart: pending.restart.clone(),
+            images: images.clone(),
+            crashes_cleared: pending.crashes_cleared.clone(),
+            ota: ota.clone(),
+            status: status.clone(),
+            auth: pending.auth.clone(),
+        },
     )?;
 
     // --- Slint platform ---
@@ -78,26 +188,24 @@ fn main() -> anyhow::Result<()> {
     // DMA-refreshes the display. No SPI, no manual pixel pushing.
     let (_display, framebuffer) = display::init()?;
 
-    // --- Backlight PWM (600Hz, 8-bit, GPIO 2) ---
-    // The JC8048W550 backlight is driven through a boost LED driver IC (U5).
-    // GPIO 2 connects to the IC's EN pin, which accepts PWM for dimming.
-    // 600Hz matches the Arduino demos for this board.
-    let timer = LedcTimerDriver::new(
+    // --- Backlight PWM (600Hz, 8-bit, GPIO 2), at the saved brightness ---
+    let backlight: SharedBacklight = Rc::new(RefCell::new(LedcBacklight::new(
         peripherals.ledc.timer0,
-        &TimerConfig::new()
-            .frequency(600.Hz().into())
-            .resolution(esp_idf_hal::ledc::Resolution::Bits8),
-    )?;
-    let mut backlight = LedcDriver::new(peripherals.ledc.channel0, timer, peripherals.pins.gpio2)?;
-    let max_duty = backlight.get_max_duty();
-    backlight.set_duty(max_duty / 2)?; // Start at 50% brightness
-    log::info!("Backlight PWM initialized (GPIO 2, 600Hz, 50%)");
+        peripherals.ledc.channel0,
+        peripherals.pins.gpio2,
+        brightness,
+    )?));
 
-    // --- Touch (GT911 over I2C) ---
-    let touch = match TouchController::new(
+    // --- Shared I2C bus (GT911 touch + MAX17048 fuel gauge) ---
+    let i2c_bus = i2c::init(
         peripherals.i2c0,
         peripherals.pins.gpio19.into(), // SDA
         peripherals.pins.gpio20.into(), // SCL
+    )?;
+
+    // --- Touch (GT911 over I2C) ---
+    let touch = match TouchController::new(
+        i2c_bus.clone(),
         peripherals.pins.gpio38.into(), // Touch RST
     ) {
         Ok(t) => Some(t),
@@ -107,45 +215,45 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
+    // --- Battery (MAX17048 over I2C) ---
+    let mut battery = match Max17048::new(i2c_bus) {
+        Ok(b) => Some(b),
+        Err(e) => {
+            log::warn!("Battery gauge init failed (showing placeholder): {e}");
+            None
+        }
+    };
+
     // --- Create UI ---
     let ui = BadgeUI::new().map_err(|e| anyhow::anyhow!("Failed to create UI: {:?}", e))?;
 
-    // Set initial values from saved (or default) profile
-    apply_profile_colors(&ui, &saved_profile);
-    ui.set_display_name(saved_profile.display_name.into());
-    ui.set_tagline(saved_profile.tagline.into());
-    ui.set_twitter_handle(saved_profile.twitter_handle.into());
-    ui.set_discord_handle(saved_profile.discord_handle.into());
+    // Show the active profile with its avatar and background from SPIFFS
+    if let Ok(set) = pending.profiles.lock() {
+        show_active_profile(&ui, &*images, &set);
+    }
     ui.set_battery_percent(100);
-    ui.set_wifi_ip(ap_ip.to_string().into());
-    ui.set_firmware_version(sysinfo::firmware_version().into());
-
-    // Restore saved avatar from SPIFFS (216 KB @ 240x300)
-    if let Some(mut rgb_data) = storage::load_image("avatar", storage::AVATAR_IMAGE_SIZE) {
-        apply_rounded_corners(
-            &mut rgb_data,
-            storage::AVATAR_WIDTH,
-            storage::AVATAR_HEIGHT,
-            20,
-            [0x2a, 0x2a, 0x4a],
-        );
-        let buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
-            &rgb_data,
-            storage::AVATAR_WIDTH,
-            storage::AVATAR_HEIGHT,
+    if let Some(reading) = battery.as_mut().and_then(|b| b.read().ok()) {
+        log::info!(
+            "Battery: {:.1}% ({:.2} V)",
+            reading.percent,
+            reading.voltage
         );
-        ui.set_avatar_image(Image::from_rgb8(buffer));
+        ui.set_battery_percent(reading.display_percent());
+        if let Ok(mut status) = status.lock() {
+            status.battery = Some(reading);
+        }
     }
-
-    // Restore saved background from SPIFFS
-    if let Some(rgb_data) = storage::load_image("background", storage::BACKGROUND_IMAGE_SIZE) {
-        let buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
-            &rgb_data,
-            platform::DISPLAY_WIDTH,
-            platform::DISPLAY_HEIGHT,
-        );
-        ui.set_background_image(Image::from_rgb8(buffer));
+    ui.set_wifi_ip(ap_ip.to_string().into());
+    show_running_ap(&ui);
+    if let Ok(auth) = pending.auth.lock() {
+        ui.set_admin_pin(auth.pin().into());
+    }
+    ui.set_firmware_version(sysinfo::firmware_version().into());
+    show_log_levels(&ui, &log_filter);
+    if let Ok(settings) = pending.settings.lock() {
+        show_settings(&ui, &settings);
     }
+    show_last_crash(&ui);
 
     // Flag to dismiss virtual keyboard from the main loop (dispatch_event
     // doesn't reliably defocus TextInput when called from inside a callback).
@@ -159,10 +267,10 @@ fn main() -> anyhow::Result<()> {
     // before any UI callbacks are wired so that nothing else can grab the
     // wifi handle while we're holding it.
     let mut sta_connected = false;
-    if let Some((ssid, password)) = storage::load_wifi_credentials(&nvs.borrow()) {
+    if let Some((ssid, password)) = storage::load_wifi_credentials(&*nvs.borrow()) {
         log::info!("Auto-connecting to saved WiFi: {ssid}");
         if let Ok(mut wifi) = wifi_handle.lock() {
-            match wifi::connect_sta(&mut wifi, &ssid, &password) {
+            match wifi.connect_sta(&ssid, &password) {
                 Ok(ip) => {
                     ui.set_sta_connected(true);
                     ui.set_sta_ssid(ssid.into());
@@ -178,8 +286,12 @@ fn main() -> anyhow::Result<()> {
             }
         }
     }
+    if ap_fallback {
+        ui.set_toast_message("AP settings failed, using defaults".into());
+        ui.set_toast_visible(true);
+    }
     // Track whether we have saved credentials (for dimmed icon vs hidden)
-    let has_wifi_credentials = storage::load_wifi_credentials(&nvs.borrow()).is_some();
+    let has_wifi_credentials = storage::load_wifi_credentials(&*nvs.borrow()).is_some();
     ui.set_has_wifi_credentials(has_wifi_credentials || sta_connected);
 
     // --- Wire all UI callbacks (brightness, virtual keyboard, WiFi) ---
@@ -188,12 +300,30 @@ fn main() -> anyhow::Result<()> {
     let wifi_state = WiFiState::new();
     callbacks::wire(
         &ui,
-        wifi_handle.clone(),
-        wifi_state.clone(),
-        nvs.clone(),
-        backlight,
-        max_duty,
+        UiDeps {
+            wifi_handle: wifi_handle.clone(),
+            wifi_state: wifi_state.clone(),
+            nvs: nvs.clone(),
+            images: images.clone(),
+            profiles: pending.profiles.clone(),
+            settings: pending.settings.clone(),
+            reset: pending.factory_reset.clone(),
+            restart: pending.restart.clone(),
+            auth: pending.auth.clone(),
+            backlight: backlight.clone(),
+            ota: ota.clone(),
+        },
     );
+    if boot_reset {
+        factory_reset::request(&pending.factory_reset);
+        ui.set_factory_reset_status(factory_reset::ResetStep::Requested.status().into());
+    }
+
+    // --- Confirm this image so the bootloader doesn't roll back ---
+    // Reaching this point means display, storage and WiFi all came up.
+    if let Err(e) = ota::mark_valid() {
+        log::warn!("Failed to mark firmware valid: {e}");
+    }
 
     log::info!("Boot complete, entering main loop");
 
@@ -202,9 +332,14 @@ fn main() -> anyhow::Result<()> {
         framebuffer,
         ui,
         touch,
+        battery,
+        status,
         nvs,
+        images,
+        backlight,
         wifi_handle,
         wifi_state,
+        ota,
         pending,
         dismiss_keyboard,
         sta_connected,
@@ -0,0 +1,93 @@
+//! ESP app image header checks for OTA.
+//!
+//! Run on the first bytes of an upload or download before the OTA slot is
+//! erased, so a wrong-chip or non-app binary is rejected with a readable
+//! error instead of a failed `esp_ota_end`.
+
+/// First byte of every ESP app image (`ESP_IMAGE_HEADER_MAGIC`).
+const IMAGE_MAGIC: u8 = 0xE9;
+
+/// `chip_id` of ESP32-S3 images in the extended header (`ESP_CHIP_ID_ESP32S3`).
+const CHIP_ID_ESP32S3: u16 = 0x0009;
+
+/// Magic word at the start of `esp_app_desc_t` (`ESP_APP_DESC_MAGIC_WORD`).
+const APP_DESC_MAGIC: u32 = 0xABCD_5432;
+
+/// Bytes needed by [`validate_image_header`]: image header (24) + first
+/// segment header (8) + `esp_app_desc_t` up to the end of `project_name`.
+pub const APP_HEADER_LEN: usize = 112;
+
+/// Version info read from a firmware image's `esp_app_desc_t`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppImageInfo {
+    pub version: String,
+    pub project_name: String,
+}
+
+/// A firmware image that must not be flashed (bad header, wrong chip, wrong
+/// size, truncated, failed verification). Carried inside `anyhow::Error` so
+/// callers can tell it apart from I/O errors with `is::<InvalidImage>()`.
+#[derive(Debug)]
+pub struct InvalidImage(pub String);
+
+impl std::fmt::Display for InvalidImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidImage {}
+
+/// Check the first [`APP_HEADER_LEN`] bytes of an app image before anything
+/// is written to flash.
+///
+/// `image_len` is the full image size if known (Content-Length); it must fit
+/// in `slot_size`, the size of the OTA partition being written.
+pub fn validate_image_header(
+    header: &[u8],
+    image_len: Option<usize>,
+    slot_size: usize,
+) -> Result<AppImageInfo, InvalidImage> {
+    if header.len() < APP_HEADER_LEN {
+        return Err(InvalidImage(format!(
+            "Image truncated: {} bytes is smaller than an app header",
+            header.len()
+        )));
+    }
+    if header[0] != IMAGE_MAGIC {
+        return Err(InvalidImage(format!(
+            "Not an ESP app image (magic 0x{:02X}, expected 0x{IMAGE_MAGIC:02X})",
+            header[0]
+        )));
+    }
+    let chip_id = u16::from_le_bytes([header[12], header[13]]);
+    if chip_id != CHIP_ID_ESP32S3 {
+        return Err(InvalidImage(format!(
+            "Image is for chip id {chip_id}, this badge is an ESP32-S3 (chip id {CHIP_ID_ESP32S3})"
+        )));
+    }
+    let desc_magic = u32::from_le_bytes([header[32], header[33], header[34], header[35]]);
+    if desc_magic != APP_DESC_MAGIC {
+        return Err(InvalidImage(
+            "Image has no app description (is this a bootloader or partition table?)".into(),
+        ));
+    }
+    if let Some(len) = image_len {
+        if len > slot_size {
+            return Err(InvalidImage(format!(
+                "Image is {len} bytes, OTA slot holds {slot_size}"
+            )));
+        }
+    }
+
+    Ok(AppImageInfo {
+        version: c_string(&header[48..80]),
+        project_name: c_string(&header[80..112]),
+    })
+}
+
+/// Decode a NUL-padded fixed-size C string field.
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
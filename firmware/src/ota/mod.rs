@@ -0,0 +1,748 @@
+//! OTA firmware updates from GitHub Releases.
+//!
+//! See `docs/software/ota-update.md`. The flow is:
+//!
+//! 1. [`check_update`] fetches the latest release JSON and compares its tag
+//!    against the running version ([`check_release`] does the parsing, so it
+//!    runs on the host against canned responses).
+//! 2. [`perform_update`] streams `firmware.bin` into the inactive OTA slot
+//!    through [`flash_image`], which `POST /api/firmware` also uses for
+//!    images uploaded over the AP. Uploaded images are only staged: they
+//!    boot after someone taps Install on the badge ([`activate_staged`]).
+//! 3. After the reboot, [`mark_valid`] confirms the new image so the
+//!    bootloader does not roll back.
+//!
+//! Both network calls block for seconds, so the UI goes through
+//! [`OtaUpdater`], which runs them on worker threads and publishes an
+//! [`OtaProgress`] that the main loop polls every ~2 s.
+
+// Only the ESP flash path checks images; the simulator has nothing to flash.
+#[cfg(not(feature = "simulator"))]
+pub mod image;
+
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+
+use crate::BadgeUI;
+
+/// GitHub API endpoint for the latest release.
+#[cfg(not(feature = "simulator"))]
+const RELEASES_URL: &str = "https://api.github.com/repos/Hebububu/VRCBadge/releases/latest";
+
+/// Release asset that holds the app image.
+const ASSET_NAME: &str = "firmware.bin";
+
+/// Download chunk size (one `EspOtaUpdate::write` per chunk).
+const CHUNK_SIZE: usize = 4096;
+
+/// Upper bound for the release JSON; anything larger is not a release.
+#[cfg(not(feature = "simulator"))]
+const MAX_RELEASE_JSON: usize = 64 * 1024;
+
+/// A newer release found by [`check_update`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateInfo {
+    /// Version without the `v` prefix (e.g. "0.2.0").
+    pub version: String,
+    pub download_url: String,
+}
+
+// ---------------------------------------------------------------------------
+// Release parsing (pure, host-testable)
+// ---------------------------------------------------------------------------
+
+/// The fields of a `releases/latest` response that matter here.
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Parse a `releases/latest` response and return the release if it is newer
+/// than `current_version`.
+///
+/// Errors if the JSON is not a release (no `tag_name`) or has no
+/// `firmware.bin` asset.
+pub fn check_release(json: &str, current_version: &str) -> anyhow::Result<Option<UpdateInfo>> {
+    let release: Release = serde_json::from_str(json)
+        .map_err(|e| anyhow::anyhow!("Release JSON not understood: {e}"))?;
+    let tag = release.tag_name.as_str();
+    let version = tag.strip_prefix('v').unwrap_or(tag);
+
+    if !is_newer(version, current_version) {
+        return Ok(None);
+    }
+
+    let asset = release
+        .assets
+        .into_iter()
+        .find(|asset| asset.name == ASSET_NAME)
+        .ok_or_else(|| anyhow::anyhow!("Release {tag} has no {ASSET_NAME} asset"))?;
+
+    Ok(Some(UpdateInfo {
+        version: version.to_string(),
+        download_url: asset.browser_download_url,
+    }))
+}
+
+/// Whether dotted version `remote` is greater than `current`.
+///
+/// Compares numerically per component, so "0.10.0" > "0.9.0". Pre-release
+/// and build suffixes ("-rc1", "+abc") are ignored. Unparseable versions are
+/// never considered newer.
+pub fn is_newer(remote: &str, current: &str) -> bool {
+    match (version_parts(remote), version_parts(current)) {
+        (Some(remote), Some(current)) => remote > current,
+        _ => false,
+    }
+}
+
+/// Split "1.2.3[-pre][+build]" into `[1, 2, 3]`.
+fn version_parts(version: &str) -> Option<Vec<u64>> {
+    let core = version.split(['-', '+']).next()?;
+    core.split('.').map(|part| part.parse().ok()).collect()
+}
+
+// ---------------------------------------------------------------------------
+// ESP-IDF backend (HTTPS client + OTA partitions)
+// ---------------------------------------------------------------------------
+
+/// Check GitHub for a newer release.
+#[cfg(not(feature = "simulator"))]
+pub fn check_update() -> anyhow::Result<Option<UpdateInfo>> {
+    use esp_idf_svc::http::Method;
+
+    let mut conn = https_connection()?;
+    conn.initiate_request(
+        Method::Get,
+        RELEASES_URL,
+        &[
+            ("User-Agent", "VRCBadge"),
+            ("Accept", "application/vnd.github+json"),
+        ],
+    )?;
+    conn.initiate_response()?;
+    let status = conn.status();
+    if status != 200 {
+        anyhow::bail!("GitHub API returned HTTP {status}");
+    }
+
+    let mut body = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = conn.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if body.len() + n > MAX_RELEASE_JSON {
+            anyhow::bail!("Release JSON larger than {MAX_RELEASE_JSON} bytes");
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+
+    let json = String::from_utf8_lossy(&body);
+    check_release(&json, crate::sysinfo::firmware_version())
+}
+
+/// Download and flash firmware via OTA.
+///
+/// Calls `progress_cb(downloaded_bytes, total_bytes)` after every chunk;
+/// `total_bytes` is 0 if the server sent no `Content-Length`. On any error
+/// the update is aborted and the running slot stays bootable.
+#[cfg(not(feature = "simulator"))]
+pub fn perform_update(url: &str, progress_cb: impl FnMut(usize, usize)) -> anyhow::Result<()> {
+    use esp_idf_svc::http::Method;
+
+    let mut conn = https_connection()?;
+    conn.initiate_request(Method::Get, url, &[("User-Agent", "VRCBadge")])?;
+    conn.initiate_response()?;
+    let status = conn.status();
+    if status != 200 {
+        anyhow::bail!("Firmware download returned HTTP {status}");
+    }
+    let total = conn
+        .header("Content-Length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    log::info!("OTA: downloading {total} bytes from {url}");
+
+    flash_image(total, |buf| Ok(conn.read(buf)?), progress_cb, true)?;
+    Ok(())
+}
+
+/// Stream an app image from `read` into the inactive OTA slot.
+///
+/// The header is validated with [`image::validate_image_header`] before the slot is
+/// erased, and `EspOtaUpdate::complete` verifies the image checksum and
+/// switches the boot partition only after every byte is written. With
+/// `activate` false the boot partition is switched straight back, leaving
+/// the image staged for [`activate_staged`]. `total` is the expected size
+/// (0 = unknown). Rejected images come back as [`image::InvalidImage`];
+/// either way the running slot stays bootable.
+#[cfg(not(feature = "simulator"))]
+pub fn flash_image(
+    total: usize,
+    mut read: impl FnMut(&mut [u8]) -> anyhow::Result<usize>,
+    mut progress_cb: impl FnMut(usize, usize),
+    activate: bool,
+) -> anyhow::Result<image::AppImageInfo> {
+    use esp_idf_svc::io::Write;
+    use esp_idf_svc::ota::EspOta;
+
+    use image::{validate_image_header, InvalidImage, APP_HEADER_LEN};
+
+    let slot_size = update_slot_size()?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+
+    // Fill the header before touching flash.
+    let mut filled = 0;
+    while filled < APP_HEADER_LEN {
+        let n = read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    let info = validate_image_header(&buf[..filled], (total != 0).then_some(total), slot_size)?;
+    log::info!(
+        "OTA: image {} v{} ({total} bytes)",
+        info.project_name,
+        info.version
+    );
+
+    let mut ota = EspOta::new()?;
+    let mut update = ota.initiate_update()?;
+    let mut written = 0;
+    let result = (|| -> anyhow::Result<()> {
+        let mut n = filled;
+        while n > 0 {
+            written += n;
+            if written > slot_size {
+                return Err(
+                    InvalidImage(format!("Image exceeds OTA slot ({slot_size} bytes)")).into(),
+                );
+            }
+            update.write_all(&buf[..n])?;
+            progress_cb(written, total);
+            n = read(&mut buf)?;
+        }
+        if total != 0 && written != total {
+            return Err(
+                InvalidImage(format!("Image truncated: got {written} of {total} bytes")).into(),
+            );
+        }
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => {
+            update
+                .complete()
+                .map_err(|e| InvalidImage(format!("Image verification failed: {e}")))?;
+            if !activate {
+                // Keep booting the running slot until the upload is confirmed.
+                esp_idf_sys::esp!(unsafe {
+                    esp_idf_sys::esp_ota_set_boot_partition(
+                        esp_idf_sys::esp_ota_get_running_partition(),
+                    )
+                })?;
+                log::info!("OTA: {written} bytes written and verified, staged until confirmed");
+                return Ok(info);
+            }
+            log::info!("OTA: {written} bytes written and verified, next boot uses the new image");
+            Ok(info)
+        }
+        Err(e) => {
+            if let Err(abort_err) = update.abort() {
+                log::warn!("OTA abort failed: {abort_err}");
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Boot the image left staged by `flash_image(.., activate: false)` from
+/// the next restart on. ESP-IDF verifies it again before switching.
+#[cfg(not(feature = "simulator"))]
+pub fn activate_staged() -> anyhow::Result<()> {
+    let partition = unsafe { esp_idf_sys::esp_ota_get_next_update_partition(std::ptr::null()) };
+    if partition.is_null() {
+        anyhow::bail!("No OTA update partition (partition table lacks ota_0/ota_1)");
+    }
+    esp_idf_sys::esp!(unsafe { esp_idf_sys::esp_ota_set_boot_partition(partition) })?;
+    log::info!("OTA: staged image activated, next boot uses it");
+    Ok(())
+}
+
+/// Size of the OTA slot the next update will be written to.
+#[cfg(not(feature = "simulator"))]
+fn update_slot_size() -> anyhow::Result<usize> {
+    let partition = unsafe { esp_idf_sys::esp_ota_get_next_update_partition(std::ptr::null()) };
+    if partition.is_null() {
+        anyhow::bail!("No OTA update partition (partition table lacks ota_0/ota_1)");
+    }
+    Ok(unsafe { (*partition).size } as usize)
+}
+
+/// Mark running firmware as valid (prevents rollback on next reboot).
+#[cfg(not(feature = "simulator"))]
+pub fn mark_valid() -> anyhow::Result<()> {
+    let mut ota = esp_idf_svc::ota::EspOta::new()?;
+    let slot = ota.get_running_slot()?;
+    ota.mark_running_slot_valid()?;
+    log::info!("OTA: running from {} (marked valid)", slot.label);
+    Ok(())
+}
+
+/// Reboot into the freshly flashed image.
+#[cfg(not(feature = "simulator"))]
+pub fn restart() -> ! {
+    esp_idf_hal::reset::restart()
+}
+
+/// HTTPS client with the certificate bundle attached and redirects followed
+/// (asset downloads bounce through `objects.githubusercontent.com`).
+#[cfg(not(feature = "simulator"))]
+fn https_connection() -> anyhow::Result<esp_idf_svc::http::client::EspHttpConnection> {
+    use esp_idf_svc::http::client::{Configuration, EspHttpConnection, FollowRedirectsPolicy};
+
+    let config = Configuration {
+        buffer_size: Some(4096),
+        // Redirect targets carry long signed query strings.
+        buffer_size_tx: Some(2048),
+        timeout: Some(std::time::Duration::from_secs(30)),
+        follow_redirects_policy: FollowRedirectsPolicy::FollowAll,
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        ..Default::default()
+    };
+    Ok(EspHttpConnection::new(&config)?)
+}
+
+/// Simulator stand-in: parses `sim-storage/release.json` (a saved GitHub
+/// `releases/latest` response) if present, else reports up to date.
+#[cfg(feature = "simulator")]
+pub fn check_update() -> anyhow::Result<Option<UpdateInfo>> {
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    match std::fs::read_to_string(format!("{}/release.json", crate::simulator::STORAGE_DIR)) {
+        Ok(json) => check_release(&json, crate::sysinfo::firmware_version()),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Simulator stand-in: fakes a 1.5 MB download for the progress bar, then
+/// fails since there is no flash to write to.
+#[cfg(feature = "simulator")]
+pub fn perform_update(_url: &str, mut progress_cb: impl FnMut(usize, usize)) -> anyhow::Result<()> {
+    const TOTAL: usize = 1536 * 1024;
+    for downloaded in (CHUNK_SIZE..TOTAL).step_by(64 * 1024) {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        progress_cb(downloaded, TOTAL);
+    }
+    anyhow::bail!("OTA is not available in the simulator")
+}
+
+/// Simulator stand-in: quit instead of rebooting.
+#[cfg(feature = "simulator")]
+pub fn restart() -> ! {
+    log::info!("Simulator: restart requested, exiting");
+    std::process::exit(0)
+}
+
+// ---------------------------------------------------------------------------
+// Background worker + UI polling
+// ---------------------------------------------------------------------------
+
+/// Where the update flow currently is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OtaState {
+    Idle,
+    Checking,
+    UpToDate,
+    UpdateAvailable,
+    Downloading,
+    Installing,
+    /// An uploaded image (version) is verified and waits for Install on
+    /// the badge.
+    #[cfg(not(feature = "simulator"))]
+    Staged(String),
+    Done,
+    Failed(String),
+}
+
+/// Shared between the OTA worker thread and the main loop.
+#[derive(Debug)]
+pub struct OtaProgress {
+    pub state: OtaState,
+    pub downloaded: usize,
+    pub total: usize,
+    /// Release found by the last successful check.
+    pub update: Option<UpdateInfo>,
+    /// Set by the worker on every change, cleared by [`OtaUpdater::poll`].
+    changed: bool,
+}
+
+impl OtaProgress {
+    /// Download progress in 0.0..=1.0 (0.0 while the size is unknown).
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            (self.downloaded as f32 / self.total as f32).min(1.0)
+        }
+    }
+
+    /// Whether a worker thread is running.
+    pub fn busy(&self) -> bool {
+        matches!(
+            self.state,
+            OtaState::Checking | OtaState::Downloading | OtaState::Installing | OtaState::Done
+        )
+    }
+
+    /// Status line for the About page.
+    pub fn status_text(&self) -> String {
+        match &self.state {
+            OtaState::Idle => "Not checked".into(),
+            OtaState::Checking => "Checking...".into(),
+            OtaState::UpToDate => "Up to date".into(),
+            OtaState::UpdateAvailable => match &self.update {
+                Some(info) => format!("v{} available", info.version),
+                None => "Update available".into(),
+            },
+            OtaState::Downloading => {
+                format!("Downloading {}%", (self.fraction() * 100.0) as u32)
+            }
+            OtaState::Installing => "Verifying...".into(),
+            #[cfg(not(feature = "simulator"))]
+            OtaState::Staged(version) => format!("v{version} uploaded"),
+            OtaState::Done => "Restarting...".into(),
+            OtaState::Failed(msg) => format!("Failed: {msg}"),
+        }
+    }
+
+    /// Version of an upload waiting for Install on the badge.
+    fn staged_version(&self) -> Option<String> {
+        #[cfg(not(feature = "simulator"))]
+        if let OtaState::Staged(version) = &self.state {
+            return Some(version.clone());
+        }
+        None
+    }
+
+    fn set_state(&mut self, state: OtaState) {
+        self.state = state;
+        self.changed = true;
+    }
+}
+
+/// Owns the shared [`OtaProgress`] and runs check / install on worker
+/// threads. Cheap to clone (one `Arc`), like `WiFiState`.
+#[derive(Clone)]
+pub struct OtaUpdater {
+    progress: Arc<Mutex<OtaProgress>>,
+}
+
+impl OtaUpdater {
+    pub fn new() -> Self {
+        Self {
+            progress: Arc::new(Mutex::new(OtaProgress {
+                state: OtaState::Idle,
+                downloaded: 0,
+                total: 0,
+                update: None,
+                changed: true,
+            })),
+        }
+    }
+
+    /// Spawn a background release check. Ignored while another OTA
+    /// operation is running.
+    pub fn spawn_check(&self) {
+        if !self.begin(OtaState::Checking) {
+            return;
+        }
+        let progress = self.progress.clone();
+        std::thread::Builder::new()
+            .name("ota-check".into())
+            .stack_size(12 * 1024)
+            .spawn(move || {
+                let outcome = check_update();
+                let Ok(mut p) = progress.lock() else { return };
+                match outcome {
+                    Ok(Some(info)) => {
+                        log::info!("OTA: update available: v{}", info.version);
+                        p.update = Some(info);
+                        p.set_state(OtaState::UpdateAvailable);
+                    }
+                    Ok(None) => {
+                        log::info!("OTA: firmware is up to date");
+                        p.update = None;
+                        p.set_state(OtaState::UpToDate);
+                    }
+                    Err(e) => {
+                        log::warn!("OTA: update check failed: {e}");
+                        p.set_state(OtaState::Failed(format!("{e}")));
+                    }
+                }
+            })
+            .ok();
+    }
+
+    /// Spawn the download + flash of the release found by the last check,
+    /// then reboot into it. A staged upload is activated instead.
+    pub fn spawn_install(&self) {
+        #[cfg(not(feature = "simulator"))]
+        if self.install_staged() {
+            return;
+        }
+        let url = match self.progress.lock() {
+            Ok(p) if !p.busy() => match &p.update {
+                Some(info) => info.download_url.clone(),
+                None => return,
+            },
+            _ => return,
+        };
+        if !self.begin(OtaState::Downloading) {
+            return;
+        }
+        let updater = self.clone();
+        std::thread::Builder::new()
+            .name("ota-update".into())
+            .stack_size(12 * 1024)
+            .spawn(move || {
+                match perform_update(&url, |downloaded, total| updater.report(downloaded, total)) {
+                    Ok(()) => updater.restart_soon(),
+                    Err(e) => updater.fail(&e),
+                }
+            })
+            .ok();
+    }
+
+    /// Start an upload pushed over HTTP (`POST /api/firmware`). Returns
+    /// `false` if another OTA operation is already running.
+    #[cfg(not(feature = "simulator"))]
+    pub fn begin_upload(&self, total: usize) -> bool {
+        if !self.begin(OtaState::Downloading) {
+            return false;
+        }
+        if let Ok(mut p) = self.progress.lock() {
+            p.total = total;
+        }
+        true
+    }
+
+    /// Upload finished: wait for Install on the badge before booting it.
+    #[cfg(not(feature = "simulator"))]
+    pub fn stage(&self, version: &str) {
+        if let Ok(mut p) = self.progress.lock() {
+            p.set_state(OtaState::Staged(version.to_string()));
+        }
+    }
+
+    /// Activate a staged upload and reboot into it. Returns `false` if
+    /// nothing is staged.
+    #[cfg(not(feature = "simulator"))]
+    fn install_staged(&self) -> bool {
+        let staged = self
+            .progress
+            .lock()
+            .is_ok_and(|p| matches!(p.state, OtaState::Staged(_)));
+        if staged {
+            match activate_staged() {
+                Ok(()) => self.restart_soon(),
+                Err(e) => self.fail(&e),
+            }
+        }
+        staged
+    }
+
+    /// Record `written` of `total` bytes flashed.
+    pub fn report(&self, written: usize, total: usize) {
+        if let Ok(mut p) = self.progress.lock() {
+            p.downloaded = written;
+            p.total = total;
+            if total != 0 && written >= total {
+                p.set_state(OtaState::Installing);
+            }
+            p.changed = true;
+        }
+    }
+
+    /// Record a failed download / upload.
+    pub fn fail(&self, err: &anyhow::Error) {
+        log::error!("OTA: update failed: {err}");
+        if let Ok(mut p) = self.progress.lock() {
+            p.set_state(OtaState::Failed(format!("{err}")));
+        }
+    }
+
+    /// The new image is written and verified: show "Restarting..." for a
+    /// couple of main-loop ticks, then reboot into it. Gives the HTTP
+    /// handler time to send its response.
+    pub fn restart_soon(&self) {
+        if let Ok(mut p) = self.progress.lock() {
+            p.set_state(OtaState::Done);
+        }
+        std::thread::Builder::new()
+            .name("ota-restart".into())
+            .stack_size(2048)
+            .spawn(|| {
+                std::thread::sleep(std::time::Duration::from_secs(3));
+                restart();
+            })
+            .ok();
+    }
+
+    /// `GET /api/firmware` body: running version plus any update in flight.
+    #[cfg(not(feature = "simulator"))]
+    pub fn status_json(&self) -> String {
+        let Ok(p) = self.progress.lock() else {
+            return "{}".into();
+        };
+        serde_json::json!({
+            "version": crate::sysinfo::firmware_version(),
+            "status": p.status_text(),
+            "busy": p.busy(),
+            "written": p.downloaded,
+            "total": p.total,
+        })
+        .to_string()
+    }
+
+    /// Apply any progress change to the About page. Must be called from the
+    /// main thread.
+    pub fn poll(&self, ui: &BadgeUI) {
+        let Ok(mut p) = self.progress.try_lock() else {
+            return;
+        };
+        if !p.changed {
+            return;
+        }
+        p.changed = false;
+
+        let staged = p.staged_version();
+        let available = p.state == OtaState::UpdateAvailable;
+        ui.set_update_available(available || staged.is_some());
+        ui.set_update_version(
+            staged
+                .clone()
+                .or_else(|| p.update.as_ref().map(|info| info.version.clone()))
+                .unwrap_or_default()
+                .into(),
+        );
+        ui.set_update_status(p.status_text().into());
+        ui.set_update_busy(p.busy());
+        ui.set_update_progress(match p.state {
+            OtaState::Downloading | OtaState::Installing | OtaState::Done => p.fraction(),
+            _ => 0.0,
+        });
+
+        if let (true, Some(info)) = (available, &p.update) {
+            ui.set_toast_message(format!("Update available: v{}", info.version).into());
+            ui.set_toast_visible(true);
+        }
+        if let Some(version) = staged {
+            ui.set_toast_message(
+                format!("v{version} uploaded: Install it on the About page").into(),
+            );
+            ui.set_toast_visible(true);
+        }
+    }
+
+    /// Move to `state` unless a worker is already running.
+    fn begin(&self, state: OtaState) -> bool {
+        let Ok(mut p) = self.progress.lock() else {
+            return false;
+        };
+        if p.busy() {
+            return false;
+        }
+        p.downloaded = 0;
+        p.total = 0;
+        p.set_state(state);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `releases/latest` as GitHub sends it, trimmed to one release with a
+    /// firmware image and its checksum file.
+    const RELEASE: &str = include_str!("testdata/release_latest.json");
+
+    /// A release published without build artifacts.
+    const RELEASE_WITHOUT_ASSETS: &str = include_str!("testdata/release_no_assets.json");
+
+    #[test]
+    fn newer_release_is_found() {
+        let update = check_release(RELEASE, "0.1.0").unwrap().unwrap();
+        assert_eq!(update.version, "0.2.0");
+        assert_eq!(
+            update.download_url,
+            "https://github.com/Hebububu/VRCBadge/releases/download/v0.2.0/firmware.bin"
+        );
+    }
+
+    #[test]
+    fn same_or_older_release_is_no_update() {
+        assert_eq!(check_release(RELEASE, "0.2.0").unwrap(), None);
+        assert_eq!(check_release(RELEASE, "0.10.0").unwrap(), None);
+        // Not newer, so the missing image doesn't matter
+        assert_eq!(
+            check_release(RELEASE_WITHOUT_ASSETS, "0.3.0").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn release_without_firmware_is_an_error() {
+        let err = check_release(RELEASE_WITHOUT_ASSETS, "0.1.0").unwrap_err();
+        assert!(err.to_string().contains("firmware.bin"), "{err}");
+    }
+
+    #[test]
+    fn other_responses_are_errors() {
+        // What the API answers for a repository without releases
+        let not_found =
+            r#"{"message": "Not Found", "documentation_url": "https://docs.github.com/rest"}"#;
+        assert!(check_release(not_found, "0.1.0").is_err());
+        assert!(check_release("<html>rate limited</html>", "0.1.0").is_err());
+        assert!(check_release("", "0.1.0").is_err());
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(is_newer("0.10.0", "0.9.0"));
+        assert!(is_newer("1.0.0", "0.99.99"));
+        assert!(is_newer("0.2", "0.1.9"));
+        assert!(!is_newer("0.2.0", "0.2.0"));
+        assert!(!is_newer("0.1.9", "0.2.0"));
+        // Longer wins when one is a prefix of the other
+        assert!(is_newer("0.2.0.1", "0.2.0"));
+    }
+
+    #[test]
+    fn version_suffixes_are_ignored() {
+        assert!(!is_newer("0.2.0-rc1", "0.2.0"));
+        assert!(is_newer("0.3.0-rc1", "0.2.0"));
+        assert!(!is_newer("0.2.0+abc", "0.2.0"));
+    }
+
+    #[test]
+    fn unparseable_versions_are_never_newer() {
+        assert!(!is_newer("latest", "0.1.0"));
+        assert!(!is_newer("", "0.1.0"));
+        assert!(!is_newer("1.x.0", "0.1.0"));
+        assert!(!is_newer("1.0.0", "dev"));
+    }
+}
@@ -5,104 +5,737 @@
 //! loop polls those slots once per ~2-second tick, applies the update to
 //! the Slint UI, and persists it to NVS / SPIFFS.
 //!
-//! Three slots live here (profile, avatar, background) plus the always-live
-//! `current_profile` snapshot used by `GET /api/profile`.
+//! Four slots live here (avatar, animated avatar, background, log levels),
+//! a flag for a cleared crash history, and the profile set shared with the HTTP server
+//! plus its queue of [`ProfileOp`]s to persist. Backups add two more: a
+//! checked restore to apply, and a request for the saved WiFi network
+//! (which only the main thread can read from NVS). Device settings are
+//! shared like profiles, with a flag for changes made over HTTP, and a
+//! requested restart is one more flag. A running [`factory_reset`] takes
+//! over the poll until the badge restarts. The web [`Auth`] state lives here
+//! too, so the poll can show a client's approval prompt on the badge.
 
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use esp_idf_svc::nvs::{EspNvs, NvsDefault};
-use slint::{Image, Rgb8Pixel, SharedPixelBuffer};
+use slint::Image;
 
-use crate::ui_helpers::{apply_profile_colors, apply_rounded_corners};
-use crate::{platform, profile, storage, web, BadgeUI};
+use crate::animation;
+use crate::auth::{Auth, SharedAuth};
+#[cfg(any(not(feature = "simulator"), test))]
+use crate::backup::{BackupSettings, WifiCredentials};
+use crate::factory_reset::{self, PendingFactoryReset, ResetStep};
+use crate::hal::{ImageStore, SharedBacklight, SharedKvStore};
+use crate::image_codec::Animation;
+use crate::log_filter::LogFilter;
+use crate::profile::{PendingProfileOps, ProfileOp, ProfileOps, ProfileSet, SharedProfiles};
+use crate::settings::{self, DeviceSettings, SharedSettings};
+use crate::ui_helpers::{
+    show_active_profile, show_approval, show_background, show_log_levels, show_profile,
+    show_profile_list, show_saved_avatar, show_settings,
+};
+use crate::{ota, storage, BadgeUI};
+
+/// An upload for one profile: the one active when it arrived, so switching
+/// profiles before the main loop gets to it doesn't move it to another.
+pub struct ProfileUpload<T> {
+    pub profile_id: u32,
+    pub data: T,
+}
+
+/// Shared state for passing image data from the HTTP thread to the main loop.
+pub type SharedImageData = Arc<Mutex<Option<ProfileUpload<Vec<u8>>>>>;
+
+/// Animated avatar decoded by the HTTP thread, ready to save and play.
+pub type PendingAnimation = Arc<Mutex<Option<ProfileUpload<Animation>>>>;
+
+/// Log filter set over HTTP. Already active in the logger; the main loop
+/// only persists it and refreshes the Logs page.
+pub type PendingLogFilter = Arc<Mutex<Option<LogFilter>>>;
+
+/// Configuration from `POST /api/restore`, already checked, with its
+/// images written under the new profile ids. The main loop saves it to NVS
+/// and restarts.
+#[cfg(any(not(feature = "simulator"), test))]
+pub struct RestoredConfig {
+    pub profiles: ProfileSet,
+    pub settings: BackupSettings,
+}
+
+#[cfg(any(not(feature = "simulator"), test))]
+pub type PendingRestore = Arc<Mutex<Option<RestoredConfig>>>;
+
+/// `GET /api/backup?wifi=1` asking the main loop for the saved WiFi network.
+#[cfg(any(not(feature = "simulator"), test))]
+#[derive(Default)]
+pub enum WifiExport {
+    #[default]
+    Idle,
+    /// Set by the HTTP handler, which then waits for `Ready`.
+    Requested,
+    Ready(Option<WifiCredentials>),
+}
+
+#[cfg(any(not(feature = "simulator"), test))]
+pub type PendingWifiExport = Arc<Mutex<WifiExport>>;
 
 /// All shared slots used to hand updates from the HTTP server to the main loop.
 ///
-/// Cheaply cloneable: each field is an `Arc`. `web::init` clones the inner
-/// fields it needs.
+/// Cheaply cloneable: each field is an `Arc`. The fields the HTTP handlers
+/// need are cloned into a `web::WebContext`.
 #[derive(Clone)]
 pub struct PendingState {
-    pub avatar: web::SharedImageData,
-    pub background: web::SharedImageData,
-    pub profile: profile::PendingProfile,
-    /// Always-live snapshot of the currently displayed profile. Used by the
-    /// HTTP server to serve `GET /api/profile`.
-    pub current_profile: profile::CurrentProfile,
+    /// Avatar upload.
+    pub avatar: SharedImageData,
+    /// Animated avatar upload; replaces a still one.
+    pub avatar_animation: PendingAnimation,
+    /// Background upload; an empty vec clears it.
+    pub background: SharedImageData,
+    pub log_filter: PendingLogFilter,
+    /// Set by `DELETE /api/crashes`; the About page's crash row is cleared.
+    pub crashes_cleared: Arc<AtomicBool>,
+    /// All saved profiles. Edited in place by the `/api/profile*` handlers
+    /// and by on-device switching.
+    pub profiles: SharedProfiles,
+    /// Changes made to `profiles` over HTTP, still to be persisted.
+    pub profile_ops: PendingProfileOps,
+    /// Backup restore and WiFi export, over HTTP only.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub restore: PendingRestore,
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub wifi_export: PendingWifiExport,
+    pub factory_reset: PendingFactoryReset,
+    /// Device settings. Edited in place by `POST /api/settings` and the
+    /// Settings page.
+    pub settings: SharedSettings,
+    /// Set by `POST /api/settings`; the main loop saves and applies them.
+    pub settings_changed: Arc<AtomicBool>,
+    /// Set by `POST /api/restart` and the Access Point page; the main loop
+    /// restarts once everything pending is saved.
+    pub restart: Arc<AtomicBool>,
+    /// Web sessions; an approval prompt here is shown on the badge.
+    pub auth: SharedAuth,
 }
 
 impl PendingState {
-    pub fn new(initial_profile: profile::Profile) -> Self {
+    pub fn new(profiles: ProfileSet, settings: DeviceSettings, auth: Auth) -> Self {
         Self {
-            avatar: std::sync::Arc::new(std::sync::Mutex::new(None)),
-            background: std::sync::Arc::new(std::sync::Mutex::new(None)),
-            profile: std::sync::Arc::new(std::sync::Mutex::new(None)),
-            current_profile: std::sync::Arc::new(std::sync::Mutex::new(initial_profile)),
+            avatar: Arc::new(Mutex::new(None)),
+            avatar_animation: Arc::new(Mutex::new(None)),
+            background: Arc::new(Mutex::new(None)),
+            log_filter: Arc::new(Mutex::new(None)),
+            crashes_cleared: Arc::new(AtomicBool::new(false)),
+            profiles: Arc::new(Mutex::new(profiles)),
+            profile_ops: Arc::new(Mutex::new(ProfileOps::default())),
+            #[cfg(any(not(feature = "simulator"), test))]
+            restore: Arc::new(Mutex::new(None)),
+            #[cfg(any(not(feature = "simulator"), test))]
+            wifi_export: Arc::new(Mutex::new(WifiExport::Idle)),
+            factory_reset: Arc::new(Mutex::new(None)),
+            settings: Arc::new(Mutex::new(settings)),
+            settings_changed: Arc::new(AtomicBool::new(false)),
+            restart: Arc::new(AtomicBool::new(false)),
+            auth: Arc::new(Mutex::new(auth)),
         }
     }
 
     /// Drain any pending profile / avatar / background updates and apply
     /// them to the UI + persistent storage. Must be called from the main
     /// thread (NVS is `!Send`).
-    pub fn poll_into_ui(&self, ui: &BadgeUI, nvs: &Rc<RefCell<EspNvs<NvsDefault>>>) {
-        // Profile update
-        if let Ok(mut pending) = self.profile.try_lock() {
-            if let Some(new_profile) = pending.take() {
-                ui.set_display_name(new_profile.display_name.clone().into());
-                ui.set_tagline(new_profile.tagline.clone().into());
-                ui.set_twitter_handle(new_profile.twitter_handle.clone().into());
-                ui.set_discord_handle(new_profile.discord_handle.clone().into());
-                apply_profile_colors(ui, &new_profile);
-                // Update current profile snapshot for future GET /api/profile
-                if let Ok(mut current) = self.current_profile.try_lock() {
-                    *current = new_profile.clone();
+    pub fn poll_into_ui(
+        &self,
+        ui: &BadgeUI,
+        nvs: &SharedKvStore,
+        images: &dyn ImageStore,
+        backlight: &SharedBacklight,
+    ) {
+        // Factory reset: one step per tick and nothing else until the
+        // restart, so no pending change is written back after the erase.
+        let reset = match self.factory_reset.try_lock() {
+            Ok(mut step) if *step == Some(ResetStep::Confirm) => {
+                // Asked for over HTTP: erasing waits for a tap on the badge.
+                *step = None;
+                ui.set_current_page(1);
+                ui.set_factory_reset_confirm(true);
+                None
+            }
+            Ok(step) => *step,
+            Err(_) => None,
+        };
+        if let Some(step) = reset {
+            let next = factory_reset::advance(step, ui, nvs, images);
+            if let Ok(mut step) = self.factory_reset.lock() {
+                *step = next;
+            }
+            return;
+        }
+
+        // Restore from backup: replaces everything below, then restarts.
+        #[cfg(any(not(feature = "simulator"), test))]
+        {
+            let restore = match self.restore.try_lock() {
+                Ok(mut pending) => pending.take(),
+                Err(_) => None,
+            };
+            if let Some(restore) = restore {
+                self.apply_restore(restore, ui, nvs, images);
+                return;
+            }
+
+            // Saved WiFi network for a backup
+            if let Ok(mut export) = self.wifi_export.try_lock() {
+                if matches!(*export, WifiExport::Requested) {
+                    let wifi = storage::load_wifi_credentials(&*nvs.borrow())
+                        .map(|(ssid, password)| WifiCredentials { ssid, password });
+                    *export = WifiExport::Ready(wifi);
                 }
-                storage::save_profile(&mut nvs.borrow_mut(), &new_profile);
-                log::info!("Badge profile updated");
             }
         }
 
+        // Profile changes
+        let ops = match self.profile_ops.try_lock() {
+            Ok(mut ops) => ops.take(),
+            Err(_) => Vec::new(),
+        };
+        if !ops.is_empty() {
+            let result = self.apply_profile_ops(ops, ui, nvs, images);
+            if let Ok(mut ops) = self.profile_ops.lock() {
+                ops.finish(result);
+            }
+        }
+
+        // Log level change
+        if let Ok(mut pending) = self.log_filter.try_lock() {
+            if let Some(filter) = pending.take() {
+                show_log_levels(ui, &filter);
+                settings::update(&self.settings, &mut *nvs.borrow_mut(), |s| {
+                    s.log_levels = filter.to_string()
+                });
+            }
+        }
+
+        // Settings changed over HTTP (log levels are already live)
+        if self.settings_changed.swap(false, Ordering::Relaxed) {
+            if let Ok(s) = self.settings.lock() {
+                storage::save_settings(&mut *nvs.borrow_mut(), &s);
+                show_settings(ui, &s);
+                show_log_levels(ui, &s.log_filter());
+                if let Err(e) = backlight.borrow_mut().set_percent(s.brightness as f32) {
+                    log::warn!("Failed to set brightness: {e}");
+                }
+                log::info!("Device settings updated");
+            }
+        }
+
+        // Web clients waiting for approval: show the first one's code, and
+        // move on once it has been answered or has lapsed.
+        if let Ok(mut auth) = self.auth.try_lock() {
+            show_approval(ui, auth.prompt(Instant::now()));
+        }
+
+        // Crash history deleted
+        if self.crashes_cleared.swap(false, Ordering::Relaxed) {
+            ui.set_about_last_crash("".into());
+        }
+
+        // Uploads are saved for the profile they were sent for, and only
+        // shown if it is still the active one.
+        let (active, ids) = match self.profiles.try_lock() {
+            Ok(set) => (
+                set.active_id(),
+                set.profiles.keys().copied().collect::<Vec<_>>(),
+            ),
+            // Leave uploads queued until the next tick.
+            Err(_) => return,
+        };
+
         // Avatar image upload
-        if let Ok(mut pending) = self.avatar.try_lock() {
-            if let Some(mut rgb_data) = pending.take() {
-                // Save raw image first (without rounded corners) so reload
-                // works if border-radius changes in the future.
-                storage::save_image("avatar", &rgb_data);
-                apply_rounded_corners(
-                    &mut rgb_data,
-                    storage::AVATAR_WIDTH,
-                    storage::AVATAR_HEIGHT,
-                    20,
-                    [0x2a, 0x2a, 0x4a],
-                );
-                let buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
-                    &rgb_data,
-                    storage::AVATAR_WIDTH,
-                    storage::AVATAR_HEIGHT,
-                );
-                ui.set_avatar_image(Image::from_rgb8(buffer));
-                log::info!("Avatar image updated");
+        if let Some(upload) = take_upload(&self.avatar, &ids) {
+            let id = upload.profile_id;
+            // Save raw image first (without rounded corners) so reload
+            // works if border-radius changes in the future.
+            storage::save_image(
+                images,
+                &storage::avatar_name(id),
+                storage::AVATAR_WIDTH,
+                storage::AVATAR_HEIGHT,
+                &upload.data,
+            );
+            if id == active {
+                animation::stop();
+                show_saved_avatar(ui, upload.data);
+            }
+            log::info!("Avatar image of profile {id} updated");
+        }
+
+        // Animated avatar upload (same file as the still avatar)
+        if let Some(upload) = take_upload(&self.avatar_animation, &ids) {
+            let id = upload.profile_id;
+            storage::save_animation(images, &storage::avatar_name(id), &upload.data);
+            if id == active {
+                animation::play(ui, upload.data);
             }
+            log::info!("Animated avatar of profile {id} updated");
         }
 
         // Background image upload. An empty vec signals "clear".
-        if let Ok(mut pending) = self.background.try_lock() {
-            if let Some(rgb_data) = pending.take() {
-                if rgb_data.is_empty() {
+        if let Some(upload) = take_upload(&self.background, &ids) {
+            let id = upload.profile_id;
+            let name = storage::background_name(id);
+            if upload.data.is_empty() {
+                if id == active {
                     ui.set_background_image(Image::default());
-                    storage::delete_image("background");
-                    log::info!("Background image cleared");
-                } else {
-                    let buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
-                        &rgb_data,
-                        platform::DISPLAY_WIDTH,
-                        platform::DISPLAY_HEIGHT,
-                    );
-                    ui.set_background_image(Image::from_rgb8(buffer));
-                    storage::save_image("background", &rgb_data);
-                    log::info!("Background image updated");
                 }
+                storage::delete_image(images, &name);
+                log::info!("Background image of profile {id} cleared");
+            } else {
+                if id == active {
+                    show_background(ui, &upload.data);
+                }
+                storage::save_image(
+                    images,
+                    &name,
+                    storage::BACKGROUND_WIDTH,
+                    storage::BACKGROUND_HEIGHT,
+                    &upload.data,
+                );
+                log::info!("Background image of profile {id} updated");
+            }
+        }
+
+        // Restart requested, e.g. to bring up new AP settings. Last, so
+        // everything above is saved first.
+        if self.restart.load(Ordering::Relaxed) {
+            log::info!("Restart requested, restarting");
+            ota::restart();
+        }
+    }
+
+    /// Persist profile changes made over HTTP and refresh the badge if the
+    /// active profile changed.
+    ///
+    /// A profile that can't be written goes back to its saved version (or
+    /// away, if it is new), so the badge shows what it will boot with. The
+    /// error is returned for the handler waiting on the change.
+    fn apply_profile_ops(
+        &self,
+        ops: Vec<ProfileOp>,
+        ui: &BadgeUI,
+        nvs: &SharedKvStore,
+        images: &dyn ImageStore,
+    ) -> Result<(), String> {
+        let Ok(mut set) = self.profiles.lock() else {
+            return Err("Profile store unavailable".into());
+        };
+        let mut nvs = nvs.borrow_mut();
+        // Edits to the active profile only need its fields redrawn; a switch
+        // also reloads its images.
+        let mut edited = false;
+        let mut switched = false;
+        let mut error = None;
+        for op in ops {
+            match op {
+                ProfileOp::Saved(id) => {
+                    let Some(profile) = set.get(id) else {
+                        continue;
+                    };
+                    if let Err(e) = storage::save_profile(&mut *nvs, id, profile) {
+                        log::error!("{e}");
+                        match storage::load_profile(&*nvs, id) {
+                            Some(saved) => {
+                                set.profiles.insert(id, saved);
+                            }
+                            None => {
+                                let _ = set.remove(id);
+                            }
+                        }
+                        switched = true;
+                        error = Some(e.to_string());
+                    }
+                    edited |= id == set.active_id();
+                }
+                #[cfg(any(not(feature = "simulator"), test))]
+                ProfileOp::Deleted(id) => storage::delete_profile(&mut *nvs, images, id),
+                #[cfg(any(not(feature = "simulator"), test))]
+                ProfileOp::Activated(_) => switched = true,
+            }
+        }
+        if let Err(e) = storage::save_profile_index(&mut *nvs, &set.index) {
+            log::error!("{e}");
+            error = Some(e.to_string());
+        }
+        if error.is_some() {
+            ui.set_toast_message("Profile not saved".into());
+            ui.set_toast_visible(true);
+        }
+
+        if switched {
+            show_active_profile(ui, images, &set);
+            log::info!("Switched to profile {}", set.active_id());
+        } else {
+            if edited {
+                show_profile(ui, set.active());
+                log::info!("Badge profile updated");
             }
+            show_profile_list(ui, &set);
         }
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Save a restored configuration and restart into it. If the profiles
+    /// can't be written the old ones stay, and the badge keeps running.
+    #[cfg(any(not(feature = "simulator"), test))]
+    fn apply_restore(
+        &self,
+        restore: RestoredConfig,
+        ui: &BadgeUI,
+        nvs: &SharedKvStore,
+        images: &dyn ImageStore,
+    ) {
+        let Ok(mut set) = self.profiles.lock() else {
+            return;
+        };
+        let mut nvs = nvs.borrow_mut();
+        if let Err(e) = storage::replace_profiles(&mut *nvs, images, &set, &restore.profiles) {
+            log::error!("Restore failed, keeping the current profiles: {e}");
+            ui.set_toast_message("Restore failed".into());
+            ui.set_toast_visible(true);
+            return;
+        }
+        *set = restore.profiles;
+        drop(set);
+
+        if let Some(wifi) = &restore.settings.wifi {
+            storage::save_wifi_credentials(&mut *nvs, &wifi.ssid, &wifi.password);
+        }
+        if let Some(device) = &restore.settings.device {
+            settings::update(&self.settings, &mut *nvs, |s| match s.patched(device) {
+                Ok(restored) => *s = restored,
+                Err(e) => log::error!("Restored device settings rejected: {e:?}"),
+            });
+        } else if let Some(filter) = restore
+            .settings
+            .log_levels
+            .as_deref()
+            .and_then(|spec| spec.parse::<LogFilter>().ok())
+        {
+            // Backups from before device settings were included.
+            settings::update(&self.settings, &mut *nvs, |s| {
+                s.log_levels = filter.to_string()
+            });
+        }
+        log::info!("Backup restored, restarting");
+        ota::restart();
+    }
+}
+
+/// Take the upload in `slot`, unless its profile (not in `ids`) was
+/// deleted since.
+fn take_upload<T>(slot: &Mutex<Option<ProfileUpload<T>>>, ids: &[u32]) -> Option<ProfileUpload<T>> {
+    let upload = slot.try_lock().ok()?.take()?;
+    if !ids.contains(&upload.profile_id) {
+        log::warn!("Upload for deleted profile {} dropped", upload.profile_id);
+        return None;
+    }
+    Some(upload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::hal::fake::{with_ui, FakeBacklight, MemoryImageStore, MemoryKvStore};
+    use crate::profile::Profile;
+
+    /// Pending state over in-memory NVS and SPIFFS, as loaded at boot.
+    struct Badge {
+        pending: PendingState,
+        nvs: Rc<RefCell<MemoryKvStore>>,
+        images: MemoryImageStore,
+        backlight: FakeBacklight,
+    }
+
+    impl Badge {
+        fn new() -> Self {
+            let nvs = Rc::new(RefCell::new(MemoryKvStore::default()));
+            let images = MemoryImageStore::default();
+            let profiles = storage::load_profiles(&mut *nvs.borrow_mut(), &images);
+            let settings = storage::load_settings(&mut *nvs.borrow_mut());
+            Self {
+                pending: PendingState::new(profiles, settings, Auth::new("123456".into())),
+                nvs,
+                images,
+                backlight: FakeBacklight::default(),
+            }
+        }
+
+        fn poll(&self, ui: &BadgeUI) {
+            let nvs: SharedKvStore = self.nvs.clone();
+            let backlight: SharedBacklight = Rc::new(RefCell::new(self.backlight.clone()));
+            self.pending
+                .poll_into_ui(ui, &nvs, &self.images, &backlight);
+        }
+
+        fn active_id(&self) -> u32 {
+            self.pending.profiles.lock().unwrap().active_id()
+        }
+    }
+
+    #[test]
+    fn profile_changes_are_saved_and_shown() {
+        with_ui(|ui| {
+            let badge = Badge::new();
+            let id = {
+                let mut set = badge.pending.profiles.lock().unwrap();
+                let profile = Profile {
+                    display_name: "Con".into(),
+                    ..Profile::default()
+                };
+                let id = set.create("Convention", profile).unwrap();
+                set.activate(id).unwrap();
+                id
+            };
+            let ticket = {
+                let mut ops = badge.pending.profile_ops.lock().unwrap();
+                ops.push(ProfileOp::Saved(id));
+                ops.push(ProfileOp::Activated(id))
+            };
+            assert_eq!(
+                badge.pending.profile_ops.lock().unwrap().outcome(ticket),
+                None
+            );
+            badge.poll(ui);
+
+            let ops = badge.pending.profile_ops.lock().unwrap();
+            assert!(ops.is_empty());
+            assert_eq!(ops.outcome(ticket), Some(Ok(())));
+            assert_eq!(ui.get_display_name(), "Con");
+            let saved = storage::load_profiles(&mut *badge.nvs.borrow_mut(), &badge.images);
+            assert_eq!(saved.active_id(), id);
+            assert_eq!(saved.active().display_name, "Con");
+        });
+    }
+
+    #[test]
+    fn a_failed_profile_save_is_reported_and_undone() {
+        with_ui(|ui| {
+            let badge = Badge::new();
+            let (edited, created) = {
+                let mut set = badge.pending.profiles.lock().unwrap();
+                let edited = set.active_id();
+                let profile = Profile {
+                    display_name: "Unsaved".into(),
+                    ..Profile::default()
+                };
+                set.update(edited, None, Some(profile.clone())).unwrap();
+                let created = set.create("Convention", profile).unwrap();
+                (edited, created)
+            };
+            let ticket = {
+                let mut ops = badge.pending.profile_ops.lock().unwrap();
+                ops.push(ProfileOp::Saved(edited));
+                ops.push(ProfileOp::Saved(created))
+            };
+            badge.nvs.borrow_mut().full = true;
+            badge.poll(ui);
+
+            let outcome = badge.pending.profile_ops.lock().unwrap().outcome(ticket);
+            assert!(matches!(outcome, Some(Err(_))), "{outcome:?}");
+            assert!(ui.get_toast_visible());
+            // Back to what the badge will boot with
+            let set = badge.pending.profiles.lock().unwrap();
+            assert_eq!(set.active().display_name, Profile::default().display_name);
+            assert!(set.get(created).is_none());
+            assert_eq!(set.index.entries.len(), 1);
+        });
+    }
+
+    /// Avatar and background sized RGB888 uploads for profile `id`.
+    fn queue_uploads(badge: &Badge, id: u32) {
+        let avatar_len = (storage::AVATAR_WIDTH * storage::AVATAR_HEIGHT * 3) as usize;
+        let background_len = (storage::BACKGROUND_WIDTH * storage::BACKGROUND_HEIGHT * 3) as usize;
+        *badge.pending.avatar.lock().unwrap() = Some(ProfileUpload {
+            profile_id: id,
+            data: vec![0xFF; avatar_len],
+        });
+        *badge.pending.background.lock().unwrap() = Some(ProfileUpload {
+            profile_id: id,
+            data: vec![0x00; background_len],
+        });
+    }
+
+    #[test]
+    fn uploads_are_saved_for_the_active_profile() {
+        with_ui(|ui| {
+            let badge = Badge::new();
+            let active = badge.active_id();
+            queue_uploads(&badge, active);
+            badge.poll(ui);
+
+            assert!(badge.pending.avatar.lock().unwrap().is_none());
+            let avatar = storage::avatar_name(active);
+            let background = storage::background_name(active);
+            assert!(badge.images.read(&avatar).is_ok());
+            assert!(badge.images.read(&background).is_ok());
+
+            // An empty background clears it
+            *badge.pending.background.lock().unwrap() = Some(ProfileUpload {
+                profile_id: active,
+                data: Vec::new(),
+            });
+            badge.poll(ui);
+            assert!(badge.images.read(&background).is_err());
+            assert!(badge.images.read(&avatar).is_ok());
+        });
+    }
+
+    #[test]
+    fn uploads_stay_with_the_profile_they_were_sent_for() {
+        with_ui(|ui| {
+            let badge = Badge::new();
+            let first = badge.active_id();
+            queue_uploads(&badge, first);
+            // Switched on the badge before the next tick
+            let second = {
+                let mut set = badge.pending.profiles.lock().unwrap();
+                let second = set.create("Second", Profile::default()).unwrap();
+                set.activate(second).unwrap();
+                second
+            };
+            badge.poll(ui);
+
+            assert!(badge.images.read(&storage::avatar_name(first)).is_ok());
+            assert!(badge.images.read(&storage::background_name(first)).is_ok());
+            assert!(badge.images.read(&storage::avatar_name(second)).is_err());
+            assert!(badge
+                .images
+                .read(&storage::background_name(second))
+                .is_err());
+
+            // Deleting a profile takes its images with it, and one deleted in
+            // the meantime gets nothing
+            badge
+                .pending
+                .profiles
+                .lock()
+                .unwrap()
+                .remove(first)
+                .unwrap();
+            let ticket = badge
+                .pending
+                .profile_ops
+                .lock()
+                .unwrap()
+                .push(ProfileOp::Deleted(first));
+            badge.poll(ui);
+            assert!(badge.images.read(&storage::avatar_name(first)).is_err());
+            assert!(storage::load_profile(&*badge.nvs.borrow(), first).is_none());
+            assert_eq!(
+                badge.pending.profile_ops.lock().unwrap().outcome(ticket),
+                Some(Ok(()))
+            );
+
+            queue_uploads(&badge, first);
+            badge.poll(ui);
+            assert!(badge.images.read(&storage::avatar_name(first)).is_err());
+            assert!(badge.pending.avatar.lock().unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn settings_changes_are_saved_and_applied() {
+        with_ui(|ui| {
+            let badge = Badge::new();
+            *badge.pending.log_filter.lock().unwrap() = Some("warn".parse().unwrap());
+            badge.poll(ui);
+            let saved = storage::load_settings(&mut *badge.nvs.borrow_mut());
+            assert_eq!(saved.log_levels, "warn");
+
+            badge.pending.settings.lock().unwrap().brightness = 40;
+            badge
+                .pending
+                .settings_changed
+                .store(true, Ordering::Relaxed);
+            badge.poll(ui);
+            assert!(!badge.pending.settings_changed.load(Ordering::Relaxed));
+            assert_eq!(badge.backlight.percent.get(), 40.0);
+            let saved = storage::load_settings(&mut *badge.nvs.borrow_mut());
+            assert_eq!(saved.brightness, 40);
+            assert_eq!(saved.log_levels, "warn");
+        });
+    }
+
+    #[test]
+    fn wifi_export_reads_the_saved_network() {
+        with_ui(|ui| {
+            let badge = Badge::new();
+            storage::save_wifi_credentials(&mut *badge.nvs.borrow_mut(), "HomeNet", "secret123");
+            *badge.pending.wifi_export.lock().unwrap() = WifiExport::Requested;
+            badge.poll(ui);
+
+            let export = std::mem::take(&mut *badge.pending.wifi_export.lock().unwrap());
+            let WifiExport::Ready(Some(wifi)) = export else {
+                panic!("WiFi network not handed over");
+            };
+            assert_eq!(wifi.ssid, "HomeNet");
+            assert_eq!(wifi.password, "secret123");
+        });
+    }
+
+    #[test]
+    fn approval_prompts_come_up_in_turn() {
+        with_ui(|ui| {
+            let badge = Badge::new();
+            let now = Instant::now();
+            let codes: Vec<String> = {
+                let mut auth = badge.pending.auth.lock().unwrap();
+                ["t1", "t2"]
+                    .into_iter()
+                    .map(|token| {
+                        let client = std::net::Ipv4Addr::LOCALHOST;
+                        auth.login(client, "123456", token.into(), now).unwrap();
+                        match auth.check_change(token, now) {
+                            crate::auth::Access::Pending { code, .. } => code,
+                            other => panic!("expected a prompt, got {other:?}"),
+                        }
+                    })
+                    .collect()
+            };
+
+            badge.poll(ui);
+            assert!(ui.get_approval_visible());
+            assert_eq!(ui.get_approval_code(), codes[0].as_str());
+
+            let shown = ui.get_approval_code();
+            assert!(badge
+                .pending
+                .auth
+                .lock()
+                .unwrap()
+                .answer_prompt(&shown, true, now));
+            badge.poll(ui);
+            assert_eq!(ui.get_approval_code(), codes[1].as_str());
+
+            badge.pending.auth.lock().unwrap().logout("t2");
+            badge.poll(ui);
+            assert!(!ui.get_approval_visible());
+        });
+    }
+
+    #[test]
+    fn http_factory_reset_waits_for_the_badge() {
+        with_ui(|ui| {
+            let badge = Badge::new();
+            *badge.pending.factory_reset.lock().unwrap() = Some(ResetStep::Confirm);
+            badge.poll(ui);
+
+            assert_eq!(*badge.pending.factory_reset.lock().unwrap(), None);
+            assert!(ui.get_factory_reset_confirm());
+            assert_eq!(ui.get_current_page(), 1);
+            assert!(!badge.nvs.borrow().entries.is_empty());
+        });
     }
 }
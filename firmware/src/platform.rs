@@ -1,8 +1,13 @@
+#[cfg(not(feature = "simulator"))]
 use std::rc::Rc;
+#[cfg(not(feature = "simulator"))]
 use std::time::{Duration, Instant};
 
+#[cfg(not(feature = "simulator"))]
 use slint::platform::software_renderer::{MinimalSoftwareWindow, RepaintBufferType};
+#[cfg(not(feature = "simulator"))]
 use slint::platform::{Platform, PlatformError, WindowAdapter};
+#[cfg(not(feature = "simulator"))]
 use slint::PhysicalSize;
 
 /// Display dimensions (800 wide x 480 tall, native landscape).
@@ -13,11 +18,14 @@ pub const DISPLAY_HEIGHT: u32 = 480;
 ///
 /// Uses `MinimalSoftwareWindow` for full framebuffer rendering and
 /// `std::time::Instant` for timekeeping (available in esp-idf std mode).
+/// The simulator uses Slint's winit backend instead.
+#[cfg(not(feature = "simulator"))]
 pub struct Esp32Platform {
     window: Rc<MinimalSoftwareWindow>,
     start: Instant,
 }
 
+#[cfg(not(feature = "simulator"))]
 impl Esp32Platform {
     pub fn new() -> Self {
         let window = MinimalSoftwareWindow::new(RepaintBufferType::ReusedBuffer);
@@ -35,6 +43,7 @@ impl Esp32Platform {
     }
 }
 
+#[cfg(not(feature = "simulator"))]
 impl Platform for Esp32Platform {
     fn create_window_adapter(&self) -> Result<Rc<dyn WindowAdapter>, PlatformError> {
         Ok(self.window.clone())
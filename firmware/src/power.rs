@@ -0,0 +1,510 @@
+//! Power policy: how much of the badge runs, from the battery level, the
+//! idle state and how long the AP has gone unused.
+//!
+//! | Mode       | Below | Until above | Effect                                           |
+//! | ---------- | ----- | ----------- | ------------------------------------------------ |
+//! | `Normal`   |       |             | everything as configured                         |
+//! | `Saver`    | 20 %  | 25 %        | STA off, brightness ≤ 30 %, CPU ≤ 160 MHz, low battery badge |
+//! | `Critical` | 8 %   | 12 %        | as `Saver`, plus AP off and brightness ≤ 10 %    |
+//!
+//! The gaps between entering and leaving keep a cell hovering around a
+//! threshold from switching back and forth. Charging always means `Normal`.
+//!
+//! Independently of the battery, the AP can turn off after
+//! `ap_off_after_secs` with no client joined and no touch; a touch brings
+//! it back. DFS (dynamic frequency scaling) is always on, between 80 MHz
+//! and the mode's maximum; automatic light sleep is allowed while the
+//! screen is off. ESP-IDF only enters light sleep when no driver holds a
+//! power lock, which the WiFi AP does, so in practice that needs the AP
+//! off as well.
+//!
+//! [`PowerPolicy::step`] is pure: the event loop feeds it a
+//! [`PowerInput`] every ~2 s and applies the returned [`PowerActions`]
+//! (radios via `WifiController`, CPU via [`apply_cpu`]).
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::battery::BatteryReading;
+use crate::idle::IdleState;
+use crate::settings::{PowerSettings, MIN_BRIGHTNESS};
+
+/// Battery percent below which `Saver` starts, and above which it ends.
+pub const SAVER_ENTER: f32 = 20.0;
+pub const SAVER_EXIT: f32 = 25.0;
+
+/// Battery percent below which `Critical` starts, and above which it ends
+/// (back to `Saver`).
+pub const CRITICAL_ENTER: f32 = 8.0;
+pub const CRITICAL_EXIT: f32 = 12.0;
+
+/// Brightness cap in `Saver`, in percent.
+pub const SAVER_BRIGHTNESS: u8 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerMode {
+    Normal,
+    Saver,
+    Critical,
+}
+
+impl PowerMode {
+    /// Highest backlight level allowed in this mode, if capped.
+    pub fn brightness_cap(self) -> Option<u8> {
+        match self {
+            Self::Normal => None,
+            Self::Saver => Some(SAVER_BRIGHTNESS),
+            Self::Critical => Some(MIN_BRIGHTNESS),
+        }
+    }
+
+    /// `brightness` with this mode's cap applied.
+    pub fn brightness(self, brightness: u8) -> u8 {
+        self.brightness_cap()
+            .map_or(brightness, |cap| brightness.min(cap))
+    }
+
+    /// Low battery badge: warning shown, animated avatar paused.
+    #[cfg(not(feature = "simulator"))]
+    pub fn low_battery(self) -> bool {
+        self != Self::Normal
+    }
+}
+
+/// The mode for `reading`, given the mode before it. No reading (gauge
+/// missing or busy) keeps the current mode; with the saver disabled in the
+/// settings it is always `Normal`.
+pub fn battery_mode(
+    prev: PowerMode,
+    reading: Option<&BatteryReading>,
+    saver_enabled: bool,
+) -> PowerMode {
+    let Some(reading) = reading else {
+        return prev;
+    };
+    if !saver_enabled || reading.charge_rate > 0.0 {
+        return PowerMode::Normal;
+    }
+    let percent = reading.percent;
+    match prev {
+        _ if percent < CRITICAL_ENTER => PowerMode::Critical,
+        PowerMode::Critical if percent <= CRITICAL_EXIT => PowerMode::Critical,
+        _ if percent < SAVER_ENTER => PowerMode::Saver,
+        PowerMode::Critical | PowerMode::Saver if percent <= SAVER_EXIT => PowerMode::Saver,
+        _ => PowerMode::Normal,
+    }
+}
+
+/// Which radios the policy allows. The STA only comes back if the policy
+/// turned it off; a network the user left is not rejoined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Radios {
+    pub ap: bool,
+    pub sta: bool,
+}
+
+/// Radios for `mode` when the AP has had no client and no touch for
+/// `ap_quiet`.
+pub fn radios(mode: PowerMode, ap_off_after_secs: u32, ap_quiet: Duration) -> Radios {
+    let ap_timed_out =
+        ap_off_after_secs > 0 && ap_quiet >= Duration::from_secs(ap_off_after_secs as u64);
+    Radios {
+        ap: mode != PowerMode::Critical && !ap_timed_out,
+        sta: mode == PowerMode::Normal,
+    }
+}
+
+/// Power management configuration for ESP-IDF (`esp_pm_configure`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuPlan {
+    pub max_mhz: u32,
+    pub min_mhz: u32,
+    pub light_sleep: bool,
+}
+
+/// CPU limits for `mode` with the screen in `screen`.
+pub fn cpu_plan(mode: PowerMode, screen: IdleState) -> CpuPlan {
+    CpuPlan {
+        max_mhz: if mode == PowerMode::Normal { 240 } else { 160 },
+        min_mhz: 80,
+        light_sleep: screen == IdleState::Off,
+    }
+}
+
+/// What the policy sees each step.
+pub struct PowerInput<'a> {
+    pub battery: Option<&'a BatteryReading>,
+    pub screen: IdleState,
+    /// Time since the last touch.
+    pub idle_for: Duration,
+    pub ap_clients: u8,
+    pub sta_connected: bool,
+    pub settings: &'a PowerSettings,
+}
+
+/// Changes for the caller to make; `None` means leave it as it is.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PowerActions {
+    pub mode: Option<PowerMode>,
+    pub cpu: Option<CpuPlan>,
+    pub ap: Option<bool>,
+    pub sta: Option<StaAction>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaAction {
+    /// Disconnect; the policy will reconnect later.
+    Suspend,
+    /// Rejoin the saved network dropped by `Suspend`.
+    Resume,
+}
+
+/// Policy state between steps.
+pub struct PowerPolicy {
+    mode: PowerMode,
+    cpu: Option<CpuPlan>,
+    ap: bool,
+    sta_suspended: bool,
+    last_ap_client: Instant,
+}
+
+impl PowerPolicy {
+    /// Starts in `Normal` with the AP up, as `wifi::init` leaves it.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            mode: PowerMode::Normal,
+            cpu: None,
+            ap: true,
+            sta_suspended: false,
+            last_ap_client: now,
+        }
+    }
+
+    #[cfg(not(feature = "simulator"))]
+    pub fn mode(&self) -> PowerMode {
+        self.mode
+    }
+
+    /// Whether the policy has the AP up.
+    pub fn ap_enabled(&self) -> bool {
+        self.ap
+    }
+
+    /// Decide what changes for `input` at `now`, and assume the caller
+    /// makes those changes.
+    pub fn step(&mut self, input: &PowerInput, now: Instant) -> PowerActions {
+        let mut actions = PowerActions::default();
+
+        let mode = battery_mode(self.mode, input.battery, input.settings.battery_saver);
+        if mode != self.mode {
+            self.mode = mode;
+            actions.mode = Some(mode);
+        }
+
+        let cpu = cpu_plan(mode, input.screen);
+        if self.cpu != Some(cpu) {
+            self.cpu = Some(cpu);
+            actions.cpu = Some(cpu);
+        }
+
+        if input.ap_clients > 0 {
+            self.last_ap_client = now;
+        }
+        let ap_quiet = now
+            .saturating_duration_since(self.last_ap_client)
+            .min(input.idle_for);
+        let radios = radios(mode, input.settings.ap_off_after_secs, ap_quiet);
+        if radios.ap != self.ap {
+            self.ap = radios.ap;
+            actions.ap = Some(radios.ap);
+            if radios.ap {
+                // Count from now, not from before the AP went down.
+                self.last_ap_client = now;
+            }
+        }
+
+        if !radios.sta && input.sta_connected {
+            self.sta_suspended = true;
+            actions.sta = Some(StaAction::Suspend);
+        } else if radios.sta && self.sta_suspended {
+            self.sta_suspended = false;
+            actions.sta = Some(StaAction::Resume);
+        }
+        actions
+    }
+}
+
+/// Configure DFS and automatic light sleep. Needs `CONFIG_PM_ENABLE` (and
+/// `CONFIG_FREERTOS_USE_TICKLESS_IDLE` for light sleep) in sdkconfig.
+#[cfg(not(feature = "simulator"))]
+pub fn apply_cpu(plan: CpuPlan) -> anyhow::Result<()> {
+    let config = esp_idf_sys::esp_pm_config_t {
+        max_freq_mhz: plan.max_mhz as i32,
+        min_freq_mhz: plan.min_mhz as i32,
+        light_sleep_enable: plan.light_sleep,
+    };
+    esp_idf_sys::esp!(unsafe {
+        esp_idf_sys::esp_pm_configure(&config as *const _ as *const core::ffi::c_void)
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn on_battery(percent: f32) -> BatteryReading {
+        BatteryReading {
+            percent,
+            voltage: 3.7,
+            charge_rate: -5.0,
+        }
+    }
+
+    /// Mode after feeding `levels` in order, starting from `Normal`.
+    fn mode_after(levels: &[f32]) -> PowerMode {
+        levels.iter().fold(PowerMode::Normal, |mode, &percent| {
+            battery_mode(mode, Some(&on_battery(percent)), true)
+        })
+    }
+
+    #[test]
+    fn modes_follow_the_thresholds_going_down() {
+        assert_eq!(mode_after(&[50.0, 20.0]), PowerMode::Normal);
+        assert_eq!(mode_after(&[50.0, 19.9]), PowerMode::Saver);
+        assert_eq!(mode_after(&[19.0, 8.0]), PowerMode::Saver);
+        assert_eq!(mode_after(&[19.0, 7.9]), PowerMode::Critical);
+        // A sudden drop skips `Saver`.
+        assert_eq!(mode_after(&[50.0, 5.0]), PowerMode::Critical);
+    }
+
+    #[test]
+    fn hysteresis_on_the_way_up() {
+        assert_eq!(mode_after(&[19.0, 22.0]), PowerMode::Saver);
+        assert_eq!(mode_after(&[19.0, 25.0]), PowerMode::Saver);
+        assert_eq!(mode_after(&[19.0, 25.1]), PowerMode::Normal);
+
+        assert_eq!(mode_after(&[7.0, 10.0]), PowerMode::Critical);
+        assert_eq!(mode_after(&[7.0, 12.0]), PowerMode::Critical);
+        assert_eq!(mode_after(&[7.0, 12.1]), PowerMode::Saver);
+        assert_eq!(mode_after(&[7.0, 30.0]), PowerMode::Normal);
+    }
+
+    #[test]
+    fn hovering_at_a_threshold_does_not_flap() {
+        let levels = [19.9, 20.1, 19.9, 20.1, 20.5];
+        let mut mode = PowerMode::Normal;
+        for percent in levels {
+            mode = battery_mode(mode, Some(&on_battery(percent)), true);
+            assert_eq!(mode, PowerMode::Saver, "{percent} %");
+        }
+    }
+
+    #[test]
+    fn charging_disabled_or_unknown() {
+        let charging = BatteryReading {
+            charge_rate: 10.0,
+            ..on_battery(5.0)
+        };
+        assert_eq!(
+            battery_mode(PowerMode::Critical, Some(&charging), true),
+            PowerMode::Normal
+        );
+        assert_eq!(
+            battery_mode(PowerMode::Saver, Some(&on_battery(5.0)), false),
+            PowerMode::Normal
+        );
+        assert_eq!(battery_mode(PowerMode::Saver, None, true), PowerMode::Saver);
+    }
+
+    #[test]
+    fn brightness_caps() {
+        assert_eq!(PowerMode::Normal.brightness(80), 80);
+        assert_eq!(PowerMode::Saver.brightness(80), SAVER_BRIGHTNESS);
+        assert_eq!(PowerMode::Saver.brightness(20), 20);
+        assert_eq!(PowerMode::Critical.brightness(80), MIN_BRIGHTNESS);
+    }
+
+    #[test]
+    fn radios_per_mode_and_ap_timeout() {
+        let quiet = Duration::from_secs(600);
+        assert_eq!(
+            radios(PowerMode::Normal, 0, quiet),
+            Radios {
+                ap: true,
+                sta: true
+            }
+        );
+        assert_eq!(
+            radios(PowerMode::Saver, 0, quiet),
+            Radios {
+                ap: true,
+                sta: false
+            }
+        );
+        assert_eq!(
+            radios(PowerMode::Critical, 0, quiet),
+            Radios {
+                ap: false,
+                sta: false
+            }
+        );
+        assert!(radios(PowerMode::Normal, 600, quiet - Duration::from_secs(1)).ap);
+        assert!(!radios(PowerMode::Normal, 600, quiet).ap);
+    }
+
+    #[test]
+    fn cpu_scaling_and_light_sleep() {
+        assert_eq!(
+            cpu_plan(PowerMode::Normal, IdleState::Active),
+            CpuPlan {
+                max_mhz: 240,
+                min_mhz: 80,
+                light_sleep: false
+            }
+        );
+        assert_eq!(cpu_plan(PowerMode::Saver, IdleState::Dimmed).max_mhz, 160);
+        assert!(!cpu_plan(PowerMode::Normal, IdleState::Dimmed).light_sleep);
+        assert!(cpu_plan(PowerMode::Normal, IdleState::Off).light_sleep);
+        assert!(cpu_plan(PowerMode::Critical, IdleState::Off).light_sleep);
+    }
+
+    struct Harness {
+        policy: PowerPolicy,
+        start: Instant,
+        settings: PowerSettings,
+        battery: BatteryReading,
+        screen: IdleState,
+        last_touch: Duration,
+        ap_clients: u8,
+        sta_connected: bool,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let start = Instant::now();
+            Self {
+                policy: PowerPolicy::new(start),
+                start,
+                settings: PowerSettings::default(),
+                battery: on_battery(80.0),
+                screen: IdleState::Active,
+                last_touch: Duration::ZERO,
+                ap_clients: 0,
+                sta_connected: true,
+            }
+        }
+
+        /// Step the policy at `at` seconds after the start.
+        fn step(&mut self, at: u64) -> PowerActions {
+            let at = Duration::from_secs(at);
+            let input = PowerInput {
+                battery: Some(&self.battery),
+                screen: self.screen,
+                idle_for: at.saturating_sub(self.last_touch),
+                ap_clients: self.ap_clients,
+                sta_connected: self.sta_connected,
+                settings: &self.settings,
+            };
+            let actions = self.policy.step(&input, self.start + at);
+            match actions.sta {
+                Some(StaAction::Suspend) => self.sta_connected = false,
+                Some(StaAction::Resume) => self.sta_connected = true,
+                None => {}
+            }
+            actions
+        }
+    }
+
+    #[test]
+    fn first_step_configures_the_cpu_only() {
+        let mut h = Harness::new();
+        let actions = h.step(0);
+        assert_eq!(
+            actions,
+            PowerActions {
+                cpu: Some(cpu_plan(PowerMode::Normal, IdleState::Active)),
+                ..Default::default()
+            }
+        );
+        assert_eq!(h.step(2), PowerActions::default());
+    }
+
+    #[test]
+    fn saver_suspends_and_resumes_the_station() {
+        let mut h = Harness::new();
+        h.step(0);
+        h.battery = on_battery(15.0);
+        let actions = h.step(2);
+        assert_eq!(actions.mode, Some(PowerMode::Saver));
+        assert_eq!(actions.sta, Some(StaAction::Suspend));
+        assert_eq!(actions.ap, None);
+        assert_eq!(h.step(4).sta, None);
+
+        h.battery = on_battery(30.0);
+        let actions = h.step(6);
+        assert_eq!(actions.mode, Some(PowerMode::Normal));
+        assert_eq!(actions.sta, Some(StaAction::Resume));
+    }
+
+    #[test]
+    fn station_left_by_the_user_is_not_rejoined() {
+        let mut h = Harness::new();
+        h.sta_connected = false;
+        h.step(0);
+        h.battery = on_battery(15.0);
+        assert_eq!(h.step(2).sta, None);
+        h.battery = on_battery(30.0);
+        assert_eq!(h.step(4).sta, None);
+    }
+
+    #[test]
+    fn critical_turns_the_ap_off_until_it_recovers() {
+        let mut h = Harness::new();
+        h.step(0);
+        h.battery = on_battery(5.0);
+        let actions = h.step(2);
+        assert_eq!(actions.mode, Some(PowerMode::Critical));
+        assert_eq!(actions.ap, Some(false));
+        assert!(!h.policy.ap_enabled());
+
+        h.battery = on_battery(15.0);
+        let actions = h.step(4);
+        assert_eq!(actions.mode, Some(PowerMode::Saver));
+        assert_eq!(actions.ap, Some(true));
+    }
+
+    #[test]
+    fn unused_ap_turns_off_and_a_touch_brings_it_back() {
+        let mut h = Harness::new();
+        h.settings.ap_off_after_secs = 60;
+        h.step(0);
+        h.ap_clients = 1;
+        assert_eq!(h.step(50).ap, None);
+        h.ap_clients = 0;
+        // Counted from the last client, not from the start.
+        assert_eq!(h.step(100).ap, None);
+        assert_eq!(h.step(110).ap, Some(false));
+        assert_eq!(h.step(200).ap, None);
+
+        h.last_touch = Duration::from_secs(210);
+        assert_eq!(h.step(212).ap, Some(true));
+        // The count restarts when the AP comes back.
+        assert_eq!(h.step(271).ap, None);
+        assert_eq!(h.step(272).ap, Some(false));
+    }
+
+    #[test]
+    fn screen_off_allows_light_sleep() {
+        let mut h = Harness::new();
+        h.step(0);
+        h.screen = IdleState::Off;
+        let actions = h.step(2);
+        assert_eq!(actions.cpu.map(|plan| plan.light_sleep), Some(true));
+        assert_eq!(h.step(4).cpu, None);
+        h.screen = IdleState::Active;
+        assert_eq!(h.step(6).cpu.map(|plan| plan.light_sleep), Some(false));
+    }
+}
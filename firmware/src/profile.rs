@@ -1,17 +1,70 @@
+//! Badge profiles: the displayed fields of one profile, and the set of
+//! named profiles the user switches between.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
+/// Most saved profiles. Each can carry an avatar and background on SPIFFS:
+/// photos compress to roughly 0.5 MB together, so 8 fit in the ~7.6 MB
+/// usable of the 8 MB storage partition. Worst-case images (noise, up to
+/// 1.1 MB per background and 1.5 MB per animated avatar) fill it after about
+/// three, so uploads check the free space first rather than relying on this limit.
+#[cfg(any(not(feature = "simulator"), test))]
+pub const MAX_PROFILES: usize = 8;
+
+/// Longest profile name (the label in the switcher, not the display name).
+pub const MAX_PROFILE_NAME: usize = 24;
+
+/// Longest display name (one line in large type beside the avatar).
+pub const MAX_DISPLAY_NAME: usize = 32;
+
+/// Longest tagline (one line on the accent bar).
+pub const MAX_TAGLINE: usize = 64;
+
+/// Most links and fields on one profile (rows beside the avatar).
+pub const MAX_LINKS: usize = 6;
+
+/// Longest link or field text.
+pub const MAX_LINK_TEXT: usize = 64;
+
+/// Longest caption of a custom field.
+pub const MAX_LINK_LABEL: usize = 16;
+
+/// Most QR codes one profile can carry.
+pub const MAX_QR_CODES: usize = 4;
+
+/// Longest URL in a [`QrCode::Url`]. Keeps the code readable at badge size
+/// (a 256-byte URL is at most a version 12 code, 4 px per module on the page).
+pub const MAX_QR_URL: usize = 256;
+
+/// Largest profile as stored in NVS (versioned JSON). NVS strings may be
+/// up to 4000 bytes, but the partition is only 24 KB.
+pub const MAX_PROFILE_BYTES: usize = 3072;
+
+/// All profiles together in NVS. Leaves the rest of the 24 KB partition to
+/// the profile index, settings, WiFi network and ESP-IDF's own entries.
+pub const PROFILES_NVS_BUDGET: usize = 12 * 1024;
+
 /// Badge profile data — editable via the web interface.
 ///
 /// Colors are stored as CSS hex strings (e.g. `"#1a1a2e"`).
 /// Missing/invalid colors fall back to defaults in the UI.
+///
+/// This is the current schema ([`crate::profile_schema::PROFILE_VERSION`]);
+/// JSON from NVS or HTTP goes through `profile_schema` to be migrated and
+/// validated rather than being deserialized directly.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Profile {
     pub display_name: String,
     pub tagline: String,
-    pub twitter_handle: String,
-    pub discord_handle: String,
+    /// Social links and free-form fields, shown top to bottom beside the
+    /// avatar.
+    #[serde(default)]
+    pub links: Vec<ProfileLink>,
     /// Background solid color (CSS hex, e.g. "#1a1a2e").
     /// Used when no background image is uploaded.
     #[serde(default = "default_background_color")]
@@ -22,17 +75,74 @@ pub struct Profile {
     /// Tagline bar background color (CSS hex, e.g. "#1b4f72").
     #[serde(default = "default_tagline_background_color")]
     pub tagline_background_color: String,
+    /// QR codes on the QR page, in order.
+    #[serde(default)]
+    pub qr_codes: Vec<QrCode>,
+}
+
+/// One row beside the avatar: an icon for `kind`, then `value`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileLink {
+    pub kind: LinkKind,
+    /// Caption of a `custom` field (e.g. "Timezone"); unused by other kinds.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub label: String,
+    pub value: String,
+}
+
+/// What a [`ProfileLink`] is; picks its icon and color on the badge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    Twitter,
+    Discord,
+    Vrchat,
+    Bluesky,
+    Mastodon,
+    Pronouns,
+    Website,
+    Custom,
 }
 
-fn default_background_color() -> String {
+impl LinkKind {
+    /// Name used in JSON and by the Slint model.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Twitter => "twitter",
+            Self::Discord => "discord",
+            Self::Vrchat => "vrchat",
+            Self::Bluesky => "bluesky",
+            Self::Mastodon => "mastodon",
+            Self::Pronouns => "pronouns",
+            Self::Website => "website",
+            Self::Custom => "custom",
+        }
+    }
+}
+
+/// What a QR code on the badge points at.
+///
+/// Serialized with a `type` tag, e.g. `{"type":"url","url":"https://…"}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QrCode {
+    /// Any http(s) link, e.g. a profile page.
+    Url { url: String },
+    /// A VRChat user page, by user id (`usr_…`).
+    Vrchat { user_id: String },
+    /// Join string for the badge's own access point.
+    Wifi,
+}
+
+pub(crate) fn default_background_color() -> String {
     "#1a1a2e".into()
 }
 
-fn default_tagline_color() -> String {
+pub(crate) fn default_tagline_color() -> String {
     "#e0e8f0".into()
 }
 
-fn default_tagline_background_color() -> String {
+pub(crate) fn default_tagline_background_color() -> String {
     "#1b4f72".into()
 }
 
@@ -41,11 +151,24 @@ impl Default for Profile {
         Self {
             display_name: "Hebu".into(),
             tagline: "Hello from VRCBadge!".into(),
-            twitter_handle: "@Hebu_VRC".into(),
-            discord_handle: "hebu".into(),
+            links: vec![
+                ProfileLink {
+                    kind: LinkKind::Twitter,
+                    label: String::new(),
+                    value: "@Hebu_VRC".into(),
+                },
+                ProfileLink {
+                    kind: LinkKind::Discord,
+                    label: String::new(),
+                    value: "hebu".into(),
+                },
+            ],
             background_color: default_background_color(),
             tagline_color: default_tagline_color(),
             tagline_background_color: default_tagline_background_color(),
+            // No WiFi code: it would show the AP password to anyone
+            // looking at the badge.
+            qr_codes: Vec::new(),
         }
     }
 }
@@ -63,8 +186,443 @@ pub fn parse_hex_color(hex: &str) -> Option<slint::Color> {
     Some(slint::Color::from_rgb_u8(r, g, b))
 }
 
-/// Shared current profile (read by GET /api/profile).
-pub type CurrentProfile = Arc<Mutex<Profile>>;
+// ---------------------------------------------------------------------------
+// Profile set
+// ---------------------------------------------------------------------------
+
+/// Name and id of one saved profile.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileEntry {
+    pub id: u32,
+    pub name: String,
+}
+
+/// Which profiles exist, in switcher order, and which one is shown.
+/// Stored in NVS separately from the profile data.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileIndex {
+    pub active: u32,
+    /// Next id to hand out; ids are never reused, so a deleted profile's
+    /// leftover files can't attach to a new one.
+    pub next_id: u32,
+    pub entries: Vec<ProfileEntry>,
+}
+
+/// Why a profile operation was refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProfileError {
+    NotFound(u32),
+    Invalid(String),
+    /// The profile fields failed validation, one entry per problem.
+    Fields(Vec<FieldError>),
+    /// Saving it would go over [`MAX_PROFILE_BYTES`] or
+    /// [`PROFILES_NVS_BUDGET`].
+    TooLarge(String),
+}
+
+/// One invalid profile field. `field` is a JSON path such as `tagline` or
+/// `links[2].label` (empty for the profile as a whole).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.field.is_empty() {
+            return f.write_str(&self.message);
+        }
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "No profile with id {id}"),
+            Self::Invalid(msg) | Self::TooLarge(msg) => f.write_str(msg),
+            Self::Fields(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("; ")?;
+                    }
+                    write!(f, "{e}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+/// All saved profiles: the index plus each profile's fields.
+///
+/// The main loop keeps this in sync with NVS; the HTTP server reads and
+/// edits it directly, then queues a [`ProfileOp`] so the main loop persists
+/// the change. Invariant: at least one profile, and `index.active` exists.
+#[derive(Clone, Debug)]
+pub struct ProfileSet {
+    pub index: ProfileIndex,
+    pub profiles: BTreeMap<u32, Profile>,
+}
+
+impl ProfileSet {
+    /// A set holding just `profile` as id 1.
+    pub fn single(name: &str, profile: Profile) -> Self {
+        Self {
+            index: ProfileIndex {
+                active: 1,
+                next_id: 2,
+                entries: vec![ProfileEntry {
+                    id: 1,
+                    name: name.into(),
+                }],
+            },
+            profiles: BTreeMap::from([(1, profile)]),
+        }
+    }
+
+    pub fn active_id(&self) -> u32 {
+        self.index.active
+    }
+
+    /// The profile on screen.
+    pub fn active(&self) -> &Profile {
+        &self.profiles[&self.index.active]
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Profile> {
+        self.profiles.get(&id)
+    }
+
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.entry(id).map(|e| e.name.as_str())
+    }
+
+    /// Add a profile at the end of the list. Returns its id.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn create(&mut self, name: &str, profile: Profile) -> Result<u32, ProfileError> {
+        if self.index.entries.len() >= MAX_PROFILES {
+            return Err(ProfileError::Invalid(format!(
+                "At most {MAX_PROFILES} profiles"
+            )));
+        }
+        let name = validate_name(name)?;
+        profile.validate()?;
+        let id = self.index.next_id;
+        self.check_fits(id, &profile)?;
+        self.index.next_id += 1;
+        self.index.entries.push(ProfileEntry { id, name });
+        self.profiles.insert(id, profile);
+        Ok(id)
+    }
+
+    /// Rename and/or replace the fields of profile `id`.
+    pub fn update(
+        &mut self,
+        id: u32,
+        name: Option<&str>,
+        profile: Option<Profile>,
+    ) -> Result<(), ProfileError> {
+        let name = name.map(validate_name).transpose()?;
+        if let Some(profile) = &profile {
+            profile.validate()?;
+            self.check_fits(id, profile)?;
+        }
+        let entry = self
+            .index
+            .entries
+            .iter_mut()
+            .find(|e| e.id == id)
+            .ok_or(ProfileError::NotFound(id))?;
+        if let Some(name) = name {
+            entry.name = name;
+        }
+        if let Some(profile) = profile {
+            self.profiles.insert(id, profile);
+        }
+        Ok(())
+    }
+
+    /// Delete profile `id`. The last profile can't be deleted; deleting the
+    /// active one makes its neighbour active.
+    pub fn remove(&mut self, id: u32) -> Result<(), ProfileError> {
+        let pos = self.position(id).ok_or(ProfileError::NotFound(id))?;
+        if self.index.entries.len() == 1 {
+            return Err(ProfileError::Invalid(
+                "Can't delete the only profile".into(),
+            ));
+        }
+        self.index.entries.remove(pos);
+        self.profiles.remove(&id);
+        if self.index.active == id {
+            let next = pos.min(self.index.entries.len() - 1);
+            self.index.active = self.index.entries[next].id;
+        }
+        Ok(())
+    }
+
+    pub fn activate(&mut self, id: u32) -> Result<(), ProfileError> {
+        self.position(id).ok_or(ProfileError::NotFound(id))?;
+        self.index.active = id;
+        Ok(())
+    }
+
+    /// Id `steps` places after the active one in list order, wrapping
+    /// (negative steps go backwards). Used by the badge-page swipe.
+    pub fn neighbour(&self, steps: i32) -> u32 {
+        let len = self.index.entries.len() as i32;
+        let pos = self.position(self.index.active).unwrap_or(0) as i32;
+        let next = (pos + steps).rem_euclid(len);
+        self.index.entries[next as usize].id
+    }
+
+    /// Repair an index loaded from storage: drop entries without data and
+    /// point `active` at an existing profile. Returns `false` if nothing
+    /// is left.
+    pub fn normalize(&mut self) -> bool {
+        let profiles = &self.profiles;
+        self.index.entries.retain(|e| profiles.contains_key(&e.id));
+        self.profiles
+            .retain(|id, _| self.index.entries.iter().any(|e| e.id == *id));
+        let Some(first) = self.index.entries.first() else {
+            return false;
+        };
+        if self.position(self.index.active).is_none() {
+            self.index.active = first.id;
+        }
+        let max_id = self.index.entries.iter().map(|e| e.id).max().unwrap_or(0);
+        self.index.next_id = self.index.next_id.max(max_id + 1);
+        true
+    }
+
+    /// Check that the set still fits the NVS budget with `profile` saved as
+    /// `id` (new or replacing the current one).
+    fn check_fits(&self, id: u32, profile: &Profile) -> Result<(), ProfileError> {
+        let others: usize = self
+            .profiles
+            .iter()
+            .filter(|(other, _)| **other != id)
+            .map(|(_, p)| stored_len(p))
+            .sum();
+        check_total_len(others + check_profile_len(id, profile)?)
+    }
+
+    fn entry(&self, id: u32) -> Option<&ProfileEntry> {
+        self.index.entries.iter().find(|e| e.id == id)
+    }
+
+    fn position(&self, id: u32) -> Option<usize> {
+        self.index.entries.iter().position(|e| e.id == id)
+    }
+}
+
+fn validate_name(name: &str) -> Result<String, ProfileError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_PROFILE_NAME {
+        return Err(ProfileError::Invalid(format!(
+            "Profile name must be 1-{MAX_PROFILE_NAME} characters"
+        )));
+    }
+    Ok(name.to_string())
+}
+
+/// Bytes `profile` takes in NVS.
+fn stored_len(profile: &Profile) -> usize {
+    crate::profile_schema::to_json(profile).to_string().len()
+}
+
+/// `profile`'s stored size if it is within [`MAX_PROFILE_BYTES`].
+fn check_profile_len(id: u32, profile: &Profile) -> Result<usize, ProfileError> {
+    let len = stored_len(profile);
+    if len > MAX_PROFILE_BYTES {
+        return Err(ProfileError::TooLarge(format!(
+            "Profile {id} takes {len} bytes, at most {MAX_PROFILE_BYTES} fit. Shorten its text or \
+             QR codes."
+        )));
+    }
+    Ok(len)
+}
+
+fn check_total_len(total: usize) -> Result<(), ProfileError> {
+    if total > PROFILES_NVS_BUDGET {
+        return Err(ProfileError::TooLarge(format!(
+            "Profiles would take {total} bytes, at most {PROFILES_NVS_BUDGET} fit. Shorten or \
+             delete a profile."
+        )));
+    }
+    Ok(())
+}
+
+/// Change to the profile set made over HTTP, already applied to the shared
+/// [`ProfileSet`]; the main loop persists it and updates the badge. The
+/// simulator only edits the active profile.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProfileOp {
+    /// Profile created, renamed or edited.
+    Saved(u32),
+    /// Profile deleted (its NVS key and images should go too).
+    #[cfg(any(not(feature = "simulator"), test))]
+    Deleted(u32),
+    /// A different profile is now active.
+    #[cfg(any(not(feature = "simulator"), test))]
+    Activated(u32),
+}
+
+/// All saved profiles (read by the `/api/profile*` handlers).
+pub type SharedProfiles = Arc<Mutex<ProfileSet>>;
 
-/// Shared pending profile update (written by POST /api/profile, consumed by main loop).
-pub type PendingProfile = Arc<Mutex<Option<Profile>>>;
+/// Profile changes queued by the HTTP server for the main loop to persist,
+/// and how saving them went, so a handler can wait for its change to reach
+/// NVS and report a failed write.
+#[derive(Debug, Default)]
+pub struct ProfileOps {
+    queue: Vec<ProfileOp>,
+    /// Ops queued so far; an op's count is its ticket.
+    queued: u64,
+    /// Ops taken by the main loop so far.
+    taken: u64,
+    /// Ops whose save has finished, failed or not.
+    done: u64,
+    /// Tickets of the last batch that failed to save, and why.
+    failed: Option<(RangeInclusive<u64>, String)>,
+}
+
+impl ProfileOps {
+    /// Queue `op`. Returns its ticket for [`Self::outcome`].
+    pub fn push(&mut self, op: ProfileOp) -> u64 {
+        self.queue.push(op);
+        self.queued += 1;
+        self.queued
+    }
+
+    /// Take everything queued, to save and then report with [`Self::finish`].
+    pub fn take(&mut self) -> Vec<ProfileOp> {
+        self.taken = self.queued;
+        std::mem::take(&mut self.queue)
+    }
+
+    /// Record how saving the ops from the last [`Self::take`] went.
+    pub fn finish(&mut self, result: Result<(), String>) {
+        if let Err(e) = result {
+            self.failed = Some((self.done + 1..=self.taken, e));
+        }
+        self.done = self.taken;
+    }
+
+    /// How saving the op with `ticket` went, or `None` while it is queued.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn outcome(&self, ticket: u64) -> Option<Result<(), String>> {
+        if ticket > self.done {
+            return None;
+        }
+        match &self.failed {
+            Some((tickets, e)) if tickets.contains(&ticket) => Some(Err(e.clone())),
+            _ => Some(Ok(())),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// Queued profile changes from the HTTP server, drained by the main loop.
+pub type PendingProfileOps = Arc<Mutex<ProfileOps>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A profile with every field at its longest, in `c` characters.
+    fn full_profile(c: char) -> Profile {
+        let text = |len: usize| std::iter::repeat_n(c, len).collect::<String>();
+        let url = format!("https://example.com/{}", text(MAX_QR_URL - 20));
+        Profile {
+            display_name: text(MAX_DISPLAY_NAME),
+            tagline: text(MAX_TAGLINE),
+            links: (0..MAX_LINKS)
+                .map(|_| ProfileLink {
+                    kind: LinkKind::Custom,
+                    label: text(MAX_LINK_LABEL),
+                    value: text(MAX_LINK_TEXT),
+                })
+                .collect(),
+            qr_codes: vec![QrCode::Url { url }; MAX_QR_CODES],
+            ..Profile::default()
+        }
+    }
+
+    #[test]
+    fn the_default_profile_shows_no_wifi_code() {
+        let profile = Profile::default();
+        assert!(!profile.qr_codes.contains(&QrCode::Wifi));
+        assert_eq!(profile.validate(), Ok(()));
+    }
+
+    #[test]
+    fn a_full_profile_fits() {
+        let mut set = ProfileSet::single("Default", Profile::default());
+        assert_eq!(full_profile('a').validate(), Ok(()));
+        assert!(set.create("Full", full_profile('a')).is_ok());
+    }
+
+    #[test]
+    fn profiles_over_the_nvs_budget_are_refused() {
+        let mut set = ProfileSet::single("Default", Profile::default());
+        // Within the character limits, but three bytes a character
+        let wide = Profile {
+            qr_codes: full_profile('a').qr_codes,
+            ..full_profile('€')
+        };
+        assert!(matches!(
+            set.update(1, None, Some(wide.clone())),
+            Err(ProfileError::TooLarge(_))
+        ));
+        assert!(matches!(
+            set.create("Wide", wide),
+            Err(ProfileError::TooLarge(_))
+        ));
+        assert_eq!(set.index.entries.len(), 1);
+
+        // Full profiles fill the budget before MAX_PROFILES is reached
+        let created = std::iter::from_fn(|| set.create("Full", full_profile('a')).ok()).count();
+        assert!(created + 1 < MAX_PROFILES);
+        assert!(matches!(
+            set.create("Full", full_profile('a')),
+            Err(ProfileError::TooLarge(_))
+        ));
+        // Replacing one with a smaller profile still works
+        let last = set.index.entries.last().unwrap().id;
+        assert!(set.update(last, None, Some(Profile::default())).is_ok());
+    }
+
+    #[test]
+    fn profile_ops_report_how_their_save_went() {
+        let mut ops = ProfileOps::default();
+        let first = ops.push(ProfileOp::Saved(1));
+        assert_eq!(ops.take(), vec![ProfileOp::Saved(1)]);
+        let second = ops.push(ProfileOp::Activated(1));
+        ops.finish(Err("NVS full".into()));
+        assert_eq!(ops.outcome(first), Some(Err("NVS full".into())));
+        assert_eq!(ops.outcome(second), None);
+
+        assert_eq!(ops.take(), vec![ProfileOp::Activated(1)]);
+        ops.finish(Ok(()));
+        assert!(ops.is_empty());
+        assert_eq!(ops.outcome(second), Some(Ok(())));
+        assert_eq!(ops.outcome(first), Some(Err("NVS full".into())));
+    }
+}
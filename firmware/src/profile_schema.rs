@@ -0,0 +1,499 @@
+//! Profile JSON schema: versions, migrations and field validation.
+//!
+//! Profile JSON carries a `version`; JSON without one is version 1. Each
+//! migration upgrades one version in place on the `serde_json` map, so any
+//! older profile — from NVS or from an HTTP client — reaches
+//! [`PROFILE_VERSION`] before it becomes a [`Profile`]:
+//!
+//! | Version | Changes |
+//! |---------|---------|
+//! | 1 | `twitter_handle` / `discord_handle`; no `qr_codes` |
+//! | 2 | `links` replace the handles; `qr_codes` (empty for v1) |
+//!
+//! Reading is strict over HTTP ([`from_json`], then [`Profile::validate`]
+//! in `ProfileSet`) and forgiving from NVS ([`from_stored_json`]): a stored
+//! profile keeps every field that is still readable and valid, so one bad
+//! field — or a profile written by newer firmware — doesn't wipe the rest.
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+
+use crate::profile::{
+    self, FieldError, LinkKind, Profile, ProfileError, ProfileLink, QrCode, MAX_DISPLAY_NAME,
+    MAX_LINKS, MAX_LINK_LABEL, MAX_LINK_TEXT, MAX_QR_CODES, MAX_QR_URL, MAX_TAGLINE,
+};
+
+/// Schema version written by this firmware.
+pub const PROFILE_VERSION: u64 = 2;
+
+/// Profile as JSON in the current schema, with its `version`.
+pub fn to_json(profile: &Profile) -> Value {
+    let mut value = serde_json::to_value(profile).unwrap_or_else(|_| json!({}));
+    if let Value::Object(map) = &mut value {
+        map.insert("version".into(), PROFILE_VERSION.into());
+    }
+    value
+}
+
+/// Decode profile JSON sent over HTTP, migrating older versions. Field
+/// values are not validated here; `ProfileSet::create` / `update` do that.
+#[cfg(any(not(feature = "simulator"), test))]
+pub fn from_json(value: Value) -> Result<Profile, ProfileError> {
+    let Value::Object(mut map) = value else {
+        return Err(ProfileError::Invalid(
+            "Profile must be a JSON object".into(),
+        ));
+    };
+    migrate(&mut map)?;
+    serde_json::from_value(Value::Object(map))
+        .map_err(|e| ProfileError::Invalid(format!("Invalid profile: {e}")))
+}
+
+/// Decode a profile saved in NVS, keeping whatever can be kept. Fields that
+/// are missing, unreadable or invalid fall back to defaults (bad links and
+/// QR codes are dropped); each one is reported so the caller can log it.
+pub fn from_stored_json(json: &str) -> (Profile, Vec<FieldError>) {
+    let mut notes = Vec::new();
+    let mut map = match serde_json::from_str::<Value>(json) {
+        Ok(Value::Object(map)) => map,
+        Ok(_) => {
+            notes.push(FieldError::new("", "not a JSON object"));
+            return (Profile::default(), notes);
+        }
+        Err(e) => {
+            notes.push(FieldError::new("", e.to_string()));
+            return (Profile::default(), notes);
+        }
+    };
+    if let Err(e) = migrate(&mut map) {
+        // Newer firmware wrote this; read the fields we know.
+        notes.push(FieldError::new("version", e.to_string()));
+    }
+
+    let mut p = Profile::default();
+    take(&mut map, "display_name", &mut p.display_name, &mut notes);
+    take(&mut map, "tagline", &mut p.tagline, &mut notes);
+    take_list(&mut map, "links", &mut p.links, &mut notes);
+    take(
+        &mut map,
+        "background_color",
+        &mut p.background_color,
+        &mut notes,
+    );
+    take(&mut map, "tagline_color", &mut p.tagline_color, &mut notes);
+    take(
+        &mut map,
+        "tagline_background_color",
+        &mut p.tagline_background_color,
+        &mut notes,
+    );
+    take_list(&mut map, "qr_codes", &mut p.qr_codes, &mut notes);
+    notes.extend(p.repair());
+    (p, notes)
+}
+
+/// Replace `slot` with `map[key]` if it is present and decodes.
+fn take<T: DeserializeOwned>(
+    map: &mut Map<String, Value>,
+    key: &str,
+    slot: &mut T,
+    notes: &mut Vec<FieldError>,
+) {
+    if let Some(value) = map.remove(key) {
+        match serde_json::from_value(value) {
+            Ok(v) => *slot = v,
+            Err(e) => notes.push(FieldError::new(key, e.to_string())),
+        }
+    }
+}
+
+/// Like [`take`] for a list, decoding each element on its own so one bad
+/// element only loses itself.
+fn take_list<T: DeserializeOwned>(
+    map: &mut Map<String, Value>,
+    key: &str,
+    slot: &mut Vec<T>,
+    notes: &mut Vec<FieldError>,
+) {
+    let Some(value) = map.remove(key) else { return };
+    let Value::Array(items) = value else {
+        notes.push(FieldError::new(key, "expected a list"));
+        return;
+    };
+    slot.clear();
+    for (i, item) in items.into_iter().enumerate() {
+        match serde_json::from_value(item) {
+            Ok(v) => slot.push(v),
+            Err(e) => notes.push(FieldError::new(format!("{key}[{i}]"), e.to_string())),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Migrations
+// ---------------------------------------------------------------------------
+
+/// Upgrade `map` to [`PROFILE_VERSION`] in place. Fails on a version newer
+/// than this firmware knows.
+fn migrate(map: &mut Map<String, Value>) -> Result<(), ProfileError> {
+    let version = match map.get("version") {
+        None => 1,
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| ProfileError::Invalid("Profile version must be a number".into()))?,
+    };
+    if version > PROFILE_VERSION {
+        return Err(ProfileError::Invalid(format!(
+            "Profile version {version} is newer than this firmware supports ({PROFILE_VERSION})"
+        )));
+    }
+    if version < 2 {
+        migrate_v1(map);
+    }
+    map.insert("version".into(), PROFILE_VERSION.into());
+    Ok(())
+}
+
+/// v1 → v2: the Twitter and Discord handles become the first links, and
+/// profiles from before QR codes get none; a WiFi code would show the AP
+/// password on the badge. Firmware that wrote `links` / `qr_codes` without
+/// a version is left as it is.
+fn migrate_v1(map: &mut Map<String, Value>) {
+    let handles: Vec<Value> = [
+        (LinkKind::Twitter, "twitter_handle"),
+        (LinkKind::Discord, "discord_handle"),
+    ]
+    .into_iter()
+    .filter_map(|(kind, key)| match map.remove(key) {
+        Some(Value::String(handle)) if !handle.is_empty() => {
+            Some(json!({ "kind": kind.as_str(), "value": handle }))
+        }
+        _ => None,
+    })
+    .collect();
+    map.entry("links").or_insert(Value::Array(handles));
+    map.entry("qr_codes").or_insert_with(|| json!([]));
+}
+
+// ---------------------------------------------------------------------------
+// Validation
+// ---------------------------------------------------------------------------
+
+impl Profile {
+    /// Check every field, reporting all problems at once.
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        let errors = self.field_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ProfileError::Fields(errors))
+        }
+    }
+
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let mut check = |field: String, result: Result<(), String>| {
+            if let Err(message) = result {
+                errors.push(FieldError::new(field, message));
+            }
+        };
+        check(
+            "display_name".into(),
+            check_text(&self.display_name, 1, MAX_DISPLAY_NAME),
+        );
+        check("tagline".into(), check_text(&self.tagline, 0, MAX_TAGLINE));
+        for (field, color) in self.colors() {
+            check(field.into(), check_color(color));
+        }
+        check("links".into(), check_count(self.links.len(), MAX_LINKS));
+        for (i, link) in self.links.iter().enumerate() {
+            check(
+                format!("links[{i}].value"),
+                check_text(&link.value, 1, MAX_LINK_TEXT),
+            );
+            if link.kind == LinkKind::Custom {
+                check(
+                    format!("links[{i}].label"),
+                    check_text(&link.label, 1, MAX_LINK_LABEL),
+                );
+            }
+        }
+        check(
+            "qr_codes".into(),
+            check_count(self.qr_codes.len(), MAX_QR_CODES),
+        );
+        for (i, code) in self.qr_codes.iter().enumerate() {
+            let field = match code {
+                QrCode::Url { .. } => format!("qr_codes[{i}].url"),
+                QrCode::Vrchat { .. } => format!("qr_codes[{i}].user_id"),
+                QrCode::Wifi => format!("qr_codes[{i}]"),
+            };
+            check(field, check_qr_code(code));
+        }
+        errors
+    }
+
+    /// Make a profile read from storage valid: overlong text is cut, bad
+    /// colors get their defaults, and invalid links and QR codes are dropped.
+    /// Returns what was wrong.
+    fn repair(&mut self) -> Vec<FieldError> {
+        let errors = self.field_errors();
+        if errors.is_empty() {
+            return errors;
+        }
+        let defaults = Profile::default();
+        repair_text(
+            &mut self.display_name,
+            MAX_DISPLAY_NAME,
+            &defaults.display_name,
+        );
+        repair_text(&mut self.tagline, MAX_TAGLINE, "");
+        for (color, default) in [
+            (
+                &mut self.background_color,
+                profile::default_background_color(),
+            ),
+            (&mut self.tagline_color, profile::default_tagline_color()),
+            (
+                &mut self.tagline_background_color,
+                profile::default_tagline_background_color(),
+            ),
+        ] {
+            if check_color(color).is_err() {
+                *color = default;
+            }
+        }
+        self.links.retain(link_is_valid);
+        self.links.truncate(MAX_LINKS);
+        self.qr_codes.retain(|code| check_qr_code(code).is_ok());
+        self.qr_codes.truncate(MAX_QR_CODES);
+        errors
+    }
+
+    fn colors(&self) -> [(&'static str, &str); 3] {
+        [
+            ("background_color", &self.background_color),
+            ("tagline_color", &self.tagline_color),
+            ("tagline_background_color", &self.tagline_background_color),
+        ]
+    }
+}
+
+fn link_is_valid(link: &ProfileLink) -> bool {
+    check_text(&link.value, 1, MAX_LINK_TEXT).is_ok()
+        && (link.kind != LinkKind::Custom || check_text(&link.label, 1, MAX_LINK_LABEL).is_ok())
+}
+
+/// One line of `min..=max` characters (not counting surrounding spaces for
+/// the minimum).
+fn check_text(text: &str, min: usize, max: usize) -> Result<(), String> {
+    let len = text.chars().count();
+    if text.trim().chars().count() < min || len > max {
+        return Err(if min == 0 {
+            format!("at most {max} characters")
+        } else {
+            format!("must be {min}-{max} characters")
+        });
+    }
+    if text.chars().any(char::is_control) {
+        return Err("must be a single line".into());
+    }
+    Ok(())
+}
+
+/// Strip control characters and cut to `max` characters; use `default` if
+/// nothing is left.
+fn repair_text(text: &mut String, max: usize, default: &str) {
+    let cleaned: String = text.chars().filter(|c| !c.is_control()).take(max).collect();
+    *text = if cleaned.trim().is_empty() {
+        default.into()
+    } else {
+        cleaned
+    };
+}
+
+fn check_count(len: usize, max: usize) -> Result<(), String> {
+    if len > max {
+        return Err(format!("at most {max} entries"));
+    }
+    Ok(())
+}
+
+/// `#rrggbb`, as produced by an HTML color picker.
+fn check_color(color: &str) -> Result<(), String> {
+    let valid = color
+        .strip_prefix('#')
+        .is_some_and(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()));
+    if !valid {
+        return Err("must be a #rrggbb hex color".into());
+    }
+    Ok(())
+}
+
+fn check_qr_code(code: &QrCode) -> Result<(), String> {
+    match code {
+        QrCode::Url { url } => {
+            let scheme_ok = url.starts_with("https://") || url.starts_with("http://");
+            if !scheme_ok || url.len() > MAX_QR_URL || url.chars().any(char::is_whitespace) {
+                return Err(format!(
+                    "must be an http(s) link of at most {MAX_QR_URL} characters"
+                ));
+            }
+        }
+        QrCode::Vrchat { user_id } => {
+            let valid = user_id.strip_prefix("usr_").is_some_and(|rest| {
+                rest.len() == 36 && rest.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+            });
+            if !valid {
+                return Err("must look like usr_xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx".into());
+            }
+        }
+        QrCode::Wifi => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(kind: LinkKind, value: &str) -> ProfileLink {
+        ProfileLink {
+            kind,
+            label: String::new(),
+            value: value.into(),
+        }
+    }
+
+    fn v1() -> Value {
+        json!({
+            "display_name": "Hebu",
+            "tagline": "Hi",
+            "twitter_handle": "@hebu",
+            "discord_handle": "hebu#1",
+            "background_color": "#000000",
+        })
+    }
+
+    #[test]
+    fn v1_handles_become_links_without_qr_codes() {
+        let profile = from_json(v1()).unwrap();
+        assert_eq!(
+            profile.links,
+            [
+                link(LinkKind::Twitter, "@hebu"),
+                link(LinkKind::Discord, "hebu#1")
+            ]
+        );
+        assert!(profile.qr_codes.is_empty());
+        assert_eq!(profile.background_color, "#000000");
+        assert!(profile.validate().is_ok());
+    }
+
+    #[test]
+    fn empty_v1_handles_are_dropped() {
+        let mut value = v1();
+        value["twitter_handle"] = json!("");
+        value.as_object_mut().unwrap().remove("discord_handle");
+        let profile = from_json(value).unwrap();
+        assert!(profile.links.is_empty());
+    }
+
+    #[test]
+    fn unversioned_links_are_kept() {
+        let mut value = v1();
+        value["links"] = json!([{ "kind": "website", "value": "example.com" }]);
+        value["qr_codes"] = json!([]);
+        let profile = from_json(value).unwrap();
+        assert_eq!(profile.links, [link(LinkKind::Website, "example.com")]);
+        assert!(profile.qr_codes.is_empty());
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let profile = Profile {
+            links: vec![link(LinkKind::Pronouns, "they/them")],
+            qr_codes: vec![QrCode::Url {
+                url: "https://example.com".into(),
+            }],
+            ..Profile::default()
+        };
+        let value = to_json(&profile);
+        assert_eq!(value["version"], PROFILE_VERSION);
+        let back = from_json(value).unwrap();
+        assert_eq!(back.links, profile.links);
+        assert_eq!(back.qr_codes, profile.qr_codes);
+    }
+
+    #[test]
+    fn newer_versions_are_refused_over_http_and_read_from_storage() {
+        let mut value = to_json(&Profile::default());
+        value["version"] = json!(PROFILE_VERSION + 1);
+        value["display_name"] = json!("Future");
+        assert!(from_json(value.clone()).is_err());
+
+        let (profile, notes) = from_stored_json(&value.to_string());
+        assert_eq!(profile.display_name, "Future");
+        assert!(notes.iter().any(|n| n.field == "version"));
+    }
+
+    #[test]
+    fn stored_v1_profiles_migrate() {
+        let (profile, notes) = from_stored_json(&v1().to_string());
+        assert!(notes.is_empty(), "{notes:?}");
+        assert_eq!(profile.links.len(), 2);
+        assert!(profile.qr_codes.is_empty());
+    }
+
+    #[test]
+    fn stored_profiles_keep_what_is_valid() {
+        let value = json!({
+            "version": PROFILE_VERSION,
+            "display_name": "Hebu",
+            "tagline": 42,
+            "tagline_color": "red",
+            "links": [
+                { "kind": "website", "value": "example.com" },
+                { "kind": "no_such_kind", "value": "x" },
+                { "kind": "custom", "value": "UTC+1" },
+            ],
+        });
+        let (profile, notes) = from_stored_json(&value.to_string());
+        assert_eq!(profile.display_name, "Hebu");
+        assert_eq!(profile.tagline, Profile::default().tagline);
+        assert_eq!(profile.tagline_color, profile::default_tagline_color());
+        // The unknown kind fails to decode; the custom field has no label.
+        assert_eq!(profile.links, [link(LinkKind::Website, "example.com")]);
+        assert!(profile.validate().is_ok());
+        let fields: Vec<&str> = notes.iter().map(|n| n.field.as_str()).collect();
+        assert!(fields.contains(&"tagline"), "{fields:?}");
+        assert!(fields.contains(&"links[1]"), "{fields:?}");
+        assert!(fields.contains(&"tagline_color"), "{fields:?}");
+    }
+
+    #[test]
+    fn validation_reports_every_field() {
+        let profile = Profile {
+            display_name: " ".into(),
+            tagline: "two\nlines".into(),
+            background_color: "#12345".into(),
+            qr_codes: vec![QrCode::Vrchat {
+                user_id: "usr_nope".into(),
+            }],
+            ..Profile::default()
+        };
+        let Err(ProfileError::Fields(errors)) = profile.validate() else {
+            panic!("expected field errors");
+        };
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "display_name",
+                "tagline",
+                "background_color",
+                "qr_codes[0].user_id"
+            ]
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! QR codes for the QR page: the text each [`QrCode`] encodes, and the
+//! encoded matrix drawn as an RGB888 image.
+//!
+//! Codes use error correction level M, dark modules on white, with the
+//! 4-module quiet zone the spec asks for. Modules are scaled by a whole
+//! number of pixels so every module has the same size on screen.
+
+use qrcodegen::{QrCode as Matrix, QrCodeEcc};
+
+use crate::profile::QrCode;
+use crate::settings::ApSettings;
+
+/// Side of the rendered image in pixels (square, centred on the QR page).
+pub const QR_IMAGE_SIZE: u32 = 300;
+
+/// Quiet zone around the code, in modules.
+const QUIET_ZONE: u32 = 4;
+
+const DARK: [u8; 3] = [0x00, 0x00, 0x00];
+const LIGHT: [u8; 3] = [0xff, 0xff, 0xff];
+
+/// Text encoded by `code`. `ap` is the badge's access point.
+pub fn payload(code: &QrCode, ap: &ApSettings) -> String {
+    match code {
+        QrCode::Url { url } => url.clone(),
+        QrCode::Vrchat { user_id } => format!("https://vrchat.com/home/user/{user_id}"),
+        QrCode::Wifi => {
+            let mut text = if ap.password.is_empty() {
+                format!("WIFI:T:nopass;S:{};", escape_wifi(&ap.ssid))
+            } else {
+                format!(
+                    "WIFI:T:WPA;S:{};P:{};",
+                    escape_wifi(&ap.ssid),
+                    escape_wifi(&ap.password)
+                )
+            };
+            if ap.hidden {
+                // Phones don't see a hidden network in scans, so they
+                // need telling to probe for it.
+                text.push_str("H:true;");
+            }
+            text.push(';');
+            text
+        }
+    }
+}
+
+/// Short caption shown above the code.
+pub fn label(code: &QrCode) -> &'static str {
+    match code {
+        QrCode::Url { .. } => "Link",
+        QrCode::Vrchat { .. } => "VRChat",
+        QrCode::Wifi => "Join badge WiFi",
+    }
+}
+
+/// Encode `text` and draw it as a [`QR_IMAGE_SIZE`]² RGB888 image.
+///
+/// Fails only if the text is too long for any QR version, which profile
+/// validation rules out for the codes a profile can hold.
+pub fn render(text: &str) -> anyhow::Result<Vec<u8>> {
+    let matrix = Matrix::encode_text(text, QrCodeEcc::Medium)
+        .map_err(|e| anyhow::anyhow!("QR encode failed: {e}"))?;
+    let modules = matrix.size() as u32;
+    let side = modules + 2 * QUIET_ZONE;
+    let scale = (QR_IMAGE_SIZE / side).max(1);
+    // Centre the code; the leftover pixels widen the quiet zone.
+    let offset = (QR_IMAGE_SIZE.saturating_sub(modules * scale)) / 2;
+
+    let size = QR_IMAGE_SIZE as usize;
+    let mut rgb = Vec::with_capacity(size * size * 3);
+    for y in 0..QR_IMAGE_SIZE {
+        for x in 0..QR_IMAGE_SIZE {
+            let dark = match (module_at(x, offset, scale), module_at(y, offset, scale)) {
+                (Some(mx), Some(my)) if mx < modules && my < modules => {
+                    matrix.get_module(mx as i32, my as i32)
+                }
+                _ => false,
+            };
+            rgb.extend_from_slice(if dark { &DARK } else { &LIGHT });
+        }
+    }
+    Ok(rgb)
+}
+
+/// Module index under pixel `p`, if it lies past the leading margin.
+fn module_at(p: u32, offset: u32, scale: u32) -> Option<u32> {
+    p.checked_sub(offset).map(|p| p / scale)
+}
+
+/// Escape the characters the WiFi QR format reserves (`\ ; , : "`).
+fn escape_wifi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read the code back from the image: find its bounding box from the
+    /// finder patterns, then sample the centre of every module and compare
+    /// it with the matrix `qrcodegen` produced for `text`.
+    fn assert_renders(text: &str) {
+        let rgb = render(text).unwrap();
+        let size = QR_IMAGE_SIZE as usize;
+        assert_eq!(rgb.len(), size * size * 3);
+        let dark = |x: usize, y: usize| {
+            let px = &rgb[(y * size + x) * 3..][..3];
+            assert!(px == DARK || px == LIGHT, "stray colour at {x},{y}");
+            px == DARK
+        };
+
+        let matrix = Matrix::encode_text(text, QrCodeEcc::Medium).unwrap();
+        let modules = matrix.size() as usize;
+        // Module (0, 0) is the dark corner of the top-left finder pattern,
+        // and the top-right finder ends the first row.
+        let (left, top) = (0..size * size)
+            .map(|i| (i % size, i / size))
+            .find(|&(x, y)| dark(x, y))
+            .unwrap();
+        let right = (0..size).rev().find(|&x| dark(x, top)).unwrap();
+        let width = right + 1 - left;
+        assert_eq!(width % modules, 0, "modules are not all the same size");
+        let scale = width / modules;
+        assert!(left >= QUIET_ZONE as usize * scale, "quiet zone too narrow");
+        assert!(size - 1 - right >= QUIET_ZONE as usize * scale);
+        assert_eq!(left, top);
+
+        for y in 0..size {
+            for x in 0..size {
+                let inside = (left..=right).contains(&x) && (top..top + width).contains(&y);
+                if !inside {
+                    assert!(!dark(x, y), "dark pixel in the quiet zone at {x},{y}");
+                }
+            }
+        }
+        for my in 0..modules {
+            for mx in 0..modules {
+                let x = left + mx * scale + scale / 2;
+                let y = top + my * scale + scale / 2;
+                assert_eq!(
+                    dark(x, y),
+                    matrix.get_module(mx as i32, my as i32),
+                    "module {mx},{my} of {text:?}"
+                );
+            }
+        }
+    }
+
+    fn ap(ssid: &str, password: &str, hidden: bool) -> ApSettings {
+        ApSettings {
+            ssid: ssid.into(),
+            password: password.into(),
+            hidden,
+            ..ApSettings::default()
+        }
+    }
+
+    #[test]
+    fn wifi_payload_with_password() {
+        assert_eq!(
+            payload(&QrCode::Wifi, &ap("VRCBadge-3F1A", "hunter22", false)),
+            "WIFI:T:WPA;S:VRCBadge-3F1A;P:hunter22;;"
+        );
+    }
+
+    #[test]
+    fn wifi_payload_for_an_open_network() {
+        assert_eq!(
+            payload(&QrCode::Wifi, &ap("VRCBadge-3F1A", "", false)),
+            "WIFI:T:nopass;S:VRCBadge-3F1A;;"
+        );
+    }
+
+    #[test]
+    fn hidden_networks_are_flagged() {
+        assert_eq!(
+            payload(&QrCode::Wifi, &ap("Badge", "hunter22", true)),
+            "WIFI:T:WPA;S:Badge;P:hunter22;H:true;;"
+        );
+        assert_eq!(
+            payload(&QrCode::Wifi, &ap("Badge", "", true)),
+            "WIFI:T:nopass;S:Badge;H:true;;"
+        );
+    }
+
+    #[test]
+    fn reserved_characters_are_escaped() {
+        assert_eq!(
+            payload(&QrCode::Wifi, &ap(r#"a;b,c:d"e\f"#, r#"p;a:s\s,w""#, false)),
+            r#"WIFI:T:WPA;S:a\;b\,c\:d\"e\\f;P:p\;a\:s\\s\,w\";;"#
+        );
+        // Anything else goes through as is.
+        assert_eq!(escape_wifi("Café 🦊 #1"), "Café 🦊 #1");
+    }
+
+    #[test]
+    fn link_payloads() {
+        let ap = ApSettings::default();
+        let url = QrCode::Url {
+            url: "https://example.com/a?b=c;d".into(),
+        };
+        assert_eq!(payload(&url, &ap), "https://example.com/a?b=c;d");
+        let vrchat = QrCode::Vrchat {
+            user_id: "usr_0123".into(),
+        };
+        assert_eq!(
+            payload(&vrchat, &ap),
+            "https://vrchat.com/home/user/usr_0123"
+        );
+    }
+
+    #[test]
+    fn rendered_codes_match_the_encoded_matrix() {
+        assert_renders("https://vrchat.com/home/user/usr_0123abcd-ef45-6789-abcd-ef0123456789");
+        assert_renders(&payload(
+            &QrCode::Wifi,
+            &ap(r#"Badge;1,"x":\"#, "pa;ss:word", true),
+        ));
+    }
+}
@@ -0,0 +1,436 @@
+//! Device settings: how the badge behaves, as opposed to what it shows
+//! (`profile`). Stored as one JSON value under the NVS key `settings`,
+//! loaded at boot and edited from the Settings page and `/api/settings`.
+//!
+//! Unknown keys in NVS are ignored and missing ones take their defaults, so
+//! firmware versions can add settings without a migration.
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+#[cfg(any(not(feature = "simulator"), test))]
+use serde_json::Value;
+
+use crate::hal::KeyValueStore;
+use crate::log_filter::LogFilter;
+use crate::profile::FieldError;
+use crate::storage;
+
+/// Lowest backlight brightness in percent (the slider's minimum; darker is
+/// unreadable in daylight).
+pub const MIN_BRIGHTNESS: u8 = 10;
+
+/// Longest dim, screen or AP timeout in seconds.
+pub const MAX_IDLE_TIMEOUT: u32 = 3600;
+
+/// Dim and screen timeouts the Settings page steps through, in seconds
+/// (0 = never).
+pub const TIMEOUT_STEPS: [u32; 5] = [0, 30, 60, 120, 300];
+
+/// Start of the badge's default AP SSID; see [`default_ap_ssid`].
+pub const AP_SSID_PREFIX: &str = "VRCBadge";
+
+/// Most stations the AP lets join at once (ESP-IDF's limit).
+pub const MAX_AP_CLIENTS: u8 = 10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    /// Backlight brightness in percent ([`MIN_BRIGHTNESS`]-100).
+    pub brightness: u8,
+    /// Seconds without a touch before the backlight dims; 0 = never.
+    /// A minute by default, to save the battery.
+    pub dim_after_secs: u32,
+    /// Seconds without a touch before the screen turns off; 0 = never.
+    /// Five minutes by default.
+    pub screen_timeout_secs: u32,
+    /// Log level filter spec, e.g. `info,wifi=debug` (see `log_filter`).
+    pub log_levels: String,
+    pub ap: ApSettings,
+    pub ui: UiPreferences,
+    pub power: PowerSettings,
+}
+
+/// The badge's own access point. Read once at boot by `wifi::init`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApSettings {
+    /// Empty for this badge's default name (see [`default_ap_ssid`]).
+    pub ssid: String,
+    /// WPA2 passphrase; empty for an open network.
+    pub password: String,
+    /// 2.4 GHz channel, 1-13.
+    pub channel: u8,
+    /// Stations allowed at once, 1-[`MAX_AP_CLIENTS`].
+    pub max_clients: u8,
+    /// Leave the SSID out of beacons; phones join from the QR code or by
+    /// typing the name.
+    pub hidden: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiPreferences {
+    /// Battery percentage beside the battery icon on the badge page.
+    pub show_battery_percent: bool,
+    /// Swiping the badge page switches profiles.
+    pub swipe_profiles: bool,
+}
+
+/// Battery and radio power saving (see `power`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerSettings {
+    /// Low battery saver: dimmer screen and radios off as the battery runs
+    /// down.
+    pub battery_saver: bool,
+    /// Seconds with no AP client and no touch before the AP turns off;
+    /// 0 = never.
+    pub ap_off_after_secs: u32,
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        Self {
+            brightness: 50,
+            dim_after_secs: 60,
+            screen_timeout_secs: 300,
+            log_levels: LogFilter::default().to_string(),
+            ap: ApSettings::default(),
+            ui: UiPreferences::default(),
+            power: PowerSettings::default(),
+        }
+    }
+}
+
+impl Default for ApSettings {
+    fn default() -> Self {
+        Self {
+            ssid: String::new(),
+            password: String::new(),
+            channel: 1,
+            max_clients: 4,
+            hidden: false,
+        }
+    }
+}
+
+impl ApSettings {
+    /// These settings with an empty SSID replaced by the default name for
+    /// the AP with MAC address `mac`.
+    pub fn for_device(&self, mac: [u8; 6]) -> Self {
+        let mut ap = self.clone();
+        if ap.ssid.is_empty() {
+            ap.ssid = default_ap_ssid(mac);
+        }
+        ap
+    }
+}
+
+/// Default SSID for the AP with MAC address `mac`, e.g. `VRCBadge-3F1A`:
+/// badges side by side at an event each get their own network.
+pub fn default_ap_ssid(mac: [u8; 6]) -> String {
+    format!("{AP_SSID_PREFIX}-{:02X}{:02X}", mac[4], mac[5])
+}
+
+impl Default for UiPreferences {
+    fn default() -> Self {
+        Self {
+            show_battery_percent: true,
+            swipe_profiles: true,
+        }
+    }
+}
+
+impl Default for PowerSettings {
+    fn default() -> Self {
+        Self {
+            battery_saver: true,
+            ap_off_after_secs: 0,
+        }
+    }
+}
+
+impl DeviceSettings {
+    /// Every problem with these settings, one entry per field.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        if !(MIN_BRIGHTNESS..=100).contains(&self.brightness) {
+            errors.push(FieldError::new(
+                "brightness",
+                format!("must be {MIN_BRIGHTNESS}-100"),
+            ));
+        }
+        if self.dim_after_secs > MAX_IDLE_TIMEOUT {
+            errors.push(FieldError::new(
+                "dim_after_secs",
+                format!("must be 0-{MAX_IDLE_TIMEOUT}"),
+            ));
+        }
+        if self.screen_timeout_secs > MAX_IDLE_TIMEOUT {
+            errors.push(FieldError::new(
+                "screen_timeout_secs",
+                format!("must be 0-{MAX_IDLE_TIMEOUT}"),
+            ));
+        }
+        if let Err(e) = self.log_levels.parse::<LogFilter>() {
+            errors.push(FieldError::new("log_levels", e));
+        }
+        if self.ap.ssid.len() > 32 {
+            errors.push(FieldError::new(
+                "ap.ssid",
+                "must be empty (default name) or up to 32 bytes",
+            ));
+        } else if self.ap.ssid.chars().any(char::is_control) {
+            errors.push(FieldError::new(
+                "ap.ssid",
+                "must not contain control characters",
+            ));
+        }
+        let password = &self.ap.password;
+        let printable = password.bytes().all(|b| (0x20..0x7f).contains(&b));
+        if !password.is_empty() && (!(8..=63).contains(&password.len()) || !printable) {
+            errors.push(FieldError::new(
+                "ap.password",
+                "must be empty (open network) or 8-63 printable ASCII characters",
+            ));
+        }
+        if !(1..=13).contains(&self.ap.channel) {
+            errors.push(FieldError::new("ap.channel", "must be 1-13"));
+        }
+        if !(1..=MAX_AP_CLIENTS).contains(&self.ap.max_clients) {
+            errors.push(FieldError::new(
+                "ap.max_clients",
+                format!("must be 1-{MAX_AP_CLIENTS}"),
+            ));
+        }
+        if self.power.ap_off_after_secs > MAX_IDLE_TIMEOUT {
+            errors.push(FieldError::new(
+                "power.ap_off_after_secs",
+                format!("must be 0-{MAX_IDLE_TIMEOUT}"),
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The saved log levels, or the default filter if they don't parse.
+    pub fn log_filter(&self) -> LogFilter {
+        self.log_levels.parse().unwrap_or_default()
+    }
+
+    /// These settings with the fields in `patch` replaced, e.g.
+    /// `{"brightness": 80, "ui": {"swipe_profiles": false}}`. Objects merge
+    /// key by key; unknown keys are errors so a typo isn't silently dropped.
+    /// The result is validated.
+    #[cfg(any(not(feature = "simulator"), test))]
+    pub fn patched(&self, patch: &Value) -> Result<Self, Vec<FieldError>> {
+        let mut merged =
+            serde_json::to_value(self).map_err(|e| vec![FieldError::new("", e.to_string())])?;
+        let mut errors = Vec::new();
+        merge(&mut merged, patch, "", &mut errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let settings: Self =
+            serde_json::from_value(merged).map_err(|e| vec![FieldError::new("", e.to_string())])?;
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+/// Merge `patch` into `target` (both JSON), recording unknown keys and
+/// objects replaced by non-objects in `errors`.
+#[cfg(any(not(feature = "simulator"), test))]
+fn merge(target: &mut Value, patch: &Value, path: &str, errors: &mut Vec<FieldError>) {
+    let (Value::Object(target), Value::Object(patch)) = (target, patch) else {
+        errors.push(FieldError::new(path, "expected an object"));
+        return;
+    };
+    for (key, value) in patch {
+        let field = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        match target.get_mut(key) {
+            None => errors.push(FieldError::new(field, "unknown setting")),
+            Some(slot @ Value::Object(_)) => merge(slot, value, &field, errors),
+            Some(slot) => *slot = value.clone(),
+        }
+    }
+}
+
+/// Settings shared between the main loop, UI callbacks and the HTTP server.
+pub type SharedSettings = Arc<Mutex<DeviceSettings>>;
+
+/// Apply `edit` to the shared settings and save them to NVS. Main thread
+/// only (NVS is `!Send`).
+pub fn update(
+    shared: &SharedSettings,
+    nvs: &mut dyn KeyValueStore,
+    edit: impl FnOnce(&mut DeviceSettings),
+) {
+    let Ok(mut settings) = shared.lock() else {
+        return;
+    };
+    edit(&mut settings);
+    storage::save_settings(nvs, &settings);
+}
+
+/// The timeout after `secs` in [`TIMEOUT_STEPS`], wrapping round to
+/// "never". A value set over HTTP that isn't a step goes to the next
+/// larger one.
+pub fn next_timeout(secs: u32) -> u32 {
+    TIMEOUT_STEPS
+        .iter()
+        .copied()
+        .find(|&step| step > secs)
+        .unwrap_or(0)
+}
+
+/// Timeout as shown on the Settings page, e.g. `Never`, `30 s`, `2 min`.
+pub fn timeout_label(secs: u32) -> String {
+    match secs {
+        0 => "Never".into(),
+        s if s % 60 == 0 => format!("{} min", s / 60),
+        s => format!("{s} s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(errors: &[FieldError]) -> Vec<&str> {
+        errors.iter().map(|e| e.field.as_str()).collect()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(DeviceSettings::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn the_screen_dims_and_turns_off_by_default() {
+        let s = DeviceSettings::default();
+        assert!(s.dim_after_secs > 0);
+        assert!(s.screen_timeout_secs > s.dim_after_secs);
+        // Both are steps the Settings page offers
+        assert!(TIMEOUT_STEPS.contains(&s.dim_after_secs));
+        assert!(TIMEOUT_STEPS.contains(&s.screen_timeout_secs));
+    }
+
+    #[test]
+    fn validate_reports_every_bad_field() {
+        let settings = DeviceSettings {
+            brightness: MIN_BRIGHTNESS - 1,
+            dim_after_secs: MAX_IDLE_TIMEOUT + 1,
+            screen_timeout_secs: MAX_IDLE_TIMEOUT + 1,
+            log_levels: "loud".into(),
+            ap: ApSettings {
+                ssid: "x".repeat(33),
+                password: "short".into(),
+                channel: 14,
+                max_clients: 0,
+                hidden: false,
+            },
+            ui: UiPreferences::default(),
+            power: PowerSettings {
+                battery_saver: true,
+                ap_off_after_secs: MAX_IDLE_TIMEOUT + 1,
+            },
+        };
+        let errors = settings.validate().unwrap_err();
+        assert_eq!(
+            fields(&errors),
+            [
+                "brightness",
+                "dim_after_secs",
+                "screen_timeout_secs",
+                "log_levels",
+                "ap.ssid",
+                "ap.password",
+                "ap.channel",
+                "ap.max_clients",
+                "power.ap_off_after_secs",
+            ]
+        );
+    }
+
+    #[test]
+    fn ap_password_is_open_or_wpa2() {
+        let with_password = |password: &str| {
+            let mut settings = DeviceSettings::default();
+            settings.ap.password = password.into();
+            settings.validate()
+        };
+        assert!(with_password("").is_ok());
+        assert!(with_password("12345678").is_ok());
+        assert!(with_password(&"a".repeat(63)).is_ok());
+        assert!(with_password("1234567").is_err());
+        assert!(with_password(&"a".repeat(64)).is_err());
+        assert!(with_password("pässwort").is_err());
+    }
+
+    #[test]
+    fn patch_merges_nested_keys() {
+        let settings = DeviceSettings::default();
+        let patched = settings
+            .patched(&json!({ "brightness": 80, "ui": { "swipe_profiles": false } }))
+            .unwrap();
+        assert_eq!(patched.brightness, 80);
+        assert!(!patched.ui.swipe_profiles);
+        // Siblings of a patched key keep their values.
+        assert_eq!(
+            patched.ui.show_battery_percent,
+            settings.ui.show_battery_percent
+        );
+        assert_eq!(patched.ap, settings.ap);
+        assert_eq!(settings.patched(&json!({})).unwrap(), settings);
+    }
+
+    #[test]
+    fn patch_rejects_unknown_keys_and_bad_shapes() {
+        let settings = DeviceSettings::default();
+        let errors = settings
+            .patched(&json!({ "brigthness": 80, "ap": { "chanel": 6 }, "ui": true }))
+            .unwrap_err();
+        let mut fields = fields(&errors);
+        fields.sort();
+        assert_eq!(fields, ["ap.chanel", "brigthness", "ui"]);
+
+        assert!(settings.patched(&json!([1, 2])).is_err());
+        assert!(settings.patched(&json!({ "brightness": "high" })).is_err());
+    }
+
+    #[test]
+    fn patch_validates_the_result() {
+        let errors = DeviceSettings::default()
+            .patched(&json!({ "ap": { "channel": 0 } }))
+            .unwrap_err();
+        assert_eq!(fields(&errors), ["ap.channel"]);
+    }
+
+    #[test]
+    fn stored_settings_fill_in_missing_keys() {
+        let settings: DeviceSettings =
+            serde_json::from_value(json!({ "brightness": 70, "retired_setting": 1 })).unwrap();
+        assert_eq!(settings.brightness, 70);
+        assert_eq!(settings.ap, ApSettings::default());
+    }
+
+    #[test]
+    fn timeouts_step_through_the_presets() {
+        assert_eq!(next_timeout(0), 30);
+        assert_eq!(next_timeout(300), 0);
+        assert_eq!(next_timeout(45), 60);
+        assert_eq!(timeout_label(0), "Never");
+        assert_eq!(timeout_label(120), "2 min");
+        assert_eq!(timeout_label(45), "45 s");
+    }
+}
@@ -0,0 +1,420 @@
+//! Host-side simulator for the badge UI.
+//!
+//! Runs `BadgeUI` in an 800x480 desktop window (Slint winit backend) with
+//! the same callbacks and `PendingState` plumbing as the firmware, so badge
+//! pages can be iterated on without flashing the JC8048W550. Hardware is
+//! replaced by stand-ins:
+//!
+//! - NVS → [`nvs::SimNvs`] (JSON file in `./sim-storage`)
+//! - SPIFFS → [`FsImageStore`] on the `./sim-storage` directory
+//! - WiFi → [`FakeWifi`] with the networks in [`demo_networks`]
+//! - Backlight → [`FakeBacklight`] (logs the level)
+//! - Idle dim / screen off → not simulated (no touch hook); the timeout
+//!   settings are saved as on the badge
+//! - MAX17048 → [`Max17048`] on a [`FakeI2c`] register map (see [`demo_battery`])
+//! - OTA → `ota` stand-ins (always "Up to date")
+//! - `sysinfo` → fixed heap / PSRAM readings
+//! - Crash log → `sim-storage/crashes.json` (a panic in the simulator is
+//!   reported on the next run)
+//!
+//! ```text
+//! cargo sim                                        # from firmware/
+//! cargo sim -- --profile me.json --avatar a.gif --background bg.jpg
+//! ```
+//!
+//! Files given on the command line are pushed into `PendingState` exactly
+//! like web uploads (`.png`/`.jpg`/`.gif` decoded, anything else raw RGB888), and pushed again whenever they change on disk, so
+//! editing `me.json` updates the badge on the next ~2 s tick.
+
+pub mod nvs;
+
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use slint::ComponentHandle;
+
+use crate::auth::{self, Auth};
+use crate::battery::{self, Max17048};
+use crate::callbacks::UiDeps;
+use crate::hal::fake::{FakeBacklight, FakeI2c, FakeNetwork, FakeWifi};
+use crate::hal::{SharedBacklight, SharedImageStore, SharedKvStore, SharedWifi, StaStatus};
+use crate::image_decode::{self, DecodedImage, UploadFormat};
+use crate::ota::OtaUpdater;
+use crate::pending::{PendingState, ProfileUpload};
+use crate::profile::ProfileOp;
+use crate::storage::FsImageStore;
+use crate::ui_helpers::{
+    show_active_profile, show_last_crash, show_log_levels, show_running_ap, show_settings,
+};
+use crate::wifi_state::{self, RunningAp, WiFiState};
+use crate::{callbacks, crash, logger, storage, sysinfo, BadgeUI};
+
+/// Host directory standing in for the SPIFFS partition (and the NVS file).
+pub const STORAGE_DIR: &str = "sim-storage";
+
+/// Simulated AP address, same as the badge's default.
+const AP_IP: &str = "192.168.71.1";
+
+/// Simulated AP MAC address (locally administered), for the default SSID.
+const AP_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0xb4, 0xd6];
+
+/// Networks shown by the WiFi scan page.
+fn demo_networks() -> Vec<FakeNetwork> {
+    [
+        ("HomeNet", -42, "badge1234"),
+        ("Convention-Guest", -63, ""),
+        ("Neighbor5G", -81, "hunter22"),
+    ]
+    .into_iter()
+    .map(|(ssid, rssi, password)| FakeNetwork {
+        ssid: ssid.into(),
+        rssi,
+        password: password.into(),
+    })
+    .collect()
+}
+
+/// Fuel gauge registers for a partly discharged cell: 76.5%, 3.90 V,
+/// discharging at ~2 %/h.
+fn demo_battery() -> FakeI2c {
+    let mut bus = FakeI2c::default();
+    bus.set_u16(battery::MAX17048_ADDR, battery::REG_VERSION, 0x0012);
+    bus.set_u16(battery::MAX17048_ADDR, battery::REG_SOC, 0x4C80);
+    bus.set_u16(battery::MAX17048_ADDR, battery::REG_VCELL, 0xC300);
+    bus.set_u16(battery::MAX17048_ADDR, battery::REG_CRATE, 0xFFF6);
+    bus
+}
+
+/// Which `PendingState` slot a watched file feeds.
+#[derive(Clone, Copy)]
+enum UploadKind {
+    Profile,
+    Avatar,
+    Background,
+}
+
+/// A file passed on the command line, re-sent whenever its mtime changes.
+struct WatchedUpload {
+    kind: UploadKind,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedUpload {
+    /// Push the file into `pending` if it changed since the last call.
+    fn poll(&mut self, pending: &PendingState) {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        let data = match std::fs::read(&self.path) {
+            Ok(d) => d,
+            Err(e) => {
+                log::warn!("Failed to read {}: {e}", self.path.display());
+                return;
+            }
+        };
+
+        match self.kind {
+            UploadKind::Profile => match serde_json::from_slice(&data) {
+                Ok(profile) => {
+                    // Like `POST /api/profile`: replaces the active profile.
+                    let Ok(mut set) = pending.profiles.lock() else {
+                        return;
+                    };
+                    let id = set.active_id();
+                    if set.update(id, None, Some(profile)).is_ok() {
+                        if let Ok(mut ops) = pending.profile_ops.lock() {
+                            ops.push(ProfileOp::Saved(id));
+                        }
+                    }
+                }
+                Err(e) => log::warn!("{}: invalid profile JSON: {e}", self.path.display()),
+            },
+            UploadKind::Avatar => {
+                let (w, h) = (storage::AVATAR_WIDTH, storage::AVATAR_HEIGHT);
+                let Some(profile_id) = active_profile(pending) else {
+                    return;
+                };
+                match decode_image_file(&self.path, data, w, h, true) {
+                    Some(DecodedImage::Still(data)) => {
+                        if let Ok(mut slot) = pending.avatar.lock() {
+                            *slot = Some(ProfileUpload { profile_id, data });
+                        }
+                    }
+                    Some(DecodedImage::Animated(data)) => {
+                        if let Ok(mut slot) = pending.avatar_animation.lock() {
+                            *slot = Some(ProfileUpload { profile_id, data });
+                        }
+                    }
+                    None => {}
+                }
+            }
+            UploadKind::Background => {
+                let (w, h) = (storage::BACKGROUND_WIDTH, storage::BACKGROUND_HEIGHT);
+                let Some(profile_id) = active_profile(pending) else {
+                    return;
+                };
+                if let Some(DecodedImage::Still(data)) =
+                    decode_image_file(&self.path, data, w, h, false)
+                {
+                    if let Ok(mut slot) = pending.background.lock() {
+                        *slot = Some(ProfileUpload { profile_id, data });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Id of the profile on screen, which a watched image file is for.
+fn active_profile(pending: &PendingState) -> Option<u32> {
+    pending.profiles.lock().ok().map(|set| set.active_id())
+}
+
+/// Decode an image file the way the `/api/avatar` and `/api/background`
+/// handlers do: `.png`, `.jpg` and `.gif` files are decoded and cropped,
+/// anything else must be raw RGB888 of exactly the target size.
+fn decode_image_file(
+    path: &Path,
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    animated: bool,
+) -> Option<DecodedImage> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let format = match extension.as_deref() {
+        Some("png") => UploadFormat::Png,
+        Some("jpg" | "jpeg") => UploadFormat::Jpeg,
+        Some("gif") => UploadFormat::Gif,
+        _ => UploadFormat::Raw,
+    };
+    if format == UploadFormat::Raw {
+        let expected = (width * height * 3) as usize;
+        if data.len() != expected {
+            log::warn!("Expected {expected} bytes, got {}", data.len());
+            return None;
+        }
+        return Some(DecodedImage::Still(data));
+    }
+    image_decode::decode_upload(format, data.as_slice(), width, height, animated)
+        .map_err(|e| log::warn!("{}: {e}", path.display()))
+        .ok()
+}
+
+/// Parse `--profile`, `--avatar` and `--background` arguments.
+fn parse_args() -> anyhow::Result<Vec<WatchedUpload>> {
+    let mut uploads = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let kind = match flag.as_str() {
+            "--profile" => UploadKind::Profile,
+            "--avatar" => UploadKind::Avatar,
+            "--background" => UploadKind::Background,
+            other => anyhow::bail!(
+                "unknown argument '{other}' (expected --profile, --avatar or --background)"
+            ),
+        };
+        let path = args
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("{flag} needs a file path"))?;
+        uploads.push(WatchedUpload {
+            kind,
+            path: path.into(),
+            modified: None,
+        });
+    }
+    Ok(uploads)
+}
+
+/// Run the simulator until the window is closed.
+pub fn run() -> anyhow::Result<()> {
+    logger::init();
+    let boot_time = Instant::now();
+    log::info!("VRCBadge simulator starting");
+
+    let mut uploads = parse_args()?;
+
+    // --- Stand-in storage + WiFi ---
+    std::fs::create_dir_all(STORAGE_DIR)?;
+    crash::init(STORAGE_DIR);
+    let nvs: SharedKvStore = Rc::new(RefCell::new(nvs::SimNvs::open(format!(
+        "{STORAGE_DIR}/nvs-badge.json"
+    ))?));
+    let fs_images = FsImageStore::new(STORAGE_DIR);
+    fs_images.recover();
+    fs_images.migrate_raw_images();
+    let images: SharedImageStore = Arc::new(fs_images);
+    let mut fake_wifi = FakeWifi::new(demo_networks());
+    // Pretend the developer's laptop is joined to the AP, and make scan /
+    // connect take about as long as on the badge.
+    fake_wifi.clients = 1;
+    fake_wifi.delay = Duration::from_millis(1500);
+    let wifi_handle: SharedWifi = Arc::new(Mutex::new(fake_wifi));
+    let settings = storage::load_settings(&mut *nvs.borrow_mut());
+    let log_filter = settings.log_filter();
+    logger::set_filter(log_filter.clone());
+    wifi_state::set_running_ap(RunningAp::new(&settings.ap, AP_MAC));
+    let backlight: SharedBacklight = Rc::new(RefCell::new(FakeBacklight::default()));
+    let _ = backlight
+        .borrow_mut()
+        .set_percent(settings.brightness as f32);
+    let mut battery = Max17048::new(demo_battery())?;
+    let pin = auth::load_or_create_pin(&mut *nvs.borrow_mut());
+    let profiles = storage::load_profiles(&mut *nvs.borrow_mut(), &*images);
+    let pending = PendingState::new(profiles, settings, Auth::new(pin));
+
+    // --- Create UI (same initial state as the firmware boot) ---
+    let ui = BadgeUI::new().map_err(|e| anyhow::anyhow!("Failed to create UI: {:?}", e))?;
+    if let Ok(set) = pending.profiles.lock() {
+        show_active_profile(&ui, &*images, &set);
+    }
+    ui.set_battery_percent(battery.read()?.display_percent());
+    ui.set_wifi_ip(AP_IP.into());
+    show_running_ap(&ui);
+    if let Ok(auth) = pending.auth.lock() {
+        ui.set_admin_pin(auth.pin().into());
+    }
+    ui.set_firmware_version(sysinfo::firmware_version().into());
+    show_log_levels(&ui, &log_filter);
+    if let Ok(settings) = pending.settings.lock() {
+        show_settings(&ui, &settings);
+    }
+    show_last_crash(&ui);
+    ui.set_has_wifi_credentials(storage::load_wifi_credentials(&*nvs.borrow()).is_some());
+
+    let dismiss_keyboard = Arc::new(AtomicBool::new(false));
+    let sta_connected = Rc::new(Cell::new(false));
+    let wifi_state = WiFiState::new();
+    let ota = OtaUpdater::new();
+    callbacks::wire(
+        &ui,
+        UiDeps {
+            wifi_handle: wifi_handle.clone(),
+            wifi_state: wifi_state.clone(),
+            nvs: nvs.clone(),
+            images: images.clone(),
+            profiles: pending.profiles.clone(),
+            settings: pending.settings.clone(),
+            reset: pending.factory_reset.clone(),
+            restart: pending.restart.clone(),
+            auth: pending.auth.clone(),
+            backlight: backlight.clone(),
+            ota: ota.clone(),
+        },
+    );
+
+    // Fast tick: deferred keyboard dismiss + WiFi results (event loop 2b/2c).
+    let fast_tick = slint::Timer::default();
+    {
+        let weak = ui.as_weak();
+        let nvs = nvs.clone();
+        let dismiss_keyboard = dismiss_keyboard.clone();
+        let sta_connected = sta_connected.clone();
+        fast_tick.start(
+            slint::TimerMode::Repeated,
+            Duration::from_millis(50),
+            move || {
+                let Some(ui) = weak.upgrade() else { return };
+                if dismiss_keyboard.swap(false, Ordering::Relaxed) {
+                    let escape: slint::SharedString = slint::platform::Key::Escape.into();
+                    ui.window()
+                        .dispatch_event(slint::platform::WindowEvent::KeyPressed {
+                            text: escape.clone(),
+                        });
+                    ui.window()
+                        .dispatch_event(slint::platform::WindowEvent::KeyReleased { text: escape });
+                }
+                let mut connected = sta_connected.get();
+                wifi_state.poll(
+                    &ui,
+                    &mut *nvs.borrow_mut(),
+                    &dismiss_keyboard,
+                    &mut connected,
+                );
+                sta_connected.set(connected);
+            },
+        );
+    }
+
+    // Slow tick: the event loop's ~2 s periodic poll.
+    let slow_tick = slint::Timer::default();
+    {
+        let weak = ui.as_weak();
+        let mut toast_shown_at = ui.get_toast_visible().then(Instant::now);
+        let mut ota_checked = false;
+        slow_tick.start(
+            slint::TimerMode::Repeated,
+            Duration::from_secs(2),
+            move || {
+                let Some(ui) = weak.upgrade() else { return };
+                match battery.read() {
+                    Ok(reading) => ui.set_battery_percent(reading.display_percent()),
+                    Err(e) => log::warn!("Battery read failed: {e}"),
+                }
+                if let Ok(wifi) = wifi_handle.try_lock() {
+                    ui.set_wifi_clients(wifi.ap_clients() as i32);
+                    match wifi.sta_status() {
+                        StaStatus::Connected { ssid, ip, rssi } => {
+                            if !sta_connected.get() {
+                                log::info!("WiFi STA connected: {ssid} ({ip}, {rssi} dBm)");
+                            }
+                            sta_connected.set(true);
+                            ui.set_sta_connected(true);
+                            ui.set_sta_ssid(ssid.into());
+                            ui.set_sta_ip(ip.to_string().into());
+                        }
+                        StaStatus::Disconnected => {
+                            sta_connected.set(false);
+                            ui.set_sta_connected(false);
+                        }
+                    }
+                }
+
+                if sta_connected.get() && !ota_checked {
+                    ota.spawn_check();
+                    ota_checked = true;
+                } else if !sta_connected.get() {
+                    ota_checked = false;
+                }
+                ota.poll(&ui);
+
+                if ui.get_toast_visible() && toast_shown_at.is_none() {
+                    toast_shown_at = Some(Instant::now());
+                }
+                if let Some(shown_at) = toast_shown_at {
+                    if shown_at.elapsed() >= Duration::from_secs(5) {
+                        ui.set_toast_visible(false);
+                        ui.set_toast_message("".into());
+                        toast_shown_at = None;
+                    }
+                }
+
+                ui.set_about_uptime(sysinfo::uptime_string(&boot_time).into());
+                ui.set_about_heap(format!("{} KB", sysinfo::free_heap_kb()).into());
+                ui.set_about_psram(format!("{} KB", sysinfo::free_psram_kb()).into());
+                ui.set_log_text(logger::snapshot().into());
+
+                for upload in uploads.iter_mut() {
+                    upload.poll(&pending);
+                }
+                pending.poll_into_ui(&ui, &nvs, &*images, &backlight);
+            },
+        );
+    }
+
+    log::info!("Simulator ready, entering Slint event loop");
+    ui.run()
+        .map_err(|e| anyhow::anyhow!("Slint event loop failed: {:?}", e))
+}
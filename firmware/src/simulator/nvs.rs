@@ -0,0 +1,57 @@
+//! JSON-file [`KeyValueStore`] standing in for the `badge` NVS namespace.
+//!
+//! Unlike [`MemoryKvStore`](crate::hal::fake::MemoryKvStore) this persists
+//! across simulator runs. Every write rewrites the whole file; the namespace
+//! only ever holds a handful of short strings.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::hal::KeyValueStore;
+
+pub struct SimNvs {
+    path: PathBuf,
+    entries: BTreeMap<String, String>,
+}
+
+impl SimNvs {
+    /// Open (or start empty) the namespace file at `path`.
+    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, entries })
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&self.entries)?)?;
+        Ok(())
+    }
+}
+
+impl KeyValueStore for SimNvs {
+    fn get_str(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn set_str(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.entries.insert(key.to_string(), value.to_string());
+        self.flush()
+    }
+
+    fn remove(&mut self, key: &str) -> anyhow::Result<bool> {
+        let removed = self.entries.remove(key).is_some();
+        if removed {
+            self.flush()?;
+        }
+        Ok(removed)
+    }
+
+    fn erase_all(&mut self) -> anyhow::Result<()> {
+        self.entries.clear();
+        self.flush()
+    }
+}
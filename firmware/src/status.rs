@@ -0,0 +1,69 @@
+//! System telemetry served by `GET /api/status`.
+//!
+//! The data sources (WiFi driver, I2C fuel gauge, heap counters) are owned
+//! or polled by the main loop, so the loop rebuilds a [`StatusSnapshot`]
+//! every ~2 s tick and publishes a copy into a shared slot. The HTTP handler
+//! only serializes the latest copy.
+
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::battery::BatteryReading;
+use crate::power::PowerMode;
+use crate::sysinfo;
+
+/// Station link details while joined to an external network.
+#[derive(Debug, Clone, Serialize)]
+pub struct StaInfo {
+    pub ssid: String,
+    pub ip: String,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+}
+
+/// SPIFFS usage in bytes.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StorageUsage {
+    pub used_bytes: usize,
+    pub total_bytes: usize,
+}
+
+/// Everything `GET /api/status` reports.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusSnapshot {
+    pub firmware_version: &'static str,
+    pub uptime_secs: u64,
+    /// Same "1h 2m 3s" text as the About page.
+    pub uptime: String,
+    pub free_heap_kb: u32,
+    pub free_psram_kb: u32,
+    pub ap_clients: u8,
+    /// `None` while not joined to an external network.
+    pub sta: Option<StaInfo>,
+    /// `None` until the first gauge read, or if no MAX17048 is fitted.
+    pub battery: Option<BatteryReading>,
+    pub power_mode: PowerMode,
+    /// `None` if SPIFFS could not report its usage.
+    pub storage: Option<StorageUsage>,
+}
+
+impl StatusSnapshot {
+    pub fn new() -> Self {
+        Self {
+            firmware_version: sysinfo::firmware_version(),
+            uptime_secs: 0,
+            uptime: "0s".into(),
+            free_heap_kb: 0,
+            free_psram_kb: 0,
+            ap_clients: 0,
+            sta: None,
+            battery: None,
+            power_mode: PowerMode::Normal,
+            storage: None,
+        }
+    }
+}
+
+/// Latest snapshot, written by the main loop and read by the HTTP server.
+pub type SharedStatus = Arc<Mutex<StatusSnapshot>>;
@@ -1,16 +1,39 @@
-//! Persistent storage: NVS for profile data, SPIFFS for images.
+//! Persistent storage: NVS for profiles and settings, SPIFFS for images.
 //!
 //! SPIFFS is mounted at `/storage` via the raw ESP-IDF C API (with
 //! `format_if_mount_failed: true` so the first boot auto-formats).
 //! NVS uses the default `nvs` partition with namespace `"badge"`.
+//!
+//! The load/save helpers work against the [`KeyValueStore`] and
+//! [`ImageStore`] traits; `EspNvs` and [`FsImageStore`] are the on-device
+//! backends.
+//!
+//! Files are never rewritten in place: [`replace_file`] writes a temporary
+//! copy and renames it over, so a brown-out mid-write leaves the old or the
+//! new file, and [`recover_file`] sorts out the leftovers at boot. NVS needs
+//! none of this; ESP-IDF commits each key atomically.
 
+#[cfg(not(feature = "simulator"))]
 use std::ffi::CString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
+#[cfg(not(feature = "simulator"))]
 use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
-use esp_idf_sys::{esp, esp_vfs_spiffs_conf_t, esp_vfs_spiffs_register};
+#[cfg(not(feature = "simulator"))]
+use esp_idf_sys::{
+    esp, esp_spiffs_info, esp_vfs_spiffs_conf_t, esp_vfs_spiffs_register, nvs_close, nvs_commit,
+    nvs_erase_all, nvs_handle_t, nvs_open, nvs_open_mode_t_NVS_READWRITE,
+};
 
+use crate::auth;
+use crate::hal::{FileSystem, ImageStore, KeyValueStore};
+use crate::image_codec::{self, Animation};
+use crate::log_filter::LogFilter;
 use crate::platform::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
-use crate::profile::Profile;
+use crate::profile::{Profile, ProfileIndex, ProfileSet};
+use crate::profile_schema;
+use crate::settings::DeviceSettings;
 
 /// Avatar image dimensions (must match the Slint UI container: 240x300, 3:4 ratio).
 pub const AVATAR_WIDTH: u32 = 240;
@@ -18,36 +41,63 @@ pub const AVATAR_HEIGHT: u32 = 300;
 pub const AVATAR_IMAGE_SIZE: usize = (AVATAR_WIDTH * AVATAR_HEIGHT * 3) as usize;
 
 /// Background image dimensions (full display: 800x480).
+pub const BACKGROUND_WIDTH: u32 = DISPLAY_WIDTH;
+pub const BACKGROUND_HEIGHT: u32 = DISPLAY_HEIGHT;
 pub const BACKGROUND_IMAGE_SIZE: usize = (DISPLAY_WIDTH * DISPLAY_HEIGHT * 3) as usize;
 
 /// NVS namespace for badge settings (max 15 chars).
+#[cfg(not(feature = "simulator"))]
 const NVS_NAMESPACE: &str = "badge";
 
-/// NVS key for the JSON-serialized profile (max 15 chars).
+/// NVS key of the single profile saved before multi-profile support; only
+/// read once, for migration (max 15 chars).
 const NVS_KEY_PROFILE: &str = "profile";
 
+/// NVS key for the JSON profile index (names, order, active id).
+const NVS_KEY_PROFILE_INDEX: &str = "profile_index";
+
 /// NVS key for saved WiFi station SSID (max 15 chars).
 const NVS_KEY_WIFI_SSID: &str = "wifi_ssid";
 
 /// NVS key for saved WiFi station password (max 15 chars).
 const NVS_KEY_WIFI_PASS: &str = "wifi_pass";
 
+/// NVS key for the JSON [`DeviceSettings`] (max 15 chars).
+const NVS_KEY_SETTINGS: &str = "settings";
+
+/// NVS key for the web API admin PIN (see `auth`; max 15 chars).
+const NVS_KEY_ADMIN_PIN: &str = "admin_pin";
+
+/// NVS key of the log level spec saved before [`DeviceSettings`]; only read
+/// once, for migration (max 15 chars).
+const NVS_KEY_LOG_LEVELS: &str = "log_levels";
+
+/// Extension of image files in [`FsImageStore`] ([`image_codec`] format).
+const IMAGE_EXT: &str = "img";
+
+/// Extension of raw RGB888 image files from older firmware.
+const LEGACY_IMAGE_EXT: &str = "rgb";
+
 /// SPIFFS mount path.
-const SPIFFS_MOUNT: &str = "/storage";
+#[cfg(not(feature = "simulator"))]
+pub const SPIFFS_MOUNT: &str = "/storage";
 
 /// SPIFFS partition label (must match `partitions.csv`).
+#[cfg(not(feature = "simulator"))]
 const SPIFFS_LABEL: &str = "storage";
 
 // ---------------------------------------------------------------------------
 // Initialization
 // ---------------------------------------------------------------------------
 
-/// Mount the SPIFFS `storage` partition at `/storage`.
+/// Mount the SPIFFS `storage` partition at `/storage` and return an image
+/// store rooted there.
 ///
 /// On first boot the partition is unformatted, so we set
-/// `format_if_mount_failed = true`.  Formatting a 12 MB partition takes
-/// roughly 5-10 seconds — this only happens once.
-pub fn init_spiffs() -> anyhow::Result<()> {
+/// `format_if_mount_failed = true`.  Formatting the 8 MB partition takes
+/// roughly 5 seconds — this only happens once.
+#[cfg(not(feature = "simulator"))]
+pub fn init_spiffs() -> anyhow::Result<FsImageStore> {
     let base_path = CString::new(SPIFFS_MOUNT)?;
     let label = CString::new(SPIFFS_LABEL)?;
 
@@ -62,10 +112,30 @@ pub fn init_spiffs() -> anyhow::Result<()> {
     .map_err(|e| anyhow::anyhow!("Failed to mount SPIFFS partition '{}': {e}", SPIFFS_LABEL))?;
 
     log::info!("SPIFFS mounted at {SPIFFS_MOUNT}");
-    Ok(())
+    Ok(FsImageStore::new(SPIFFS_MOUNT))
+}
+
+/// SPIFFS usage as `(used_bytes, total_bytes)`.
+#[cfg(not(feature = "simulator"))]
+pub fn spiffs_usage() -> anyhow::Result<(usize, usize)> {
+    let label = CString::new(SPIFFS_LABEL)?;
+    let mut total = 0;
+    let mut used = 0;
+    esp!(unsafe { esp_spiffs_info(label.as_ptr(), &mut total, &mut used) })?;
+    Ok((used, total))
+}
+
+/// Bytes that new files can still take on SPIFFS: the free space less a
+/// tenth of the total, which SPIFFS needs spare to garbage-collect into
+/// (writes start failing well before it reads as full).
+#[cfg(not(feature = "simulator"))]
+pub fn spiffs_free() -> anyhow::Result<usize> {
+    let (used, total) = spiffs_usage()?;
+    Ok(total.saturating_sub(used).saturating_sub(total / 10))
 }
 
 /// Open an NVS read-write handle for the `"badge"` namespace.
+#[cfg(not(feature = "simulator"))]
 pub fn init_nvs(partition: EspDefaultNvsPartition) -> anyhow::Result<EspNvs<NvsDefault>> {
     let nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
     log::info!("NVS namespace '{NVS_NAMESPACE}' opened");
@@ -73,118 +143,652 @@ pub fn init_nvs(partition: EspDefaultNvsPartition) -> anyhow::Result<EspNvs<NvsD
 }
 
 // ---------------------------------------------------------------------------
-// Profile (NVS)
+// Backends
 // ---------------------------------------------------------------------------
 
-/// Load the saved profile from NVS, returning `None` on any error or if no
-/// profile has been saved yet.
-pub fn load_profile(nvs: &EspNvs<NvsDefault>) -> Option<Profile> {
-    // Query the stored string length (includes NUL terminator).
-    let len = match nvs.str_len(NVS_KEY_PROFILE) {
-        Ok(Some(len)) => len,
-        Ok(None) => return None, // key does not exist
-        Err(e) => {
-            log::warn!("NVS str_len('{NVS_KEY_PROFILE}') error: {e}");
-            return None;
+#[cfg(not(feature = "simulator"))]
+impl KeyValueStore for EspNvs<NvsDefault> {
+    fn get_str(&self, key: &str) -> anyhow::Result<Option<String>> {
+        // Query the stored string length (includes NUL terminator).
+        let Some(len) = self.str_len(key)? else {
+            return Ok(None);
+        };
+        let mut buf = vec![0u8; len];
+        Ok(EspNvs::get_str(self, key, &mut buf)?.map(str::to_string))
+    }
+
+    fn set_str(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        EspNvs::set_str(self, key, value)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> anyhow::Result<bool> {
+        Ok(EspNvs::remove(self, key)?)
+    }
+
+    fn erase_all(&mut self) -> anyhow::Result<()> {
+        // `EspNvs` has no erase-all, so open a second handle on the
+        // namespace for it.
+        let namespace = CString::new(NVS_NAMESPACE)?;
+        let mut handle: nvs_handle_t = 0;
+        esp!(unsafe {
+            nvs_open(
+                namespace.as_ptr(),
+                nvs_open_mode_t_NVS_READWRITE,
+                &mut handle,
+            )
+        })?;
+        let result = esp!(unsafe { nvs_erase_all(handle) })
+            .and_then(|()| esp!(unsafe { nvs_commit(handle) }));
+        unsafe { nvs_close(handle) };
+        Ok(result?)
+    }
+}
+
+/// [`ImageStore`] over a directory, one `<name>.img` file per image.
+///
+/// On the badge the directory is the SPIFFS mount; `std::fs` works there
+/// through the ESP-IDF VFS layer. Writes go through [`replace_file`] and
+/// every file carries CRCs ([`image_codec::verify`]), so
+/// [`FsImageStore::recover`] can tell a good copy from a torn one.
+///
+/// Firmware before the compressed format wrote raw RGB888 `<name>.rgb`
+/// files; [`FsImageStore::migrate_raw_images`] converts them.
+pub struct FsImageStore<F: FileSystem = StdFs> {
+    root: PathBuf,
+    fs: F,
+}
+
+impl FsImageStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self::with_fs(root, StdFs)
+    }
+}
+
+impl<F: FileSystem> FsImageStore<F> {
+    /// Store on another [`FileSystem`], e.g. `hal::fake::FaultyFs` to check
+    /// the write sequence against power loss on the host.
+    pub fn with_fs(root: impl Into<PathBuf>, fs: F) -> Self {
+        Self {
+            root: root.into(),
+            fs,
         }
-    };
+    }
 
-    let mut buf = vec![0u8; len];
-    let json = match nvs.get_str(NVS_KEY_PROFILE, &mut buf) {
-        Ok(Some(s)) => s,
-        Ok(None) => return None,
-        Err(e) => {
-            log::warn!("NVS get_str('{NVS_KEY_PROFILE}') error: {e}");
-            return None;
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(format!("{name}.{IMAGE_EXT}"))
+    }
+
+    /// Finish or undo image writes cut short by a power loss: each image
+    /// left with a `.tmp` or `.bak` copy keeps its newest copy that passes
+    /// its checksums. Call once at boot, before images are loaded.
+    pub fn recover(&self) {
+        let paths = match self.fs.list(&self.root) {
+            Ok(paths) => paths,
+            Err(e) => {
+                log::warn!("Failed to list {}: {e}", self.root.display());
+                return;
+            }
+        };
+        let mut interrupted: Vec<PathBuf> = paths
+            .iter()
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                let image = name
+                    .strip_suffix(TMP_SUFFIX)
+                    .or_else(|| name.strip_suffix(BAK_SUFFIX))?;
+                let ext = Path::new(image).extension()?;
+                (ext == IMAGE_EXT).then(|| path.with_file_name(image))
+            })
+            .collect();
+        interrupted.sort();
+        interrupted.dedup();
+        for path in interrupted {
+            recover_file(&self.fs, &path, |data| image_codec::verify(data).is_ok());
         }
-    };
+    }
+
+    /// Re-encode every legacy raw `.rgb` file into the compressed format
+    /// and delete the original. Dimensions come from the file size (avatar
+    /// or background); anything else is left alone. Call once at boot,
+    /// before images are loaded.
+    pub fn migrate_raw_images(&self) {
+        let paths = match self.fs.list(&self.root) {
+            Ok(paths) => paths,
+            Err(e) => {
+                log::warn!("Failed to list {}: {e}", self.root.display());
+                return;
+            }
+        };
+        for path in paths {
+            if path.extension().and_then(|ext| ext.to_str()) != Some(LEGACY_IMAGE_EXT) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let rgb = match self.fs.read(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Failed to read {}: {e}", path.display());
+                    continue;
+                }
+            };
+            let (width, height) = match rgb.len() {
+                AVATAR_IMAGE_SIZE => (AVATAR_WIDTH, AVATAR_HEIGHT),
+                BACKGROUND_IMAGE_SIZE => (BACKGROUND_WIDTH, BACKGROUND_HEIGHT),
+                len => {
+                    log::warn!("{}: unexpected size {len}, not migrating", path.display());
+                    continue;
+                }
+            };
+            let encoded = image_codec::encode_rgb888(&rgb, width as u16, height as u16);
+            if let Err(e) = self.write(name, &encoded) {
+                log::error!("Failed to write {name}.{IMAGE_EXT}: {e}");
+                continue;
+            }
+            let _ = self.fs.remove(&path);
+            log::info!(
+                "Migrated {name}.{LEGACY_IMAGE_EXT} ({} KB -> {} KB)",
+                rgb.len() / 1024,
+                encoded.len() / 1024
+            );
+        }
+    }
+}
 
-    match serde_json::from_str::<Profile>(json) {
-        Ok(profile) => {
-            log::info!("Loaded profile from NVS");
-            Some(profile)
+impl<F: FileSystem> ImageStore for FsImageStore<F> {
+    fn read(&self, name: &str) -> std::io::Result<Vec<u8>> {
+        self.fs.read(&self.path(name))
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        replace_file(&self.fs, &self.path(name), data)
+    }
+
+    fn remove(&self, name: &str) -> std::io::Result<()> {
+        let path = self.path(name);
+        // Leftovers too, or recovery would bring the image back.
+        for suffix in [TMP_SUFFIX, BAK_SUFFIX] {
+            let _ = self.fs.remove(&with_suffix(&path, suffix));
         }
+        self.fs.remove(&path)
+    }
+
+    fn erase_all(&self) -> std::io::Result<()> {
+        // Keep going past a failed file so one bad entry doesn't leave the
+        // rest behind; report the first error.
+        let mut result = Ok(());
+        for path in self.fs.list(&self.root)? {
+            if let Err(e) = self.fs.remove(&path) {
+                log::warn!("Failed to remove {}: {e}", path.display());
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// [`FileSystem`] on `std::fs` (SPIFFS through the ESP-IDF VFS layer).
+pub struct StdFs;
+
+impl FileSystem for StdFs {
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(data)?;
+        file.sync_all()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn list(&self, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Crash-safe files
+// ---------------------------------------------------------------------------
+
+/// Suffix of the new copy while it is being written.
+const TMP_SUFFIX: &str = ".tmp";
+/// Suffix of the old copy while the new one is renamed into place.
+const BAK_SUFFIX: &str = ".bak";
+
+/// Replace `path` with `data` so that losing power at any step leaves a
+/// complete copy for [`recover_file`]:
+///
+/// 1. write `<path>.tmp` and flush it;
+/// 2. rename `<path>` to `<path>.bak` — from here on the new data wins;
+/// 3. rename `<path>.tmp` to `<path>`;
+/// 4. remove `<path>.bak`.
+///
+/// SPIFFS can't rename onto an existing file, hence the `.bak` step instead
+/// of a single rename. On an error the old file is put back where possible.
+pub fn replace_file(fs: &dyn FileSystem, path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = with_suffix(path, TMP_SUFFIX);
+    let bak = with_suffix(path, BAK_SUFFIX);
+    if let Err(e) = fs.write(&tmp, data) {
+        let _ = fs.remove(&tmp);
+        return Err(e);
+    }
+    // A `.bak` left by an earlier failed step 4 would block the rename.
+    match fs.remove(&bak) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    match fs.rename(path, &bak) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            let _ = fs.remove(&tmp);
+            return Err(e);
+        }
+        _ => {}
+    }
+    if let Err(e) = fs.rename(&tmp, path) {
+        let _ = fs.rename(&bak, path);
+        return Err(e);
+    }
+    // The new file is in place; a `.bak` that survives this is removed by
+    // the next `recover_file`.
+    let _ = fs.remove(&bak);
+    Ok(())
+}
+
+/// Clean up after a [`replace_file`] that was cut short. If `path` is
+/// missing or fails `valid`, the newest valid copy takes its place: the
+/// `.tmp` (the new data, written in full) before the `.bak` (the old data).
+/// Leftover copies are then removed. Returns quickly when there are none.
+pub fn recover_file(fs: &dyn FileSystem, path: &Path, valid: impl Fn(&[u8]) -> bool) {
+    let tmp = with_suffix(path, TMP_SUFFIX);
+    let bak = with_suffix(path, BAK_SUFFIX);
+    // `None`: missing or unreadable.
+    let check = |p: &Path| fs.read(p).ok().map(|data| valid(&data));
+    let (tmp_ok, bak_ok) = (check(&tmp), check(&bak));
+    if tmp_ok.is_none() && bak_ok.is_none() {
+        return;
+    }
+
+    if check(path) != Some(true) {
+        let source = match (tmp_ok, bak_ok) {
+            (Some(true), _) => Some(&tmp),
+            (_, Some(true)) => Some(&bak),
+            _ => None,
+        };
+        if let Some(source) = source {
+            let _ = fs.remove(path);
+            if let Err(e) = fs.rename(source, path) {
+                log::error!("Failed to recover {}: {e}", path.display());
+                return;
+            }
+            log::warn!("Recovered {} from {}", path.display(), source.display());
+        } else {
+            log::error!("{}: no intact copy to recover", path.display());
+        }
+    }
+    for leftover in [&tmp, &bak] {
+        if fs.remove(leftover).is_ok() {
+            log::info!("Removed leftover {}", leftover.display());
+        }
+    }
+}
+
+/// `path` with `suffix` appended to the file name (`a.img` -> `a.img.tmp`).
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// ---------------------------------------------------------------------------
+// Profiles (NVS)
+// ---------------------------------------------------------------------------
+
+/// NVS key holding profile `id` (`profile_<id>`, within the 15-char limit).
+fn profile_key(id: u32) -> String {
+    format!("profile_{id}")
+}
+
+/// SPIFFS image name of profile `id`'s avatar.
+pub fn avatar_name(id: u32) -> String {
+    format!("avatar_{id}")
+}
+
+/// SPIFFS image name of profile `id`'s background.
+pub fn background_name(id: u32) -> String {
+    format!("background_{id}")
+}
+
+/// Load all saved profiles.
+///
+/// On the first boot with profile support, the single legacy profile
+/// (NVS key `profile`, images `avatar` and `background`) becomes profile 1,
+/// "Default". With nothing saved at all, the built-in default is used.
+pub fn load_profiles(nvs: &mut dyn KeyValueStore, images: &dyn ImageStore) -> ProfileSet {
+    let index = match nvs.get_str(NVS_KEY_PROFILE_INDEX) {
+        Ok(Some(json)) => match serde_json::from_str::<ProfileIndex>(&json) {
+            Ok(index) => Some(index),
+            Err(e) => {
+                log::warn!("Failed to parse profile index: {e}");
+                None
+            }
+        },
+        Ok(None) => None,
         Err(e) => {
-            log::warn!("Failed to parse stored profile JSON: {e}");
+            log::warn!("NVS get_str('{NVS_KEY_PROFILE_INDEX}') error: {e}");
             None
         }
+    };
+
+    let Some(index) = index else {
+        return migrate_legacy_profile(nvs, images);
+    };
+
+    let profiles = index
+        .entries
+        .iter()
+        .filter_map(|entry| Some((entry.id, load_profile(nvs, entry.id)?)))
+        .collect();
+    let mut set = ProfileSet { index, profiles };
+    if !set.normalize() {
+        log::warn!("No readable profiles in NVS, starting over");
+        return migrate_legacy_profile(nvs, images);
+    }
+    log::info!(
+        "Loaded {} profile(s), active: {}",
+        set.index.entries.len(),
+        set.name(set.active_id()).unwrap_or("?")
+    );
+    set
+}
+
+/// Turn the pre-multi-profile layout into profile 1 and save it.
+fn migrate_legacy_profile(nvs: &mut dyn KeyValueStore, images: &dyn ImageStore) -> ProfileSet {
+    let legacy = load_legacy_profile(nvs);
+    let migrated = legacy.is_some();
+    let set = ProfileSet::single("Default", legacy.unwrap_or_default());
+
+    for (old, new) in [
+        ("avatar", avatar_name(1)),
+        ("background", background_name(1)),
+    ] {
+        match images.read(old) {
+            Ok(data) => {
+                if let Err(e) = images.write(&new, &data) {
+                    log::error!("Failed to migrate image {old}: {e}");
+                    continue;
+                }
+                let _ = images.remove(old);
+                log::info!("Migrated image {old} -> {new}");
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Failed to read image {old} for migration: {e}"),
+        }
     }
+
+    if let Err(e) =
+        save_profile(nvs, 1, set.active()).and_then(|()| save_profile_index(nvs, &set.index))
+    {
+        log::error!("{e}");
+    }
+    if migrated {
+        let _ = nvs.remove(NVS_KEY_PROFILE);
+        log::info!("Migrated single profile to profile set");
+    }
+    set
+}
+
+/// Load the pre-multi-profile single profile, if any.
+fn load_legacy_profile(nvs: &dyn KeyValueStore) -> Option<Profile> {
+    let json = nvs.get_str(NVS_KEY_PROFILE).ok()??;
+    Some(parse_stored_profile("legacy profile", &json))
 }
 
-/// Save the profile to NVS as a JSON string.
-pub fn save_profile(nvs: &mut EspNvs<NvsDefault>, profile: &Profile) {
-    let json = match serde_json::to_string(profile) {
-        Ok(j) => j,
+/// Load profile `id` from NVS, returning `None` if it can't be read or
+/// doesn't exist. Damaged fields are repaired rather than losing the
+/// profile (see [`profile_schema::from_stored_json`]).
+pub fn load_profile(nvs: &dyn KeyValueStore, id: u32) -> Option<Profile> {
+    let key = profile_key(id);
+    let json = match nvs.get_str(&key) {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            log::warn!("Profile {id} is not in NVS");
+            return None;
+        }
         Err(e) => {
-            log::error!("Failed to serialize profile: {e}");
-            return;
+            log::warn!("NVS get_str('{key}') error: {e}");
+            return None;
         }
     };
 
-    if let Err(e) = nvs.set_str(NVS_KEY_PROFILE, &json) {
-        log::error!("Failed to write profile to NVS: {e}");
-    } else {
-        log::info!("Profile saved to NVS ({} bytes)", json.len());
+    Some(parse_stored_profile(&format!("profile {id}"), &json))
+}
+
+fn parse_stored_profile(what: &str, json: &str) -> Profile {
+    let (profile, notes) = profile_schema::from_stored_json(json);
+    for note in notes {
+        log::warn!("Stored {what}: {note} (repaired)");
     }
+    profile
+}
+
+/// Save profile `id` to NVS as versioned JSON.
+pub fn save_profile(nvs: &mut dyn KeyValueStore, id: u32, profile: &Profile) -> anyhow::Result<()> {
+    let json = profile_schema::to_json(profile).to_string();
+    nvs.set_str(&profile_key(id), &json)
+        .map_err(|e| anyhow::anyhow!("Failed to write profile {id} to NVS: {e}"))?;
+    log::info!("Profile {id} saved to NVS ({} bytes)", json.len());
+    Ok(())
+}
+
+/// Save the profile list and active id to NVS.
+pub fn save_profile_index(nvs: &mut dyn KeyValueStore, index: &ProfileIndex) -> anyhow::Result<()> {
+    let json = serde_json::to_string(index)?;
+    nvs.set_str(NVS_KEY_PROFILE_INDEX, &json)
+        .map_err(|e| anyhow::anyhow!("Failed to write profile index to NVS: {e}"))
+}
+
+/// Remove profile `id`'s NVS entry and images. The index is saved separately.
+#[cfg(any(not(feature = "simulator"), test))]
+pub fn delete_profile(nvs: &mut dyn KeyValueStore, images: &dyn ImageStore, id: u32) {
+    if let Err(e) = nvs.remove(&profile_key(id)) {
+        log::warn!("Failed to remove profile {id} from NVS: {e}");
+    }
+    delete_image(images, &avatar_name(id));
+    delete_image(images, &background_name(id));
+    log::info!("Profile {id} deleted");
+}
+
+/// Replace all saved profiles with `restored` (from a backup, its images
+/// already on SPIFFS under its own ids).
+///
+/// The new profiles are written first and the index last: until the index
+/// is written the badge still boots into `old`, so on any error the new
+/// entries and images are removed again and `old` stays as it was. Only
+/// after the switch are the old profiles deleted.
+#[cfg(any(not(feature = "simulator"), test))]
+pub fn replace_profiles(
+    nvs: &mut dyn KeyValueStore,
+    images: &dyn ImageStore,
+    old: &ProfileSet,
+    restored: &ProfileSet,
+) -> anyhow::Result<()> {
+    let written = write_profiles(nvs, restored);
+    if let Err(e) = written {
+        for entry in &restored.index.entries {
+            delete_profile(nvs, images, entry.id);
+        }
+        return Err(e);
+    }
+    for entry in &old.index.entries {
+        delete_profile(nvs, images, entry.id);
+    }
+    log::info!("Restored {} profiles", restored.index.entries.len());
+    Ok(())
+}
+
+/// Write every profile of `set`, then its index, stopping at the first error.
+#[cfg(any(not(feature = "simulator"), test))]
+fn write_profiles(nvs: &mut dyn KeyValueStore, set: &ProfileSet) -> anyhow::Result<()> {
+    for entry in &set.index.entries {
+        if let Some(profile) = set.get(entry.id) {
+            let json = profile_schema::to_json(profile).to_string();
+            nvs.set_str(&profile_key(entry.id), &json)?;
+        }
+    }
+    nvs.set_str(NVS_KEY_PROFILE_INDEX, &serde_json::to_string(&set.index)?)
 }
 
 // ---------------------------------------------------------------------------
 // Images (SPIFFS)
 // ---------------------------------------------------------------------------
 
-/// Load a raw RGB888 image file from SPIFFS.
+/// Load and decode a saved image as RGB888.
 ///
-/// Returns `None` if the file does not exist, cannot be read, or has an
-/// unexpected size.  `expected_size` is the exact byte count we expect
-/// (e.g. `AVATAR_IMAGE_SIZE` or `BACKGROUND_IMAGE_SIZE`).
-pub fn load_image(name: &str, expected_size: usize) -> Option<Vec<u8>> {
-    let path = format!("{SPIFFS_MOUNT}/{name}.rgb");
-    let data = match std::fs::read(&path) {
-        Ok(d) => d,
+/// Returns `None` if the file does not exist, cannot be read or decoded, or
+/// is not `width` x `height` (e.g. `AVATAR_WIDTH` x `AVATAR_HEIGHT`).
+pub fn load_image(images: &dyn ImageStore, name: &str, width: u32, height: u32) -> Option<Vec<u8>> {
+    let data = read_image_file(images, name)?;
+    decode_image(name, &data, width, height)
+}
+
+/// A profile's saved avatar: a still image or an animation.
+pub enum SavedAvatar {
+    /// RGB888, `AVATAR_WIDTH` x `AVATAR_HEIGHT`.
+    Still(Vec<u8>),
+    Animated(Animation),
+}
+
+/// Load the avatar of profile `id`. Animations share the avatar's file, so
+/// uploading one replaces the other.
+pub fn load_avatar(images: &dyn ImageStore, id: u32) -> Option<SavedAvatar> {
+    let name = avatar_name(id);
+    let data = read_image_file(images, &name)?;
+    if !image_codec::is_animation(&data) {
+        return decode_image(&name, &data, AVATAR_WIDTH, AVATAR_HEIGHT).map(SavedAvatar::Still);
+    }
+
+    let animation = match Animation::parse(data) {
+        Ok(animation) => animation,
+        Err(e) => {
+            log::warn!("Animation {name}: {e}, ignoring");
+            return None;
+        }
+    };
+    let size = (u32::from(animation.width), u32::from(animation.height));
+    if size != (AVATAR_WIDTH, AVATAR_HEIGHT) {
+        log::warn!(
+            "Animation {name}: unexpected size {}x{}, ignoring",
+            size.0,
+            size.1
+        );
+        return None;
+    }
+    log::info!(
+        "Loaded animation {name} ({} frames, {} KB on flash)",
+        animation.frame_count(),
+        animation.encoded_len() / 1024
+    );
+    Some(SavedAvatar::Animated(animation))
+}
+
+/// Read a saved image file; `None` (logged unless missing) on failure.
+fn read_image_file(images: &dyn ImageStore, name: &str) -> Option<Vec<u8>> {
+    match images.read(name) {
+        Ok(d) => Some(d),
         Err(e) => {
             // `NotFound` is expected on first boot — don't warn for it.
             if e.kind() != std::io::ErrorKind::NotFound {
-                log::warn!("Failed to read {path}: {e}");
+                log::warn!("Failed to read image {name}: {e}");
             }
+            None
+        }
+    }
+}
+
+/// Decode a saved still image, checking it is `width` x `height`.
+fn decode_image(name: &str, data: &[u8], width: u32, height: u32) -> Option<Vec<u8>> {
+    let (header, rgb) = match image_codec::decode_rgb888(data) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            log::warn!("Image {name}: {e}, ignoring");
             return None;
         }
     };
-
-    if data.len() != expected_size {
+    if (u32::from(header.width), u32::from(header.height)) != (width, height) {
         log::warn!(
-            "{path}: unexpected size {} (expected {expected_size}), ignoring",
-            data.len()
+            "Image {name}: unexpected size {}x{} (expected {width}x{height}), ignoring",
+            header.width,
+            header.height
         );
         return None;
     }
 
-    log::info!("Loaded {path} ({} KB)", data.len() / 1024);
-    Some(data)
+    log::info!("Loaded image {name} ({} KB on flash)", data.len() / 1024);
+    Some(rgb)
 }
 
 /// Delete a saved image from SPIFFS.  Returns `true` if the file was removed.
-pub fn delete_image(name: &str) -> bool {
-    let path = format!("{SPIFFS_MOUNT}/{name}.rgb");
-    match std::fs::remove_file(&path) {
+pub fn delete_image(images: &dyn ImageStore, name: &str) -> bool {
+    match images.remove(name) {
         Ok(()) => {
-            log::info!("Deleted {path}");
+            log::info!("Deleted image {name}");
             true
         }
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
         Err(e) => {
-            log::warn!("Failed to delete {path}: {e}");
+            log::warn!("Failed to delete image {name}: {e}");
             false
         }
     }
 }
 
-/// Save a raw RGB888 image to SPIFFS.  Logs errors but never panics.
-pub fn save_image(name: &str, data: &[u8]) {
-    let path = format!("{SPIFFS_MOUNT}/{name}.rgb");
-    if let Err(e) = std::fs::write(&path, data) {
-        log::error!("Failed to write {path}: {e}");
+/// Encode `width` x `height` RGB888 pixels and save them to SPIFFS.  Logs
+/// errors but never panics.
+pub fn save_image(images: &dyn ImageStore, name: &str, width: u32, height: u32, rgb: &[u8]) {
+    if rgb.len() != (width * height * 3) as usize {
+        log::error!(
+            "Image {name}: {} bytes is not {width}x{height} RGB888",
+            rgb.len()
+        );
+        return;
+    }
+    let encoded = image_codec::encode_rgb888(rgb, width as u16, height as u16);
+    if let Err(e) = images.write(name, &encoded) {
+        log::error!("Failed to write image {name}: {e}");
+    } else {
+        log::info!(
+            "Saved image {name} ({} KB, {} KB raw)",
+            encoded.len() / 1024,
+            rgb.len() / 1024
+        );
+    }
+}
+
+/// Save an animation container to SPIFFS as-is.  Logs errors but never
+/// panics.
+pub fn save_animation(images: &dyn ImageStore, name: &str, animation: &Animation) {
+    if let Err(e) = images.write(name, animation.as_bytes()) {
+        log::error!("Failed to write animation {name}: {e}");
     } else {
-        log::info!("Saved {path} ({} KB)", data.len() / 1024);
+        log::info!(
+            "Saved animation {name} ({} frames, {} KB)",
+            animation.frame_count(),
+            animation.encoded_len() / 1024
+        );
     }
 }
 
@@ -195,15 +799,9 @@ pub fn save_image(name: &str, data: &[u8]) {
 /// Load saved WiFi station credentials from NVS.
 ///
 /// Returns `None` if no credentials are saved.
-pub fn load_wifi_credentials(nvs: &EspNvs<NvsDefault>) -> Option<(String, String)> {
-    let ssid_len = match nvs.str_len(NVS_KEY_WIFI_SSID) {
-        Ok(Some(len)) => len,
-        _ => return None,
-    };
-
-    let mut ssid_buf = vec![0u8; ssid_len];
-    let ssid = match nvs.get_str(NVS_KEY_WIFI_SSID, &mut ssid_buf) {
-        Ok(Some(s)) => s.to_string(),
+pub fn load_wifi_credentials(nvs: &dyn KeyValueStore) -> Option<(String, String)> {
+    let ssid = match nvs.get_str(NVS_KEY_WIFI_SSID) {
+        Ok(Some(s)) => s,
         _ => return None,
     };
 
@@ -212,23 +810,18 @@ pub fn load_wifi_credentials(nvs: &EspNvs<NvsDefault>) -> Option<(String, String
     }
 
     // Password is optional (open networks have empty password)
-    let password = match nvs.str_len(NVS_KEY_WIFI_PASS) {
-        Ok(Some(len)) => {
-            let mut pass_buf = vec![0u8; len];
-            match nvs.get_str(NVS_KEY_WIFI_PASS, &mut pass_buf) {
-                Ok(Some(s)) => s.to_string(),
-                _ => String::new(),
-            }
-        }
-        _ => String::new(),
-    };
+    let password = nvs
+        .get_str(NVS_KEY_WIFI_PASS)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
 
     log::info!("Loaded WiFi credentials for SSID: {ssid}");
     Some((ssid, password))
 }
 
 /// Save WiFi station credentials to NVS.
-pub fn save_wifi_credentials(nvs: &mut EspNvs<NvsDefault>, ssid: &str, password: &str) {
+pub fn save_wifi_credentials(nvs: &mut dyn KeyValueStore, ssid: &str, password: &str) {
     if let Err(e) = nvs.set_str(NVS_KEY_WIFI_SSID, ssid) {
         log::error!("Failed to save WiFi SSID to NVS: {e}");
         return;
@@ -241,8 +834,371 @@ pub fn save_wifi_credentials(nvs: &mut EspNvs<NvsDefault>, ssid: &str, password:
 }
 
 /// Delete saved WiFi credentials from NVS.
-pub fn delete_wifi_credentials(nvs: &mut EspNvs<NvsDefault>) {
+pub fn delete_wifi_credentials(nvs: &mut dyn KeyValueStore) {
     let _ = nvs.remove(NVS_KEY_WIFI_SSID);
     let _ = nvs.remove(NVS_KEY_WIFI_PASS);
     log::info!("WiFi credentials deleted");
 }
+
+// ---------------------------------------------------------------------------
+// Device Settings (NVS)
+// ---------------------------------------------------------------------------
+
+/// Load the device settings from NVS.
+///
+/// Falls back to the defaults for anything missing or invalid. Log levels
+/// saved by older firmware under their own key are moved into the settings.
+pub fn load_settings(nvs: &mut dyn KeyValueStore) -> DeviceSettings {
+    let json = match nvs.get_str(NVS_KEY_SETTINGS) {
+        Ok(json) => json,
+        Err(e) => {
+            log::warn!("NVS get_str('{NVS_KEY_SETTINGS}') error: {e}");
+            return DeviceSettings::default();
+        }
+    };
+    let Some(json) = json else {
+        return migrate_settings(nvs);
+    };
+    let settings: DeviceSettings = match serde_json::from_str(&json) {
+        Ok(settings) => settings,
+        Err(e) => {
+            log::warn!("Ignoring saved settings: {e}");
+            return DeviceSettings::default();
+        }
+    };
+    match settings.validate() {
+        Ok(()) => settings,
+        Err(errors) => {
+            // Keep what is valid rather than dropping everything.
+            let defaults = DeviceSettings::default();
+            let mut fixed = settings;
+            for e in &errors {
+                log::warn!("Saved setting {e}, using the default");
+                match e.field.as_str() {
+                    "brightness" => fixed.brightness = defaults.brightness,
+                    "dim_after_secs" => fixed.dim_after_secs = defaults.dim_after_secs,
+                    "screen_timeout_secs" => {
+                        fixed.screen_timeout_secs = defaults.screen_timeout_secs
+                    }
+                    "log_levels" => fixed.log_levels = defaults.log_levels.clone(),
+                    "ap.ssid" => fixed.ap.ssid = defaults.ap.ssid.clone(),
+                    "ap.password" => fixed.ap.password = defaults.ap.password.clone(),
+                    "ap.channel" => fixed.ap.channel = defaults.ap.channel,
+                    "ap.max_clients" => fixed.ap.max_clients = defaults.ap.max_clients,
+                    "power.ap_off_after_secs" => {
+                        fixed.power.ap_off_after_secs = defaults.power.ap_off_after_secs
+                    }
+                    _ => return defaults,
+                }
+            }
+            fixed
+        }
+    }
+}
+
+/// First boot with device settings: start from the defaults plus the log
+/// levels older firmware saved, and save the result.
+fn migrate_settings(nvs: &mut dyn KeyValueStore) -> DeviceSettings {
+    let mut settings = DeviceSettings::default();
+    if let Ok(Some(spec)) = nvs.get_str(NVS_KEY_LOG_LEVELS) {
+        match spec.parse::<LogFilter>() {
+            Ok(filter) => settings.log_levels = filter.to_string(),
+            Err(e) => log::warn!("Ignoring saved log levels '{spec}': {e}"),
+        }
+        save_settings(nvs, &settings);
+        let _ = nvs.remove(NVS_KEY_LOG_LEVELS);
+        log::info!("Migrated log levels into device settings");
+    }
+    settings
+}
+
+/// Save the device settings to NVS.
+pub fn save_settings(nvs: &mut dyn KeyValueStore, settings: &DeviceSettings) {
+    let json = match serde_json::to_string(settings) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Failed to serialize settings: {e}");
+            return;
+        }
+    };
+    if let Err(e) = nvs.set_str(NVS_KEY_SETTINGS, &json) {
+        log::error!("Failed to save settings to NVS: {e}");
+        return;
+    }
+    log::debug!("Settings saved");
+}
+
+// ---------------------------------------------------------------------------
+// Admin PIN (NVS)
+// ---------------------------------------------------------------------------
+
+/// Load the web API admin PIN, if one is saved and well-formed.
+pub fn load_admin_pin(nvs: &dyn KeyValueStore) -> Option<String> {
+    match nvs.get_str(NVS_KEY_ADMIN_PIN) {
+        Ok(Some(pin)) if auth::is_valid_pin(&pin) => Some(pin),
+        Ok(Some(_)) => {
+            log::warn!("Ignoring malformed admin PIN in NVS");
+            None
+        }
+        Ok(None) => None,
+        Err(e) => {
+            log::warn!("NVS get_str('{NVS_KEY_ADMIN_PIN}') error: {e}");
+            None
+        }
+    }
+}
+
+/// Save the web API admin PIN to NVS.
+pub fn save_admin_pin(nvs: &mut dyn KeyValueStore, pin: &str) {
+    if let Err(e) = nvs.set_str(NVS_KEY_ADMIN_PIN, pin) {
+        log::error!("Failed to save admin PIN to NVS: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::fake::{FaultyFs, MemoryImageStore, MemoryKvStore};
+
+    #[test]
+    fn first_boot_saves_the_default_profile() {
+        let mut nvs = MemoryKvStore::default();
+        let set = load_profiles(&mut nvs, &MemoryImageStore::default());
+        assert_eq!(set.index.entries.len(), 1);
+        assert_eq!(set.active().display_name, Profile::default().display_name);
+        assert!(nvs.entries.contains_key(NVS_KEY_PROFILE_INDEX));
+        assert!(nvs.entries.contains_key(&profile_key(set.active_id())));
+    }
+
+    #[test]
+    fn legacy_profile_becomes_profile_1() {
+        let mut nvs = MemoryKvStore::default();
+        let legacy = Profile {
+            display_name: "Old".into(),
+            ..Profile::default()
+        };
+        nvs.set_str(NVS_KEY_PROFILE, &serde_json::to_string(&legacy).unwrap())
+            .unwrap();
+        let images = MemoryImageStore::default();
+        images.write("avatar", &[0x40; 12]).unwrap();
+
+        let set = load_profiles(&mut nvs, &images);
+        assert_eq!(set.active_id(), 1);
+        assert_eq!(set.active().display_name, "Old");
+        assert!(!nvs.entries.contains_key(NVS_KEY_PROFILE));
+        assert_eq!(images.read(&avatar_name(1)).unwrap(), [0x40; 12]);
+        assert!(images.read("avatar").is_err());
+    }
+
+    #[test]
+    fn profiles_round_trip() {
+        let mut nvs = MemoryKvStore::default();
+        let images = MemoryImageStore::default();
+        let mut set = load_profiles(&mut nvs, &images);
+        let profile = Profile {
+            display_name: "Con".into(),
+            ..Profile::default()
+        };
+        let id = set.create("Convention", profile).unwrap();
+        set.activate(id).unwrap();
+        save_profile(&mut nvs, id, set.get(id).unwrap()).unwrap();
+        save_profile_index(&mut nvs, &set.index).unwrap();
+
+        let loaded = load_profiles(&mut nvs, &images);
+        assert_eq!(loaded.index.entries.len(), 2);
+        assert_eq!(loaded.active_id(), id);
+        assert_eq!(loaded.active().display_name, "Con");
+
+        delete_profile(&mut nvs, &images, id);
+        assert!(!nvs.entries.contains_key(&profile_key(id)));
+    }
+
+    #[test]
+    fn images_must_have_the_expected_size() {
+        let images = MemoryImageStore::default();
+        let rgb = [0xFF, 0xFF, 0xFF, 0, 0, 0];
+        assert_eq!(load_image(&images, "avatar", 2, 1), None);
+        save_image(&images, "avatar", 2, 1, &rgb);
+        assert_eq!(load_image(&images, "avatar", 2, 1), Some(rgb.to_vec()));
+        assert_eq!(load_image(&images, "avatar", 1, 2), None);
+        save_image(&images, "avatar", 3, 1, &rgb);
+        assert_eq!(load_image(&images, "avatar", 2, 1), Some(rgb.to_vec()));
+        assert!(delete_image(&images, "avatar"));
+        assert!(!delete_image(&images, "avatar"));
+    }
+
+    #[test]
+    fn wifi_credentials_round_trip() {
+        let mut nvs = MemoryKvStore::default();
+        assert_eq!(load_wifi_credentials(&nvs), None);
+        save_wifi_credentials(&mut nvs, "HomeNet", "secret123");
+        assert_eq!(
+            load_wifi_credentials(&nvs),
+            Some(("HomeNet".into(), "secret123".into()))
+        );
+        save_wifi_credentials(&mut nvs, "", "");
+        assert_eq!(load_wifi_credentials(&nvs), None);
+        save_wifi_credentials(&mut nvs, "Cafe", "");
+        delete_wifi_credentials(&mut nvs);
+        assert!(nvs.entries.is_empty());
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut nvs = MemoryKvStore::default();
+        assert_eq!(load_settings(&mut nvs), DeviceSettings::default());
+        let settings = DeviceSettings {
+            brightness: 75,
+            ..DeviceSettings::default()
+        };
+        save_settings(&mut nvs, &settings);
+        assert_eq!(load_settings(&mut nvs), settings);
+    }
+
+    #[test]
+    fn invalid_saved_settings_fall_back_field_by_field() {
+        let mut nvs = MemoryKvStore::default();
+        let saved = serde_json::json!({
+            "brightness": 3,
+            "ap": { "channel": 20 },
+            "ui": { "swipe_profiles": false },
+        });
+        nvs.set_str(NVS_KEY_SETTINGS, &saved.to_string()).unwrap();
+        let settings = load_settings(&mut nvs);
+        let defaults = DeviceSettings::default();
+        assert_eq!(settings.brightness, defaults.brightness);
+        assert_eq!(settings.ap.channel, defaults.ap.channel);
+        assert!(!settings.ui.swipe_profiles);
+
+        nvs.set_str(NVS_KEY_SETTINGS, "not json").unwrap();
+        assert_eq!(load_settings(&mut nvs), defaults);
+    }
+
+    #[test]
+    fn old_log_levels_move_into_the_settings() {
+        let mut nvs = MemoryKvStore::default();
+        nvs.set_str(NVS_KEY_LOG_LEVELS, "warn,wifi=debug").unwrap();
+        let settings = load_settings(&mut nvs);
+        assert_eq!(
+            settings.log_levels,
+            "warn,wifi=debug".parse::<LogFilter>().unwrap().to_string()
+        );
+        assert!(!nvs.entries.contains_key(NVS_KEY_LOG_LEVELS));
+        assert_eq!(load_settings(&mut nvs), settings);
+    }
+
+    #[test]
+    fn admin_pin_is_created_once() {
+        let mut nvs = MemoryKvStore::default();
+        let pin = auth::load_or_create_pin(&mut nvs);
+        assert!(auth::is_valid_pin(&pin));
+        assert_eq!(auth::load_or_create_pin(&mut nvs), pin);
+
+        save_admin_pin(&mut nvs, "12ab");
+        assert_eq!(load_admin_pin(&nvs), None);
+        assert!(auth::is_valid_pin(&auth::load_or_create_pin(&mut nvs)));
+    }
+
+    /// `replace_file` over an existing file makes five changes: write the
+    /// `.tmp`, remove a stale `.bak`, rename the file to `.bak`, rename the
+    /// `.tmp` over it, remove the `.bak`.
+    const REPLACE_STEPS: usize = 5;
+
+    fn image(shade: u8) -> Vec<u8> {
+        image_codec::encode_rgb888(&[shade; 8 * 8 * 3], 8, 8)
+    }
+
+    fn store() -> FsImageStore<FaultyFs> {
+        FsImageStore::with_fs("/storage", FaultyFs::default())
+    }
+
+    fn leftovers(store: &FsImageStore<FaultyFs>) -> Vec<PathBuf> {
+        let files = store.fs.files.lock().unwrap();
+        files
+            .keys()
+            .filter(|p| p.extension().is_some_and(|ext| ext != IMAGE_EXT))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn replace_survives_power_loss_at_every_step() {
+        let (old, new) = (image(0x20), image(0xC0));
+        for step in 0..REPLACE_STEPS {
+            let store = store();
+            store.write("avatar_1", &old).unwrap();
+            store.fs.fail_at(step);
+            // Removing the `.bak` at the end is best-effort.
+            let written = store.write("avatar_1", &new).is_ok();
+            assert_eq!(written, step == REPLACE_STEPS - 1, "step {step}");
+
+            store.fs.power_on();
+            store.recover();
+            // Until the new copy is renamed into place the old one stands;
+            // from then on the new one does.
+            let expected = if step < 3 { &old } else { &new };
+            assert_eq!(&store.read("avatar_1").unwrap(), expected, "step {step}");
+            assert!(leftovers(&store).is_empty(), "step {step}");
+        }
+    }
+
+    #[test]
+    fn first_write_survives_power_loss() {
+        let new = image(0xC0);
+        // No old file: write `.tmp`, remove `.bak`, rename (not found),
+        // rename `.tmp`, remove `.bak`.
+        for step in 0..REPLACE_STEPS {
+            let store = store();
+            store.fs.fail_at(step);
+            let written = store.write("avatar_1", &new).is_ok();
+
+            store.fs.power_on();
+            store.recover();
+            match store.read("avatar_1") {
+                Ok(data) => assert_eq!(data, new, "step {step}"),
+                Err(e) => {
+                    assert!(!written, "step {step}");
+                    assert_eq!(e.kind(), std::io::ErrorKind::NotFound, "step {step}");
+                }
+            }
+            assert!(leftovers(&store).is_empty(), "step {step}");
+        }
+    }
+
+    #[test]
+    fn write_without_faults_leaves_no_leftovers() {
+        let store = store();
+        store.write("avatar_1", &image(1)).unwrap();
+        store.write("avatar_1", &image(2)).unwrap();
+        assert_eq!(store.read("avatar_1").unwrap(), image(2));
+        assert!(leftovers(&store).is_empty());
+    }
+
+    #[test]
+    fn recover_rejects_a_bad_checksum() {
+        let (old, new) = (image(0x20), image(0xC0));
+        let store = store();
+        let path = store.path("avatar_1");
+        let mut corrupt = new.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xFF;
+        {
+            let mut files = store.fs.files.lock().unwrap();
+            files.insert(path.clone(), corrupt.clone());
+            files.insert(with_suffix(&path, BAK_SUFFIX), old.clone());
+        }
+        store.recover();
+        assert_eq!(store.read("avatar_1").unwrap(), old);
+
+        // A corrupt `.tmp` never replaces a good file.
+        store
+            .fs
+            .files
+            .lock()
+            .unwrap()
+            .insert(with_suffix(&path, TMP_SUFFIX), corrupt);
+        store.recover();
+        assert_eq!(store.read("avatar_1").unwrap(), old);
+        assert!(leftovers(&store).is_empty());
+    }
+}
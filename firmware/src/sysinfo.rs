@@ -1,18 +1,32 @@
 use std::time::Instant;
 
 /// Returns free internal heap memory in KB.
+#[cfg(not(feature = "simulator"))]
 pub fn free_heap_kb() -> u32 {
     let bytes = unsafe { esp_idf_sys::esp_get_free_heap_size() };
     bytes / 1024
 }
 
 /// Returns free PSRAM (SPI RAM) in KB.
+#[cfg(not(feature = "simulator"))]
 pub fn free_psram_kb() -> u32 {
     let bytes =
         unsafe { esp_idf_sys::heap_caps_get_free_size(esp_idf_sys::MALLOC_CAP_SPIRAM as u32) };
     (bytes / 1024) as u32
 }
 
+/// Simulator stand-in: a typical post-boot reading from the badge.
+#[cfg(feature = "simulator")]
+pub fn free_heap_kb() -> u32 {
+    180
+}
+
+/// Simulator stand-in: a typical post-boot reading from the badge.
+#[cfg(feature = "simulator")]
+pub fn free_psram_kb() -> u32 {
+    6900
+}
+
 /// Format uptime as "Xh Ym Zs" from a start instant.
 pub fn uptime_string(start: &Instant) -> String {
     let secs = start.elapsed().as_secs();
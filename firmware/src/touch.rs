@@ -1,47 +1,46 @@
-use esp_idf_hal::gpio::{AnyIOPin, AnyOutputPin, PinDriver};
-use esp_idf_hal::i2c::config::Config as I2cConfig;
-use esp_idf_hal::i2c::{I2cDriver, I2C0};
+use esp_idf_hal::gpio::{AnyOutputPin, PinDriver};
+use esp_idf_hal::i2c::I2cDriver;
 use gt911::Gt911Blocking;
 use slint::platform::software_renderer::MinimalSoftwareWindow;
 use slint::platform::{PointerEventButton, WindowEvent};
 use slint::LogicalPosition;
 
+use crate::i2c::SharedI2c;
 use crate::platform::DISPLAY_HEIGHT;
 use crate::platform::DISPLAY_WIDTH;
 
 /// GT911 default I2C address on the JC8048W550 board.
 const GT911_ADDR: u8 = 0x5D;
 
-/// I2C bus speed — use 100kHz (standard mode) for reliability with internal pull-ups.
-const I2C_FREQ: u32 = 100_000;
-
 /// Touch state machine — tracks whether a finger is currently down.
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum TouchState {
     Released,
     Pressed,
+    /// A touch that woke the screen; nothing is dispatched until release.
+    Swallowed,
 }
 
 /// Touch controller wrapper that handles GT911 polling and Slint event dispatch.
-pub struct TouchController<'a> {
-    driver: Gt911Blocking<I2cDriver<'a>>,
-    i2c: I2cDriver<'a>,
+pub struct TouchController {
+    driver: Gt911Blocking<I2cDriver<'static>>,
+    i2c: SharedI2c,
     state: TouchState,
     last_position: LogicalPosition,
     error_count: u32,
 }
 
-impl<'a> TouchController<'a> {
+impl TouchController {
     /// Initialize the GT911 touch controller.
     ///
     /// On the JC8048W550 board:
-    /// - SDA = GPIO 19, SCL = GPIO 20
+    /// - SDA = GPIO 19, SCL = GPIO 20 (shared bus, see `i2c.rs`)
     /// - RST = GPIO 38 (active-low reset)
     /// - INT = not connected (polled mode)
     ///
     /// The INT pin is unused on this board, so we skip the address-selection
     /// sequence and always use address 0x5D.
-    pub fn new(i2c: I2C0, sda: AnyIOPin, scl: AnyIOPin, rst: AnyOutputPin) -> anyhow::Result<Self> {
+    pub fn new(i2c: SharedI2c, rst: AnyOutputPin) -> anyhow::Result<Self> {
         // Reset the GT911: pull RST low for 20ms, then release high.
         let mut rst_pin = PinDriver::output(rst)?;
         rst_pin.set_low()?;
@@ -54,14 +53,10 @@ impl<'a> TouchController<'a> {
         // Wait for GT911 to boot
         esp_idf_hal::delay::FreeRtos::delay_ms(100);
 
-        // Configure I2C
-        let i2c_config = I2cConfig::new().baudrate(I2C_FREQ.into());
-        let mut i2c_driver = I2cDriver::new(i2c, sda, scl, &i2c_config)?;
-
         // Initialize GT911 at default address 0x5D
         let driver = Gt911Blocking::new(GT911_ADDR);
         driver
-            .init(&mut i2c_driver)
+            .init(&mut *i2c.borrow_mut())
             .map_err(|e| anyhow::anyhow!("GT911 init failed at 0x{:02X}: {:?}", GT911_ADDR, e))?;
 
         log::info!(
@@ -71,20 +66,26 @@ impl<'a> TouchController<'a> {
 
         Ok(Self {
             driver,
-            i2c: i2c_driver,
+            i2c,
             state: TouchState::Released,
             last_position: LogicalPosition::new(0.0, 0.0),
             error_count: 0,
         })
     }
 
-    /// Poll touch input and dispatch events to the Slint window.
+    /// Poll touch input and dispatch events to the Slint window. Returns
+    /// whether a finger is on the screen.
+    ///
+    /// With `wake_only` (screen dimmed or off) a new touch is swallowed
+    /// whole: the caller wakes the screen and the UI sees nothing until
+    /// the finger lifts.
     ///
     /// Call this once per iteration of the main event loop.
     /// On the JC8048W550, the GT911 reports coordinates directly in the display's
     /// native 800x480 orientation (0,0 = top-left, no inversion needed).
-    pub fn poll(&mut self, window: &MinimalSoftwareWindow) {
-        match self.driver.get_touch(&mut self.i2c) {
+    pub fn poll(&mut self, window: &MinimalSoftwareWindow, wake_only: bool) -> bool {
+        let touch = self.driver.get_touch(&mut *self.i2c.borrow_mut());
+        match touch {
             Ok(Some(point)) => {
                 // GT911 on the JC8048W550 reports coordinates directly in
                 // display orientation (0,0 = top-left, 799,479 = bottom-right).
@@ -95,6 +96,7 @@ impl<'a> TouchController<'a> {
                 self.last_position = position;
 
                 match self.state {
+                    TouchState::Released if wake_only => self.state = TouchState::Swallowed,
                     TouchState::Released => {
                         // New touch — send PointerPressed
                         window.dispatch_event(WindowEvent::PointerPressed {
@@ -107,7 +109,9 @@ impl<'a> TouchController<'a> {
                         // Finger still down — send PointerMoved
                         window.dispatch_event(WindowEvent::PointerMoved { position });
                     }
+                    TouchState::Swallowed => {}
                 }
+                true
             }
             Ok(None) => {
                 // Finger lifted
@@ -117,13 +121,15 @@ impl<'a> TouchController<'a> {
                         button: PointerEventButton::Left,
                     });
                     window.dispatch_event(WindowEvent::PointerExited);
-                    self.state = TouchState::Released;
                 }
+                self.state = TouchState::Released;
+                false
             }
-            Err(gt911::Error::NotReady) => {}
+            Err(gt911::Error::NotReady) => self.state != TouchState::Released,
             Err(e) => {
                 self.error_count = self.error_count.wrapping_add(1);
                 log::warn!("Touch read error #{}: {:?}", self.error_count, e);
+                false
             }
         }
     }
@@ -1,7 +1,25 @@
 //! Pure UI helpers and pixel manipulation routines used by the main loop.
 
-use crate::profile;
-use crate::BadgeUI;
+use std::rc::Rc;
+
+use slint::{Image, Rgb8Pixel, SharedPixelBuffer, VecModel};
+
+use crate::animation;
+use crate::crash;
+use crate::hal::ImageStore;
+use crate::log_filter::{self, LogFilter};
+use crate::profile::{self, LinkKind, ProfileSet};
+use crate::settings::{self, ApSettings, DeviceSettings};
+use crate::storage::SavedAvatar;
+use crate::wifi_state;
+use crate::{platform, qr, storage, BadgeUI, LogLevelChip, ProfileItem, ProfileLinkItem};
+
+/// Avatar corner radius in pixels (matches the `border-radius` of the
+/// avatar container in `BadgePage`).
+const AVATAR_CORNER_RADIUS: u32 = 20;
+
+/// Avatar container background, painted into the cut-off corners.
+const AVATAR_CORNER_BG: [u8; 3] = [0x2a, 0x2a, 0x4a];
 
 /// Paint pixels outside a rounded rectangle with a background color.
 ///
@@ -44,6 +62,136 @@ pub fn apply_rounded_corners(data: &mut [u8], w: u32, h: u32, radius: u32, bg: [
     }
 }
 
+/// Show a raw (unrounded) RGB888 avatar as stored on SPIFFS.
+pub fn show_saved_avatar(ui: &BadgeUI, rgb_data: Vec<u8>) {
+    ui.set_avatar_image(avatar_image(rgb_data));
+}
+
+/// Turn a raw RGB888 avatar (or animation frame) into a Slint image with
+/// the rounded corners baked in.
+pub fn avatar_image(mut rgb_data: Vec<u8>) -> Image {
+    apply_rounded_corners(
+        &mut rgb_data,
+        storage::AVATAR_WIDTH,
+        storage::AVATAR_HEIGHT,
+        AVATAR_CORNER_RADIUS,
+        AVATAR_CORNER_BG,
+    );
+    let buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
+        &rgb_data,
+        storage::AVATAR_WIDTH,
+        storage::AVATAR_HEIGHT,
+    );
+    Image::from_rgb8(buffer)
+}
+
+/// Show a full-screen RGB888 background image.
+pub fn show_background(ui: &BadgeUI, rgb_data: &[u8]) {
+    let buffer = SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
+        rgb_data,
+        platform::DISPLAY_WIDTH,
+        platform::DISPLAY_HEIGHT,
+    );
+    ui.set_background_image(Image::from_rgb8(buffer));
+}
+
+/// Apply all text and color fields of a profile to the Slint UI.
+pub fn show_profile(ui: &BadgeUI, p: &profile::Profile) {
+    ui.set_display_name(p.display_name.clone().into());
+    ui.set_tagline(p.tagline.clone().into());
+    let links: Vec<ProfileLinkItem> = p
+        .links
+        .iter()
+        .map(|link| ProfileLinkItem {
+            kind: link.kind.as_str().into(),
+            label: match link.kind {
+                LinkKind::Custom => link.label.clone().into(),
+                _ => Default::default(),
+            },
+            value: link.value.clone().into(),
+        })
+        .collect();
+    ui.set_profile_links(Rc::new(VecModel::from(links)).into());
+    apply_profile_colors(ui, p);
+    ui.set_qr_count(p.qr_codes.len() as i32);
+    // Keep an open QR page in step with an edited or switched profile.
+    if ui.get_current_page() == 7 {
+        if p.qr_codes.is_empty() {
+            ui.set_current_page(0);
+        } else {
+            show_qr(ui, p, ui.get_qr_index() as usize);
+        }
+    }
+}
+
+/// Render QR code `index` of `p` (clamped to the last one) into the QR
+/// page properties.
+pub fn show_qr(ui: &BadgeUI, p: &profile::Profile, index: usize) {
+    let index = index.min(p.qr_codes.len().saturating_sub(1));
+    let Some(code) = p.qr_codes.get(index) else {
+        return;
+    };
+    let text = qr::payload(code, wifi_state::running_ap());
+    let image = match qr::render(&text) {
+        Ok(rgb) => Image::from_rgb8(SharedPixelBuffer::<Rgb8Pixel>::clone_from_slice(
+            &rgb,
+            qr::QR_IMAGE_SIZE,
+            qr::QR_IMAGE_SIZE,
+        )),
+        Err(e) => {
+            log::warn!("{e}");
+            Image::default()
+        }
+    };
+    ui.set_qr_index(index as i32);
+    ui.set_qr_image(image);
+    ui.set_qr_label(qr::label(code).into());
+    ui.set_qr_text(text.into());
+}
+
+/// Show the active profile of `set`: its fields, plus its avatar (still or
+/// animated) and background from SPIFFS (cleared if that profile has none).
+pub fn show_active_profile(ui: &BadgeUI, images: &dyn ImageStore, set: &ProfileSet) {
+    let id = set.active_id();
+    show_profile(ui, set.active());
+    match storage::load_avatar(images, id) {
+        Some(SavedAvatar::Still(rgb_data)) => {
+            animation::stop();
+            show_saved_avatar(ui, rgb_data);
+        }
+        Some(SavedAvatar::Animated(anim)) => animation::play(ui, anim),
+        None => {
+            animation::stop();
+            ui.set_avatar_image(Image::default());
+        }
+    }
+    match storage::load_image(
+        images,
+        &storage::background_name(id),
+        storage::BACKGROUND_WIDTH,
+        storage::BACKGROUND_HEIGHT,
+    ) {
+        Some(rgb_data) => show_background(ui, &rgb_data),
+        None => ui.set_background_image(Image::default()),
+    }
+    show_profile_list(ui, set);
+}
+
+/// Fill the list on the Settings → Profiles page.
+pub fn show_profile_list(ui: &BadgeUI, set: &ProfileSet) {
+    let items: Vec<ProfileItem> = set
+        .index
+        .entries
+        .iter()
+        .map(|entry| ProfileItem {
+            id: entry.id as i32,
+            name: entry.name.clone().into(),
+            active: entry.id == set.active_id(),
+        })
+        .collect();
+    ui.set_profiles(Rc::new(VecModel::from(items)).into());
+}
+
 /// Apply color properties from a profile to the Slint UI.
 pub fn apply_profile_colors(ui: &BadgeUI, p: &profile::Profile) {
     if let Some(c) = profile::parse_hex_color(&p.background_color) {
@@ -56,3 +204,69 @@ pub fn apply_profile_colors(ui: &BadgeUI, p: &profile::Profile) {
         ui.set_tagline_background_color(c);
     }
 }
+
+/// Fill the level chips on the Logs page: the default level first (target
+/// `""`), then each of `log_filter::UI_TARGETS` with its effective level.
+pub fn show_log_levels(ui: &BadgeUI, filter: &LogFilter) {
+    let default = LogLevelChip {
+        target: "".into(),
+        label: "all".into(),
+        level: log_filter::level_name(filter.default_level()).into(),
+    };
+    let chips: Vec<LogLevelChip> = std::iter::once(default)
+        .chain(log_filter::UI_TARGETS.iter().map(|target| LogLevelChip {
+            target: (*target).into(),
+            label: (*target).into(),
+            level: log_filter::level_name(filter.level_for(target)).into(),
+        }))
+        .collect();
+    ui.set_log_levels(Rc::new(VecModel::from(chips)).into());
+}
+
+/// Show the device settings on the Settings page and apply the UI
+/// preferences. The backlight itself is set by the caller.
+pub fn show_settings(ui: &BadgeUI, s: &DeviceSettings) {
+    ui.set_display_brightness(s.brightness as f32);
+    ui.set_dim_after_label(settings::timeout_label(s.dim_after_secs).into());
+    ui.set_screen_timeout_label(settings::timeout_label(s.screen_timeout_secs).into());
+    ui.set_show_battery_percent(s.ui.show_battery_percent);
+    ui.set_swipe_profiles(s.ui.swipe_profiles);
+}
+
+/// Show the running access point on the WiFi page.
+pub fn show_running_ap(ui: &BadgeUI) {
+    ui.set_wifi_ap_ssid(wifi_state::running_ap().ssid.as_str().into());
+    ui.set_ap_default_ssid(wifi_state::default_ap_ssid().into());
+}
+
+/// Fill the Access Point page's form with the saved settings.
+pub fn show_ap_form(ui: &BadgeUI, ap: &ApSettings) {
+    ui.set_ap_ssid(ap.ssid.as_str().into());
+    ui.set_ap_password(ap.password.as_str().into());
+    ui.set_ap_channel(ap.channel as i32);
+    ui.set_ap_max_clients(ap.max_clients as i32);
+    ui.set_ap_hidden(ap.hidden);
+    ui.set_ap_error("".into());
+}
+
+/// Show the approval prompt with `code`, or hide it for `None`. Leaves an
+/// unchanged prompt alone.
+pub fn show_approval(ui: &BadgeUI, code: Option<&str>) {
+    match code {
+        Some(code) if !ui.get_approval_visible() || ui.get_approval_code() != code => {
+            ui.set_approval_code(code.into());
+            ui.set_approval_visible(true);
+        }
+        None if ui.get_approval_visible() => ui.set_approval_visible(false),
+        _ => {}
+    }
+}
+
+/// Show the newest crash report (if any) on the About page.
+pub fn show_last_crash(ui: &BadgeUI) {
+    let summary = crash::load_history()
+        .last()
+        .map(crash::CrashReport::summary)
+        .unwrap_or_default();
+    ui.set_about_last_crash(summary.into());
+}
@@ -1,32 +1,234 @@
-use esp_idf_svc::http::server::EspHttpServer;
-use esp_idf_svc::http::Method;
-use esp_idf_svc::io::Write;
+use std::net::Ipv4Addr;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
-use crate::profile::{CurrentProfile, PendingProfile};
-use crate::storage;
+use esp_idf_svc::http::server::{EspHttpConnection, EspHttpServer, Request};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::{EspIOError, Write};
+use serde::Deserialize;
 
-use super::SharedImageData;
+use crate::auth::{self, Access, LoginError, SharedAuth};
+use crate::backup::{self, BackupSettings, RestoreError, WifiCredentials};
+use crate::crash;
+use crate::factory_reset;
+use crate::image_codec;
+use crate::image_decode::{self, DecodedImage, UploadError, UploadFormat, MAX_ENCODED_UPLOAD};
+use crate::log_filter::{self, LogFilter};
+use crate::logger;
+use crate::ota;
+use crate::ota::image::InvalidImage;
+use crate::pending::{PendingWifiExport, ProfileUpload, RestoredConfig, WifiExport};
+use crate::profile::{PendingProfileOps, ProfileError, ProfileOp, SharedProfiles, MAX_PROFILES};
+use crate::profile_schema;
+use crate::settings::DeviceSettings;
+use crate::storage::{self, AVATAR_HEIGHT, AVATAR_WIDTH, BACKGROUND_HEIGHT, BACKGROUND_WIDTH};
+use crate::web::WebContext;
+use crate::wifi_state;
 
 /// Maximum body size for profile JSON (4 KB — plenty for a few short strings).
 const MAX_PROFILE_BODY: usize = 4096;
 
+/// Body of `POST /api/profiles`. Without `profile`, the new profile starts
+/// as a copy of the active one's fields.
+#[derive(Deserialize)]
+struct NewProfile {
+    name: String,
+    profile: Option<serde_json::Value>,
+}
+
+/// Body of `PUT /api/profiles/<id>`; omitted fields are left unchanged.
+#[derive(Deserialize)]
+struct ProfileEdit {
+    name: Option<String>,
+    profile: Option<serde_json::Value>,
+}
+
+/// Body of `POST /api/factory-reset`; `confirm` must be `true`.
+#[derive(Deserialize)]
+struct FactoryResetRequest {
+    confirm: bool,
+}
+
+/// Body of `POST /api/login`.
+#[derive(Deserialize)]
+struct LoginRequest {
+    pin: String,
+}
+
+/// Maximum body size for a login.
+const MAX_LOGIN_BODY: usize = 256;
+
+/// Maximum body size for a factory reset request.
+const MAX_FACTORY_RESET_BODY: usize = 256;
+
+/// Maximum body size for a settings change.
+const MAX_SETTINGS_BODY: usize = 2048;
+
+/// Maximum body size for a log level spec (`{"spec": "..."}`).
+const MAX_LOG_LEVELS_BODY: usize = 1024;
+
+/// How long `GET /api/backup?wifi=1` waits for the main loop to read the
+/// WiFi credentials (it polls every ~2 s).
+const WIFI_EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a profile change waits for the main loop to write it to NVS.
+const PROFILE_SAVE_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Register API route handlers.
-pub fn register(
-    server: &mut EspHttpServer<'static>,
-    pending_background: SharedImageData,
-    pending_avatar: SharedImageData,
-    current_profile: CurrentProfile,
-    pending_profile: PendingProfile,
-) -> anyhow::Result<()> {
+pub fn register(server: &mut EspHttpServer<'static>, ctx: WebContext) -> anyhow::Result<()> {
+    let WebContext {
+        pending_background,
+        pending_avatar,
+        pending_avatar_animation,
+        profiles,
+        profile_ops,
+        pending_log_filter,
+        pending_restore,
+        wifi_export,
+        factory_reset,
+        settings,
+        settings_changed,
+        restart,
+        images,
+        crashes_cleared,
+        ota,
+        status,
+        auth,
+    } = ctx;
+
     // Health check
     server.fn_handler("/api/health", Method::Get, |req| {
         req.into_ok_response()?.write_all(b"OK").map(|_| ())
     })?;
 
-    // Get current profile as JSON
-    server.fn_handler("/api/profile", Method::Get, move |req| {
-        let json = match current_profile.lock() {
-            Ok(profile) => serde_json::to_string(&*profile).unwrap_or_default(),
+    // Log in with the admin PIN shown on the badge (see auth.rs). The token
+    // comes back as a cookie for the web page and in the body for scripts.
+    let auth_for_login = auth.clone();
+    server.fn_handler("/api/login", Method::Post, move |mut req| {
+        let Some(login) = read_body(&mut req, MAX_LOGIN_BODY)?
+            .and_then(|body| serde_json::from_slice::<LoginRequest>(&body).ok())
+        else {
+            let mut resp =
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
+            return resp.write_all(b"Expected {\"pin\": \"123456\"}");
+        };
+        let token = auth::new_token();
+        let client = client_ip(&mut req);
+        let result = match auth_for_login.lock() {
+            Ok(mut auth) => auth.login(client, &login.pin, token.clone(), Instant::now()),
+            Err(_) => {
+                let mut resp = req.into_response(
+                    500,
+                    Some("Internal Server Error"),
+                    &[("Content-Type", "text/plain")],
+                )?;
+                return resp.write_all(b"Sessions unavailable");
+            }
+        };
+        match result {
+            Ok(()) => {
+                log::info!("Web session opened");
+                let cookie = format!(
+                    "{}={token}; Path=/; HttpOnly; SameSite=Strict",
+                    auth::SESSION_COOKIE
+                );
+                let mut resp = req.into_response(
+                    200,
+                    Some("OK"),
+                    &[
+                        ("Content-Type", "application/json; charset=utf-8"),
+                        ("Set-Cookie", &cookie),
+                    ],
+                )?;
+                resp.write_all(serde_json::json!({ "token": token }).to_string().as_bytes())
+            }
+            Err(e @ LoginError::WrongPin { attempts_left }) => {
+                let json = serde_json::json!({
+                    "error": e.to_string(),
+                    "attempts_left": attempts_left,
+                });
+                let mut resp = req.into_response(
+                    401,
+                    Some("Unauthorized"),
+                    &[("Content-Type", "application/json; charset=utf-8")],
+                )?;
+                resp.write_all(json.to_string().as_bytes())
+            }
+            Err(e @ LoginError::Locked { retry_after }) => {
+                let secs = retry_after.as_secs().max(1);
+                let json = serde_json::json!({
+                    "error": e.to_string(),
+                    "retry_after_secs": secs,
+                });
+                let secs = secs.to_string();
+                let mut resp = req.into_response(
+                    429,
+                    Some("Too Many Requests"),
+                    &[
+                        ("Content-Type", "application/json; charset=utf-8"),
+                        ("Retry-After", &secs),
+                    ],
+                )?;
+                resp.write_all(json.to_string().as_bytes())
+            }
+        }
+    })?;
+
+    // End the session the request carries, if any
+    let auth_for_logout = auth.clone();
+    server.fn_handler("/api/logout", Method::Post, move |req| {
+        if let Some(token) = auth::request_token(req.header("Cookie"), req.header("Authorization"))
+        {
+            if let Ok(mut auth) = auth_for_logout.lock() {
+                auth.logout(token);
+            }
+        }
+        let cookie = format!("{}=; Path=/; Max-Age=0", auth::SESSION_COOKIE);
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "text/plain"), ("Set-Cookie", &cookie)],
+        )?;
+        resp.write_all(b"OK")
+    })?;
+
+    // Whether the request carries an open session, and whether the badge
+    // has allowed it (or its approval code, while that is queued or shown)
+    let auth_for_session = auth.clone();
+    server.fn_handler("/api/session", Method::Get, move |req| {
+        let token = auth::request_token(req.header("Cookie"), req.header("Authorization"));
+        let json = match (token, auth_for_session.lock()) {
+            (Some(token), Ok(mut auth)) if auth.is_valid(token) => {
+                let approved = auth.is_approved(token);
+                let pending = auth.pending_code(token, Instant::now());
+                serde_json::json!({
+                    "authenticated": true,
+                    "approved": approved,
+                    "code": pending.map(|(code, _)| code),
+                    "ahead": pending.map_or(0, |(_, ahead)| ahead),
+                })
+            }
+            _ => serde_json::json!({
+                "authenticated": false,
+                "approved": false,
+                "code": null,
+                "ahead": 0,
+            }),
+        }
+        .to_string();
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes())
+    })?;
+
+    // System telemetry snapshot (refreshed by the main loop every ~2 s)
+    let status_for_get = status.clone();
+    server.fn_handler("/api/status", Method::Get, move |req| {
+        let json = match status_for_get.lock() {
+            Ok(status) => serde_json::to_string(&*status).unwrap_or_default(),
             Err(_) => "{}".into(),
         };
         let mut resp = req.into_response(
@@ -37,34 +239,275 @@ pub fn register(
         resp.write_all(json.as_bytes()).map(|_| ())
     })?;
 
-    // Update profile from JSON
-    server.fn_handler("/api/profile", Method::Post, move |mut req| {
-        let content_len = req
-            .header("Content-Length")
-            .and_then(|v| v.parse::<usize>().ok())
+    // Battery gauge reading (null when no MAX17048 answered)
+    server.fn_handler("/api/battery", Method::Get, move |req| {
+        let json = match status.lock() {
+            Ok(status) => serde_json::to_string(&status.battery).unwrap_or_default(),
+            Err(_) => "null".into(),
+        };
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
+
+    // Log ring: plain text by default, `?format=json` for structured entries.
+    // `?since=<seq>` returns only newer records. The live tail is an SSE
+    // stream on its own port (log_stream.rs); this is the polling fallback.
+    server.fn_handler("/api/logs", Method::Get, |req| {
+        let uri = req.uri().to_string();
+        let since = query_param(&uri, "since")
+            .and_then(|v| v.parse::<u64>().ok())
             .unwrap_or(0);
+        let (entries, next) = logger::entries_since(since);
+
+        if query_param(&uri, "format") == Some("json") {
+            let json = serde_json::json!({ "next": next, "entries": entries }).to_string();
+            let mut resp = req.into_response(
+                200,
+                Some("OK"),
+                &[("Content-Type", "application/json; charset=utf-8")],
+            )?;
+            return resp.write_all(json.as_bytes()).map(|_| ());
+        }
+
+        let mut text = String::new();
+        for entry in &entries {
+            text.push_str(&entry.text_line());
+            text.push('\n');
+        }
+        let disposition = if query_param(&uri, "download").is_some() {
+            "attachment; filename=\"vrcbadge-logs.txt\""
+        } else {
+            "inline"
+        };
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[
+                ("Content-Type", "text/plain; charset=utf-8"),
+                ("Content-Disposition", disposition),
+            ],
+        )?;
+        resp.write_all(text.as_bytes()).map(|_| ())
+    })?;
+
+    // Active log level filter
+    server.fn_handler("/api/log-levels", Method::Get, |req| {
+        let json = log_levels_json(&logger::filter());
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
 
-        if content_len == 0 || content_len > MAX_PROFILE_BODY {
+    // Replace the log level filter: `{"spec": "info,wifi=debug"}`. Applied
+    // immediately; the main loop persists it to NVS and updates the Logs page.
+    let auth_for_log_levels = auth.clone();
+    server.fn_handler("/api/log-levels", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_log_levels)? else {
+            return Ok(());
+        };
+        let Some(body) = read_body(&mut req, MAX_LOG_LEVELS_BODY)? else {
             let mut resp =
                 req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
-            resp.write_all(b"Invalid content length")?;
-            return Ok(());
+            return resp.write_all(b"Invalid content length");
+        };
+
+        let parsed = serde_json::from_slice::<serde_json::Value>(&body)
+            .map_err(|e| format!("Invalid JSON: {e}"))
+            .and_then(|body| match body.get("spec").and_then(|s| s.as_str()) {
+                Some(spec) => spec.parse::<LogFilter>(),
+                None => Err("Missing \"spec\" string".into()),
+            });
+        let filter = match parsed {
+            Ok(f) => f,
+            Err(msg) => {
+                let mut resp =
+                    req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
+                resp.write_all(msg.as_bytes())?;
+                return Ok(());
+            }
+        };
+
+        logger::set_filter(filter.clone());
+        log::info!("Log levels set via web: {filter}");
+        let json = log_levels_json(&filter);
+        if let Ok(mut pending) = pending_log_filter.lock() {
+            *pending = Some(filter);
         }
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
 
-        // Read body
-        let mut buf = vec![0u8; content_len];
-        let mut total_read = 0;
-        while total_read < content_len {
-            let n = req.read(&mut buf[total_read..])?;
-            if n == 0 {
-                break;
+    // Device settings (AP password left out)
+    let settings_for_get = settings.clone();
+    server.fn_handler("/api/settings", Method::Get, move |req| {
+        let json = match settings_for_get.lock() {
+            Ok(settings) => settings_json(&settings),
+            Err(_) => "{}".into(),
+        };
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
+
+    // Change device settings: a JSON object with just the fields to change.
+    // Log levels apply at once; the main loop saves the rest to NVS and
+    // applies brightness and UI preferences. AP changes need a restart.
+    let settings_for_set = settings.clone();
+    let auth_for_settings = auth.clone();
+    server.fn_handler("/api/settings", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_settings)? else {
+            return Ok(());
+        };
+        let Some(body) = read_body(&mut req, MAX_SETTINGS_BODY)? else {
+            let mut resp =
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
+            return resp.write_all(b"Invalid content length");
+        };
+        let patch = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(patch) => patch,
+            Err(e) => {
+                let mut resp =
+                    req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
+                return resp.write_all(format!("Invalid JSON: {e}").as_bytes());
             }
-            total_read += n;
+        };
+        let Ok(mut current) = settings_for_set.lock() else {
+            let mut resp = req.into_response(
+                500,
+                Some("Internal Server Error"),
+                &[("Content-Type", "text/plain")],
+            )?;
+            return resp.write_all(b"Settings unavailable");
+        };
+        // The logger holds the live levels; the copy in `current` is only
+        // refreshed on the main loop's next poll.
+        current.log_levels = logger::filter().to_string();
+        let updated = match current.patched(&patch) {
+            Ok(updated) => updated,
+            Err(errors) => {
+                drop(current);
+                let json = serde_json::json!({ "error": "Invalid settings", "fields": errors })
+                    .to_string();
+                let mut resp = req.into_response(
+                    400,
+                    Some("Bad Request"),
+                    &[("Content-Type", "application/json; charset=utf-8")],
+                )?;
+                return resp.write_all(json.as_bytes());
+            }
+        };
+        if updated.log_levels != current.log_levels {
+            logger::set_filter(updated.log_filter());
         }
+        *current = updated;
+        let json = settings_json(&current);
+        drop(current);
+        settings_changed.store(true, Ordering::Relaxed);
+        log::info!("Device settings changed via web");
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
 
-        // Parse JSON
-        let profile = match serde_json::from_slice(&buf[..total_read]) {
-            Ok(p) => p,
+    // Restart, e.g. to bring up changed AP settings. The main loop
+    // restarts on its next poll, after saving anything pending.
+    let auth_for_restart = auth.clone();
+    server.fn_handler("/api/restart", Method::Post, move |req| {
+        let Some(req) = authorize_change(req, &auth_for_restart)? else {
+            return Ok(());
+        };
+        restart.store(true, Ordering::Relaxed);
+        log::info!("Restart requested via web");
+        let mut resp =
+            req.into_response(202, Some("Accepted"), &[("Content-Type", "text/plain")])?;
+        resp.write_all(b"Restarting")
+    })?;
+
+    // Crash history, newest first
+    server.fn_handler("/api/crashes", Method::Get, |req| {
+        let mut history = crash::load_history();
+        history.reverse();
+        let json = serde_json::to_string(&history).unwrap_or_else(|_| "[]".into());
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
+
+    // Clear crash history
+    let auth_for_crashes = auth.clone();
+    server.fn_handler("/api/crashes", Method::Delete, move |req| {
+        let Some(req) = authorize_change(req, &auth_for_crashes)? else {
+            return Ok(());
+        };
+        if let Err(e) = crash::clear_history() {
+            let mut resp = req.into_response(
+                500,
+                Some("Internal Server Error"),
+                &[("Content-Type", "text/plain")],
+            )?;
+            resp.write_all(format!("{e}").as_bytes())?;
+            return Ok(());
+        }
+        crashes_cleared.store(true, Ordering::Relaxed);
+        req.into_ok_response()?.write_all(b"OK").map(|_| ())
+    })?;
+
+    // Get the active profile as JSON
+    let profiles_for_get = profiles.clone();
+    server.fn_handler("/api/profile", Method::Get, move |req| {
+        let json = match profiles_for_get.lock() {
+            Ok(set) => profile_schema::to_json(set.active()).to_string(),
+            Err(_) => "{}".into(),
+        };
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
+
+    // Replace the active profile's fields from JSON
+    let profiles_for_post = profiles.clone();
+    let ops_for_post = profile_ops.clone();
+    let auth_for_profile = auth.clone();
+    server.fn_handler("/api/profile", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_profile)? else {
+            return Ok(());
+        };
+        let Some(body) = read_body(&mut req, MAX_PROFILE_BODY)? else {
+            let mut resp =
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
+            resp.write_all(b"Invalid content length")?;
+            return Ok(());
+        };
+
+        // Parse JSON (any schema version)
+        let profile = match serde_json::from_slice(&body) {
+            Ok(value) => match profile_schema::from_json(value) {
+                Ok(p) => p,
+                Err(e) => return profile_error_response(req, &e),
+            },
             Err(e) => {
                 let mut resp =
                     req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
@@ -74,109 +517,962 @@ pub fn register(
             }
         };
 
-        // Store for the main loop to pick up
-        if let Ok(mut pending) = pending_profile.lock() {
-            *pending = Some(profile);
+        // Apply, then queue for the main loop to persist and show
+        let ticket = match profiles_for_post.lock() {
+            Ok(mut set) => {
+                let id = set.active_id();
+                if let Err(e) = set.update(id, None, Some(profile)) {
+                    drop(set);
+                    return profile_error_response(req, &e);
+                }
+                queue_profile_op(&ops_for_post, ProfileOp::Saved(id))
+            }
+            Err(_) => 0,
+        };
+        if let Err(e) = wait_for_profile_save(&ops_for_post, ticket) {
+            return insufficient_storage(req, &e);
         }
 
         log::info!("Profile updated via web");
         req.into_ok_response()?.write_all(b"OK").map(|_| ())
     })?;
 
-    // Avatar image upload (240x300 raw RGB888)
-    server.fn_handler("/api/avatar", Method::Post, move |mut req| {
-        let expected = storage::AVATAR_IMAGE_SIZE;
-        let content_len = req
-            .header("Content-Length")
-            .and_then(|v| v.parse::<usize>().ok())
-            .unwrap_or(0);
+    // List saved profiles
+    let profiles_for_list = profiles.clone();
+    server.fn_handler("/api/profiles", Method::Get, move |req| {
+        let json = match profiles_for_list.lock() {
+            Ok(set) => serde_json::json!({
+                "active": set.active_id(),
+                "profiles": set.index.entries,
+            })
+            .to_string(),
+            Err(_) => "{}".into(),
+        };
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
 
-        if content_len != expected {
+    // Create a profile: `{"name": "Convention", "profile": {...}}`
+    let profiles_for_create = profiles.clone();
+    let ops_for_create = profile_ops.clone();
+    let auth_for_new_profile = auth.clone();
+    server.fn_handler("/api/profiles", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_new_profile)? else {
+            return Ok(());
+        };
+        let Some(body) = read_body(&mut req, MAX_PROFILE_BODY)? else {
             let mut resp =
                 req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
-            let msg = format!("Expected {expected} bytes, got {content_len}");
-            resp.write_all(msg.as_bytes()).map(|_| ())?;
+            resp.write_all(b"Invalid content length")?;
             return Ok(());
+        };
+        let new = match serde_json::from_slice::<NewProfile>(&body) {
+            Ok(n) => n,
+            Err(e) => {
+                let mut resp =
+                    req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
+                resp.write_all(format!("Invalid JSON: {e}").as_bytes())?;
+                return Ok(());
+            }
+        };
+
+        let profile = match new.profile.map(profile_schema::from_json).transpose() {
+            Ok(p) => p,
+            Err(e) => return profile_error_response(req, &e),
+        };
+        let created = match profiles_for_create.lock() {
+            Ok(mut set) => {
+                let profile = profile.unwrap_or_else(|| set.active().clone());
+                set.create(&new.name, profile)
+            }
+            Err(_) => Err(ProfileError::Invalid("Profile store unavailable".into())),
+        };
+        match created {
+            Ok(id) => {
+                let ticket = queue_profile_op(&ops_for_create, ProfileOp::Saved(id));
+                if let Err(e) = wait_for_profile_save(&ops_for_create, ticket) {
+                    return insufficient_storage(req, &e);
+                }
+                log::info!("Profile {id} created via web");
+                let json = serde_json::json!({ "id": id }).to_string();
+                let mut resp = req.into_response(
+                    201,
+                    Some("Created"),
+                    &[("Content-Type", "application/json; charset=utf-8")],
+                )?;
+                resp.write_all(json.as_bytes()).map(|_| ())
+            }
+            Err(e) => profile_error_response(req, &e),
         }
+    })?;
 
-        let mut buf = vec![0u8; expected];
-        let mut total_read = 0;
-        while total_read < expected {
-            let n = req.read(&mut buf[total_read..])?;
-            if n == 0 {
-                break;
+    // One profile: `{"id", "name", "active", "profile"}`
+    let profiles_for_one = profiles.clone();
+    server.fn_handler("/api/profiles/*", Method::Get, move |req| {
+        let found = match (profile_path(req.uri()), profiles_for_one.lock()) {
+            (Some((id, "")), Ok(set)) => match (set.name(id), set.get(id)) {
+                (Some(name), Some(profile)) => Ok(serde_json::json!({
+                    "id": id,
+                    "name": name,
+                    "active": id == set.active_id(),
+                    "profile": profile_schema::to_json(profile),
+                })
+                .to_string()),
+                _ => Err(ProfileError::NotFound(id)),
+            },
+            _ => Err(ProfileError::Invalid("Expected /api/profiles/<id>".into())),
+        };
+        match found {
+            Ok(json) => {
+                let mut resp = req.into_response(
+                    200,
+                    Some("OK"),
+                    &[("Content-Type", "application/json; charset=utf-8")],
+                )?;
+                resp.write_all(json.as_bytes()).map(|_| ())
             }
-            total_read += n;
+            Err(e) => profile_error_response(req, &e),
         }
+    })?;
 
-        if total_read != expected {
+    // Rename and/or edit a profile: `{"name"?, "profile"?}`
+    let profiles_for_edit = profiles.clone();
+    let ops_for_edit = profile_ops.clone();
+    let auth_for_profile_put = auth.clone();
+    server.fn_handler("/api/profiles/*", Method::Put, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_profile_put)? else {
+            return Ok(());
+        };
+        let Some((id, "")) = profile_path(req.uri()) else {
+            let e = ProfileError::Invalid("Expected /api/profiles/<id>".into());
+            return profile_error_response(req, &e);
+        };
+        let Some(body) = read_body(&mut req, MAX_PROFILE_BODY)? else {
             let mut resp =
                 req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
-            let msg = format!("Incomplete body: got {total_read} of {expected} bytes");
-            resp.write_all(msg.as_bytes()).map(|_| ())?;
+            resp.write_all(b"Invalid content length")?;
+            return Ok(());
+        };
+        let edit = match serde_json::from_slice::<ProfileEdit>(&body) {
+            Ok(e) => e,
+            Err(e) => {
+                let mut resp =
+                    req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
+                resp.write_all(format!("Invalid JSON: {e}").as_bytes())?;
+                return Ok(());
+            }
+        };
+
+        let profile = match edit.profile.map(profile_schema::from_json).transpose() {
+            Ok(p) => p,
+            Err(e) => return profile_error_response(req, &e),
+        };
+        let updated = match profiles_for_edit.lock() {
+            Ok(mut set) => set.update(id, edit.name.as_deref(), profile),
+            Err(_) => Err(ProfileError::Invalid("Profile store unavailable".into())),
+        };
+        match updated {
+            Ok(()) => {
+                let ticket = queue_profile_op(&ops_for_edit, ProfileOp::Saved(id));
+                if let Err(e) = wait_for_profile_save(&ops_for_edit, ticket) {
+                    return insufficient_storage(req, &e);
+                }
+                log::info!("Profile {id} updated via web");
+                req.into_ok_response()?.write_all(b"OK").map(|_| ())
+            }
+            Err(e) => profile_error_response(req, &e),
+        }
+    })?;
+
+    // Delete a profile (and its images). Deleting the active one switches
+    // the badge to its neighbour.
+    let profiles_for_delete = profiles.clone();
+    let ops_for_delete = profile_ops.clone();
+    let auth_for_profile_delete = auth.clone();
+    server.fn_handler("/api/profiles/*", Method::Delete, move |req| {
+        let Some(req) = authorize_change(req, &auth_for_profile_delete)? else {
+            return Ok(());
+        };
+        let removed = match (profile_path(req.uri()), profiles_for_delete.lock()) {
+            (Some((id, "")), Ok(mut set)) => {
+                let was_active = set.active_id() == id;
+                set.remove(id).map(|()| {
+                    let mut ticket = queue_profile_op(&ops_for_delete, ProfileOp::Deleted(id));
+                    if was_active {
+                        let active = set.active_id();
+                        ticket = queue_profile_op(&ops_for_delete, ProfileOp::Activated(active));
+                    }
+                    (id, ticket)
+                })
+            }
+            _ => Err(ProfileError::Invalid("Expected /api/profiles/<id>".into())),
+        };
+        match removed {
+            Ok((id, ticket)) => {
+                if let Err(e) = wait_for_profile_save(&ops_for_delete, ticket) {
+                    return insufficient_storage(req, &e);
+                }
+                log::info!("Profile {id} deleted via web");
+                req.into_ok_response()?.write_all(b"OK").map(|_| ())
+            }
+            Err(e) => profile_error_response(req, &e),
+        }
+    })?;
+
+    // Whole-badge backup: profiles, images and settings in one archive (see
+    // backup.rs). The saved WiFi password is only included with `?wifi=1`.
+    // Handing all of that out needs the same approval as a change.
+    let profiles_for_backup = profiles.clone();
+    let images_for_backup = images.clone();
+    let settings_for_backup = settings.clone();
+    let auth_for_backup = auth.clone();
+    server.fn_handler("/api/backup", Method::Get, move |req| {
+        let Some(req) = authorize_change(req, &auth_for_backup)? else {
+            return Ok(());
+        };
+        let secrets = query_param(req.uri(), "wifi") == Some("1");
+        let wifi = if secrets {
+            match request_wifi_credentials(&wifi_export) {
+                Some(wifi) => wifi,
+                None => {
+                    let mut resp = req.into_response(
+                        503,
+                        Some("Service Unavailable"),
+                        &[("Content-Type", "text/plain")],
+                    )?;
+                    return resp.write_all(b"Timed out reading WiFi settings, try again");
+                }
+            }
+        } else {
+            None
+        };
+        let settings = BackupSettings {
+            log_levels: Some(logger::filter().to_string()),
+            wifi,
+            device: settings_for_backup
+                .lock()
+                .ok()
+                .and_then(|s| backup::device_settings(&s, secrets)),
+        };
+        let Ok(set) = profiles_for_backup.lock().map(|set| set.clone()) else {
+            let mut resp = req.into_response(
+                500,
+                Some("Internal Server Error"),
+                &[("Content-Type", "text/plain")],
+            )?;
+            return resp.write_all(b"Profile store unavailable");
+        };
+
+        // No Content-Length: the archive is sent chunked as it is built.
+        let resp = req.into_response(
+            200,
+            Some("OK"),
+            &[
+                ("Content-Type", "application/octet-stream"),
+                (
+                    "Content-Disposition",
+                    "attachment; filename=\"vrcbadge-backup.vbak\"",
+                ),
+            ],
+        )?;
+        let mut out = ResponseWriter(resp);
+        match backup::write_backup(&mut out, &set, &*images_for_backup, &settings) {
+            Ok(()) => log::info!("Backup downloaded via web"),
+            // Headers are gone; all that's left is to cut the transfer short.
+            Err(e) => log::error!("Backup download failed: {e}"),
+        }
+        Ok(())
+    })?;
+
+    // Restore a backup. Streamed in, checked entry by entry and its images
+    // written under fresh profile ids; the main loop then switches NVS over
+    // and restarts.
+    let profiles_for_restore = profiles.clone();
+    let auth_for_restore = auth.clone();
+    server.fn_handler("/api/restore", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_restore)? else {
             return Ok(());
+        };
+        if pending_restore.lock().map_or(true, |p| p.is_some()) {
+            let mut resp =
+                req.into_response(409, Some("Conflict"), &[("Content-Type", "text/plain")])?;
+            return resp.write_all(b"A restore is already in progress");
+        }
+        let content_len = req
+            .header("Content-Length")
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        if content_len == 0 {
+            let mut resp = req.into_response(
+                411,
+                Some("Length Required"),
+                &[("Content-Type", "text/plain")],
+            )?;
+            return resp.write_all(b"Content-Length required");
+        }
+
+        // The restored images go next to the current ones until the switch,
+        // and they make up nearly all of the archive, so it has to fit in
+        // the free space. Refuse before reading any of it.
+        if let Some(msg) = storage_shortfall("Backup", content_len) {
+            return insufficient_storage(req, &msg);
+        }
+
+        // Reserve ids the restored profiles can take without touching the
+        // current ones' files. Handed back if the restore fails.
+        let first_id = match profiles_for_restore.lock() {
+            Ok(mut set) => {
+                let first = set.index.next_id;
+                set.index.next_id += MAX_PROFILES as u32;
+                first
+            }
+            Err(_) => {
+                let mut resp = req.into_response(
+                    500,
+                    Some("Internal Server Error"),
+                    &[("Content-Type", "text/plain")],
+                )?;
+                return resp.write_all(b"Profile store unavailable");
+            }
+        };
+        let mut body = BodyReader {
+            req: &mut req,
+            remaining: content_len,
+        };
+        let restore = match backup::restore_backup(&mut body, first_id, &*images) {
+            Ok(r) => r,
+            Err(e) => {
+                if let Ok(mut set) = profiles_for_restore.lock() {
+                    // Unless a profile was created meanwhile, which took an
+                    // id after the reserved ones.
+                    if set.index.next_id == first_id + MAX_PROFILES as u32 {
+                        set.index.next_id = first_id;
+                    }
+                }
+                let (status, reason) = match e {
+                    RestoreError::Invalid(_) => (400, "Bad Request"),
+                    RestoreError::Io(_) => (500, "Internal Server Error"),
+                };
+                log::warn!("Restore failed: {e}");
+                let mut resp =
+                    req.into_response(status, Some(reason), &[("Content-Type", "text/plain")])?;
+                return resp.write_all(e.to_string().as_bytes());
+            }
+        };
+
+        log::info!(
+            "Backup received ({} profiles, {} images), restoring",
+            restore.set.index.entries.len(),
+            restore.images.len()
+        );
+        if let Ok(mut pending) = pending_restore.lock() {
+            *pending = Some(RestoredConfig {
+                profiles: restore.set,
+                settings: restore.settings,
+            });
+        }
+        req.into_ok_response()?
+            .write_all(b"Restored, the badge is restarting")
+            .map(|_| ())
+    })?;
+
+    // Factory reset: `{"confirm": true}`. Only opens the confirm dialog on
+    // the badge; once Erase is tapped there, the main loop erases NVS and
+    // SPIFFS step by step, showing progress, then restarts.
+    let auth_for_reset = auth.clone();
+    server.fn_handler("/api/factory-reset", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_reset)? else {
+            return Ok(());
+        };
+        let confirmed = read_body(&mut req, MAX_FACTORY_RESET_BODY)?
+            .and_then(|body| serde_json::from_slice::<FactoryResetRequest>(&body).ok())
+            .is_some_and(|body| body.confirm);
+        if !confirmed {
+            let mut resp =
+                req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
+            return resp.write_all(b"Expected {\"confirm\": true}");
         }
+        if !factory_reset::prompt(&factory_reset) {
+            let mut resp =
+                req.into_response(409, Some("Conflict"), &[("Content-Type", "text/plain")])?;
+            return resp.write_all(b"A factory reset is already in progress");
+        }
+        let mut resp =
+            req.into_response(202, Some("Accepted"), &[("Content-Type", "text/plain")])?;
+        resp.write_all(b"Confirm the factory reset on the badge")
+    })?;
 
-        if let Ok(mut pending) = pending_avatar.lock() {
-            *pending = Some(buf);
+    // Show a profile on the badge: POST /api/profiles/<id>/activate
+    let profiles_for_activate = profiles.clone();
+    let auth_for_activate = auth.clone();
+    server.fn_handler("/api/profiles/*", Method::Post, move |req| {
+        let Some(req) = authorize_change(req, &auth_for_activate)? else {
+            return Ok(());
+        };
+        let activated = match (profile_path(req.uri()), profiles_for_activate.lock()) {
+            (Some((id, "activate")), Ok(mut set)) => set.activate(id).map(|()| id),
+            _ => Err(ProfileError::Invalid(
+                "Expected /api/profiles/<id>/activate".into(),
+            )),
+        };
+        match activated {
+            Ok(id) => {
+                let ticket = queue_profile_op(&profile_ops, ProfileOp::Activated(id));
+                if let Err(e) = wait_for_profile_save(&profile_ops, ticket) {
+                    return insufficient_storage(req, &e);
+                }
+                log::info!("Profile {id} activated via web");
+                req.into_ok_response()?.write_all(b"OK").map(|_| ())
+            }
+            Err(e) => profile_error_response(req, &e),
         }
+    })?;
 
-        log::info!("Avatar image received ({expected} bytes)");
+    // Avatar image upload: 240x300 raw RGB888, a PNG/JPEG to scale and crop,
+    // or a GIF (animated or not)
+    let profiles_for_avatar = profiles.clone();
+    let auth_for_avatar = auth.clone();
+    server.fn_handler("/api/avatar", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_avatar)? else {
+            return Ok(());
+        };
+        let image = match read_image_upload(&mut req, AVATAR_WIDTH, AVATAR_HEIGHT, true)? {
+            Ok(image) => image,
+            Err(e) => return upload_error_response(req, &e),
+        };
+        // The new file is written before the old one goes, so it has to fit
+        // next to it.
+        let needed = match &image {
+            DecodedImage::Still(_) => {
+                image_codec::max_encoded_len(AVATAR_WIDTH as u16, AVATAR_HEIGHT as u16)
+            }
+            DecodedImage::Animated(anim) => anim.encoded_len(),
+        };
+        if let Some(msg) = storage_shortfall("Avatar", needed) {
+            return insufficient_storage(req, &msg);
+        }
+        let Some(profile_id) = active_profile(&profiles_for_avatar) else {
+            let mut resp = req.into_response(
+                500,
+                Some("Internal Server Error"),
+                &[("Content-Type", "text/plain")],
+            )?;
+            return resp.write_all(b"Profile store unavailable");
+        };
+        match image {
+            DecodedImage::Still(data) => {
+                if let Ok(mut pending) = pending_avatar.lock() {
+                    *pending = Some(ProfileUpload { profile_id, data });
+                }
+                log::info!("Avatar image received for profile {profile_id}");
+            }
+            DecodedImage::Animated(data) => {
+                log::info!(
+                    "Animated avatar received for profile {profile_id} ({} frames, {} KB)",
+                    data.frame_count(),
+                    data.encoded_len() / 1024
+                );
+                if let Ok(mut pending) = pending_avatar_animation.lock() {
+                    *pending = Some(ProfileUpload { profile_id, data });
+                }
+            }
+        }
         req.into_ok_response()?.write_all(b"OK").map(|_| ())
     })?;
 
     // Clear background image (revert to solid color)
     let pending_bg_delete = pending_background.clone();
+    let profiles_for_background_delete = profiles.clone();
+    let auth_for_background_delete = auth.clone();
     server.fn_handler("/api/background", Method::Delete, move |req| {
+        let Some(req) = authorize_change(req, &auth_for_background_delete)? else {
+            return Ok(());
+        };
+        let Some(profile_id) = active_profile(&profiles_for_background_delete) else {
+            let mut resp = req.into_response(
+                500,
+                Some("Internal Server Error"),
+                &[("Content-Type", "text/plain")],
+            )?;
+            return resp.write_all(b"Profile store unavailable");
+        };
         // Signal the main loop to clear the background by sending an empty vec.
         if let Ok(mut pending) = pending_bg_delete.lock() {
-            *pending = Some(Vec::new());
+            *pending = Some(ProfileUpload {
+                profile_id,
+                data: Vec::new(),
+            });
         }
         log::info!("Background image clear requested");
         req.into_ok_response()?.write_all(b"OK").map(|_| ())
     })?;
 
-    // Background image upload (480x320 raw RGB888)
-    server.fn_handler("/api/background", Method::Post, move |mut req| {
-        let expected = storage::BACKGROUND_IMAGE_SIZE;
+    // Background image upload: display-sized raw RGB888, or a PNG/JPEG/GIF
+    // to scale and crop (a GIF's first frame only)
+    let auth_for_background = auth.clone();
+    server.fn_handler("/api/background", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_background)? else {
+            return Ok(());
+        };
+        let buf = match read_image_upload(&mut req, BACKGROUND_WIDTH, BACKGROUND_HEIGHT, false)? {
+            Ok(DecodedImage::Still(buf)) => buf,
+            Ok(DecodedImage::Animated(_)) => {
+                let e = UploadError::Unsupported("Animated backgrounds are not supported".into());
+                return upload_error_response(req, &e);
+            }
+            Err(e) => return upload_error_response(req, &e),
+        };
+        let needed =
+            image_codec::max_encoded_len(BACKGROUND_WIDTH as u16, BACKGROUND_HEIGHT as u16);
+        if let Some(msg) = storage_shortfall("Background", needed) {
+            return insufficient_storage(req, &msg);
+        }
+        let Some(profile_id) = active_profile(&profiles) else {
+            let mut resp = req.into_response(
+                500,
+                Some("Internal Server Error"),
+                &[("Content-Type", "text/plain")],
+            )?;
+            return resp.write_all(b"Profile store unavailable");
+        };
+
+        if let Ok(mut pending) = pending_background.lock() {
+            *pending = Some(ProfileUpload {
+                profile_id,
+                data: buf,
+            });
+        }
+
+        log::info!("Background image received for profile {profile_id}");
+        req.into_ok_response()?.write_all(b"OK").map(|_| ())
+    })?;
+
+    // Firmware status: running version + progress of any update in flight
+    let ota_status = ota.clone();
+    server.fn_handler("/api/firmware", Method::Get, move |req| {
+        let json = ota_status.status_json();
+        let mut resp = req.into_response(
+            200,
+            Some("OK"),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        resp.write_all(json.as_bytes()).map(|_| ())
+    })?;
+
+    // Firmware upload (raw ESP app image, streamed into the inactive OTA slot).
+    // The verified image is only staged: it boots after someone taps Install
+    // on the badge itself, so a session alone can't replace the firmware.
+    let auth_for_firmware = auth.clone();
+    server.fn_handler("/api/firmware", Method::Post, move |req| {
+        let Some(mut req) = authorize_change(req, &auth_for_firmware)? else {
+            return Ok(());
+        };
         let content_len = req
             .header("Content-Length")
             .and_then(|v| v.parse::<usize>().ok())
             .unwrap_or(0);
 
-        if content_len != expected {
+        if content_len == 0 {
+            let mut resp = req.into_response(
+                411,
+                Some("Length Required"),
+                &[("Content-Type", "text/plain")],
+            )?;
+            resp.write_all(b"Content-Length required").map(|_| ())?;
+            return Ok(());
+        }
+
+        if !ota.begin_upload(content_len) {
             let mut resp =
-                req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
-            let msg = format!("Expected {expected} bytes, got {content_len}");
-            resp.write_all(msg.as_bytes()).map(|_| ())?;
+                req.into_response(409, Some("Conflict"), &[("Content-Type", "text/plain")])?;
+            resp.write_all(b"Another firmware update is in progress")
+                .map(|_| ())?;
             return Ok(());
         }
 
-        let mut buf = vec![0u8; expected];
-        let mut total_read = 0;
-        while total_read < expected {
-            let n = req.read(&mut buf[total_read..])?;
-            if n == 0 {
-                break;
+        log::info!("Firmware upload started ({content_len} bytes)");
+        let outcome = ota::flash_image(
+            content_len,
+            |buf| Ok(req.read(buf)?),
+            |written, total| ota.report(written, total),
+            false,
+        );
+
+        match outcome {
+            Ok(info) => {
+                let json = serde_json::json!({ "version": info.version }).to_string();
+                log::info!(
+                    "Firmware v{} uploaded via web, waiting for Install on the badge",
+                    info.version
+                );
+                ota.stage(&info.version);
+                let mut resp = req.into_response(
+                    202,
+                    Some("Accepted"),
+                    &[("Content-Type", "application/json; charset=utf-8")],
+                )?;
+                resp.write_all(json.as_bytes()).map(|_| ())
+            }
+            Err(e) => {
+                ota.fail(&e);
+                let (status, reason) = if e.is::<InvalidImage>() {
+                    (400, "Bad Request")
+                } else {
+                    (500, "Internal Server Error")
+                };
+                let mut resp =
+                    req.into_response(status, Some(reason), &[("Content-Type", "text/plain")])?;
+                resp.write_all(format!("{e}").as_bytes()).map(|_| ())
             }
-            total_read += n;
         }
+    })?;
 
-        if total_read != expected {
-            let mut resp =
-                req.into_response(400, Some("Bad Request"), &[("Content-Type", "text/plain")])?;
-            let msg = format!("Incomplete body: got {total_read} of {expected} bytes");
-            resp.write_all(msg.as_bytes()).map(|_| ())?;
-            return Ok(());
+    Ok(())
+}
+
+/// Value of `key` in the URI's query string (no percent-decoding; the
+/// parameters used here are plain numbers and keywords).
+fn query_param<'a>(uri: &'a str, key: &str) -> Option<&'a str> {
+    let (_, query) = uri.split_once('?')?;
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == key).then_some(v)
+    })
+}
+
+/// JSON body for the log level endpoints.
+fn log_levels_json(filter: &LogFilter) -> String {
+    let overrides: serde_json::Map<String, serde_json::Value> = filter
+        .overrides()
+        .iter()
+        .map(|(target, level)| (target.clone(), log_filter::level_name(*level).into()))
+        .collect();
+    serde_json::json!({
+        "spec": filter.to_string(),
+        "default": log_filter::level_name(filter.default_level()),
+        "overrides": overrides,
+        "targets": log_filter::UI_TARGETS,
+    })
+    .to_string()
+}
+
+/// Ask the main loop for the saved WiFi network. `None` if it didn't answer
+/// in time; `Some(None)` if no network is saved.
+fn request_wifi_credentials(export: &PendingWifiExport) -> Option<Option<WifiCredentials>> {
+    if let Ok(mut export) = export.lock() {
+        *export = WifiExport::Requested;
+    }
+    let start = Instant::now();
+    while start.elapsed() < WIFI_EXPORT_TIMEOUT {
+        std::thread::sleep(Duration::from_millis(100));
+        let Ok(mut export) = export.lock() else {
+            return None;
+        };
+        match std::mem::take(&mut *export) {
+            WifiExport::Ready(wifi) => return Some(wifi),
+            waiting => *export = waiting,
         }
+    }
+    if let Ok(mut export) = export.lock() {
+        *export = WifiExport::Idle;
+    }
+    None
+}
 
-        if let Ok(mut pending) = pending_background.lock() {
-            *pending = Some(buf);
+/// Settings as JSON for the web UI. The AP password is replaced by
+/// `password_set` so it can't be read back over the (open) AP, and
+/// `default_ssid` tells what an empty SSID stands for.
+fn settings_json(settings: &DeviceSettings) -> String {
+    let mut json = serde_json::to_value(settings).unwrap_or_default();
+    if let Some(ap) = json.get_mut("ap").and_then(|ap| ap.as_object_mut()) {
+        ap.remove("password");
+        ap.insert(
+            "password_set".into(),
+            (!settings.ap.password.is_empty()).into(),
+        );
+        ap.insert("default_ssid".into(), wifi_state::default_ap_ssid().into());
+    }
+    json.to_string()
+}
+
+/// Pass `req` back if its session may make changes. Otherwise answer 401
+/// (no session) or 403 (not yet allowed on the badge, where its prompt is
+/// shown or queued) and return `None`. Every handler that changes something starts
+/// with this, and so does the backup download.
+fn authorize_change<'a, 'c>(
+    req: Request<&'a mut EspHttpConnection<'c>>,
+    auth: &SharedAuth,
+) -> Result<Option<Request<&'a mut EspHttpConnection<'c>>>, EspIOError> {
+    let access = match auth::request_token(req.header("Cookie"), req.header("Authorization")) {
+        Some(token) => auth.lock().map_or(Access::NoSession, |mut auth| {
+            auth.check_change(token, Instant::now())
+        }),
+        None => Access::NoSession,
+    };
+    let json = match access {
+        Access::Allowed => return Ok(Some(req)),
+        Access::NoSession => {
+            unauthorized(req)?;
+            return Ok(None);
         }
+        Access::Pending { code, ahead } => serde_json::json!({
+            "error": format!("Tap Allow on the badge when it shows {code}"),
+            "approval": "pending",
+            "code": code,
+            "ahead": ahead,
+        }),
+    };
+    let mut resp = req.into_response(
+        403,
+        Some("Forbidden"),
+        &[("Content-Type", "application/json; charset=utf-8")],
+    )?;
+    resp.write_all(json.to_string().as_bytes())?;
+    Ok(None)
+}
 
-        log::info!("Background image received ({expected} bytes)");
-        req.into_ok_response()?.write_all(b"OK").map(|_| ())
-    })?;
+/// Address of the client behind `req`, for counting its wrong PINs.
+/// Clients whose address can't be read share [`Ipv4Addr::UNSPECIFIED`].
+fn client_ip(req: &mut Request<&mut EspHttpConnection<'_>>) -> Ipv4Addr {
+    req.connection()
+        .raw_connection()
+        .and_then(|conn| conn.source_ipv4())
+        .unwrap_or(Ipv4Addr::UNSPECIFIED)
+}
+
+/// Answer 401: no open session.
+fn unauthorized(req: Request<&mut EspHttpConnection<'_>>) -> Result<(), EspIOError> {
+    let mut resp =
+        req.into_response(401, Some("Unauthorized"), &[("Content-Type", "text/plain")])?;
+    resp.write_all(b"Log in with the web PIN from the badge's Settings page")
+}
+
+/// Read a request body of at most `max` bytes. `None` if Content-Length is
+/// missing, zero or larger than `max`.
+fn read_body(
+    req: &mut Request<&mut EspHttpConnection<'_>>,
+    max: usize,
+) -> Result<Option<Vec<u8>>, EspIOError> {
+    let content_len = req
+        .header("Content-Length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    if content_len == 0 || content_len > max {
+        return Ok(None);
+    }
+
+    let mut buf = vec![0u8; content_len];
+    let mut total_read = 0;
+    while total_read < content_len {
+        let n = req.read(&mut buf[total_read..])?;
+        if n == 0 {
+            break;
+        }
+        total_read += n;
+    }
+    buf.truncate(total_read);
+    Ok(Some(buf))
+}
+
+/// Read an avatar/background body as a `width` x `height` image.
+///
+/// The Content-Type picks the format: raw RGB888 (`application/octet-stream`
+/// or none) must be exactly the target size; PNG, JPEG and GIF are decoded,
+/// scaled and cropped as the body streams in. `animated` lets a GIF with
+/// several frames become an animation.
+fn read_image_upload(
+    req: &mut Request<&mut EspHttpConnection<'_>>,
+    width: u32,
+    height: u32,
+    animated: bool,
+) -> Result<Result<DecodedImage, UploadError>, EspIOError> {
+    let format = match UploadFormat::from_content_type(req.header("Content-Type")) {
+        Ok(format) => format,
+        Err(e) => return Ok(Err(e)),
+    };
+    let content_len = req
+        .header("Content-Length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    if format == UploadFormat::Raw {
+        let expected = width as usize * height as usize * 3;
+        if content_len != expected {
+            return Ok(Err(UploadError::Invalid(format!(
+                "Expected {expected} bytes, got {content_len}"
+            ))));
+        }
+        let buf = read_body(req, expected)?.unwrap_or_default();
+        if buf.len() != expected {
+            return Ok(Err(UploadError::Invalid(format!(
+                "Incomplete body: got {} of {expected} bytes",
+                buf.len()
+            ))));
+        }
+        return Ok(Ok(DecodedImage::Still(buf)));
+    }
+
+    if content_len == 0 {
+        return Ok(Err(UploadError::Invalid("Content-Length required".into())));
+    }
+    if content_len > MAX_ENCODED_UPLOAD {
+        return Ok(Err(UploadError::TooLarge(format!(
+            "Image is {content_len} bytes, limit is {MAX_ENCODED_UPLOAD}"
+        ))));
+    }
+    let body = BodyReader {
+        req,
+        remaining: content_len,
+    };
+    Ok(image_decode::decode_upload(
+        format, body, width, height, animated,
+    ))
+}
+
+/// Request body as a `std::io::Read`, stopping at Content-Length.
+struct BodyReader<'r, 'c, 'h> {
+    req: &'r mut Request<&'c mut EspHttpConnection<'h>>,
+    remaining: usize,
+}
+
+impl std::io::Read for BodyReader<'_, '_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.remaining);
+        if len == 0 {
+            return Ok(0);
+        }
+        let n = self
+            .req
+            .read(&mut buf[..len])
+            .map_err(std::io::Error::other)?;
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+/// Response body as a `std::io::Write`, for writers that stream into it.
+struct ResponseWriter<W>(W);
+
+impl<W: Write<Error = EspIOError>> std::io::Write for ResponseWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf).map_err(std::io::Error::other)
+    }
 
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush().map_err(std::io::Error::other)
+    }
+}
+
+/// Why `needed` more bytes of `what` won't fit on SPIFFS, or `None` if they
+/// do. Unreadable usage counts as fitting: the write will tell.
+fn storage_shortfall(what: &str, needed: usize) -> Option<String> {
+    match storage::spiffs_free() {
+        Ok(free) if needed > free => {
+            log::warn!("{what} refused: needs {needed} bytes, {free} free");
+            Some(format!(
+                "{what} needs up to {} KB but only {} KB of storage is free. Delete profiles or \
+                 images you don't need, then try again",
+                needed.div_ceil(1024),
+                free / 1024
+            ))
+        }
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!("SPIFFS usage unavailable: {e}");
+            None
+        }
+    }
+}
+
+/// 507 with the message from [`storage_shortfall`].
+fn insufficient_storage(
+    req: Request<&mut EspHttpConnection<'_>>,
+    msg: &str,
+) -> Result<(), EspIOError> {
+    let mut resp = req.into_response(
+        507,
+        Some("Insufficient Storage"),
+        &[("Content-Type", "text/plain")],
+    )?;
+    resp.write_all(msg.as_bytes())
+}
+
+/// 415 for formats the badge can't decode, 413 over the size limits, 400
+/// for anything malformed.
+fn upload_error_response(
+    req: Request<&mut EspHttpConnection<'_>>,
+    e: &UploadError,
+) -> Result<(), EspIOError> {
+    let (status, reason) = match e {
+        UploadError::Unsupported(_) => (415, "Unsupported Media Type"),
+        UploadError::TooLarge(_) => (413, "Payload Too Large"),
+        UploadError::Invalid(_) => (400, "Bad Request"),
+    };
+    log::warn!("Image upload refused: {e}");
+    let mut resp = req.into_response(status, Some(reason), &[("Content-Type", "text/plain")])?;
+    resp.write_all(e.to_string().as_bytes())
+}
+
+/// Split `/api/profiles/<id>[/<action>][?query]` into the id and the
+/// action (`""` when absent).
+fn profile_path(uri: &str) -> Option<(u32, &str)> {
+    let path = uri.split('?').next()?.strip_prefix("/api/profiles/")?;
+    let (id, action) = path.split_once('/').unwrap_or((path, ""));
+    Some((id.parse().ok()?, action))
+}
+
+/// Queue a change for the main loop to persist.
+fn queue_profile_op(ops: &PendingProfileOps, op: ProfileOp) -> u64 {
+    ops.lock().map(|mut ops| ops.push(op)).unwrap_or(0)
+}
+
+/// Wait for the main loop to write the op with `ticket` to NVS (it polls
+/// every ~2 s). `Err` if that failed; the change has then been undone. If
+/// the main loop is slow the change counts as made: it is still queued.
+fn wait_for_profile_save(ops: &PendingProfileOps, ticket: u64) -> Result<(), String> {
+    let start = Instant::now();
+    while start.elapsed() < PROFILE_SAVE_TIMEOUT {
+        std::thread::sleep(Duration::from_millis(100));
+        let Ok(ops) = ops.lock() else {
+            break;
+        };
+        if let Some(outcome) = ops.outcome(ticket) {
+            return outcome.map_err(|e| format!("Profile not saved: {e}"));
+        }
+    }
+    log::warn!("Profile change not saved yet, leaving it queued");
     Ok(())
 }
+
+/// The profile on screen, which an image upload is for.
+fn active_profile(profiles: &SharedProfiles) -> Option<u32> {
+    profiles.lock().ok().map(|set| set.active_id())
+}
+
+/// 404 for an unknown profile id, 507 over the NVS budget, 400 for anything
+/// else refused. Validation failures are JSON: `{"error", "fields": [{"field", "message"}]}`.
+fn profile_error_response(
+    req: Request<&mut EspHttpConnection<'_>>,
+    e: &ProfileError,
+) -> Result<(), EspIOError> {
+    let (status, reason) = match e {
+        ProfileError::NotFound(_) => (404, "Not Found"),
+        ProfileError::Invalid(_) | ProfileError::Fields(_) => (400, "Bad Request"),
+        ProfileError::TooLarge(_) => (507, "Insufficient Storage"),
+    };
+    if let ProfileError::Fields(errors) = e {
+        let json = serde_json::json!({ "error": "Invalid profile", "fields": errors }).to_string();
+        let mut resp = req.into_response(
+            status,
+            Some(reason),
+            &[("Content-Type", "application/json; charset=utf-8")],
+        )?;
+        return resp.write_all(json.as_bytes());
+    }
+    let mut resp = req.into_response(status, Some(reason), &[("Content-Type", "text/plain")])?;
+    resp.write_all(e.to_string().as_bytes())
+}
@@ -0,0 +1,158 @@
+//! Live log tail: `GET /api/logs/stream` as Server-Sent Events, served on
+//! its own port.
+//!
+//! The HTTP server runs every handler on one httpd task, so a response held
+//! open for as long as someone watches the logs would stall the whole API.
+//! The stream gets a plain TCP listener on [`PORT`] instead, with a thread
+//! per client (at most [`MAX_CLIENTS`]) that sleeps until the logger adds a
+//! record. Each event is one record as JSON with its `seq` as the event id,
+//! so a reconnecting browser (`Last-Event-ID`) or `?since=<seq>` resumes
+//! where it left off.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::logger;
+
+/// Port of the log stream; the page on port 80 connects to it directly.
+pub const PORT: u16 = 81;
+
+/// Streams open at once. Each costs a thread and a socket.
+const MAX_CLIENTS: usize = 2;
+
+/// A comment line goes out when nothing was logged for this long, so a
+/// client that went away is noticed by the failed write.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// A client that stops reading is dropped after this long.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest request head read; the rest is ignored.
+const MAX_HEAD: usize = 2048;
+
+static CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Start the log stream listener on a background thread.
+pub fn start() -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", PORT))?;
+    log::info!("Log stream listening on :{PORT}");
+
+    thread::Builder::new()
+        .name("log-stream".into())
+        .stack_size(4096)
+        .spawn(move || {
+            for conn in listener.incoming() {
+                let stream = match conn {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("Log stream accept error: {e}");
+                        continue;
+                    }
+                };
+                if CLIENTS.fetch_add(1, Ordering::Relaxed) >= MAX_CLIENTS {
+                    CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                    let _ = respond(stream, "503 Service Unavailable", "Too many log streams");
+                    continue;
+                }
+                let spawned = thread::Builder::new()
+                    .name("log-client".into())
+                    .stack_size(6144)
+                    .spawn(move || {
+                        if let Err(e) = serve(stream) {
+                            log::debug!("Log stream closed: {e}");
+                        }
+                        CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                    });
+                if let Err(e) = spawned {
+                    CLIENTS.fetch_sub(1, Ordering::Relaxed);
+                    log::warn!("Log stream thread failed to start: {e}");
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Answer one client: check the request, then send records until it goes.
+fn serve(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let head = read_head(&mut stream)?;
+    let Some(mut since) = requested_since(&head) else {
+        return respond(stream, "404 Not Found", "Not found");
+    };
+
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: text/event-stream\r\n\
+          Cache-Control: no-cache\r\n\
+          Access-Control-Allow-Origin: *\r\n\
+          Connection: close\r\n\r\n\
+          retry: 3000\n\n",
+    )?;
+    loop {
+        let (entries, next) = logger::wait_entries_since(since, KEEPALIVE);
+        if entries.is_empty() {
+            stream.write_all(b": keep-alive\n\n")?;
+        }
+        for entry in &entries {
+            stream.write_all(entry.sse_event().as_bytes())?;
+        }
+        since = next;
+    }
+}
+
+/// Read up to the blank line that ends the request head.
+fn read_head(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 256];
+    while !head.ends_with(b"\r\n\r\n") && head.len() < MAX_HEAD {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// The `seq` to stream after for a `GET /api/logs/stream` request head, or
+/// `None` for any other request. `Last-Event-ID` (sent on reconnects) wins
+/// over `?since=`; without either the whole ring is sent.
+fn requested_since(head: &str) -> Option<u64> {
+    let mut lines = head.lines();
+    let mut request = lines.next()?.split(' ');
+    if request.next()? != "GET" {
+        return None;
+    }
+    let target = request.next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/api/logs/stream" {
+        return None;
+    }
+    let last_event_id = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if !name.eq_ignore_ascii_case("last-event-id") {
+            return None;
+        }
+        value.trim().parse::<u64>().ok()
+    });
+    let since = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("since="))
+        .and_then(|v| v.parse::<u64>().ok());
+    Some(last_event_id.or(since).unwrap_or(0))
+}
+
+/// Send a short plain-text response and close.
+fn respond(mut stream: TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes())
+}
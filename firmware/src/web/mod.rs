@@ -1,33 +1,55 @@
 mod api;
 mod captive;
+mod log_stream;
 mod pages;
 
 use std::net::Ipv4Addr;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 use esp_idf_svc::http::server::{Configuration, EspHttpServer};
 
-use crate::profile::{CurrentProfile, PendingProfile};
+use crate::auth::SharedAuth;
+use crate::factory_reset::PendingFactoryReset;
+use crate::hal::SharedImageStore;
+use crate::ota::OtaUpdater;
+use crate::pending::{
+    PendingAnimation, PendingLogFilter, PendingRestore, PendingWifiExport, SharedImageData,
+};
+use crate::profile::{PendingProfileOps, SharedProfiles};
+use crate::settings::SharedSettings;
+use crate::status::SharedStatus;
 
-/// Shared state for passing image data from the HTTP thread to the main loop.
-pub type SharedImageData = Arc<Mutex<Option<Vec<u8>>>>;
+/// Shared state the HTTP handlers hand data through to the main loop.
+pub struct WebContext {
+    pub pending_background: SharedImageData,
+    pub pending_avatar: SharedImageData,
+    pub pending_avatar_animation: PendingAnimation,
+    pub profiles: SharedProfiles,
+    pub profile_ops: PendingProfileOps,
+    pub pending_log_filter: PendingLogFilter,
+    pub pending_restore: PendingRestore,
+    pub wifi_export: PendingWifiExport,
+    pub factory_reset: PendingFactoryReset,
+    pub settings: SharedSettings,
+    pub settings_changed: Arc<AtomicBool>,
+    pub restart: Arc<AtomicBool>,
+    pub images: SharedImageStore,
+    pub crashes_cleared: Arc<AtomicBool>,
+    pub ota: OtaUpdater,
+    pub status: SharedStatus,
+    pub auth: SharedAuth,
+}
 
 /// Start the HTTP server and register all routes.
 ///
 /// `ap_ip` is the AP's actual IP address, used for captive portal redirects.
-/// Takes shared state for passing data between HTTP handlers and the main loop.
 /// Returns the server handle — caller must hold it to keep the server alive.
-pub fn init(
-    ap_ip: Ipv4Addr,
-    pending_background: SharedImageData,
-    pending_avatar: SharedImageData,
-    current_profile: CurrentProfile,
-    pending_profile: PendingProfile,
-) -> anyhow::Result<EspHttpServer<'static>> {
+pub fn init(ap_ip: Ipv4Addr, ctx: WebContext) -> anyhow::Result<EspHttpServer<'static>> {
     let config = Configuration {
         http_port: 80,
         stack_size: 16384,
-        max_uri_handlers: 16,
+        max_uri_handlers: 40,
         uri_match_wildcard: true,
         ..Default::default()
     };
@@ -37,16 +59,15 @@ pub fn init(
 
     // Registration order matters: specific routes first, wildcard fallback last.
     pages::register(&mut server)?;
-    api::register(
-        &mut server,
-        pending_background,
-        pending_avatar,
-        current_profile,
-        pending_profile,
-    )?;
+    api::register(&mut server, ctx)?;
     captive::register(&mut server, &redirect_url)?;
 
     log::info!("HTTP server started on port 80");
 
+    // Live log tail, kept off the httpd task (see log_stream.rs)
+    if let Err(e) = log_stream::start() {
+        log::warn!("Log stream not started: {e}");
+    }
+
     Ok(server)
 }
@@ -1,11 +1,14 @@
 //! WiFi driver: AP + optional STA (station) mode.
 //!
-//! The badge always runs an access point (`VRCBadge`, open, 192.168.71.x)
-//! for the configuration web portal. Optionally, it also connects to a
+//! The badge always runs an access point (`VRCBadge-XXXX` by default, see
+//! [`ApSettings`], 192.168.71.x) for the configuration web portal. Optionally, it also connects to a
 //! nearby WiFi network in station mode (for future OTA updates, etc.).
 //!
 //! When both modes are active, ESP-IDF runs them simultaneously using
 //! `Configuration::Mixed`.
+//!
+//! The rest of the firmware reaches the driver through
+//! [`WifiController`], implemented at the bottom of this file.
 
 use std::net::Ipv4Addr;
 
@@ -13,59 +16,114 @@ use esp_idf_hal::modem::Modem;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use esp_idf_svc::wifi::{
-    AccessPointConfiguration, AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi,
+    AccessPointConfiguration, AuthMethod, BlockingWifi, ClientConfiguration, Configuration,
+    EspWifi, WifiDeviceId,
+};
+use esp_idf_sys::{
+    esp, esp_wifi_ap_get_sta_list, esp_wifi_sta_get_ap_info, wifi_ap_record_t, wifi_sta_list_t,
 };
-use esp_idf_sys::{esp, esp_wifi_ap_get_sta_list, wifi_sta_list_t};
-
-/// Result of scanning a nearby access point.
-#[derive(Debug, Clone)]
-pub struct ScannedAp {
-    pub ssid: String,
-    /// RSSI in dBm (e.g. -40 = strong, -80 = weak).
-    pub rssi: i8,
-    /// Whether the network requires authentication.
-    pub auth_required: bool,
-}
 
-/// Station connection status.
-#[derive(Debug, Clone)]
-pub enum StaStatus {
-    Disconnected,
-    Connected { ssid: String, ip: Ipv4Addr },
-}
+use crate::hal::{ScannedAp, StaStatus, WifiController};
+use crate::settings::ApSettings;
+use crate::wifi_state::{self, RunningAp};
+
+/// The WiFi driver (AP + optional STA).
+pub type Wifi = BlockingWifi<EspWifi<'static>>;
 
-/// AP configuration used for all modes.
-fn ap_config() -> AccessPointConfiguration {
-    AccessPointConfiguration {
-        ssid: "VRCBadge".try_into().unwrap(),
-        auth_method: AuthMethod::None,
-        channel: 1,
-        max_connections: 4,
+/// AP configuration from the saved settings.
+fn ap_config(ap: &ApSettings) -> anyhow::Result<AccessPointConfiguration> {
+    Ok(AccessPointConfiguration {
+        ssid: ap
+            .ssid
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("AP SSID too long"))?,
+        password: ap
+            .password
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("AP password too long"))?,
+        auth_method: if ap.password.is_empty() {
+            AuthMethod::None
+        } else {
+            AuthMethod::WPA2Personal
+        },
+        channel: ap.channel,
+        ssid_hidden: ap.hidden,
+        max_connections: ap.max_clients as u16,
         ..Default::default()
+    })
+}
+
+/// The running AP configuration, kept when switching between AP-only and
+/// Mixed mode. With the AP turned off by the power policy, the one started
+/// at boot.
+fn current_ap_config(wifi: &Wifi) -> anyhow::Result<AccessPointConfiguration> {
+    match wifi.get_configuration()? {
+        Configuration::AccessPoint(ap) | Configuration::Mixed(_, ap) => Ok(ap),
+        _ => ap_config(wifi_state::running_ap()),
     }
 }
 
-/// Initialize WiFi in AP-only mode.
+/// Initialize WiFi in AP-only mode with the saved `ap` settings. If the
+/// driver rejects them, start with the defaults instead so the badge stays
+/// reachable.
 ///
-/// Returns the WiFi handle and the AP's IP address.
-/// The handle must be kept alive for the AP to stay running.
+/// Returns the WiFi handle, the AP's IP address, what was started and
+/// whether the saved settings were rejected for the defaults. The handle
+/// must be kept alive for the AP to stay running.
 pub fn init(
     modem: Modem,
     sys_loop: EspSystemEventLoop,
     nvs: EspDefaultNvsPartition,
-) -> anyhow::Result<(BlockingWifi<EspWifi<'static>>, Ipv4Addr)> {
+    ap: &ApSettings,
+) -> anyhow::Result<(Wifi, Ipv4Addr, RunningAp, bool)> {
     let mut wifi = BlockingWifi::wrap(EspWifi::new(modem, sys_loop.clone(), Some(nvs))?, sys_loop)?;
+    // Known once the driver is initialized, before the AP starts
+    let mac = wifi.wifi().get_mac(WifiDeviceId::Ap)?;
 
-    wifi.set_configuration(&Configuration::AccessPoint(ap_config()))?;
-    wifi.start()?;
-    wifi.wait_netif_up()?;
+    let (running, fallback) = match start_ap(&mut wifi, &ap.for_device(mac)) {
+        Ok(()) => (RunningAp::new(ap, mac), false),
+        Err(e) => {
+            log::error!("AP settings rejected ({e}), starting with the defaults");
+            let defaults = ApSettings::default();
+            start_ap(&mut wifi, &defaults.for_device(mac))?;
+            (RunningAp::new(&defaults, mac), true)
+        }
+    };
 
     let ip_info = wifi.wifi().ap_netif().get_ip_info()?;
     let ip = Ipv4Addr::from(ip_info.ip.octets());
 
-    log::info!("WiFi AP started -- SSID: VRCBadge, IP: {ip}");
+    let ap = &running.settings;
+    log::info!(
+        "WiFi AP started -- SSID: {}{}, channel {}, {}, up to {} clients, IP: {ip}",
+        ap.ssid,
+        if ap.hidden { " (hidden)" } else { "" },
+        ap.channel,
+        if ap.password.is_empty() {
+            "open"
+        } else {
+            "WPA2"
+        },
+        ap.max_clients,
+    );
+
+    Ok((wifi, ip, running, fallback))
+}
 
-    Ok((wifi, ip))
+/// Configure and start the AP, leaving the radio stopped on failure.
+fn start_ap(wifi: &mut Wifi, ap: &ApSettings) -> anyhow::Result<()> {
+    let result = (|| {
+        wifi.set_configuration(&Configuration::AccessPoint(ap_config(ap)?))?;
+        wifi.start()?;
+        wifi.wait_netif_up()?;
+        anyhow::Ok(())
+    })();
+    if result.is_err() && wifi.is_started().unwrap_or(false) {
+        let _ = wifi.stop();
+    }
+    result
 }
 
 /// Scan for nearby access points.
@@ -76,7 +134,7 @@ pub fn init(
 /// previously configured), the scan runs directly.
 ///
 /// Returns up to 20 APs sorted by signal strength (strongest first).
-pub fn scan(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Result<Vec<ScannedAp>> {
+pub fn scan(wifi: &mut Wifi) -> anyhow::Result<Vec<ScannedAp>> {
     log::info!("Starting WiFi scan...");
 
     // Scanning requires the STA interface. If we're in AP-only mode,
@@ -85,7 +143,8 @@ pub fn scan(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Result<Vec<Sca
 
     if was_ap_only {
         let dummy_sta = ClientConfiguration::default();
-        wifi.set_configuration(&Configuration::Mixed(dummy_sta, ap_config()))?;
+        let ap = current_ap_config(wifi)?;
+        wifi.set_configuration(&Configuration::Mixed(dummy_sta, ap))?;
         wifi.stop()?;
         wifi.start()?;
     }
@@ -121,11 +180,7 @@ pub fn scan(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Result<Vec<Sca
 ///
 /// Switches from AP-only to Mixed (AP+STA) mode, then attempts to connect.
 /// Uses a 10-second timeout. On failure, reverts to AP-only mode.
-pub fn connect_sta(
-    wifi: &mut BlockingWifi<EspWifi<'static>>,
-    ssid: &str,
-    password: &str,
-) -> anyhow::Result<Ipv4Addr> {
+pub fn connect_sta(wifi: &mut Wifi, ssid: &str, password: &str) -> anyhow::Result<Ipv4Addr> {
     log::info!("Connecting to WiFi network: {ssid}");
 
     let auth = if password.is_empty() {
@@ -146,7 +201,8 @@ pub fn connect_sta(
     };
 
     // Switch to Mixed mode (AP stays running, STA connects)
-    wifi.set_configuration(&Configuration::Mixed(client_config, ap_config()))?;
+    let ap = current_ap_config(wifi)?;
+    wifi.set_configuration(&Configuration::Mixed(client_config, ap))?;
     wifi.stop()?;
     wifi.start()?;
 
@@ -178,7 +234,7 @@ pub fn connect_sta(
 }
 
 /// Disconnect from the external WiFi network and revert to AP-only mode.
-pub fn disconnect_sta(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Result<()> {
+pub fn disconnect_sta(wifi: &mut Wifi) -> anyhow::Result<()> {
     log::info!("Disconnecting WiFi STA");
     let _ = wifi.disconnect();
     revert_to_ap_only(wifi)?;
@@ -187,7 +243,7 @@ pub fn disconnect_sta(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Resu
 }
 
 /// Query station connection status (connected AP name + IP, or disconnected).
-pub fn sta_status(wifi: &BlockingWifi<EspWifi<'static>>) -> StaStatus {
+pub fn sta_status(wifi: &Wifi) -> StaStatus {
     if !wifi.is_connected().unwrap_or(false) {
         return StaStatus::Disconnected;
     }
@@ -201,17 +257,30 @@ pub fn sta_status(wifi: &BlockingWifi<EspWifi<'static>>) -> StaStatus {
 
     // Get the connected SSID from the current configuration
     let ssid = match wifi.get_configuration() {
-        Ok(Configuration::Mixed(client, _)) => client.ssid.to_string(),
+        Ok(Configuration::Mixed(client, _) | Configuration::Client(client)) => {
+            client.ssid.to_string()
+        }
         _ => String::new(),
     };
 
     if ip == Ipv4Addr::UNSPECIFIED {
         StaStatus::Disconnected
     } else {
-        StaStatus::Connected { ssid, ip }
+        StaStatus::Connected {
+            ssid,
+            ip,
+            rssi: sta_rssi().unwrap_or(0),
+        }
     }
 }
 
+/// Signal strength (dBm) of the AP the station is joined to.
+fn sta_rssi() -> Option<i8> {
+    let mut info: wifi_ap_record_t = unsafe { core::mem::zeroed() };
+    unsafe { esp!(esp_wifi_sta_get_ap_info(&mut info)) }.ok()?;
+    Some(info.rssi)
+}
+
 /// Query the number of stations currently connected to the AP.
 pub fn connected_clients() -> u8 {
     let mut sta_list: wifi_sta_list_t = unsafe { core::mem::zeroed() };
@@ -221,11 +290,60 @@ pub fn connected_clients() -> u8 {
     }
 }
 
+/// Turn the AP off or back on for the power policy. Off drops to
+/// station-only if the STA is in use, and stops the radio otherwise.
+pub fn set_ap_enabled(wifi: &mut Wifi, enabled: bool) -> anyhow::Result<()> {
+    match (enabled, wifi.get_configuration()?) {
+        (false, Configuration::AccessPoint(_)) if wifi.is_started()? => wifi.stop()?,
+        (false, Configuration::Mixed(client, _)) => {
+            wifi.set_configuration(&Configuration::Client(client))?
+        }
+        (true, Configuration::Client(client)) => {
+            let ap = ap_config(wifi_state::running_ap())?;
+            wifi.set_configuration(&Configuration::Mixed(client, ap))?
+        }
+        (true, Configuration::AccessPoint(_)) if !wifi.is_started()? => {
+            wifi.start()?;
+            wifi.wait_netif_up()?;
+        }
+        _ => return Ok(()),
+    }
+    log::info!("WiFi AP {}", if enabled { "on" } else { "off" });
+    Ok(())
+}
+
 /// Revert WiFi from Mixed mode back to AP-only.
-fn revert_to_ap_only(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Result<()> {
-    wifi.set_configuration(&Configuration::AccessPoint(ap_config()))?;
+fn revert_to_ap_only(wifi: &mut Wifi) -> anyhow::Result<()> {
+    let ap = current_ap_config(wifi)?;
+    wifi.set_configuration(&Configuration::AccessPoint(ap))?;
     wifi.stop()?;
     wifi.start()?;
     wifi.wait_netif_up()?;
     Ok(())
 }
+
+impl WifiController for Wifi {
+    fn scan(&mut self) -> anyhow::Result<Vec<ScannedAp>> {
+        scan(self)
+    }
+
+    fn connect_sta(&mut self, ssid: &str, password: &str) -> anyhow::Result<Ipv4Addr> {
+        connect_sta(self, ssid, password)
+    }
+
+    fn disconnect_sta(&mut self) -> anyhow::Result<()> {
+        disconnect_sta(self)
+    }
+
+    fn sta_status(&self) -> StaStatus {
+        sta_status(self)
+    }
+
+    fn ap_clients(&self) -> u8 {
+        connected_clients()
+    }
+
+    fn set_ap_enabled(&mut self, enabled: bool) -> anyhow::Result<()> {
+        set_ap_enabled(self, enabled)
+    }
+}
@@ -6,19 +6,59 @@
 //! loop, where they are drained and applied to the UI.
 
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 
-use esp_idf_svc::nvs::{EspNvs, NvsDefault};
-use esp_idf_svc::wifi::{BlockingWifi, EspWifi};
+use crate::hal::{KeyValueStore, ScannedAp, SharedWifi};
+use crate::settings::{self, ApSettings};
+use crate::{storage, BadgeUI, ScanResult};
 
-use crate::{storage, wifi, BadgeUI, ScanResult};
+/// The badge's access point as started at boot. Saved AP settings only
+/// take effect on the next boot, so the WiFi QR code reads this rather
+/// than the current settings.
+pub struct RunningAp {
+    /// The settings in use, with the default SSID filled in.
+    pub settings: ApSettings,
+    /// This badge's default SSID, used when the saved one is empty.
+    pub default_ssid: String,
+}
+
+impl RunningAp {
+    /// `settings` as started on the AP with MAC address `mac`.
+    pub fn new(settings: &ApSettings, mac: [u8; 6]) -> Self {
+        Self {
+            settings: settings.for_device(mac),
+            default_ssid: settings::default_ap_ssid(mac),
+        }
+    }
+}
+
+static RUNNING_AP: OnceLock<RunningAp> = OnceLock::new();
+
+/// Record the AP the radio was started with. Call once at boot.
+pub fn set_running_ap(ap: RunningAp) {
+    let _ = RUNNING_AP.set(ap);
+}
+
+fn running() -> &'static RunningAp {
+    RUNNING_AP.get_or_init(|| RunningAp::new(&ApSettings::default(), [0; 6]))
+}
+
+/// The running access point (the defaults before [`set_running_ap`]).
+pub fn running_ap() -> &'static ApSettings {
+    &running().settings
+}
+
+/// This badge's default SSID.
+pub fn default_ap_ssid() -> &'static str {
+    &running().default_ssid
+}
 
 /// Result of a background WiFi operation, polled by the main loop.
 pub enum WiFiOpResult {
     /// No pending result.
     Idle,
     /// Scan completed with a list of APs.
-    ScanDone(Vec<wifi::ScannedAp>),
+    ScanDone(Vec<ScannedAp>),
     /// Connect succeeded: SSID, password (for NVS save), and IP.
     ConnectOk {
         ssid: String,
@@ -48,11 +88,7 @@ impl WiFiState {
     /// Spawn a background scan. The UI is updated immediately on the main
     /// thread to show "scanning"; results land via [`Self::poll`] on the
     /// next main-loop tick.
-    pub fn spawn_scan(
-        &self,
-        wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
-        ui: slint::Weak<BadgeUI>,
-    ) {
+    pub fn spawn_scan(&self, wifi: SharedWifi, ui: slint::Weak<BadgeUI>) {
         if let Some(ui) = ui.upgrade() {
             ui.set_wifi_scanning(true);
             ui.set_wifi_connect_status("".into());
@@ -63,7 +99,7 @@ impl WiFiState {
             .stack_size(4096)
             .spawn(move || {
                 let aps = if let Ok(mut wifi) = wifi.lock() {
-                    wifi::scan(&mut wifi).unwrap_or_default()
+                    wifi.scan().unwrap_or_default()
                 } else {
                     Vec::new()
                 };
@@ -78,7 +114,7 @@ impl WiFiState {
     /// show "Connecting..."; the result lands via [`Self::poll`] later.
     pub fn spawn_connect(
         &self,
-        wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+        wifi: SharedWifi,
         ui: slint::Weak<BadgeUI>,
         ssid: String,
         password: String,
@@ -92,7 +128,7 @@ impl WiFiState {
             .stack_size(4096)
             .spawn(move || {
                 let outcome = if let Ok(mut wifi) = wifi.lock() {
-                    wifi::connect_sta(&mut wifi, &ssid, &password)
+                    wifi.connect_sta(&ssid, &password)
                 } else {
                     Err(anyhow::anyhow!("WiFi lock failed"))
                 };
@@ -111,7 +147,7 @@ impl WiFiState {
     pub fn poll(
         &self,
         ui: &BadgeUI,
-        nvs: &mut EspNvs<NvsDefault>,
+        nvs: &mut dyn KeyValueStore,
         dismiss_keyboard: &Arc<AtomicBool>,
         sta_connected: &mut bool,
     ) {
@@ -150,3 +186,132 @@ impl WiFiState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use slint::{ComponentHandle, Model};
+
+    use super::*;
+    use crate::hal::fake::{with_ui, FakeNetwork, FakeWifi, MemoryKvStore};
+
+    fn wifi() -> SharedWifi {
+        let network = |ssid: &str, rssi, password: &str| FakeNetwork {
+            ssid: ssid.into(),
+            rssi,
+            password: password.into(),
+        };
+        Arc::new(Mutex::new(FakeWifi::new(vec![
+            network("Cafe", -70, ""),
+            network("HomeNet", -40, "secret123"),
+        ])))
+    }
+
+    /// Poll until the worker thread's result has been applied.
+    fn poll_until(
+        state: &WiFiState,
+        ui: &BadgeUI,
+        nvs: &mut MemoryKvStore,
+        dismiss: &Arc<AtomicBool>,
+        sta_connected: &mut bool,
+        done: impl Fn(&BadgeUI) -> bool,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(ui) {
+            assert!(Instant::now() < deadline, "no WiFi result");
+            std::thread::sleep(Duration::from_millis(5));
+            state.poll(ui, nvs, dismiss, sta_connected);
+        }
+    }
+
+    #[test]
+    fn scan_results_reach_the_ui() {
+        with_ui(|ui| {
+            let state = WiFiState::new();
+            let (mut nvs, dismiss, mut connected) = (
+                MemoryKvStore::default(),
+                Arc::new(AtomicBool::new(false)),
+                false,
+            );
+            state.spawn_scan(wifi(), ui.as_weak());
+            assert!(ui.get_wifi_scanning());
+            poll_until(&state, ui, &mut nvs, &dismiss, &mut connected, |ui| {
+                !ui.get_wifi_scanning()
+            });
+
+            let results = ui.get_wifi_scan_results();
+            let ssids: Vec<String> = results.iter().map(|r| r.ssid.to_string()).collect();
+            assert_eq!(ssids, ["HomeNet", "Cafe"]);
+            assert!(results.row_data(0).unwrap().secure);
+            assert!(!results.row_data(1).unwrap().secure);
+            assert!(!connected);
+        });
+    }
+
+    #[test]
+    fn a_successful_connect_is_saved() {
+        with_ui(|ui| {
+            let state = WiFiState::new();
+            let (mut nvs, dismiss, mut connected) = (
+                MemoryKvStore::default(),
+                Arc::new(AtomicBool::new(false)),
+                false,
+            );
+            state.spawn_connect(wifi(), ui.as_weak(), "HomeNet".into(), "secret123".into());
+            assert_eq!(ui.get_wifi_connect_status(), "Connecting...");
+            poll_until(&state, ui, &mut nvs, &dismiss, &mut connected, |ui| {
+                ui.get_wifi_connect_status() != "Connecting..."
+            });
+
+            assert_eq!(ui.get_wifi_connect_status(), "Connected");
+            assert!(ui.get_sta_connected());
+            assert_eq!(ui.get_sta_ssid(), "HomeNet");
+            assert_eq!(ui.get_sta_ip(), "192.168.1.42");
+            assert!(connected);
+            assert!(dismiss.load(Ordering::Relaxed));
+            assert_eq!(
+                storage::load_wifi_credentials(&nvs),
+                Some(("HomeNet".into(), "secret123".into()))
+            );
+        });
+    }
+
+    #[test]
+    fn a_failed_connect_saves_nothing() {
+        with_ui(|ui| {
+            let state = WiFiState::new();
+            let (mut nvs, dismiss, mut connected) = (
+                MemoryKvStore::default(),
+                Arc::new(AtomicBool::new(false)),
+                false,
+            );
+            state.spawn_connect(wifi(), ui.as_weak(), "HomeNet".into(), "wrong".into());
+            poll_until(&state, ui, &mut nvs, &dismiss, &mut connected, |ui| {
+                ui.get_wifi_connect_status() != "Connecting..."
+            });
+
+            assert!(ui.get_wifi_connect_status().starts_with("Failed: "));
+            assert!(!ui.get_sta_connected());
+            assert!(!connected);
+            assert!(!dismiss.load(Ordering::Relaxed));
+            assert!(nvs.entries.is_empty());
+        });
+    }
+
+    #[test]
+    fn polling_with_nothing_pending_changes_nothing() {
+        with_ui(|ui| {
+            let state = WiFiState::new();
+            let (mut nvs, dismiss, mut connected) = (
+                MemoryKvStore::default(),
+                Arc::new(AtomicBool::new(false)),
+                false,
+            );
+            ui.set_wifi_connect_status("Connected".into());
+            state.poll(ui, &mut nvs, &dismiss, &mut connected);
+            assert_eq!(ui.get_wifi_connect_status(), "Connected");
+            assert!(!connected);
+        });
+    }
+}